@@ -1,7 +1,24 @@
 pub mod authors;
 pub mod book_files;
 pub mod books;
+pub mod custom_columns;
 pub mod languages;
 pub mod publishers;
 pub mod ratings;
+pub mod series;
 pub mod tags;
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use diesel::SqliteConnection;
+
+/// Locks the shared connection for a single handler call.
+///
+/// A panic on another thread while it held the lock does not leave the
+/// SQLite connection itself unusable, so a poisoned lock is recovered
+/// instead of being turned into a second panic here.
+pub(crate) fn lock_connection(
+    client: &Mutex<SqliteConnection>,
+) -> MutexGuard<'_, SqliteConnection> {
+    client.lock().unwrap_or_else(PoisonError::into_inner)
+}
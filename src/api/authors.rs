@@ -4,11 +4,13 @@ use std::sync::Mutex;
 use diesel::prelude::*;
 use diesel::SelectableHelper;
 
+use crate::api::lock_connection;
 use crate::dtos::author::NewAuthorDto;
 use crate::dtos::author::UpdateAuthorDto;
 use crate::entities::author::NewAuthor;
 use crate::entities::author::UpdateAuthorData;
 use crate::Author;
+use crate::CalibreError;
 
 pub struct AuthorsHandler {
     client: Arc<Mutex<SqliteConnection>>,
@@ -19,62 +21,62 @@ impl AuthorsHandler {
         Self { client }
     }
 
-    pub fn list(&self) -> Result<Vec<Author>, ()> {
+    pub fn list(&self) -> Result<Vec<Author>, CalibreError> {
         use crate::schema::authors::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         authors
             .select(Author::as_select())
             .load::<Author>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn create(&mut self, dto: NewAuthorDto) -> Result<Author, ()> {
+    pub fn create(&mut self, dto: NewAuthorDto) -> Result<Author, CalibreError> {
         use crate::schema::authors::dsl::*;
         let new_author = NewAuthor::try_from(dto)?;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(authors)
             .values(new_author)
             .returning(Author::as_returning())
             .get_result::<Author>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn create_if_missing(&mut self, dto: NewAuthorDto) -> Result<Author, ()> {
+    pub fn create_if_missing(&mut self, dto: NewAuthorDto) -> Result<Author, CalibreError> {
         match self.find_by_name(&dto.full_name)? {
             Some(author) => Ok(author),
             _ => self.create(dto),
         }
     }
 
-    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Author>, ()> {
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Author>, CalibreError> {
         use crate::schema::authors::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         authors
             .filter(id.eq(search_id))
             .select(Author::as_select())
             .get_result::<Author>(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn find_by_name(&mut self, search_name: &str) -> Result<Option<Author>, ()> {
+    pub fn find_by_name(&mut self, search_name: &str) -> Result<Option<Author>, CalibreError> {
         use crate::schema::authors::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         authors
             .filter(name.eq(search_name))
             .select(Author::as_select())
             .get_result::<Author>(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn update(&mut self, author_id: i32, dto: UpdateAuthorDto) -> Result<Author, ()> {
+    pub fn update(&mut self, author_id: i32, dto: UpdateAuthorDto) -> Result<Author, CalibreError> {
         use crate::schema::authors::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
         let author = UpdateAuthorData::try_from(dto)?;
 
         diesel::update(authors)
@@ -82,7 +84,7 @@ impl AuthorsHandler {
             .set(author)
             .returning(Author::as_returning())
             .get_result(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     pub fn name_author_dir(&mut self, author: &Author) -> String {
@@ -93,7 +95,7 @@ impl AuthorsHandler {
         &mut self,
         author_id: i32,
         translation: &str,
-    ) -> Result<(), ()> {
+    ) -> Result<(), CalibreError> {
         let translated_author = self.find_by_name(translation)?;
         match translated_author {
             Some(author) if author.id == author_id => Ok(()),
@@ -102,14 +104,13 @@ impl AuthorsHandler {
         }
     }
 
-    fn update_author_name(&mut self, author_id: i32, new_name: &str) -> Result<(), ()> {
+    fn update_author_name(&mut self, author_id: i32, new_name: &str) -> Result<(), CalibreError> {
         use crate::schema::authors::dsl::{authors, id, name};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::update(authors.filter(id.eq(author_id)))
             .set(name.eq(new_name))
-            .execute(&mut *connection)
-            .or(Err(()))?;
+            .execute(&mut *connection)?;
 
         Ok(())
     }
@@ -118,27 +119,25 @@ impl AuthorsHandler {
         &mut self,
         from_author_id: i32,
         to_author_id: i32,
-    ) -> Result<(), ()> {
+    ) -> Result<(), CalibreError> {
         use crate::schema::authors::dsl::authors;
         use crate::schema::books_authors_link::dsl::{author, book, books_authors_link, id};
 
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         connection
             .transaction::<_, diesel::result::Error, _>(|conn| {
                 let book_ids = books_authors_link
                     .filter(author.eq(from_author_id))
                     .select(book)
-                    .load::<i32>(conn)
-                    .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                    .load::<i32>(conn)?;
 
                 for book_id in book_ids {
                     let already_linked = books_authors_link
                         .filter(book.eq(book_id).and(author.eq(to_author_id)))
                         .select(id)
                         .first::<i32>(conn)
-                        .optional()
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?
+                        .optional()?
                         .is_some();
 
                     if already_linked {
@@ -146,36 +145,66 @@ impl AuthorsHandler {
                             books_authors_link
                                 .filter(author.eq(from_author_id).and(book.eq(book_id))),
                         )
-                        .execute(conn)
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                        .execute(conn)?;
                     } else {
                         diesel::update(
                             books_authors_link
                                 .filter(author.eq(from_author_id).and(book.eq(book_id))),
                         )
                         .set(author.eq(to_author_id))
-                        .execute(conn)
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                        .execute(conn)?;
                     }
                 }
 
-                diesel::delete(authors.find(from_author_id))
-                    .execute(conn)
-                    .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                diesel::delete(authors.find(from_author_id)).execute(conn)?;
 
                 Ok(())
             })
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn get_all_authors(&mut self) -> Result<Vec<Author>, ()> {
+    pub fn get_all_authors(&mut self) -> Result<Vec<Author>, CalibreError> {
         use crate::schema::authors::dsl::{authors, id};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         authors
             .select(Author::as_select())
             .order(id.asc())
             .get_results::<Author>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
+    }
+
+    /// The IDs of the authors that no book links to.
+    pub fn find_unused(&mut self) -> Result<Vec<i32>, CalibreError> {
+        use crate::schema::authors::dsl::{authors, id};
+        use crate::schema::books_authors_link::dsl::{author, books_authors_link};
+        let mut connection = lock_connection(&self.client);
+
+        authors
+            .filter(id.ne_all(books_authors_link.select(author)))
+            .select(id)
+            .load::<i32>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    /// Deletes those of `author_ids` that no book links to any more.
+    /// Returns how many were deleted.
+    pub fn delete_if_unused(&mut self, author_ids: &[i32]) -> Result<usize, CalibreError> {
+        use crate::schema::authors::dsl::{authors, id};
+        use crate::schema::books_authors_link::dsl::{author, books_authors_link};
+        let mut connection = lock_connection(&self.client);
+
+        let still_linked = books_authors_link
+            .filter(author.eq_any(author_ids))
+            .select(author)
+            .load::<i32>(&mut *connection)?;
+
+        diesel::delete(
+            authors
+                .filter(id.eq_any(author_ids))
+                .filter(id.ne_all(still_linked)),
+        )
+        .execute(&mut *connection)
+        .map_err(CalibreError::from)
     }
 }
@@ -3,7 +3,9 @@ use std::sync::Mutex;
 
 use diesel::prelude::*;
 
+use crate::api::lock_connection;
 use crate::entities::book_file::{BookFile, NewBookFile, UpdateBookFile};
+use crate::CalibreError;
 
 pub struct BookFilesHandler {
     client: Arc<Mutex<SqliteConnection>>,
@@ -14,47 +16,61 @@ impl BookFilesHandler {
         Self { client }
     }
 
-    pub fn create(&self, new_file: NewBookFile) -> Result<BookFile, ()> {
+    pub fn create(&self, new_file: NewBookFile) -> Result<BookFile, CalibreError> {
         use crate::schema::data::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(data)
             .values(new_file)
             .returning(BookFile::as_returning())
             .get_result(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn update(&mut self, file_id: i32, file: &UpdateBookFile) -> Result<BookFile, ()> {
+    pub fn update(
+        &mut self,
+        file_id: i32,
+        file: &UpdateBookFile,
+    ) -> Result<BookFile, CalibreError> {
         use crate::schema::data::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::update(data)
             .filter(id.eq(file_id))
             .set(file)
             .returning(BookFile::as_returning())
             .get_result(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<BookFile>, ()> {
+    pub fn delete(&mut self, file_id: i32) -> Result<(), CalibreError> {
         use crate::schema::data::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
+
+        diesel::delete(data.filter(id.eq(file_id)))
+            .execute(&mut *connection)
+            .map(|_| ())
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<BookFile>, CalibreError> {
+        use crate::schema::data::dsl::*;
+        let mut connection = lock_connection(&self.client);
 
         data.filter(id.eq(search_id))
             .select(BookFile::as_select())
             .get_result::<BookFile>(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn list_all_by_book_id(&mut self, book_id: i32) -> Result<Vec<BookFile>, ()> {
+    pub fn list_all_by_book_id(&mut self, book_id: i32) -> Result<Vec<BookFile>, CalibreError> {
         use crate::schema::data::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         data.filter(book.eq(book_id))
             .select(BookFile::as_select())
             .get_results::<BookFile>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 }
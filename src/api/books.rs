@@ -3,17 +3,120 @@ use std::sync::Mutex;
 
 use diesel::prelude::*;
 use diesel::sql_query;
-use diesel::sql_types::Integer;
+use diesel::sql_types::{BigInt, Integer, Text};
 use diesel::QueryableByName;
 
+use crate::api::custom_columns::CustomColumnsHandler;
+use crate::api::lock_connection;
+use crate::dtos::custom_column::NewCustomColumnDto;
 use crate::entities::book::{NewBook, UpdateBookData, UpsertBookIdentifier};
+use crate::entities::custom_column::{CustomColumnDatatype, CustomColumnValue};
 use crate::models::Identifier;
 use crate::Book;
+use crate::CalibreError;
+
+/// The custom column Calibre users conventionally track read books in.
+const READ_STATE_LABEL: &str = "read";
 
 #[derive(QueryableByName)]
-struct CustomValue {
-    #[diesel(sql_type = Integer)]
-    value: i32,
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+/// Whether `table` exists. Tables that only some Calibre versions create,
+/// and custom column tables, are checked before use.
+fn table_exists(connection: &mut SqliteConnection, table: &str) -> Result<bool, CalibreError> {
+    let found =
+        sql_query("SELECT COUNT(*) AS count FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind::<Text, _>(table)
+            .get_result::<Count>(connection)?;
+
+    Ok(found.count > 0)
+}
+
+/// Columns that must refer to an existing row, as `(table, column,
+/// referenced table)`. Calibre's `fkc_*` triggers guard most of them, but
+/// libraries written by older versions or other tools may not have them.
+const REFERENCES: [(&str, &str, &str); 20] = [
+    ("books_authors_link", "book", "books"),
+    ("books_authors_link", "author", "authors"),
+    ("books_languages_link", "book", "books"),
+    ("books_languages_link", "lang_code", "languages"),
+    ("books_publishers_link", "book", "books"),
+    ("books_publishers_link", "publisher", "publishers"),
+    ("books_ratings_link", "book", "books"),
+    ("books_ratings_link", "rating", "ratings"),
+    ("books_series_link", "book", "books"),
+    ("books_series_link", "series", "series"),
+    ("books_tags_link", "book", "books"),
+    ("books_tags_link", "tag", "tags"),
+    ("books_plugin_data", "book", "books"),
+    ("annotations", "book", "books"),
+    ("comments", "book", "books"),
+    ("conversion_options", "book", "books"),
+    ("data", "book", "books"),
+    ("identifiers", "book", "books"),
+    ("last_read_positions", "book", "books"),
+    ("metadata_dirtied", "book", "books"),
+];
+
+/// The tables with a `book` column that not every library has: custom
+/// column tables, and those only some Calibre versions create.
+fn optional_book_tables(connection: &mut SqliteConnection) -> Result<Vec<String>, CalibreError> {
+    use crate::schema::custom_columns::dsl::*;
+    let columns = custom_columns
+        .select((id, normalized))
+        .load::<(i32, bool)>(connection)?;
+
+    let mut tables = vec!["annotations_dirtied".to_string()];
+    tables.extend(columns.into_iter().map(|(column_id, is_normalized)| {
+        if is_normalized {
+            format!("books_custom_column_{column_id}_link")
+        } else {
+            format!("custom_column_{column_id}")
+        }
+    }));
+
+    let mut existing = Vec::new();
+    for table in tables {
+        if table_exists(connection, &table)? {
+            existing.push(table);
+        }
+    }
+    Ok(existing)
+}
+
+/// Every `(table, column, referenced table)` in this library that must
+/// refer to an existing row.
+fn references(
+    connection: &mut SqliteConnection,
+) -> Result<Vec<(String, String, String)>, CalibreError> {
+    let mut references = REFERENCES
+        .iter()
+        .map(|(table, column, referenced)| {
+            (
+                table.to_string(),
+                column.to_string(),
+                referenced.to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for table in optional_book_tables(connection)? {
+        // A normalized custom column links books to its own values table
+        if let Some(column_id) = table
+            .strip_prefix("books_custom_column_")
+            .and_then(|rest| rest.strip_suffix("_link"))
+        {
+            let values_table = format!("custom_column_{column_id}");
+            if table_exists(connection, &values_table)? {
+                references.push((table.clone(), "value".to_string(), values_table));
+            }
+        }
+        references.push((table, "book".to_string(), "books".to_string()));
+    }
+    Ok(references)
 }
 
 pub struct BooksHandler {
@@ -25,114 +128,188 @@ impl BooksHandler {
         Self { client }
     }
 
-    pub fn create(&self, new_book: NewBook) -> Result<Book, ()> {
+    pub fn create(&self, new_book: NewBook) -> Result<Book, CalibreError> {
         use crate::schema::books::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         let b = diesel::insert_into(books)
             .values(new_book)
             .returning(Book::as_returning())
-            .get_result(&mut *connection)
-            .expect("Error saving new book");
+            .get_result(&mut *connection)?;
 
         // SQLite doesn't add the UUID until after our `insert_into` call,
         // so we need to fetch it from the DB to provide it to the caller.
         let mut book_generated = b.clone();
-        let book_uuid = uuid_for_book(&mut *connection, b.id);
+        let book_uuid = uuid_for_book(&mut connection, b.id)?;
         book_generated.uuid = book_uuid;
 
         Ok(book_generated)
     }
 
-    pub fn list(&self) -> Result<Vec<Book>, ()> {
+    pub fn list(&self) -> Result<Vec<Book>, CalibreError> {
         use crate::schema::books::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         books
             .select(Book::as_select())
             .load::<Book>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn update(&mut self, book_id: i32, book: UpdateBookData) -> Result<Book, ()> {
+    pub fn update(&mut self, book_id: i32, book: UpdateBookData) -> Result<Book, CalibreError> {
         use crate::schema::books::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::update(books)
             .filter(id.eq(book_id))
             .set(book)
             .returning(Book::as_returning())
             .get_result(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Book>, ()> {
+    /// Replaces the UUID that `books_insert_trg` generated, e.g. to keep a
+    /// book's identity when it is restored.
+    pub fn set_uuid(&mut self, book_id: i32, book_uuid: &str) -> Result<(), CalibreError> {
         use crate::schema::books::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
+
+        diesel::update(books)
+            .filter(id.eq(book_id))
+            .set(uuid.eq(book_uuid))
+            .execute(&mut *connection)
+            .map(|_| ())
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Book>, CalibreError> {
+        use crate::schema::books::dsl::*;
+        let mut connection = lock_connection(&self.client);
 
         books
             .filter(id.eq(search_id))
             .select(Book::as_select())
             .get_result::<Book>(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn find_author_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, ()> {
+    /// Deletes a book and every row that refers to it, including its
+    /// custom column values. Linked authors, tags and the like are kept.
+    pub fn delete(&mut self, book_id: i32) -> Result<(), CalibreError> {
+        let mut connection = lock_connection(&self.client);
+
+        connection.transaction(|conn| {
+            // Calibre's `books_delete_trg` covers most of these, but libraries
+            // created by other tools may not have it.
+            macro_rules! delete_book_rows {
+                ($($table:ident),+ $(,)?) => {
+                    $(
+                        diesel::delete(
+                            crate::schema::$table::table
+                                .filter(crate::schema::$table::book.eq(book_id)),
+                        )
+                        .execute(conn)?;
+                    )+
+                };
+            }
+            delete_book_rows!(
+                books_authors_link,
+                books_languages_link,
+                books_publishers_link,
+                books_ratings_link,
+                books_series_link,
+                books_tags_link,
+                books_plugin_data,
+                annotations,
+                comments,
+                conversion_options,
+                data,
+                identifiers,
+                last_read_positions,
+                metadata_dirtied,
+            );
+
+            for table in optional_book_tables(conn)? {
+                sql_query(format!("DELETE FROM {table} WHERE book = ?"))
+                    .bind::<Integer, _>(book_id)
+                    .execute(conn)?;
+            }
+
+            {
+                use crate::schema::books::dsl::*;
+                let deleted = diesel::delete(books.filter(id.eq(book_id))).execute(conn)?;
+                if deleted == 0 {
+                    return Err(CalibreError::NotFound(format!("book {book_id}")));
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn find_author_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, CalibreError> {
         use crate::schema::books_authors_link::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
-        let author_ids = books_authors_link
+        books_authors_link
             .filter(book.eq(book_id))
             .select(author)
-            .load::<i32>(&mut *connection);
-
-        match author_ids {
-            Ok(ids) => Ok(ids),
-            Err(_) => Err(()),
-        }
+            .load::<i32>(&mut *connection)
+            .map_err(CalibreError::from)
     }
 
-    pub fn link_author_to_book(&mut self, book_id: i32, author_id: i32) -> Result<(), ()> {
+    pub fn link_author_to_book(
+        &mut self,
+        book_id: i32,
+        author_id: i32,
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_authors_link::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(books_authors_link)
             .values((book.eq(book_id), author.eq(author_id)))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn unlink_author_from_book(&mut self, book_id: i32, author_id: i32) -> Result<(), ()> {
+    pub fn unlink_author_from_book(
+        &mut self,
+        book_id: i32,
+        author_id: i32,
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_authors_link::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::delete(books_authors_link.filter(book.eq(book_id).and(author.eq(author_id))))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     // === === ===
     // Identifiers
     // === === ===
 
-    pub fn list_identifiers_for_book(&mut self, book_id: i32) -> Result<Vec<Identifier>, ()> {
+    pub fn list_identifiers_for_book(
+        &mut self,
+        book_id: i32,
+    ) -> Result<Vec<Identifier>, CalibreError> {
         use crate::schema::identifiers::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         identifiers
             .filter(book.eq(book_id))
             .select(Identifier::as_returning())
             .load(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     pub fn upsert_book_identifier(
         &mut self,
         update: UpsertBookIdentifier,
-    ) -> Result<Identifier, ()> {
+    ) -> Result<Identifier, CalibreError> {
         match update.id {
             Some(update_id) => self.update_book_identifier(update, update_id),
             None => self.create_book_identifier(update),
@@ -143,21 +320,24 @@ impl BooksHandler {
         &mut self,
         update: UpsertBookIdentifier,
         identifier_id: i32,
-    ) -> Result<Identifier, ()> {
+    ) -> Result<Identifier, CalibreError> {
         use crate::schema::identifiers::dsl::{id, identifiers, type_, val};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::update(identifiers)
             .filter(id.eq(identifier_id))
             .set((type_.eq(update.label), val.eq(update.value)))
             .returning(Identifier::as_returning())
             .get_result::<Identifier>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    fn create_book_identifier(&mut self, update: UpsertBookIdentifier) -> Result<Identifier, ()> {
+    fn create_book_identifier(
+        &mut self,
+        update: UpsertBookIdentifier,
+    ) -> Result<Identifier, CalibreError> {
         use crate::schema::identifiers::dsl::{book, identifiers, type_, val};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
         let lowercased_label = update.label.to_lowercase();
 
         diesel::insert_into(identifiers)
@@ -168,180 +348,238 @@ impl BooksHandler {
             ))
             .returning(Identifier::as_returning())
             .get_result::<Identifier>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn delete_book_identifier(&mut self, book_id: i32, identifier_id: i32) -> Result<(), ()> {
+    pub fn delete_book_identifier(
+        &mut self,
+        book_id: i32,
+        identifier_id: i32,
+    ) -> Result<(), CalibreError> {
         use crate::schema::identifiers::dsl::{book, id, identifiers};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::delete(identifiers.filter(book.eq(book_id).and(id.eq(identifier_id))))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     // === === ===
     // Descriptions
     // === === ===
 
-    pub fn get_description(&mut self, book_id: i32) -> Result<Option<String>, ()> {
+    pub fn get_description(&mut self, book_id: i32) -> Result<Option<String>, CalibreError> {
         use crate::schema::comments::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         comments
             .filter(book.eq(book_id))
             .select(text)
             .first(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    // === === ===
-    // Read state
-    // === === ===
-    fn get_or_create_read_state_custom_column(
-        &self,
-        connection: &mut SqliteConnection,
-    ) -> Result<i32, ()> {
-        use crate::schema::custom_columns::dsl::*;
-
-        let custom_column_id = custom_columns
-            .select(id)
-            .filter(label.eq("read"))
-            .filter(datatype.eq("bool"))
-            .first::<i32>(connection)
-            .optional()
-            .or(Err(()))?;
-
-        if custom_column_id.is_none() {
-            let column_id = diesel::insert_into(custom_columns)
-                .values((
-                    label.eq("read"),
-                    name.eq("Read"),
-                    datatype.eq("bool"),
-                    mark_for_delete.eq(false),
-                    editable.eq(true),
-                    is_multiple.eq(false),
-                    normalized.eq(false),
-                    display.eq("{}"),
-                ))
-                .returning(id)
-                .get_result::<i32>(connection)
-                .or(Err(()))?;
-
-            sql_query(format!(
-                "CREATE TABLE custom_column_{column_id} (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, value INTEGER NOT NULL);"
-            ))
-            .execute(connection)
-            .or(Err(()))?;
-
-            Ok(column_id)
-        } else {
-            Ok(custom_column_id.unwrap())
+    /// Sets a book's description after sanitising it, or removes it when
+    /// `description` is `None` or has no content. Returns what was stored.
+    pub fn set_description(
+        &mut self,
+        book_id: i32,
+        description: Option<&str>,
+    ) -> Result<Option<String>, CalibreError> {
+        use crate::schema::comments::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        let sanitized = description.and_then(crate::util::sanitize_description);
+        match &sanitized {
+            Some(html) => {
+                diesel::insert_into(comments)
+                    .values((book.eq(book_id), text.eq(html)))
+                    .on_conflict(book)
+                    .do_update()
+                    .set(text.eq(html))
+                    .execute(&mut *connection)?;
+            }
+            None => {
+                diesel::delete(comments.filter(book.eq(book_id))).execute(&mut *connection)?;
+            }
         }
-    }
 
-    pub fn get_book_read_state(&self, book_id: i32) -> Result<Option<bool>, ()> {
-        let mut connection = self.client.lock().unwrap();
-
-        let read_state_column_id = self.get_or_create_read_state_custom_column(&mut *connection)?;
-        let value = sql_query(format!(
-            "SELECT value FROM custom_column_{read_state_column_id} WHERE book = ?"
-        ))
-        .bind::<Integer, _>(book_id)
-        .get_result::<CustomValue>(&mut *connection)
-        .map(|v| v.value)
-        .or(Err(()));
-
-        match value {
-            Ok(v) => match v {
-                1 => Ok(Some(true)),
-                _ => Ok(Some(false)),
-            },
-            Err(_) => Ok(Some(false)),
-        }
+        Ok(sanitized)
     }
 
-    pub fn set_book_read_state(&mut self, book_id: i32, read_state: bool) -> Result<(), ()> {
-        let mut connection = self.client.lock().unwrap();
+    // === === ===
+    // Read state
+    // === === ===
 
-        let read_state_column_id = self.get_or_create_read_state_custom_column(&mut *connection)?;
-        let value = if read_state { 1 } else { 0 };
+    /// Whether a book is marked as read in the `#read` yes/no column.
+    /// Books are unread if the library has no such column.
+    pub fn get_book_read_state(&self, book_id: i32) -> Result<Option<bool>, CalibreError> {
+        let mut columns = CustomColumnsHandler::new(Arc::clone(&self.client));
+        let value = match columns.find_by_label(READ_STATE_LABEL)? {
+            Some(column) if column.kind()? == CustomColumnDatatype::Bool => {
+                columns.get_value(column.id, book_id)?
+            }
+            _ => None,
+        };
+
+        Ok(Some(value == Some(CustomColumnValue::Bool(true))))
+    }
 
-        sql_query(format!(
-            "INSERT OR REPLACE INTO custom_column_{read_state_column_id} (book, value) VALUES (?, ?)"
-        ))
-        .bind::<Integer, _>(book_id)
-        .bind::<Integer, _>(value)
-        .execute(&mut *connection)
-        .map(|_| ())
-        .or(Err(()))
+    /// Marks a book as read or unread, creating the `#read` column first
+    /// if the library does not have one.
+    pub fn set_book_read_state(
+        &mut self,
+        book_id: i32,
+        read_state: bool,
+    ) -> Result<(), CalibreError> {
+        let mut columns = CustomColumnsHandler::new(Arc::clone(&self.client));
+        let column = match columns.find_by_label(READ_STATE_LABEL)? {
+            Some(column) => column,
+            None => columns.create(NewCustomColumnDto {
+                label: READ_STATE_LABEL.to_string(),
+                name: "Read".to_string(),
+                datatype: CustomColumnDatatype::Bool,
+                is_multiple: false,
+                display: serde_json::json!({}),
+            })?,
+        };
+
+        columns.set_value(
+            column.id,
+            book_id,
+            Some(CustomColumnValue::Bool(read_state)),
+        )
     }
 
     // === === ===
     // Publishers
     // === === ===
 
-    pub fn link_publisher_to_book(&mut self, book_id: i32, publisher_id: i32) -> Result<(), ()> {
+    pub fn link_publisher_to_book(
+        &mut self,
+        book_id: i32,
+        publisher_id: i32,
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_publishers_link::dsl::{book, books_publishers_link, publisher};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(books_publishers_link)
             .values((book.eq(book_id), publisher.eq(publisher_id)))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     pub fn unlink_publisher_from_book(
         &mut self,
         book_id: i32,
         publisher_id: i32,
-    ) -> Result<(), ()> {
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_publishers_link::dsl::{book, books_publishers_link, publisher};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::delete(
             books_publishers_link.filter(book.eq(book_id).and(publisher.eq(publisher_id))),
         )
         .execute(&mut *connection)
         .map(|_| ())
-        .or(Err(()))
+        .map_err(CalibreError::from)
+    }
+
+    // === === ===
+    // Series
+    // === === ===
+
+    /// Puts a book in a series at `index`, taking it out of any series it
+    /// was in before. A book can only be in one series.
+    pub fn link_series_to_book(
+        &mut self,
+        book_id: i32,
+        series_id: i32,
+        index: f32,
+    ) -> Result<(), CalibreError> {
+        use crate::schema::books::dsl::{books, id, series_index};
+        use crate::schema::books_series_link::dsl::{book, books_series_link, series};
+        let mut connection = lock_connection(&self.client);
+
+        connection.transaction(|conn| {
+            diesel::delete(books_series_link.filter(book.eq(book_id))).execute(conn)?;
+            diesel::insert_into(books_series_link)
+                .values((book.eq(book_id), series.eq(series_id)))
+                .execute(conn)?;
+            diesel::update(books.filter(id.eq(book_id)))
+                .set(series_index.eq(index))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Takes a book out of its series and resets its index to 1, as
+    /// Calibre does.
+    pub fn unlink_series_from_book(&mut self, book_id: i32) -> Result<(), CalibreError> {
+        use crate::schema::books::dsl::{books, id, series_index};
+        use crate::schema::books_series_link::dsl::{book, books_series_link};
+        let mut connection = lock_connection(&self.client);
+
+        connection.transaction(|conn| {
+            diesel::delete(books_series_link.filter(book.eq(book_id))).execute(conn)?;
+            diesel::update(books.filter(id.eq(book_id)))
+                .set(series_index.eq(1.0))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    pub fn find_series_id_by_book_id(&mut self, book_id: i32) -> Result<Option<i32>, CalibreError> {
+        use crate::schema::books_series_link::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        books_series_link
+            .filter(book.eq(book_id))
+            .select(series)
+            .first::<i32>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
     }
 
     // === === ===
     // Tags
     // === === ===
 
-    pub fn link_tag_to_book(&mut self, book_id: i32, tag_id: i32) -> Result<(), ()> {
+    pub fn link_tag_to_book(&mut self, book_id: i32, tag_id: i32) -> Result<(), CalibreError> {
         use crate::schema::books_tags_link::dsl::{book, books_tags_link, tag};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(books_tags_link)
             .values((book.eq(book_id), tag.eq(tag_id)))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn unlink_tag_from_book(&mut self, book_id: i32, tag_id: i32) -> Result<(), ()> {
+    pub fn unlink_tag_from_book(&mut self, book_id: i32, tag_id: i32) -> Result<(), CalibreError> {
         use crate::schema::books_tags_link::dsl::{book, books_tags_link, tag};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::delete(books_tags_link.filter(book.eq(book_id).and(tag.eq(tag_id))))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     // === === ===
     // Ratings
     // === === ===
 
-    pub fn link_rating_to_book(&mut self, book_id: i32, rating_id: i32) -> Result<(), ()> {
+    pub fn link_rating_to_book(
+        &mut self,
+        book_id: i32,
+        rating_id: i32,
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_ratings_link::dsl::{book, books_ratings_link, rating};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         let _ =
             diesel::delete(books_ratings_link.filter(book.eq(book_id))).execute(&mut *connection);
@@ -349,130 +587,287 @@ impl BooksHandler {
             .values((book.eq(book_id), rating.eq(rating_id)))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn unlink_rating_from_book(&mut self, book_id: i32, rating_id: i32) -> Result<(), ()> {
+    pub fn unlink_rating_from_book(
+        &mut self,
+        book_id: i32,
+        rating_id: i32,
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_ratings_link::dsl::{book, books_ratings_link, rating};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::delete(books_ratings_link.filter(book.eq(book_id).and(rating.eq(rating_id))))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     // === === ===
     // Languages
     // === === ===
 
-    pub fn link_language_to_book(&mut self, book_id: i32, language_id: i32) -> Result<(), ()> {
+    pub fn link_language_to_book(
+        &mut self,
+        book_id: i32,
+        language_id: i32,
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_languages_link::dsl::{book, books_languages_link, lang_code};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(books_languages_link)
             .values((book.eq(book_id), lang_code.eq(language_id)))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn unlink_language_from_book(&mut self, book_id: i32, language_id: i32) -> Result<(), ()> {
+    pub fn unlink_language_from_book(
+        &mut self,
+        book_id: i32,
+        language_id: i32,
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_languages_link::dsl::{book, books_languages_link, lang_code};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::delete(books_languages_link.filter(book.eq(book_id).and(lang_code.eq(language_id))))
             .execute(&mut *connection)
             .map(|_| ())
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn find_publisher_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, ()> {
+    pub fn find_publisher_ids_by_book_id(
+        &mut self,
+        book_id: i32,
+    ) -> Result<Vec<i32>, CalibreError> {
         use crate::schema::books_publishers_link::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         books_publishers_link
             .filter(book.eq(book_id))
             .select(publisher)
             .load::<i32>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn find_language_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, ()> {
+    pub fn find_language_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, CalibreError> {
         use crate::schema::books_languages_link::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         books_languages_link
             .filter(book.eq(book_id))
             .select(lang_code)
             .load::<i32>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn find_identifier_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, ()> {
+    pub fn find_identifier_ids_by_book_id(
+        &mut self,
+        book_id: i32,
+    ) -> Result<Vec<i32>, CalibreError> {
         use crate::schema::identifiers::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         identifiers
             .filter(book.eq(book_id))
             .select(id)
             .load::<i32>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn find_tag_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, ()> {
+    pub fn find_tag_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, CalibreError> {
         use crate::schema::books_tags_link::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         books_tags_link
             .filter(book.eq(book_id))
             .select(tag)
             .load::<i32>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     pub fn find_book_id_by_identifier(
         &self,
         i_type: &str,
         i_value: &str,
-    ) -> Result<Option<i32>, String> {
+    ) -> Result<Option<i32>, CalibreError> {
         use crate::schema::identifiers::dsl::{book, identifiers, type_, val};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         let results: Vec<i32> = identifiers
             .filter(type_.eq(i_type))
             .filter(val.eq(i_value))
             .select(book)
-            .load(&mut *connection)
-            .map_err(|e| format!("Database query failed: {e}"))?;
+            .load(&mut *connection)?;
 
         match results.len() {
             0 => Ok(None),
             1 => Ok(Some(results[0])),
-            _ => Err(format!(
+            _ => Err(CalibreError::Conflict(format!(
                 "Multiple books found for identifier {i_type}:{i_value}"
-            )),
+            ))),
         }
     }
 
-    pub fn find_rating_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, ()> {
+    pub fn find_rating_ids_by_book_id(&mut self, book_id: i32) -> Result<Vec<i32>, CalibreError> {
         use crate::schema::books_ratings_link::dsl::*;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         books_ratings_link
             .filter(book.eq(book_id))
             .select(rating)
             .load::<i32>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_book_ids_by_author_id(&mut self, author_id: i32) -> Result<Vec<i32>, CalibreError> {
+        use crate::schema::books_authors_link::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        books_authors_link
+            .filter(author.eq(author_id))
+            .select(book)
+            .load::<i32>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_book_ids_by_publisher_id(
+        &mut self,
+        publisher_id: i32,
+    ) -> Result<Vec<i32>, CalibreError> {
+        use crate::schema::books_publishers_link::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        books_publishers_link
+            .filter(publisher.eq(publisher_id))
+            .select(book)
+            .load::<i32>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_book_ids_by_series_id(&mut self, series_id: i32) -> Result<Vec<i32>, CalibreError> {
+        use crate::schema::books_series_link::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        books_series_link
+            .filter(series.eq(series_id))
+            .select(book)
+            .load::<i32>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_book_ids_by_tag_id(&mut self, tag_id: i32) -> Result<Vec<i32>, CalibreError> {
+        use crate::schema::books_tags_link::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        books_tags_link
+            .filter(tag.eq(tag_id))
+            .select(book)
+            .load::<i32>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    /// Counts the rows that refer to a book, or a linked author, tag and
+    /// the like, that does not exist. Returns `(table, column, count)` for
+    /// each column with any.
+    pub fn find_orphaned_rows(&mut self) -> Result<Vec<(String, String, i64)>, CalibreError> {
+        let mut connection = lock_connection(&self.client);
+
+        let mut orphaned = Vec::new();
+        for (table, column, referenced) in references(&mut connection)? {
+            let found = sql_query(format!(
+                "SELECT COUNT(*) AS count FROM {table} \
+                 WHERE {column} NOT IN (SELECT id FROM {referenced})"
+            ))
+            .get_result::<Count>(&mut *connection)?;
+            if found.count > 0 {
+                orphaned.push((table, column, found.count));
+            }
+        }
+        Ok(orphaned)
+    }
+
+    /// Deletes every row that [`BooksHandler::find_orphaned_rows`] counts.
+    /// Returns how many were deleted.
+    pub fn delete_orphaned_rows(&mut self) -> Result<usize, CalibreError> {
+        let mut connection = lock_connection(&self.client);
+
+        connection.transaction(|conn| {
+            let mut deleted = 0;
+            for (table, column, referenced) in references(conn)? {
+                deleted += sql_query(format!(
+                    "DELETE FROM {table} WHERE {column} NOT IN (SELECT id FROM {referenced})"
+                ))
+                .execute(conn)?;
+            }
+            Ok(deleted)
+        })
+    }
+
+    /// Records that the books' `metadata.opf` files are out of date, as
+    /// Calibre does in `metadata_dirtied`.
+    ///
+    /// The row's `id` doubles as a sequence number: marking a book that is
+    /// already dirtied gives it a new one, so a flush that started before
+    /// the change does not clear it.
+    pub fn mark_metadata_dirtied(&mut self, book_ids: &[i32]) -> Result<(), CalibreError> {
+        use crate::schema::metadata_dirtied::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        // Replacing an existing mark gives it a new, higher sequence, as
+        // SQLite picks the new rowid before removing the old row
+        for book_id in book_ids {
+            diesel::replace_into(metadata_dirtied)
+                .values(book.eq(book_id))
+                .execute(&mut *connection)?;
+        }
+
+        Ok(())
+    }
+
+    /// The dirtied books as `(sequence, book ID)` pairs, oldest first.
+    pub fn list_metadata_dirtied(&mut self) -> Result<Vec<(i32, i32)>, CalibreError> {
+        use crate::schema::metadata_dirtied::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        metadata_dirtied
+            .select((id, book))
+            .order(id.asc())
+            .load::<(i32, i32)>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    /// Clears a book's dirtied mark, unless it was marked again after
+    /// `sequence` was read.
+    pub fn clear_metadata_dirtied(
+        &mut self,
+        book_id: i32,
+        sequence: i32,
+    ) -> Result<(), CalibreError> {
+        use crate::schema::metadata_dirtied::dsl::*;
+        let mut connection = lock_connection(&self.client);
+
+        diesel::delete(
+            metadata_dirtied
+                .filter(book.eq(book_id))
+                .filter(id.eq(sequence)),
+        )
+        .execute(&mut *connection)?;
+
+        Ok(())
     }
 }
 
-fn uuid_for_book(conn: &mut SqliteConnection, book_id: i32) -> Option<String> {
+fn uuid_for_book(
+    conn: &mut SqliteConnection,
+    book_id: i32,
+) -> Result<Option<String>, CalibreError> {
     use crate::schema::books::dsl::*;
 
     books
         .select(uuid)
         .filter(id.eq(book_id))
         .first::<Option<String>>(conn)
-        .expect("Error getting book UUID")
+        .map_err(CalibreError::from)
 }
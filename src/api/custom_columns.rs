@@ -0,0 +1,565 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Double, Integer, Nullable, Text};
+use diesel::QueryableByName;
+
+use crate::api::lock_connection;
+use crate::dtos::custom_column::NewCustomColumnDto;
+use crate::entities::custom_column::{
+    CustomColumn, CustomColumnDatatype, CustomColumnValue, NewCustomColumn,
+};
+use crate::CalibreError;
+
+/// How Calibre writes datetimes into custom columns.
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f%:z";
+
+#[derive(QueryableByName)]
+struct TextValue {
+    #[diesel(sql_type = Text)]
+    value: String,
+}
+
+#[derive(QueryableByName)]
+struct IntValue {
+    #[diesel(sql_type = BigInt)]
+    value: i64,
+}
+
+#[derive(QueryableByName)]
+struct FloatValue {
+    #[diesel(sql_type = Double)]
+    value: f64,
+}
+
+#[derive(QueryableByName)]
+struct SeriesValue {
+    #[diesel(sql_type = Text)]
+    value: String,
+    #[diesel(sql_type = Nullable<Double>)]
+    extra: Option<f64>,
+}
+
+#[derive(QueryableByName)]
+struct RowId {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+}
+
+/// A value as it is stored in a custom column table.
+enum SqlValue {
+    Text(String),
+    Int(i64),
+    Float(f64),
+}
+
+pub struct CustomColumnsHandler {
+    client: Arc<Mutex<SqliteConnection>>,
+}
+
+impl CustomColumnsHandler {
+    pub(crate) fn new(client: Arc<Mutex<SqliteConnection>>) -> Self {
+        Self { client }
+    }
+
+    /// Lists the library's custom columns, leaving out any Calibre has
+    /// marked for deletion.
+    pub fn list(&mut self) -> Result<Vec<CustomColumn>, CalibreError> {
+        use crate::schema::custom_columns::dsl::{custom_columns, id, mark_for_delete};
+        let mut connection = lock_connection(&self.client);
+
+        custom_columns
+            .filter(mark_for_delete.eq(false))
+            .select(CustomColumn::as_select())
+            .order(id.asc())
+            .get_results::<CustomColumn>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<CustomColumn>, CalibreError> {
+        use crate::schema::custom_columns::dsl::{custom_columns, id};
+        let mut connection = lock_connection(&self.client);
+
+        custom_columns
+            .filter(id.eq(search_id))
+            .select(CustomColumn::as_select())
+            .get_result::<CustomColumn>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_label(
+        &mut self,
+        search_label: &str,
+    ) -> Result<Option<CustomColumn>, CalibreError> {
+        use crate::schema::custom_columns::dsl::{custom_columns, label, mark_for_delete};
+        let mut connection = lock_connection(&self.client);
+
+        custom_columns
+            .filter(label.eq(search_label))
+            .filter(mark_for_delete.eq(false))
+            .select(CustomColumn::as_select())
+            .get_result::<CustomColumn>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
+    }
+
+    /// Creates a custom column and its tables, laid out the way Calibre
+    /// creates them.
+    pub fn create(&mut self, dto: NewCustomColumnDto) -> Result<CustomColumn, CalibreError> {
+        use crate::schema::custom_columns::dsl::custom_columns;
+        let kind = dto.datatype;
+        let new_column = NewCustomColumn::try_from(dto)?;
+        let mut connection = lock_connection(&self.client);
+
+        connection.transaction(|conn| {
+            let column = diesel::insert_into(custom_columns)
+                .values(new_column)
+                .returning(CustomColumn::as_returning())
+                .get_result::<CustomColumn>(conn)?;
+            conn.batch_execute(&create_tables_sql(&column, kind))?;
+            Ok(column)
+        })
+    }
+
+    /// Deletes a custom column along with its tables and every value in it.
+    pub fn delete(&mut self, column_id: i32) -> Result<(), CalibreError> {
+        use crate::schema::custom_columns::dsl::{custom_columns, id};
+        let column = self
+            .find_by_id(column_id)?
+            .ok_or_else(|| CalibreError::NotFound(format!("custom column {column_id}")))?;
+        let mut connection = lock_connection(&self.client);
+
+        connection.transaction(|conn| {
+            conn.batch_execute(&format!(
+                "DROP TABLE IF EXISTS {link}; DROP TABLE IF EXISTS {table};",
+                link = column.link_table_name(),
+                table = column.table_name(),
+            ))?;
+            diesel::delete(custom_columns.filter(id.eq(column_id))).execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Reads a book's value in a custom column. Composite columns have no
+    /// stored values, since Calibre computes them from their template.
+    pub fn get_value(
+        &mut self,
+        column_id: i32,
+        book_id: i32,
+    ) -> Result<Option<CustomColumnValue>, CalibreError> {
+        let column = self
+            .find_by_id(column_id)?
+            .ok_or_else(|| CalibreError::NotFound(format!("custom column {column_id}")))?;
+        let kind = column.kind()?;
+        if kind == CustomColumnDatatype::Composite {
+            return Err(CalibreError::InvalidInput(format!(
+                "#{} is a composite column, which has no stored values",
+                column.label
+            )));
+        }
+        let mut connection = lock_connection(&self.client);
+        let connection = &mut *connection;
+
+        let table = column.table_name();
+        if !column.normalized {
+            // Columns made by older libcalibre versions lack UNIQUE(book),
+            // so there may be several rows; the newest one wins.
+            let query =
+                format!("SELECT value FROM {table} WHERE book = ? ORDER BY id DESC LIMIT 1");
+            let value = match kind {
+                CustomColumnDatatype::Int => sql_query(query)
+                    .bind::<Integer, _>(book_id)
+                    .get_result::<IntValue>(connection)
+                    .optional()?
+                    .map(|row| CustomColumnValue::Int(row.value)),
+                CustomColumnDatatype::Bool => sql_query(query)
+                    .bind::<Integer, _>(book_id)
+                    .get_result::<IntValue>(connection)
+                    .optional()?
+                    .map(|row| CustomColumnValue::Bool(row.value != 0)),
+                CustomColumnDatatype::Rating => sql_query(query)
+                    .bind::<Integer, _>(book_id)
+                    .get_result::<IntValue>(connection)
+                    .optional()?
+                    .map(|row| CustomColumnValue::Rating(row.value as i32)),
+                CustomColumnDatatype::Float => sql_query(query)
+                    .bind::<Integer, _>(book_id)
+                    .get_result::<FloatValue>(connection)
+                    .optional()?
+                    .map(|row| CustomColumnValue::Float(row.value)),
+                CustomColumnDatatype::Datetime => sql_query(query)
+                    .bind::<Integer, _>(book_id)
+                    .get_result::<TextValue>(connection)
+                    .optional()?
+                    .map(|row| parse_datetime(&row.value))
+                    .transpose()?
+                    .map(CustomColumnValue::Datetime),
+                _ => sql_query(query)
+                    .bind::<Integer, _>(book_id)
+                    .get_result::<TextValue>(connection)
+                    .optional()?
+                    .map(|row| CustomColumnValue::Text(row.value)),
+            };
+            return Ok(value);
+        }
+
+        let link = column.link_table_name();
+        let value = match kind {
+            CustomColumnDatatype::Series => sql_query(format!(
+                "SELECT v.value AS value, l.extra AS extra FROM {link} l \
+                 JOIN {table} v ON v.id = l.value WHERE l.book = ?"
+            ))
+            .bind::<Integer, _>(book_id)
+            .get_result::<SeriesValue>(connection)
+            .optional()?
+            .map(|row| CustomColumnValue::Series {
+                name: row.value,
+                index: row.extra.unwrap_or(1.0),
+            }),
+            CustomColumnDatatype::Rating => sql_query(format!(
+                "SELECT v.value AS value FROM {link} l \
+                 JOIN {table} v ON v.id = l.value WHERE l.book = ?"
+            ))
+            .bind::<Integer, _>(book_id)
+            .get_result::<IntValue>(connection)
+            .optional()?
+            .map(|row| CustomColumnValue::Rating(row.value as i32)),
+            _ => {
+                let values = sql_query(format!(
+                    "SELECT v.value AS value FROM {link} l \
+                     JOIN {table} v ON v.id = l.value WHERE l.book = ? ORDER BY l.id"
+                ))
+                .bind::<Integer, _>(book_id)
+                .load::<TextValue>(connection)?
+                .into_iter()
+                .map(|row| row.value)
+                .collect::<Vec<String>>();
+
+                if column.is_multiple {
+                    Some(values)
+                        .filter(|values| !values.is_empty())
+                        .map(CustomColumnValue::TextList)
+                } else {
+                    values.into_iter().next().map(CustomColumnValue::Text)
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    /// Sets a book's value in a custom column, or removes it when `value`
+    /// is `None`. The value must match the column's datatype.
+    pub fn set_value(
+        &mut self,
+        column_id: i32,
+        book_id: i32,
+        value: Option<CustomColumnValue>,
+    ) -> Result<(), CalibreError> {
+        let column = self
+            .find_by_id(column_id)?
+            .ok_or_else(|| CalibreError::NotFound(format!("custom column {column_id}")))?;
+        let values = stored_values(&column, value)?;
+        let mut connection = lock_connection(&self.client);
+
+        let table = column.table_name();
+        if !column.normalized {
+            return connection.transaction(|conn| {
+                sql_query(format!("DELETE FROM {table} WHERE book = ?"))
+                    .bind::<Integer, _>(book_id)
+                    .execute(conn)?;
+                if let Some((value, _)) = values.first() {
+                    value.insert_for_book(conn, &table, book_id)?;
+                }
+                Ok(())
+            });
+        }
+
+        let link = column.link_table_name();
+        connection.transaction(|conn| {
+            sql_query(format!("DELETE FROM {link} WHERE book = ?"))
+                .bind::<Integer, _>(book_id)
+                .execute(conn)?;
+
+            for (value, extra) in &values {
+                let value_id = value.find_or_insert(conn, &table)?;
+                match extra {
+                    Some(extra) => sql_query(format!(
+                        "INSERT OR IGNORE INTO {link} (book, value, extra) VALUES (?, ?, ?)"
+                    ))
+                    .bind::<Integer, _>(book_id)
+                    .bind::<Integer, _>(value_id)
+                    .bind::<Double, _>(extra)
+                    .execute(conn)?,
+                    None => sql_query(format!(
+                        "INSERT OR IGNORE INTO {link} (book, value) VALUES (?, ?)"
+                    ))
+                    .bind::<Integer, _>(book_id)
+                    .bind::<Integer, _>(value_id)
+                    .execute(conn)?,
+                };
+            }
+
+            // Like Calibre, keep only values some book still has
+            sql_query(format!(
+                "DELETE FROM {table} WHERE id NOT IN (SELECT value FROM {link})"
+            ))
+            .execute(conn)?;
+            Ok(())
+        })
+    }
+}
+
+impl SqlValue {
+    fn insert_for_book(
+        &self,
+        conn: &mut SqliteConnection,
+        table: &str,
+        book_id: i32,
+    ) -> Result<(), CalibreError> {
+        let query = sql_query(format!("INSERT INTO {table} (book, value) VALUES (?, ?)"))
+            .bind::<Integer, _>(book_id);
+        match self {
+            SqlValue::Text(value) => query.bind::<Text, _>(value).execute(conn)?,
+            SqlValue::Int(value) => query.bind::<BigInt, _>(value).execute(conn)?,
+            SqlValue::Float(value) => query.bind::<Double, _>(value).execute(conn)?,
+        };
+        Ok(())
+    }
+
+    /// The ID of this value in a normalized column's value table, adding it
+    /// if it is new. Text is matched case-insensitively, as in Calibre.
+    fn find_or_insert(
+        &self,
+        conn: &mut SqliteConnection,
+        table: &str,
+    ) -> Result<i32, CalibreError> {
+        let insert = sql_query(format!("INSERT OR IGNORE INTO {table} (value) VALUES (?)"));
+        let select = sql_query(format!("SELECT id FROM {table} WHERE value = ?"));
+        let row = match self {
+            SqlValue::Text(value) => {
+                insert.bind::<Text, _>(value).execute(conn)?;
+                select.bind::<Text, _>(value).get_result::<RowId>(conn)?
+            }
+            SqlValue::Int(value) => {
+                insert.bind::<BigInt, _>(value).execute(conn)?;
+                select.bind::<BigInt, _>(value).get_result::<RowId>(conn)?
+            }
+            SqlValue::Float(value) => {
+                insert.bind::<Double, _>(value).execute(conn)?;
+                select.bind::<Double, _>(value).get_result::<RowId>(conn)?
+            }
+        };
+        Ok(row.id)
+    }
+}
+
+/// Checks `value` against the column and turns it into the values to
+/// store, each with the series index to store alongside it.
+fn stored_values(
+    column: &CustomColumn,
+    value: Option<CustomColumnValue>,
+) -> Result<Vec<(SqlValue, Option<f64>)>, CalibreError> {
+    let kind = column.kind()?;
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+
+    let values = match (kind, value) {
+        (CustomColumnDatatype::Composite, _) => {
+            return Err(CalibreError::InvalidInput(format!(
+                "#{} is a composite column, which cannot be set",
+                column.label
+            )))
+        }
+        (CustomColumnDatatype::Text, CustomColumnValue::TextList(values)) if column.is_multiple => {
+            let mut unique: Vec<String> = Vec::new();
+            for value in values.iter().map(|value| value.trim()) {
+                if !value.is_empty() && !unique.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+                    unique.push(value.to_string());
+                }
+            }
+            unique
+                .into_iter()
+                .map(|value| (SqlValue::Text(value), None))
+                .collect()
+        }
+        (
+            CustomColumnDatatype::Text
+            | CustomColumnDatatype::Comments
+            | CustomColumnDatatype::Enumeration,
+            CustomColumnValue::Text(text),
+        ) if !column.is_multiple => {
+            let text = if kind == CustomColumnDatatype::Comments {
+                text
+            } else {
+                text.trim().to_string()
+            };
+            if text.trim().is_empty() {
+                return Ok(Vec::new());
+            }
+            if kind == CustomColumnDatatype::Enumeration {
+                let settings = column.display_settings();
+                let allowed = settings["enum_values"]
+                    .as_array()
+                    .is_some_and(|values| values.iter().any(|v| v.as_str() == Some(&text)));
+                if !allowed {
+                    return Err(CalibreError::InvalidInput(format!(
+                        "{text} is not one of the values allowed in #{}",
+                        column.label
+                    )));
+                }
+            }
+            vec![(SqlValue::Text(text), None)]
+        }
+        (CustomColumnDatatype::Series, CustomColumnValue::Series { name, index }) => {
+            let name = name.trim().to_string();
+            if name.is_empty() {
+                return Ok(Vec::new());
+            }
+            vec![(SqlValue::Text(name), Some(index))]
+        }
+        (CustomColumnDatatype::Datetime, CustomColumnValue::Datetime(date)) => {
+            vec![(
+                SqlValue::Text(date.format(DATETIME_FORMAT).to_string()),
+                None,
+            )]
+        }
+        (CustomColumnDatatype::Int, CustomColumnValue::Int(value)) => {
+            vec![(SqlValue::Int(value), None)]
+        }
+        (CustomColumnDatatype::Float, CustomColumnValue::Float(value)) => {
+            vec![(SqlValue::Float(value), None)]
+        }
+        (CustomColumnDatatype::Bool, CustomColumnValue::Bool(value)) => {
+            vec![(SqlValue::Int(i64::from(value)), None)]
+        }
+        (CustomColumnDatatype::Rating, CustomColumnValue::Rating(rating)) => {
+            if !(0..=10).contains(&rating) {
+                return Err(CalibreError::InvalidInput(format!(
+                    "rating {rating} is not between 0 and 10"
+                )));
+            }
+            vec![(SqlValue::Int(i64::from(rating)), None)]
+        }
+        (_, value) => {
+            return Err(CalibreError::InvalidInput(format!(
+                "{value:?} does not fit the {}{kind} column #{}",
+                if column.is_multiple { "multiple " } else { "" },
+                column.label
+            )))
+        }
+    };
+    Ok(values)
+}
+
+fn parse_datetime(value: &str) -> Result<DateTime<Utc>, CalibreError> {
+    DateTime::parse_from_str(value, DATETIME_FORMAT)
+        .or_else(|_| DateTime::parse_from_rfc3339(value))
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| {
+            // Some old Calibre versions wrote naive timestamps in UTC
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").map(|date| date.and_utc())
+        })
+        .map_err(|_| CalibreError::InvalidInput(format!("unreadable custom column date: {value}")))
+}
+
+/// The tables and triggers Calibre creates for a new custom column.
+fn create_tables_sql(column: &CustomColumn, kind: CustomColumnDatatype) -> String {
+    let table = column.table_name();
+    let link = column.link_table_name();
+    let value_type = kind.sql_type();
+    let collate = if value_type == "TEXT" {
+        "COLLATE NOCASE"
+    } else {
+        ""
+    };
+
+    if column.normalized {
+        let series_index = if kind == CustomColumnDatatype::Series {
+            "extra REAL,"
+        } else {
+            ""
+        };
+        format!(
+            r#"
+CREATE TABLE {table}(
+    id    INTEGER PRIMARY KEY AUTOINCREMENT,
+    value {value_type} NOT NULL {collate},
+    link TEXT NOT NULL DEFAULT "",
+    UNIQUE(value));
+CREATE INDEX {table}_idx ON {table} (value {collate});
+CREATE TABLE {link}(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    book INTEGER NOT NULL,
+    value INTEGER NOT NULL,
+    {series_index}
+    UNIQUE(book, value));
+CREATE INDEX {link}_aidx ON {link} (value);
+CREATE INDEX {link}_bidx ON {link} (book);
+CREATE TRIGGER fkc_update_{link}_a
+    BEFORE UPDATE OF book ON {link}
+    BEGIN
+        SELECT CASE
+            WHEN (SELECT id from books WHERE id=NEW.book) IS NULL
+            THEN RAISE(ABORT, 'Foreign key violation: book not in books')
+        END;
+    END;
+CREATE TRIGGER fkc_update_{link}_b
+    BEFORE UPDATE OF author ON {link}
+    BEGIN
+        SELECT CASE
+            WHEN (SELECT id from {table} WHERE id=NEW.value) IS NULL
+            THEN RAISE(ABORT, 'Foreign key violation: {table} not in {table}')
+        END;
+    END;
+CREATE TRIGGER fkc_insert_{link}
+    BEFORE INSERT ON {link}
+    BEGIN
+        SELECT CASE
+            WHEN (SELECT id from books WHERE id=NEW.book) IS NULL
+            THEN RAISE(ABORT, 'Foreign key violation: book not in books')
+            WHEN (SELECT id from {table} WHERE id=NEW.value) IS NULL
+            THEN RAISE(ABORT, 'Foreign key violation: {table} not in {table}')
+        END;
+    END;
+CREATE TRIGGER fkc_delete_{link}
+    AFTER DELETE ON {table}
+    BEGIN
+        DELETE FROM {link} WHERE value=OLD.id;
+    END;
+"#
+        )
+    } else {
+        format!(
+            r#"
+CREATE TABLE {table}(
+    id    INTEGER PRIMARY KEY AUTOINCREMENT,
+    book  INTEGER,
+    value {value_type} NOT NULL {collate},
+    UNIQUE(book));
+CREATE INDEX {table}_idx ON {table} (book);
+CREATE TRIGGER fkc_insert_{table}
+    BEFORE INSERT ON {table}
+    BEGIN
+        SELECT CASE
+            WHEN (SELECT id from books WHERE id=NEW.book) IS NULL
+            THEN RAISE(ABORT, 'Foreign key violation: book not in books')
+        END;
+    END;
+CREATE TRIGGER fkc_update_{table}
+    BEFORE UPDATE OF book ON {table}
+    BEGIN
+        SELECT CASE
+            WHEN (SELECT id from books WHERE id=NEW.book) IS NULL
+            THEN RAISE(ABORT, 'Foreign key violation: book not in books')
+        END;
+    END;
+"#
+        )
+    }
+}
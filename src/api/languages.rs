@@ -3,8 +3,10 @@ use std::sync::Mutex;
 
 use diesel::prelude::*;
 
+use crate::api::lock_connection;
 use crate::dtos::language::NewLanguageDto;
 use crate::entities::language::NewLanguage;
+use crate::CalibreError;
 use crate::Language;
 
 pub struct LanguagesHandler {
@@ -16,34 +18,49 @@ impl LanguagesHandler {
         Self { client }
     }
 
-    pub fn create(&mut self, dto: NewLanguageDto) -> Result<Language, ()> {
+    pub fn create(&mut self, dto: NewLanguageDto) -> Result<Language, CalibreError> {
         use crate::schema::languages::dsl::languages;
         let new_language = NewLanguage::try_from(dto)?;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(languages)
             .values(new_language)
             .returning(Language::as_returning())
             .get_result::<Language>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn create_if_missing(&mut self, dto: NewLanguageDto) -> Result<Language, ()> {
+    pub fn create_if_missing(&mut self, dto: NewLanguageDto) -> Result<Language, CalibreError> {
         match self.find_by_lang_code(&dto.lang_code)? {
             Some(language) => Ok(language),
             _ => self.create(dto),
         }
     }
 
-    pub fn find_by_lang_code(&mut self, search_lang_code: &str) -> Result<Option<Language>, ()> {
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Language>, CalibreError> {
+        use crate::schema::languages::dsl::{id, languages};
+        let mut connection = lock_connection(&self.client);
+
+        languages
+            .filter(id.eq(search_id))
+            .select(Language::as_select())
+            .get_result::<Language>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_lang_code(
+        &mut self,
+        search_lang_code: &str,
+    ) -> Result<Option<Language>, CalibreError> {
         use crate::schema::languages::dsl::{lang_code, languages};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         languages
             .filter(lang_code.eq(search_lang_code))
             .select(Language::as_select())
             .get_result::<Language>(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 }
@@ -3,8 +3,10 @@ use std::sync::Mutex;
 
 use diesel::prelude::*;
 
+use crate::api::lock_connection;
 use crate::dtos::publisher::NewPublisherDto;
 use crate::entities::publisher::NewPublisher;
+use crate::CalibreError;
 use crate::Publisher;
 
 pub struct PublishersHandler {
@@ -16,42 +18,54 @@ impl PublishersHandler {
         Self { client }
     }
 
-    pub fn create(&mut self, dto: NewPublisherDto) -> Result<Publisher, ()> {
+    pub fn create(&mut self, dto: NewPublisherDto) -> Result<Publisher, CalibreError> {
         use crate::schema::publishers::dsl::publishers;
         let new_publisher = NewPublisher::try_from(dto)?;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(publishers)
             .values(new_publisher)
             .returning(Publisher::as_returning())
             .get_result::<Publisher>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn create_if_missing(&mut self, dto: NewPublisherDto) -> Result<Publisher, ()> {
+    pub fn create_if_missing(&mut self, dto: NewPublisherDto) -> Result<Publisher, CalibreError> {
         match self.find_by_name(&dto.name)? {
             Some(publisher) => Ok(publisher),
             _ => self.create(dto),
         }
     }
 
-    pub fn find_by_name(&mut self, search_name: &str) -> Result<Option<Publisher>, ()> {
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Publisher>, CalibreError> {
+        use crate::schema::publishers::dsl::{id, publishers};
+        let mut connection = lock_connection(&self.client);
+
+        publishers
+            .filter(id.eq(search_id))
+            .select(Publisher::as_select())
+            .get_result::<Publisher>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_name(&mut self, search_name: &str) -> Result<Option<Publisher>, CalibreError> {
         use crate::schema::publishers::dsl::{name, publishers};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         publishers
             .filter(name.eq(search_name))
             .select(Publisher::as_select())
             .get_result::<Publisher>(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
     pub fn replace_with_translation(
         &mut self,
         publisher_id: i32,
         translation: &str,
-    ) -> Result<(), ()> {
+    ) -> Result<(), CalibreError> {
         let translated_publisher = self.find_by_name(translation)?;
         match translated_publisher {
             Some(publisher) if publisher.id == publisher_id => Ok(()),
@@ -60,14 +74,17 @@ impl PublishersHandler {
         }
     }
 
-    fn update_publisher_name(&mut self, publisher_id: i32, new_name: &str) -> Result<(), ()> {
+    fn update_publisher_name(
+        &mut self,
+        publisher_id: i32,
+        new_name: &str,
+    ) -> Result<(), CalibreError> {
         use crate::schema::publishers::dsl::{id, name, publishers};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::update(publishers.filter(id.eq(publisher_id)))
             .set(name.eq(new_name))
-            .execute(&mut *connection)
-            .or(Err(()))?;
+            .execute(&mut *connection)?;
 
         Ok(())
     }
@@ -76,29 +93,27 @@ impl PublishersHandler {
         &mut self,
         from_publisher_id: i32,
         to_publisher_id: i32,
-    ) -> Result<(), ()> {
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_publishers_link::dsl::{
             book, books_publishers_link, id, publisher,
         };
         use crate::schema::publishers::dsl::publishers;
 
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         connection
             .transaction::<_, diesel::result::Error, _>(|conn| {
                 let book_ids = books_publishers_link
                     .filter(publisher.eq(from_publisher_id))
                     .select(book)
-                    .load::<i32>(conn)
-                    .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                    .load::<i32>(conn)?;
 
                 for book_id in book_ids {
                     let already_linked = books_publishers_link
                         .filter(book.eq(book_id).and(publisher.eq(to_publisher_id)))
                         .select(id)
                         .first::<i32>(conn)
-                        .optional()
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?
+                        .optional()?
                         .is_some();
 
                     if already_linked {
@@ -106,36 +121,66 @@ impl PublishersHandler {
                             books_publishers_link
                                 .filter(publisher.eq(from_publisher_id).and(book.eq(book_id))),
                         )
-                        .execute(conn)
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                        .execute(conn)?;
                     } else {
                         diesel::update(
                             books_publishers_link
                                 .filter(publisher.eq(from_publisher_id).and(book.eq(book_id))),
                         )
                         .set(publisher.eq(to_publisher_id))
-                        .execute(conn)
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                        .execute(conn)?;
                     }
                 }
 
-                diesel::delete(publishers.find(from_publisher_id))
-                    .execute(conn)
-                    .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                diesel::delete(publishers.find(from_publisher_id)).execute(conn)?;
 
                 Ok(())
             })
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn get_all_publishers(&mut self) -> Result<Vec<Publisher>, ()> {
+    pub fn get_all_publishers(&mut self) -> Result<Vec<Publisher>, CalibreError> {
         use crate::schema::publishers::dsl::{id, publishers};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         publishers
             .select(Publisher::as_select())
             .order(id.asc())
             .get_results::<Publisher>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
+    }
+
+    /// The IDs of the publishers that no book links to.
+    pub fn find_unused(&mut self) -> Result<Vec<i32>, CalibreError> {
+        use crate::schema::books_publishers_link::dsl::{books_publishers_link, publisher};
+        use crate::schema::publishers::dsl::{id, publishers};
+        let mut connection = lock_connection(&self.client);
+
+        publishers
+            .filter(id.ne_all(books_publishers_link.select(publisher)))
+            .select(id)
+            .load::<i32>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    /// Deletes those of `publisher_ids` that no book links to any more.
+    /// Returns how many were deleted.
+    pub fn delete_if_unused(&mut self, publisher_ids: &[i32]) -> Result<usize, CalibreError> {
+        use crate::schema::books_publishers_link::dsl::{books_publishers_link, publisher};
+        use crate::schema::publishers::dsl::{id, publishers};
+        let mut connection = lock_connection(&self.client);
+
+        let still_linked = books_publishers_link
+            .filter(publisher.eq_any(publisher_ids))
+            .select(publisher)
+            .load::<i32>(&mut *connection)?;
+
+        diesel::delete(
+            publishers
+                .filter(id.eq_any(publisher_ids))
+                .filter(id.ne_all(still_linked)),
+        )
+        .execute(&mut *connection)
+        .map_err(CalibreError::from)
     }
 }
@@ -3,8 +3,10 @@ use std::sync::Mutex;
 
 use diesel::prelude::*;
 
+use crate::api::lock_connection;
 use crate::dtos::rating::NewRatingDto;
 use crate::entities::rating::NewRating;
+use crate::CalibreError;
 use crate::Rating;
 
 pub struct RatingsHandler {
@@ -16,34 +18,46 @@ impl RatingsHandler {
         Self { client }
     }
 
-    pub fn create(&mut self, dto: NewRatingDto) -> Result<Rating, ()> {
+    pub fn create(&mut self, dto: NewRatingDto) -> Result<Rating, CalibreError> {
         use crate::schema::ratings::dsl::ratings;
         let new_rating = NewRating::try_from(dto)?;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(ratings)
             .values(new_rating)
             .returning(Rating::as_returning())
             .get_result::<Rating>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn create_if_missing(&mut self, dto: NewRatingDto) -> Result<Rating, ()> {
+    pub fn create_if_missing(&mut self, dto: NewRatingDto) -> Result<Rating, CalibreError> {
         match self.find_by_value(dto.rating)? {
             Some(rating) => Ok(rating),
             _ => self.create(dto),
         }
     }
 
-    pub fn find_by_value(&mut self, search_value: i32) -> Result<Option<Rating>, ()> {
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Rating>, CalibreError> {
+        use crate::schema::ratings::dsl::{id, ratings};
+        let mut connection = lock_connection(&self.client);
+
+        ratings
+            .filter(id.eq(search_id))
+            .select(Rating::as_select())
+            .get_result::<Rating>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_value(&mut self, search_value: i32) -> Result<Option<Rating>, CalibreError> {
         use crate::schema::ratings::dsl::{rating, ratings};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         ratings
             .filter(rating.eq(search_value))
             .select(Rating::as_select())
             .get_result::<Rating>(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 }
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use diesel::prelude::*;
+
+use crate::api::lock_connection;
+use crate::dtos::series::NewSeriesDto;
+use crate::entities::series::NewSeries;
+use crate::CalibreError;
+use crate::Series;
+
+pub struct SeriesHandler {
+    client: Arc<Mutex<SqliteConnection>>,
+}
+
+impl SeriesHandler {
+    pub(crate) fn new(client: Arc<Mutex<SqliteConnection>>) -> Self {
+        Self { client }
+    }
+
+    pub fn create(&mut self, dto: NewSeriesDto) -> Result<Series, CalibreError> {
+        use crate::schema::series::dsl::{id, series};
+        let new_series = NewSeries::try_from(dto)?;
+        let mut connection = lock_connection(&self.client);
+
+        // `series_insert_trg` sets `sort` after the insert, so the row is
+        // read back rather than taken from a RETURNING clause.
+        let series_id = diesel::insert_into(series)
+            .values(new_series)
+            .returning(id)
+            .get_result::<i32>(&mut *connection)?;
+
+        series
+            .filter(id.eq(series_id))
+            .select(Series::as_select())
+            .get_result::<Series>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    pub fn create_if_missing(&mut self, dto: NewSeriesDto) -> Result<Series, CalibreError> {
+        match self.find_by_name(&dto.name)? {
+            Some(series) => Ok(series),
+            _ => self.create(dto),
+        }
+    }
+
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Series>, CalibreError> {
+        use crate::schema::series::dsl::{id, series};
+        let mut connection = lock_connection(&self.client);
+
+        series
+            .filter(id.eq(search_id))
+            .select(Series::as_select())
+            .get_result::<Series>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_name(&mut self, search_name: &str) -> Result<Option<Series>, CalibreError> {
+        use crate::schema::series::dsl::{name, series};
+        let mut connection = lock_connection(&self.client);
+
+        series
+            .filter(name.eq(search_name))
+            .select(Series::as_select())
+            .get_result::<Series>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
+    }
+
+    /// Renames a series, or merges it into the series that already has
+    /// the new name.
+    pub fn replace_with_translation(
+        &mut self,
+        series_id: i32,
+        translation: &str,
+    ) -> Result<(), CalibreError> {
+        let translated_series = self.find_by_name(translation)?;
+        match translated_series {
+            Some(series) if series.id == series_id => Ok(()),
+            Some(series) => self.transfer_series_links_and_delete(series_id, series.id),
+            None => self.update_series_name(series_id, translation),
+        }
+    }
+
+    fn update_series_name(&mut self, series_id: i32, new_name: &str) -> Result<(), CalibreError> {
+        use crate::schema::series::dsl::{id, name, series};
+        let mut connection = lock_connection(&self.client);
+
+        // `series_update_trg` keeps `sort` in step with the name
+        diesel::update(series.filter(id.eq(series_id)))
+            .set(name.eq(new_name))
+            .execute(&mut *connection)?;
+
+        Ok(())
+    }
+
+    fn transfer_series_links_and_delete(
+        &mut self,
+        from_series_id: i32,
+        to_series_id: i32,
+    ) -> Result<(), CalibreError> {
+        use crate::schema::books_series_link::dsl::{books_series_link, series as link_series};
+        use crate::schema::series::dsl::series;
+
+        let mut connection = lock_connection(&self.client);
+
+        // A book is in at most one series, so its link can simply be moved
+        connection
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                diesel::update(books_series_link.filter(link_series.eq(from_series_id)))
+                    .set(link_series.eq(to_series_id))
+                    .execute(conn)?;
+
+                diesel::delete(series.find(from_series_id)).execute(conn)?;
+
+                Ok(())
+            })
+            .map_err(CalibreError::from)
+    }
+
+    pub fn get_all_series(&mut self) -> Result<Vec<Series>, CalibreError> {
+        use crate::schema::series::dsl::{id, series};
+        let mut connection = lock_connection(&self.client);
+
+        series
+            .select(Series::as_select())
+            .order(id.asc())
+            .get_results::<Series>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    /// Deletes those of `series_ids` that no book is in any more.
+    /// Returns how many were deleted.
+    pub fn delete_if_unused(&mut self, series_ids: &[i32]) -> Result<usize, CalibreError> {
+        use crate::schema::books_series_link::dsl::{books_series_link, series as link_series};
+        use crate::schema::series::dsl::{id, series};
+        let mut connection = lock_connection(&self.client);
+
+        let still_linked = books_series_link
+            .filter(link_series.eq_any(series_ids))
+            .select(link_series)
+            .load::<i32>(&mut *connection)?;
+
+        diesel::delete(
+            series
+                .filter(id.eq_any(series_ids))
+                .filter(id.ne_all(still_linked)),
+        )
+        .execute(&mut *connection)
+        .map_err(CalibreError::from)
+    }
+}
@@ -3,8 +3,10 @@ use std::sync::Mutex;
 
 use diesel::prelude::*;
 
+use crate::api::lock_connection;
 use crate::dtos::tag::NewTagDto;
 use crate::entities::tag::NewTag;
+use crate::CalibreError;
 use crate::Tag;
 
 pub struct TagsHandler {
@@ -16,37 +18,52 @@ impl TagsHandler {
         Self { client }
     }
 
-    pub fn create(&mut self, dto: NewTagDto) -> Result<Tag, ()> {
+    pub fn create(&mut self, dto: NewTagDto) -> Result<Tag, CalibreError> {
         use crate::schema::tags::dsl::tags;
         let new_tag = NewTag::try_from(dto)?;
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::insert_into(tags)
             .values(new_tag)
             .returning(Tag::as_returning())
             .get_result::<Tag>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn create_if_missing(&mut self, dto: NewTagDto) -> Result<Tag, ()> {
+    pub fn create_if_missing(&mut self, dto: NewTagDto) -> Result<Tag, CalibreError> {
         match self.find_by_name(&dto.name)? {
             Some(tag) => Ok(tag),
             _ => self.create(dto),
         }
     }
 
-    pub fn find_by_name(&mut self, search_name: &str) -> Result<Option<Tag>, ()> {
+    pub fn find_by_id(&mut self, search_id: i32) -> Result<Option<Tag>, CalibreError> {
+        use crate::schema::tags::dsl::{id, tags};
+        let mut connection = lock_connection(&self.client);
+
+        tags.filter(id.eq(search_id))
+            .select(Tag::as_select())
+            .get_result::<Tag>(&mut *connection)
+            .optional()
+            .map_err(CalibreError::from)
+    }
+
+    pub fn find_by_name(&mut self, search_name: &str) -> Result<Option<Tag>, CalibreError> {
         use crate::schema::tags::dsl::{name, tags};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         tags.filter(name.eq(search_name))
             .select(Tag::as_select())
             .get_result::<Tag>(&mut *connection)
             .optional()
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn replace_with_translation(&mut self, tag_id: i32, translation: &str) -> Result<(), ()> {
+    pub fn replace_with_translation(
+        &mut self,
+        tag_id: i32,
+        translation: &str,
+    ) -> Result<(), CalibreError> {
         let translated_tag = self.find_by_name(translation)?;
         match translated_tag {
             Some(tag) if tag.id == tag_id => Ok(()),
@@ -55,14 +72,13 @@ impl TagsHandler {
         }
     }
 
-    fn update_tag_name(&mut self, tag_id: i32, new_name: &str) -> Result<(), ()> {
+    fn update_tag_name(&mut self, tag_id: i32, new_name: &str) -> Result<(), CalibreError> {
         use crate::schema::tags::dsl::{id, name, tags};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         diesel::update(tags.filter(id.eq(tag_id)))
             .set(name.eq(new_name))
-            .execute(&mut *connection)
-            .or(Err(()))?;
+            .execute(&mut *connection)?;
 
         Ok(())
     }
@@ -71,61 +87,87 @@ impl TagsHandler {
         &mut self,
         from_tag_id: i32,
         to_tag_id: i32,
-    ) -> Result<(), ()> {
+    ) -> Result<(), CalibreError> {
         use crate::schema::books_tags_link::dsl::{book, books_tags_link, id, tag};
         use crate::schema::tags::dsl::tags;
 
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         connection
             .transaction::<_, diesel::result::Error, _>(|conn| {
                 let book_ids = books_tags_link
                     .filter(tag.eq(from_tag_id))
                     .select(book)
-                    .load::<i32>(conn)
-                    .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                    .load::<i32>(conn)?;
 
                 for book_id in book_ids {
                     let already_linked = books_tags_link
                         .filter(book.eq(book_id).and(tag.eq(to_tag_id)))
                         .select(id)
                         .first::<i32>(conn)
-                        .optional()
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?
+                        .optional()?
                         .is_some();
 
                     if already_linked {
                         diesel::delete(
                             books_tags_link.filter(tag.eq(from_tag_id).and(book.eq(book_id))),
                         )
-                        .execute(conn)
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                        .execute(conn)?;
                     } else {
                         diesel::update(
                             books_tags_link.filter(tag.eq(from_tag_id).and(book.eq(book_id))),
                         )
                         .set(tag.eq(to_tag_id))
-                        .execute(conn)
-                        .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                        .execute(conn)?;
                     }
                 }
 
-                diesel::delete(tags.find(from_tag_id))
-                    .execute(conn)
-                    .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+                diesel::delete(tags.find(from_tag_id)).execute(conn)?;
 
                 Ok(())
             })
-            .or(Err(()))
+            .map_err(CalibreError::from)
     }
 
-    pub fn get_all_tags(&mut self) -> Result<Vec<Tag>, ()> {
+    pub fn get_all_tags(&mut self) -> Result<Vec<Tag>, CalibreError> {
         use crate::schema::tags::dsl::{id, tags};
-        let mut connection = self.client.lock().unwrap();
+        let mut connection = lock_connection(&self.client);
 
         tags.select(Tag::as_select())
             .order(id.asc())
             .get_results::<Tag>(&mut *connection)
-            .or(Err(()))
+            .map_err(CalibreError::from)
+    }
+
+    /// The IDs of the tags that no book links to.
+    pub fn find_unused(&mut self) -> Result<Vec<i32>, CalibreError> {
+        use crate::schema::books_tags_link::dsl::{books_tags_link, tag};
+        use crate::schema::tags::dsl::{id, tags};
+        let mut connection = lock_connection(&self.client);
+
+        tags.filter(id.ne_all(books_tags_link.select(tag)))
+            .select(id)
+            .load::<i32>(&mut *connection)
+            .map_err(CalibreError::from)
+    }
+
+    /// Deletes those of `tag_ids` that no book links to any more.
+    /// Returns how many were deleted.
+    pub fn delete_if_unused(&mut self, tag_ids: &[i32]) -> Result<usize, CalibreError> {
+        use crate::schema::books_tags_link::dsl::{books_tags_link, tag};
+        use crate::schema::tags::dsl::{id, tags};
+        let mut connection = lock_connection(&self.client);
+
+        let still_linked = books_tags_link
+            .filter(tag.eq_any(tag_ids))
+            .select(tag)
+            .load::<i32>(&mut *connection)?;
+
+        diesel::delete(
+            tags.filter(id.eq_any(tag_ids))
+                .filter(id.ne_all(still_linked)),
+        )
+        .execute(&mut *connection)
+        .map_err(CalibreError::from)
     }
 }
@@ -17,18 +17,124 @@ use chrono::Utc;
 
 use crate::BookFile;
 
-use std::error::Error;
+use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
 use crate::dtos::author::NewAuthorDto;
 
+use crate::client::transaction::StagedDir;
 use crate::client::*;
 use crate::entities::book::{NewBook, UpdateBookData};
 use crate::Author;
 
+/// The outcome of a successful [`CalibreClient::add_book`].
+#[derive(Debug)]
+pub struct ImportReport {
+    /// The book as it was committed to the library.
+    pub book: BookWithAuthorsAndFiles,
+    pub author_ids: Vec<i32>,
+    pub publisher_ids: Vec<i32>,
+    pub tag_ids: Vec<i32>,
+    pub identifier_ids: Vec<i32>,
+    pub language_id: Option<i32>,
+    pub rating_id: Option<i32>,
+    pub series_id: Option<i32>,
+    /// Absolute paths of the book files copied into the library.
+    pub file_paths: Vec<PathBuf>,
+    /// Absolute path of the extracted cover, if there was one.
+    pub cover_path: Option<PathBuf>,
+    /// Problems that did not stop the import.
+    pub warnings: Vec<ImportWarning>,
+}
+
+/// Something that went wrong during an import without failing it.
+#[derive(Debug)]
+pub enum ImportWarning {
+    /// The primary file's cover could not be read. The book has no cover.
+    CoverExtractionFailed(CalibreError),
+    /// The language code was not recognised, so no language was linked.
+    LanguageNotRecognised(String),
+}
+
+impl std::fmt::Display for ImportWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ImportWarning::CoverExtractionFailed(e) => write!(f, "Cover extraction failed: {e}"),
+            ImportWarning::LanguageNotRecognised(code) => {
+                write!(f, "Language not recognised: {code}")
+            }
+        }
+    }
+}
+
+/// How [`CalibreClient::add_book_with_options`] re-creates a book that
+/// already had an identity, e.g. one restored from the trash.
+#[derive(Default)]
+pub(crate) struct AddBookOptions {
+    /// Insert the book with this ID instead of the next free one.
+    pub(crate) id: Option<i32>,
+    /// Keep this UUID instead of the one `books_insert_trg` generates.
+    pub(crate) uuid: Option<String>,
+    /// Use this image as the cover instead of extracting one.
+    pub(crate) cover_path: Option<PathBuf>,
+    /// Register the files already in this library-relative folder instead
+    /// of copying them into a new one. Used when rebuilding the database.
+    pub(crate) existing_dir: Option<PathBuf>,
+}
+
 impl CalibreClient {
-    pub fn add_book(&mut self, dto: NewLibraryEntryDto) -> Result<(), Box<dyn std::error::Error>> {
+    /// Adds a new book to the library, copying its files into a new book
+    /// folder.
+    ///
+    /// The database rows are written in a single transaction and the files
+    /// are staged in a temporary directory that is only moved into place
+    /// right before the commit. If any step fails, neither the rows nor the
+    /// folder are left behind.
+    pub fn add_book(&mut self, dto: NewLibraryEntryDto) -> Result<ImportReport, CalibreError> {
+        self.add_book_with_options(dto, AddBookOptions::default())
+    }
+
+    pub(crate) fn add_book_with_options(
+        &mut self,
+        dto: NewLibraryEntryDto,
+        options: AddBookOptions,
+    ) -> Result<ImportReport, CalibreError> {
+        let library_root = PathBuf::from(&self.validated_library_path.library_path);
+        if options.existing_dir.is_some() {
+            return self.transaction(|client| {
+                let (_, report) = client.insert_book_entry(dto, options, None, &library_root)?;
+                Ok(report)
+            });
+        }
+        let staging = StagedDir::new(&library_root)?;
+
+        let (report, published) = self.transaction(|client| {
+            let (book_dir_relative_path, report) =
+                client.insert_book_entry(dto, options, Some(staging.path()), &library_root)?;
+            let published = staging.publish(&library_root.join(book_dir_relative_path))?;
+            Ok((report, published))
+        })?;
+        published.keep();
+
+        Ok(report)
+    }
+
+    /// Writes every row for a new book and its files into `staging_dir`.
+    /// Returns the library-relative folder the staged files belong in, and
+    /// a report whose paths point at where they will be once published.
+    ///
+    /// Without a `staging_dir` nothing is written to disk: the files are
+    /// expected to already be in `options.existing_dir`.
+    fn insert_book_entry(
+        &mut self,
+        dto: NewLibraryEntryDto,
+        options: AddBookOptions,
+        staging_dir: Option<&Path>,
+        library_root: &Path,
+    ) -> Result<(PathBuf, ImportReport), CalibreError> {
+        let mut warnings = Vec::new();
+
         // 1. Create Authors & Book, then link them.
         // ======================================
         let authors = dto
@@ -42,50 +148,63 @@ impl CalibreClient {
             })
             .collect::<Vec<NewAuthorDto>>();
         let author_list = self.create_authors(authors)?;
-        let creatable_book = NewBook::try_from(dto.book.clone()).unwrap();
-        let book_id = self.client_v2.books().create(creatable_book).unwrap().id;
-        let timestamp = Utc::now();
-        let _ = self.client_v2.books().update(
+        let mut creatable_book = NewBook::try_from(dto.book.clone())?;
+        if let Some(book_id) = options.id {
+            if self.client_v2.books().find_by_id(book_id)?.is_some() {
+                return Err(CalibreError::Conflict(format!(
+                    "book {book_id} already exists"
+                )));
+            }
+            creatable_book.id = Some(book_id);
+        }
+        let book_id = self.client_v2.books().create(creatable_book)?.id;
+        if let Some(uuid) = &options.uuid {
+            self.client_v2.books().set_uuid(book_id, uuid)?;
+        }
+        self.client_v2.books().update(
             book_id,
             UpdateBookData {
                 author_sort: Some(combined_author_sort(&author_list)),
                 title: None,
-                timestamp: Some(timestamp),
-                pubdate: Some(default_pubdate()),
+                timestamp: Some(dto.book.timestamp.unwrap_or_else(Utc::now)),
+                pubdate: Some(dto.book.pubdate.unwrap_or_else(default_pubdate)),
                 series_index: None,
                 path: None,
                 flags: None,
                 has_cover: None,
                 last_modified: None,
             },
-        );
+        )?;
         for author in &author_list {
-            let _ = self
-                .client_v2
+            self.client_v2
                 .books()
-                .link_author_to_book(book_id, author.id);
+                .link_author_to_book(book_id, author.id)?;
         }
 
-        // 2. Create directory for book (removed author directory nesting)
+        // 2. Record the book's folder; its files are staged until commit
         // ======================================
-        let primary_author = &author_list[0].clone();
-        let book_dir_name = gen_book_folder_name(book_id);
-        let book_dir_relative_path = Path::new(&book_dir_name).to_path_buf();
-        library_relative_mkdir(&self.validated_library_path, book_dir_relative_path.clone())?;
-        // Update Book with relative path to book folder
-        let _ = self
-            .client_v2
+        let primary_author_name = author_list
+            .first()
+            .map_or_else(|| "Unknown".to_string(), |author| author.name.clone());
+        let book_dir_relative_path = match &options.existing_dir {
+            Some(existing_dir) => existing_dir.clone(),
+            None => PathBuf::from(self.config.path_scheme.book_folder_name(
+                book_id,
+                &dto.book.title,
+                &primary_author_name,
+            )),
+        };
+        self.client_v2
             .books()
-            .update(book_id, update_book_data_for_path(&book_dir_relative_path));
+            .update(book_id, update_book_data_for_path(&book_dir_relative_path))?;
 
         // 3. Create metadata, then link them.
         // ======================================
         let publishers = self.create_publishers(dto.publishers)?;
         for publisher in publishers.iter() {
-            let _ = self
-                .client_v2
+            self.client_v2
                 .books()
-                .link_publisher_to_book(book_id, publisher.id);
+                .link_publisher_to_book(book_id, publisher.id)?;
         }
 
         let identifiers = dto
@@ -98,7 +217,10 @@ impl CalibreClient {
                 value: i.value,
             })
             .collect::<Vec<UpsertBookIdentifier>>();
-        let identifiers = self.upsert_book_identifiers(identifiers)?;
+        let identifiers = identifiers
+            .into_iter()
+            .map(|dto| self.client_v2.books().upsert_book_identifier(dto))
+            .collect::<Result<Vec<Identifier>, CalibreError>>()?;
 
         let language = if let Some(language_input) = dto.language {
             if let Some(canonical_lang) = canonicalize_lang(&language_input.lang_code) {
@@ -106,13 +228,15 @@ impl CalibreClient {
                     lang_code: canonical_lang.to_639_3().to_string(),
                 })?;
 
-                let _ = self
-                    .client_v2
+                self.client_v2
                     .books()
-                    .link_language_to_book(book_id, language.id);
+                    .link_language_to_book(book_id, language.id)?;
 
                 Some(language)
             } else {
+                warnings.push(ImportWarning::LanguageNotRecognised(
+                    language_input.lang_code,
+                ));
                 None
             }
         } else {
@@ -121,140 +245,210 @@ impl CalibreClient {
 
         let tags = self.create_tags(dto.tags)?;
         for tag in tags.iter() {
-            let _ = self.client_v2.books().link_tag_to_book(book_id, tag.id);
+            self.client_v2.books().link_tag_to_book(book_id, tag.id)?;
         }
 
         let rating = if let Some(rating) = dto.rating {
             let rating = self.create_rating(rating)?;
-            let _ = self
-                .client_v2
+            self.client_v2
                 .books()
-                .link_rating_to_book(book_id, rating.id);
+                .link_rating_to_book(book_id, rating.id)?;
             Some(rating)
         } else {
             None
         };
 
-        let metadata = Metadata {
-            author_list: &author_list,
-            publisher: &publishers,
-            identifiers: &identifiers,
-            language: language.as_ref(),
-            tags: &tags,
-            rating: rating.as_ref(),
+        let series = if let Some(series) = dto.series {
+            let series = self.client_v2.series().create_if_missing(series)?;
+            self.client_v2.books().link_series_to_book(
+                book_id,
+                series.id,
+                dto.book.series_index,
+            )?;
+            Some(series)
+        } else {
+            None
         };
 
-        // 4. Copy Book files & cover image to library
+        let description = match dto.description.as_deref() {
+            Some(description) => self
+                .client_v2
+                .books()
+                .set_description(book_id, Some(description))?,
+            None => None,
+        };
+
+        // 4. Copy Book files & cover image to the staging folder
         // ===========================
-        if let Some(files) = dto.files {
-            // Copy files to library
-            let _ = self.add_book_files(
-                &files,
-                &dto.book.title,
-                book_id,
-                &primary_author.name,
-                book_dir_relative_path.clone(),
-            );
-
-            let primary_file = &files[0];
-            {
-                let cover_data = cover_image_data_from_path(primary_file.path.as_path())?;
-                if let Some(cover_data) = cover_data {
-                    let cover_path = Path::new(&book_dir_relative_path).join("cover.jpg");
-                    if library_relative_write_file(
-                        &self.validated_library_path,
-                        &cover_path,
-                        &cover_data,
-                    )
-                    .is_ok()
-                    {
+        let book_dir = library_root.join(&book_dir_relative_path);
+        let mut file_paths = Vec::new();
+        let mut cover_path = None;
+        match (dto.files, staging_dir) {
+            (Some(files), None) => {
+                let book_files = self.register_book_files(&files, book_id)?;
+                file_paths = book_files
+                    .iter()
+                    .map(|file| book_dir.join(file.as_filename()))
+                    .collect();
+
+                if let Some(cover_file_name) = find_cover_file_name(&book_dir) {
+                    let update = UpdateBookData {
+                        has_cover: Some(true),
+                        ..Default::default()
+                    };
+                    self.client_v2.books().update(book_id, update)?;
+                    cover_path = Some(book_dir.join(cover_file_name));
+                }
+            }
+            (Some(files), Some(staging_dir)) => {
+                let book_files = self.add_book_files(
+                    &files,
+                    &dto.book.title,
+                    book_id,
+                    &primary_author_name,
+                    staging_dir,
+                )?;
+                file_paths = book_files
+                    .iter()
+                    .map(|file| book_dir.join(file.as_filename()))
+                    .collect();
+
+                let cover_data = match (&options.cover_path, files.first()) {
+                    (Some(path), _) => fs::read(path).map(Some).map_err(CalibreError::io(path)),
+                    (None, Some(primary_file)) => cover_image_data_from_path(&primary_file.path),
+                    (None, None) => Ok(None),
+                };
+                match cover_data {
+                    Ok(Some(cover_data)) => {
+                        let staged_cover_path = staging_dir.join("cover.jpg");
+                        fs::write(&staged_cover_path, &cover_data)
+                            .map_err(CalibreError::io(&staged_cover_path))?;
                         let update = UpdateBookData {
                             has_cover: Some(true),
                             ..Default::default()
                         };
-                        let _ = self.client_v2.books().update(book_id, update);
+                        self.client_v2.books().update(book_id, update)?;
+                        cover_path = Some(book_dir.join("cover.jpg"));
                     }
+                    Ok(None) => {}
+                    Err(e) => warnings.push(ImportWarning::CoverExtractionFailed(e)),
                 }
             }
+            (None, _) => {}
         }
 
-        let book = self
-            .client_v2
-            .books()
-            .update(
-                book_id,
-                UpdateBookData {
-                    last_modified: Some(Utc::now()),
-                    ..Default::default()
-                },
-            )
-            .unwrap();
+        let book = self.client_v2.books().update(
+            book_id,
+            UpdateBookData {
+                last_modified: Some(Utc::now()),
+                ..Default::default()
+            },
+        )?;
 
         // 5. Create Calibre metadata file
         // ===============================
-        let metadata_opf = MetadataOpf::new(&book, &metadata).format();
-        match metadata_opf {
-            Ok(contents) => {
-                let metadata_opf_path = Path::new(&book_dir_relative_path).join("metadata.opf");
-                let _ = library_relative_write_file(
-                    &self.validated_library_path,
-                    &metadata_opf_path,
-                    contents.as_bytes(),
-                );
-            }
-            Err(_) => (),
+        if let Some(staging_dir) = staging_dir {
+            let metadata = Metadata {
+                author_list: &author_list,
+                publisher: &publishers,
+                identifiers: &identifiers,
+                language: language.as_ref(),
+                tags: &tags,
+                rating: rating.as_ref(),
+                series: series.as_ref(),
+                description: description.as_deref(),
+                cover: cover_path
+                    .as_deref()
+                    .and_then(Path::file_name)
+                    .and_then(|name| name.to_str()),
+            };
+            let contents = MetadataOpf::new(&book, &metadata, &self.config).format();
+            let metadata_opf_path = staging_dir.join("metadata.opf");
+            fs::write(&metadata_opf_path, contents)
+                .map_err(CalibreError::io(&metadata_opf_path))?;
+        }
+
+        let report = ImportReport {
+            book: self.find_book_with_authors(book_id)?,
+            author_ids: author_list.iter().map(|author| author.id).collect(),
+            publisher_ids: publishers.iter().map(|publisher| publisher.id).collect(),
+            tag_ids: tags.iter().map(|tag| tag.id).collect(),
+            identifier_ids: identifiers.iter().map(|identifier| identifier.id).collect(),
+            language_id: language.as_ref().map(|language| language.id),
+            rating_id: rating.as_ref().map(|rating| rating.id),
+            series_id: series.as_ref().map(|series| series.id),
+            file_paths,
+            cover_path,
+            warnings,
         };
 
-        Ok(())
+        Ok((book_dir_relative_path, report))
     }
 
-    fn create_authors(
-        &mut self,
-        authors: Vec<NewAuthorDto>,
-    ) -> Result<Vec<Author>, Box<dyn Error>> {
-        let x = authors
+    fn create_authors(&mut self, authors: Vec<NewAuthorDto>) -> Result<Vec<Author>, CalibreError> {
+        authors
             .into_iter()
-            .map(|dto| self.client_v2.authors().create_if_missing(dto).unwrap())
-            .collect::<Vec<Author>>();
-
-        Ok(x)
+            .map(|dto| self.client_v2.authors().create_if_missing(dto))
+            .collect::<Result<Vec<Author>, CalibreError>>()
     }
 
     fn add_book_files(
         &mut self,
-        files: &Vec<NewLibraryFileDto>,
-        book_title: &String,
+        files: &[NewLibraryFileDto],
+        book_title: &str,
         book_id: i32,
-        primary_author_name: &String,
-        book_dir_rel_path: PathBuf,
-    ) -> Result<Vec<BookFile>, ClientError> {
+        primary_author_name: &str,
+        dest_dir: &Path,
+    ) -> Result<Vec<BookFile>, CalibreError> {
+        let book_file_name = self
+            .config
+            .path_scheme
+            .book_file_name(book_title, primary_author_name);
         let book_files = self.client_v2.book_files();
 
         files
             .iter()
             .map(|file| {
-                let book_file_name = gen_book_file_name(book_title, primary_author_name);
                 let nbf = NewBookFile::try_from(NewFileDto {
                     path: file.path.clone(),
                     book_id,
-                    name: book_file_name,
-                })
-                .unwrap();
-                let added_book = book_files
-                    .create(nbf)
-                    .map_err(|_| ClientError::GenericError)?;
-
-                let book_rel_path = Path::new(&book_dir_rel_path).join(&added_book.as_filename());
-                let _ = library_relative_copy_file(
-                    &self.validated_library_path,
-                    file.path.as_path(),
-                    book_rel_path.as_path(),
-                )
-                .map_err(|_| ClientError::GenericError);
+                    name: book_file_name.clone(),
+                })?;
+                let added_book = book_files.create(nbf)?;
+
+                let dest_path = dest_dir.join(added_book.as_filename());
+                fs::copy(&file.path, &dest_path).map_err(CalibreError::io(&dest_path))?;
 
                 Ok(added_book)
             })
-            .collect::<Result<Vec<BookFile>, ClientError>>()
+            .collect::<Result<Vec<BookFile>, CalibreError>>()
+    }
+
+    /// Adds `data` rows for files that are already in the book's folder,
+    /// keeping their current names.
+    fn register_book_files(
+        &mut self,
+        files: &[NewLibraryFileDto],
+        book_id: i32,
+    ) -> Result<Vec<BookFile>, CalibreError> {
+        let book_files = self.client_v2.book_files();
+
+        files
+            .iter()
+            .map(|file| {
+                let name = file
+                    .path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let nbf = NewBookFile::try_from(NewFileDto {
+                    path: file.path.clone(),
+                    book_id,
+                    name,
+                })?;
+                book_files.create(nbf)
+            })
+            .collect::<Result<Vec<BookFile>, CalibreError>>()
     }
 
     // === Publishers ===
@@ -262,35 +456,29 @@ impl CalibreClient {
     fn create_publishers(
         &mut self,
         dto: Vec<NewPublisherDto>,
-    ) -> Result<Vec<Publisher>, Box<dyn Error>> {
-        let x = dto
-            .into_iter()
-            .map(|dto| self.client_v2.publishers().create_if_missing(dto).unwrap())
-            .collect::<Vec<Publisher>>();
-
-        Ok(x)
+    ) -> Result<Vec<Publisher>, CalibreError> {
+        dto.into_iter()
+            .map(|dto| self.client_v2.publishers().create_if_missing(dto))
+            .collect::<Result<Vec<Publisher>, CalibreError>>()
     }
 
     // === Languages ===
 
-    fn create_language(&mut self, dto: NewLanguageDto) -> Result<Language, Box<dyn Error>> {
-        Ok(self.client_v2.languages().create_if_missing(dto).unwrap())
+    fn create_language(&mut self, dto: NewLanguageDto) -> Result<Language, CalibreError> {
+        self.client_v2.languages().create_if_missing(dto)
     }
 
     // === Tags ===
 
-    fn create_tags(&mut self, tags: Vec<NewTagDto>) -> Result<Vec<Tag>, Box<dyn Error>> {
-        let x = tags
-            .into_iter()
-            .map(|dto| self.client_v2.tags().create_if_missing(dto).unwrap())
-            .collect::<Vec<Tag>>();
-
-        Ok(x)
+    fn create_tags(&mut self, tags: Vec<NewTagDto>) -> Result<Vec<Tag>, CalibreError> {
+        tags.into_iter()
+            .map(|dto| self.client_v2.tags().create_if_missing(dto))
+            .collect::<Result<Vec<Tag>, CalibreError>>()
     }
 
     // === Ratings ===
 
-    fn create_rating(&mut self, rating: NewRatingDto) -> Result<Rating, Box<dyn Error>> {
-        Ok(self.client_v2.ratings().create_if_missing(rating).unwrap())
+    fn create_rating(&mut self, rating: NewRatingDto) -> Result<Rating, CalibreError> {
+        self.client_v2.ratings().create_if_missing(rating)
     }
 }
@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::client::restore_library::{find_book_folders, id_from_folder_name, RestoreFailure};
+use crate::client::*;
+use crate::dtos::file::NewFileDto;
+use crate::entities::book::UpdateBookData;
+use crate::entities::book_file::{NewBookFile, UpdateBookFile};
+use crate::mime_type::MIMETYPE;
+use crate::opf::OpfMetadata;
+
+/// What [`CalibreClient::check_library`] found, before any fixes were
+/// applied.
+#[derive(Debug, Default)]
+pub struct LibraryCheck {
+    /// `data` rows whose file is not in the book's folder.
+    pub missing_files: Vec<MissingFile>,
+    /// Files in book folders that no `data` row refers to. The book's
+    /// `metadata.opf` and cover are not counted.
+    pub extra_files: Vec<ExtraFile>,
+    /// Files whose size is not the one in `data.uncompressed_size`.
+    pub size_mismatches: Vec<SizeMismatch>,
+    /// Books whose `has_cover` does not say whether they have a cover file.
+    pub cover_mismatches: Vec<CoverMismatch>,
+    /// Books whose `books.path` is empty, leaves the library root, or is
+    /// not a folder. Their files are not checked.
+    pub bad_paths: Vec<BadPath>,
+    /// Book folders that no book refers to.
+    pub extra_folders: Vec<PathBuf>,
+    /// Rows that refer to a book, or a linked author, tag and the like,
+    /// that does not exist.
+    pub orphaned_rows: Vec<OrphanedRows>,
+    pub unused_authors: Vec<i32>,
+    pub unused_tags: Vec<i32>,
+    pub unused_publishers: Vec<i32>,
+    /// Extra folders that [`LibraryFixes::extra_folders`] could not add
+    /// back to the library.
+    pub failed_folders: Vec<RestoreFailure>,
+    /// Size mismatches that [`LibraryFixes::size_mismatches`] could not
+    /// fix, as the file is too large for `data.uncompressed_size`.
+    pub unfixable_sizes: Vec<SizeMismatch>,
+}
+
+impl LibraryCheck {
+    /// Whether nothing was found.
+    pub fn is_clean(&self) -> bool {
+        self.missing_files.is_empty()
+            && self.extra_files.is_empty()
+            && self.size_mismatches.is_empty()
+            && self.cover_mismatches.is_empty()
+            && self.bad_paths.is_empty()
+            && self.extra_folders.is_empty()
+            && self.orphaned_rows.is_empty()
+            && self.unused_authors.is_empty()
+            && self.unused_tags.is_empty()
+            && self.unused_publishers.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingFile {
+    pub book_id: i32,
+    pub file_id: i32,
+    /// Upper-case format name, e.g. `EPUB`.
+    pub format: String,
+    /// Where the file should be.
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraFile {
+    pub book_id: i32,
+    pub path: PathBuf,
+    /// The upper-case format name, if it is a book format.
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeMismatch {
+    pub book_id: i32,
+    pub file_id: i32,
+    pub path: PathBuf,
+    /// The size in `data.uncompressed_size`.
+    pub recorded: i32,
+    /// The size on disk.
+    pub actual: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverMismatch {
+    pub book_id: i32,
+    /// The value of `has_cover`. The disk says the opposite.
+    pub has_cover: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BadPath {
+    pub book_id: i32,
+    /// The value of `books.path`.
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrphanedRows {
+    pub table: String,
+    /// The column that refers to a missing row.
+    pub column: String,
+    pub count: i64,
+}
+
+/// Which problems [`CalibreClient::check_library`] repairs. By default
+/// everything is only reported.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LibraryFixes {
+    /// Delete `data` rows whose file is missing.
+    pub missing_files: bool,
+    /// Add extra files to their book, if the book has no file in that
+    /// format yet. Other extra files are left alone, as are those too
+    /// large for `data.uncompressed_size`.
+    pub extra_files: bool,
+    /// Set `data.uncompressed_size` to the size on disk.
+    pub size_mismatches: bool,
+    /// Set `has_cover` to whether there is a cover file.
+    pub cover_mismatches: bool,
+    /// Point `books.path` at the folder the client's [`PathScheme`] gives
+    /// the book, if that folder exists and no other book uses it.
+    pub bad_paths: bool,
+    /// Add a book for each extra folder with a readable `metadata.opf`, as
+    /// [`CalibreClient::restore_library`] does.
+    pub extra_folders: bool,
+    /// Delete orphaned rows.
+    pub orphaned_rows: bool,
+    /// Delete unused authors, tags and publishers.
+    pub unused_items: bool,
+}
+
+impl CalibreClient {
+    /// Compares the database with the book folders on disk, like Calibre's
+    /// "Check library", and repairs the problems selected in `fixes`.
+    ///
+    /// The database fixes are made in one transaction. Extra folders are
+    /// then added one by one; those that fail are listed in
+    /// [`LibraryCheck::failed_folders`]. Files too large for
+    /// `data.uncompressed_size` keep their recorded size and are listed in
+    /// [`LibraryCheck::unfixable_sizes`].
+    pub fn check_library(&mut self, fixes: LibraryFixes) -> Result<LibraryCheck, CalibreError> {
+        let mut check = self.find_library_problems()?;
+
+        self.transaction(|client| client.fix_library_rows(&check, fixes))?;
+        if fixes.size_mismatches {
+            check.unfixable_sizes = check
+                .size_mismatches
+                .iter()
+                .filter(|mismatch| i32::try_from(mismatch.actual).is_err())
+                .cloned()
+                .collect();
+        }
+        if fixes.extra_folders {
+            check.failed_folders = self.add_extra_folders(&check.extra_folders)?;
+        }
+
+        Ok(check)
+    }
+
+    fn find_library_problems(&mut self) -> Result<LibraryCheck, CalibreError> {
+        let library_root = PathBuf::from(&self.validated_library_path.library_path);
+        let mut check = LibraryCheck::default();
+
+        let mut book_dirs = HashSet::new();
+        let mut books = self.client_v2.books().list()?;
+        books.sort_by_key(|book| book.id);
+        for book in books {
+            let book_dir = library_root.join(&book.path);
+            if !is_library_relative(&book.path) || !book_dir.is_dir() {
+                check.bad_paths.push(BadPath {
+                    book_id: book.id,
+                    path: book.path,
+                });
+                continue;
+            }
+
+            let mut known_names = HashSet::new();
+            for file in self.client_v2.book_files().list_all_by_book_id(book.id)? {
+                let file_name = file.as_filename();
+                let path = book_dir.join(&file_name);
+                known_names.insert(file_name);
+                match fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => {
+                        if metadata.len() != file.uncompressed_size as u64 {
+                            check.size_mismatches.push(SizeMismatch {
+                                book_id: book.id,
+                                file_id: file.id,
+                                path,
+                                recorded: file.uncompressed_size,
+                                actual: metadata.len(),
+                            });
+                        }
+                    }
+                    _ => check.missing_files.push(MissingFile {
+                        book_id: book.id,
+                        file_id: file.id,
+                        format: file.format,
+                        path,
+                    }),
+                }
+            }
+
+            let has_cover = book.has_cover.unwrap_or(false);
+            if has_cover != find_cover_file_name(&book_dir).is_some() {
+                check.cover_mismatches.push(CoverMismatch {
+                    book_id: book.id,
+                    has_cover,
+                });
+            }
+
+            let mut extra_files = fs::read_dir(&book_dir)
+                .map_err(CalibreError::io(&book_dir))?
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .filter(|path| {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    !known_names.contains(name.as_ref())
+                        && name != "metadata.opf"
+                        && !COVER_FILE_NAMES.contains(&name.as_ref())
+                })
+                .map(|path| ExtraFile {
+                    book_id: book.id,
+                    format: file_format(&path),
+                    path,
+                })
+                .collect::<Vec<ExtraFile>>();
+            extra_files.sort_by(|a, b| a.path.cmp(&b.path));
+            check.extra_files.extend(extra_files);
+
+            book_dirs.insert(book_dir);
+        }
+
+        let mut folders = Vec::new();
+        find_book_folders(&library_root, &mut folders)?;
+        check.extra_folders = folders
+            .into_iter()
+            .filter(|folder| !book_dirs.contains(folder))
+            .collect();
+
+        check.orphaned_rows = self
+            .client_v2
+            .books()
+            .find_orphaned_rows()?
+            .into_iter()
+            .map(|(table, column, count)| OrphanedRows {
+                table,
+                column,
+                count,
+            })
+            .collect();
+        check.unused_authors = self.client_v2.authors().find_unused()?;
+        check.unused_tags = self.client_v2.tags().find_unused()?;
+        check.unused_publishers = self.client_v2.publishers().find_unused()?;
+
+        Ok(check)
+    }
+
+    fn fix_library_rows(
+        &mut self,
+        check: &LibraryCheck,
+        fixes: LibraryFixes,
+    ) -> Result<(), CalibreError> {
+        if fixes.missing_files {
+            for missing in &check.missing_files {
+                self.client_v2.book_files().delete(missing.file_id)?;
+            }
+        }
+
+        if fixes.extra_files {
+            let mut added_formats = HashSet::new();
+            for extra in &check.extra_files {
+                let Some(format) = &extra.format else {
+                    continue;
+                };
+                let has_format = self
+                    .client_v2
+                    .book_files()
+                    .list_all_by_book_id(extra.book_id)?
+                    .iter()
+                    .any(|file| file.format == *format);
+                if has_format || !added_formats.insert((extra.book_id, format.clone())) {
+                    continue;
+                }
+                let name = extra
+                    .path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let new_file = match NewBookFile::try_from(NewFileDto {
+                    path: extra.path.clone(),
+                    book_id: extra.book_id,
+                    name,
+                }) {
+                    Ok(new_file) => new_file,
+                    // Too large to record its size, so it stays extra
+                    Err(CalibreError::InvalidInput(_)) => continue,
+                    Err(e) => return Err(e),
+                };
+                self.client_v2.book_files().create(new_file)?;
+            }
+        }
+
+        if fixes.size_mismatches {
+            for mismatch in &check.size_mismatches {
+                // Reported in `unfixable_sizes` instead
+                let Ok(size) = i32::try_from(mismatch.actual) else {
+                    continue;
+                };
+                let update = UpdateBookFile {
+                    uncompressed_size: Some(size),
+                    ..Default::default()
+                };
+                self.client_v2
+                    .book_files()
+                    .update(mismatch.file_id, &update)?;
+            }
+        }
+
+        if fixes.cover_mismatches {
+            for mismatch in &check.cover_mismatches {
+                let update = UpdateBookData {
+                    has_cover: Some(!mismatch.has_cover),
+                    ..Default::default()
+                };
+                self.client_v2.books().update(mismatch.book_id, update)?;
+            }
+        }
+
+        if fixes.bad_paths {
+            let library_root = PathBuf::from(&self.validated_library_path.library_path);
+            let mut used_paths = self
+                .client_v2
+                .books()
+                .list()?
+                .into_iter()
+                .map(|book| book.path)
+                .collect::<HashSet<String>>();
+            for bad_path in &check.bad_paths {
+                let book = self.find_book_with_authors(bad_path.book_id)?;
+                let path = self.config.path_scheme.book_folder_name(
+                    book.book.id,
+                    &book.book.title,
+                    book.authors
+                        .first()
+                        .map_or("Unknown", |author| author.name.as_str()),
+                );
+                if library_root.join(&path).is_dir() && used_paths.insert(path.clone()) {
+                    self.client_v2
+                        .books()
+                        .update(book.book.id, update_book_data_for_path(Path::new(&path)))?;
+                }
+            }
+        }
+
+        if fixes.orphaned_rows {
+            self.client_v2.books().delete_orphaned_rows()?;
+        }
+
+        if fixes.unused_items {
+            self.client_v2
+                .authors()
+                .delete_if_unused(&check.unused_authors)?;
+            self.client_v2.tags().delete_if_unused(&check.unused_tags)?;
+            self.client_v2
+                .publishers()
+                .delete_if_unused(&check.unused_publishers)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds a book for each folder that is still not a book's, keeping the
+    /// ID and UUID in its OPF if no other book has them. Returns the folders
+    /// that could not be added.
+    fn add_extra_folders(
+        &mut self,
+        folders: &[PathBuf],
+    ) -> Result<Vec<RestoreFailure>, CalibreError> {
+        let library_root = PathBuf::from(&self.validated_library_path.library_path);
+        let mut used_uuids = self
+            .client_v2
+            .books()
+            .list()?
+            .into_iter()
+            .filter_map(|book| book.uuid)
+            .collect::<HashSet<String>>();
+
+        // A bad path fix may have given a folder back to its book
+        let book_dirs = self
+            .client_v2
+            .books()
+            .list()?
+            .into_iter()
+            .map(|book| library_root.join(book.path))
+            .collect::<HashSet<PathBuf>>();
+
+        let mut failed = Vec::new();
+        for folder in folders.iter().filter(|folder| !book_dirs.contains(*folder)) {
+            let result = OpfMetadata::from_path(folder.join("metadata.opf")).and_then(|metadata| {
+                let id = match metadata.calibre_id.or_else(|| id_from_folder_name(folder)) {
+                    Some(id) if self.client_v2.books().find_by_id(id)?.is_none() => Some(id),
+                    _ => None,
+                };
+                self.restore_book_folder(&library_root, folder, metadata, id, &mut used_uuids)
+            });
+            if let Err(error) = result {
+                failed.push(RestoreFailure {
+                    path: folder.clone(),
+                    error,
+                });
+            }
+        }
+        Ok(failed)
+    }
+}
+
+/// The upper-case format of a book file, if its extension is exactly the
+/// one libcalibre would give that format, so a new `data` row names it.
+fn file_format(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?;
+    let format = MIMETYPE::from_file_extension(extension)?;
+    (format.to_file_extension() == extension).then(|| extension.to_uppercase())
+}
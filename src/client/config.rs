@@ -0,0 +1,91 @@
+/// Settings that control how a [`CalibreClient`](super::CalibreClient)
+/// writes to the library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientConfig {
+    /// The application credited as the producer of each `metadata.opf`, in
+    /// a `<dc:contributor opf:role="bkp">` element. `None` leaves the
+    /// contributor out.
+    pub opf_producer: Option<OpfProducer>,
+    /// The OPF version `metadata.opf` files are written in.
+    pub opf_version: OpfVersion,
+    /// How new book folders and files are named.
+    pub path_scheme: PathScheme,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            opf_producer: Some(OpfProducer {
+                name: "libcalibre".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                url: None,
+            }),
+            opf_version: OpfVersion::default(),
+            path_scheme: PathScheme::default(),
+        }
+    }
+}
+
+/// The application that wrote an OPF file, e.g. `calibre (7.0.0)
+/// [https://calibre-ebook.com]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpfProducer {
+    pub name: String,
+    pub version: Option<String>,
+    pub url: Option<String>,
+}
+
+impl std::fmt::Display for OpfProducer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(version) = &self.version {
+            write!(f, " ({version})")?;
+        }
+        if let Some(url) = &self.url {
+            write!(f, " [{url}]")?;
+        }
+        Ok(())
+    }
+}
+
+/// The version of the OPF format that `metadata.opf` files are written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpfVersion {
+    /// OPF 2.0, with `opf:` attributes. Readable by every Calibre version.
+    #[default]
+    V2,
+    /// OPF 3.0, with `<meta refines>` for roles, sort names and series.
+    V3,
+}
+
+/// How the folders and files of books added to the library are named.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathScheme {
+    /// Calibre's own layout: `Author/Title (id)/Title - Author.ext`, with
+    /// names transliterated to ASCII and shortened to Calibre's limits.
+    #[default]
+    Calibre,
+    /// The layout of earlier libcalibre versions: `id/Title - Author.ext`.
+    BookId,
+}
+
+impl PathScheme {
+    /// The library-relative folder of a book, with `/` separators as
+    /// Calibre stores them in `books.path`.
+    pub fn book_folder_name(&self, book_id: i32, title: &str, author_name: &str) -> String {
+        match self {
+            PathScheme::Calibre => {
+                super::utils::calibre_book_folder_name(book_id, title, author_name)
+            }
+            PathScheme::BookId => super::utils::gen_book_folder_name(book_id),
+        }
+    }
+
+    /// The name, without extension, given to each of a book's files.
+    pub fn book_file_name(&self, title: &str, author_name: &str) -> String {
+        match self {
+            PathScheme::Calibre => super::utils::calibre_book_file_name(title, author_name),
+            PathScheme::BookId => super::utils::gen_book_file_name(title, author_name),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::client::*;
+
+impl CalibreClient {
+    /// Deletes a book and every row that refers to it, and moves its folder
+    /// to the library's trash (see [`CalibreClient::list_trash`]). Authors,
+    /// tags, publishers and series that no other book uses are deleted with
+    /// it.
+    ///
+    /// The database changes are made in one transaction. A fresh
+    /// `metadata.opf` is written first, so the trashed folder can be
+    /// restored with [`CalibreClient::restore_book_from_trash`].
+    pub fn delete_book(&mut self, book_id: i32) -> Result<(), CalibreError> {
+        let library_root = PathBuf::from(&self.validated_library_path.library_path);
+
+        let (book_dir, trashed) = self.transaction(|client| {
+            let book = client
+                .client_v2
+                .books()
+                .find_by_id(book_id)?
+                .ok_or_else(|| CalibreError::NotFound(format!("book {book_id}")))?;
+
+            let book_dir = library_root.join(&book.path);
+            let trashed = if is_library_relative(&book.path) {
+                let opf = client.book_metadata_opf(book_id)?;
+                fs::create_dir_all(&book_dir).map_err(CalibreError::io(&book_dir))?;
+                let opf_path = book_dir.join("metadata.opf");
+                fs::write(&opf_path, opf).map_err(CalibreError::io(&opf_path))?;
+                Some(client.move_book_to_trash(book_id, &book_dir)?)
+            } else {
+                None
+            };
+
+            let author_ids = client
+                .client_v2
+                .books()
+                .find_author_ids_by_book_id(book_id)?;
+            let tag_ids = client.client_v2.books().find_tag_ids_by_book_id(book_id)?;
+            let publisher_ids = client
+                .client_v2
+                .books()
+                .find_publisher_ids_by_book_id(book_id)?;
+            let series_ids = client
+                .client_v2
+                .books()
+                .find_series_id_by_book_id(book_id)?
+                .into_iter()
+                .collect::<Vec<i32>>();
+
+            client.client_v2.books().delete(book_id)?;
+            client.client_v2.authors().delete_if_unused(&author_ids)?;
+            client.client_v2.tags().delete_if_unused(&tag_ids)?;
+            client
+                .client_v2
+                .publishers()
+                .delete_if_unused(&publisher_ids)?;
+            client.client_v2.series().delete_if_unused(&series_ids)?;
+
+            Ok((book_dir, trashed))
+        })?;
+
+        if let Some(trashed) = trashed {
+            trashed.keep();
+            // Calibre libraries nest book folders in author folders
+            if let Some(parent) = book_dir.parent().filter(|parent| *parent != library_root) {
+                // Fails, as intended, if other books are still in there
+                let _ = fs::remove_dir(parent);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::client::*;
+
+/// How long the background flusher, and the client that started it, wait
+/// for each other's write lock before failing.
+const FLUSHER_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl CalibreClient {
+    /// The IDs of the books whose `metadata.opf` is out of date, in the
+    /// order they were changed.
+    pub fn dirtied_book_ids(&mut self) -> Result<Vec<i32>, CalibreError> {
+        let dirtied = self.client_v2.books().list_metadata_dirtied()?;
+        Ok(dirtied.into_iter().map(|(_, book_id)| book_id).collect())
+    }
+
+    /// Rewrites the `metadata.opf` of every dirtied book and clears their
+    /// marks. Returns the IDs of the books that were flushed.
+    ///
+    /// Stops at the first book that cannot be written. It, and the books
+    /// after it, stay dirtied for the next flush.
+    pub fn flush_dirtied_metadata(&mut self) -> Result<Vec<i32>, CalibreError> {
+        let dirtied = self.client_v2.books().list_metadata_dirtied()?;
+
+        let mut flushed = Vec::new();
+        for (sequence, book_id) in dirtied {
+            self.write_metadata_opf(book_id)?;
+            self.client_v2
+                .books()
+                .clear_metadata_dirtied(book_id, sequence)?;
+            flushed.push(book_id);
+        }
+
+        Ok(flushed)
+    }
+
+    /// Starts a thread that calls [`CalibreClient::flush_dirtied_metadata`]
+    /// every `interval`, on its own connection to the library.
+    ///
+    /// Books that fail to flush are retried in the next round. The thread
+    /// flushes one last time when the returned [`MetadataFlusher`] is
+    /// stopped or dropped.
+    ///
+    /// This client also starts waiting for the flusher's write lock,
+    /// instead of failing while a flush is in progress.
+    pub fn spawn_metadata_flusher(
+        &mut self,
+        interval: Duration,
+    ) -> Result<MetadataFlusher, CalibreError> {
+        self.client_v2.set_busy_timeout(FLUSHER_BUSY_TIMEOUT)?;
+        let mut client = CalibreClient::new(self.validated_library_path.clone())?
+            .with_config(self.config.clone());
+        client.client_v2.set_busy_timeout(FLUSHER_BUSY_TIMEOUT)?;
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = client.flush_dirtied_metadata();
+                }
+                _ => return client.flush_dirtied_metadata().map(|_| ()),
+            }
+        });
+
+        Ok(MetadataFlusher {
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+
+    /// Records that the books' `metadata.opf` files no longer match the
+    /// database.
+    pub(crate) fn mark_metadata_dirtied(&mut self, book_ids: &[i32]) -> Result<(), CalibreError> {
+        self.client_v2.books().mark_metadata_dirtied(book_ids)
+    }
+
+    /// Flushes a single book now, if it is dirtied.
+    pub(crate) fn flush_dirtied_book(&mut self, book_id: i32) -> Result<(), CalibreError> {
+        let dirtied = self.client_v2.books().list_metadata_dirtied()?;
+        if let Some((sequence, _)) = dirtied.into_iter().find(|(_, id)| *id == book_id) {
+            self.write_metadata_opf(book_id)?;
+            self.client_v2
+                .books()
+                .clear_metadata_dirtied(book_id, sequence)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites `metadata.opf` in the book's folder, if it has one.
+    fn write_metadata_opf(&mut self, book_id: i32) -> Result<(), CalibreError> {
+        // The book may have been deleted since it was marked
+        let Some(book) = self.client_v2.books().find_by_id(book_id)? else {
+            return Ok(());
+        };
+        let book_dir = Path::new(&self.validated_library_path.library_path).join(&book.path);
+        if book.path.is_empty() || !book_dir.is_dir() {
+            return Ok(());
+        }
+
+        let opf = self.book_metadata_opf(book_id)?;
+        let opf_path = book_dir.join("metadata.opf");
+        fs::write(&opf_path, opf).map_err(CalibreError::io(&opf_path))
+    }
+}
+
+/// The background thread started by
+/// [`CalibreClient::spawn_metadata_flusher`].
+pub struct MetadataFlusher {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<Result<(), CalibreError>>>,
+}
+
+impl MetadataFlusher {
+    /// Stops the thread after a final flush, and returns that flush's
+    /// result.
+    pub fn stop(mut self) -> Result<(), CalibreError> {
+        match self.join() {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+
+    fn join(&mut self) -> Option<thread::Result<Result<(), CalibreError>>> {
+        // Dropping the sender wakes the thread up
+        self.stop.take();
+        self.handle.take().map(JoinHandle::join)
+    }
+}
+
+impl Drop for MetadataFlusher {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::Datelike;
+
+use crate::client::formats::find_format;
+use crate::client::*;
+use crate::metadata::{cbz, epub, pdf};
+use crate::mime_type::MIMETYPE;
+use crate::opf::OpfMetadata;
+
+impl CalibreClient {
+    /// Writes a copy of a book's file in `format`, e.g. `"epub"`, to
+    /// `destination` with the library's current metadata embedded in it:
+    /// the package document and cover of an EPUB, the `ComicInfo.xml` of a
+    /// CBZ, or the document information and XMP metadata of a PDF.
+    ///
+    /// The file in the library is left untouched. Other formats fail with
+    /// [`CalibreError::UnsupportedFormat`].
+    pub fn export_format(
+        &mut self,
+        book_id: i32,
+        format: &str,
+        destination: &Path,
+    ) -> Result<(), CalibreError> {
+        let book = self.find_book_with_authors(book_id)?;
+        let book_dir = self.book_dir(&book)?;
+        let file = find_format(&book, format).ok_or_else(|| {
+            CalibreError::NotFound(format!(
+                "{} format of book {book_id}",
+                format.to_uppercase()
+            ))
+        })?;
+        let source = book_dir.join(file.as_filename());
+        if same_file(&source, destination) {
+            return Err(CalibreError::InvalidInput(format!(
+                "cannot export {} over itself",
+                source.display()
+            )));
+        }
+
+        let mut metadata = OpfMetadata::parse(&self.book_metadata_opf(book_id)?)?;
+        // Calibre's placeholder for an unknown publication date
+        metadata.pubdate = metadata.pubdate.filter(|date| date.year() > 101);
+        let cover = match find_cover_file_name(&book_dir) {
+            Some(name) => {
+                let cover_path = book_dir.join(name);
+                Some(fs::read(&cover_path).map_err(CalibreError::io(&cover_path))?)
+            }
+            None => None,
+        };
+
+        match MIMETYPE::from_file_extension(&file.format) {
+            Some(MIMETYPE::EPUB) => {
+                epub::write_metadata(&source, destination, &metadata, cover.as_deref())
+            }
+            Some(MIMETYPE::CBZ) => cbz::write_metadata(&source, destination, &metadata),
+            Some(MIMETYPE::PDF) => pdf::write_metadata(&source, destination, &metadata),
+            _ => Err(CalibreError::UnsupportedFormat(file.format.clone())),
+        }
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
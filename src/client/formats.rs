@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+
+use crate::client::transaction::{MovedPath, StagedDir};
+use crate::client::*;
+use crate::cover_image::cover_image_data_from_path;
+use crate::dtos::file::NewFileDto;
+use crate::entities::book::UpdateBookData;
+use crate::entities::book_file::{NewBookFile, UpdateBookFile};
+use crate::BookFile;
+
+impl CalibreClient {
+    /// Copies the file at `path` into a book's folder as a new format. The
+    /// format is taken from the file's extension.
+    ///
+    /// Like Calibre, a book has at most one file per format, so this fails
+    /// with [`CalibreError::Conflict`] if the book already has one; use
+    /// [`CalibreClient::replace_format`] instead. With `refresh_cover`, the
+    /// book's cover is replaced by the file's own, if it has one.
+    pub fn add_format(
+        &mut self,
+        book_id: i32,
+        path: &Path,
+        refresh_cover: bool,
+    ) -> Result<BookFile, CalibreError> {
+        let book = self.find_book_with_authors(book_id)?;
+        let book_dir = self.book_dir(&book)?;
+        let new_file = NewBookFile::try_from(NewFileDto {
+            path: path.to_path_buf(),
+            book_id,
+            name: self.format_file_name(&book),
+        })?;
+        if find_format(&book, &new_file.format).is_some() {
+            return Err(CalibreError::Conflict(format!(
+                "book {book_id} already has a {} format",
+                new_file.format
+            )));
+        }
+        let cover = cover_from_format(path, refresh_cover)?;
+
+        let library_root = PathBuf::from(&self.validated_library_path.library_path);
+        let staging = StagedDir::new(&library_root)?;
+        let staged = stage_copy(path, staging.path())?;
+        let staged_cover = stage_cover(cover.as_deref(), staging.path())?;
+        let (book_file, moved, cover) = self.transaction(|client| {
+            let book_file = client.client_v2.book_files().create(new_file)?;
+            let dest = book_dir.join(book_file.as_filename());
+            if dest.exists() {
+                return Err(CalibreError::Conflict(format!(
+                    "{} already exists",
+                    dest.display()
+                )));
+            }
+            let moved = MovedPath::rename(&staged, &dest)?;
+            let cover = client.replace_cover(book_id, &book_dir, staged_cover.as_deref())?;
+            client.mark_book_modified(book_id, cover.is_some())?;
+            Ok((book_file, moved, cover))
+        })?;
+        moved.keep();
+        if let Some(cover) = cover {
+            cover.keep();
+        }
+
+        self.flush_dirtied_book(book_id)?;
+        Ok(book_file)
+    }
+
+    /// Replaces a book's file of the same format as the file at `path` with
+    /// a copy of it, keeping the old file name.
+    ///
+    /// Fails with [`CalibreError::NotFound`] if the book has no file in that
+    /// format. With `refresh_cover`, the book's cover is replaced by the
+    /// file's own, if it has one.
+    pub fn replace_format(
+        &mut self,
+        book_id: i32,
+        path: &Path,
+        refresh_cover: bool,
+    ) -> Result<BookFile, CalibreError> {
+        let book = self.find_book_with_authors(book_id)?;
+        let book_dir = self.book_dir(&book)?;
+        let new_file = NewBookFile::try_from(NewFileDto {
+            path: path.to_path_buf(),
+            book_id,
+            name: String::new(),
+        })?;
+        let existing = find_format(&book, &new_file.format).ok_or_else(|| {
+            CalibreError::NotFound(format!("{} format of book {book_id}", new_file.format))
+        })?;
+        let dest = book_dir.join(existing.as_filename());
+        let cover = cover_from_format(path, refresh_cover)?;
+
+        let library_root = PathBuf::from(&self.validated_library_path.library_path);
+        let staging = StagedDir::new(&library_root)?;
+        let staged = stage_copy(path, staging.path())?;
+        let staged_cover = stage_cover(cover.as_deref(), staging.path())?;
+        let (book_file, moved, replaced, cover) = self.transaction(|client| {
+            let update = UpdateBookFile {
+                uncompressed_size: Some(new_file.uncompressed_size),
+                ..Default::default()
+            };
+            let book_file = client.client_v2.book_files().update(existing.id, &update)?;
+            // The old file waits in the staging folder until the commit
+            let replaced = match dest.exists() {
+                true => Some(MovedPath::rename(&dest, &staging.path().join("replaced"))?),
+                false => None,
+            };
+            let moved = MovedPath::rename(&staged, &dest)?;
+            let cover = client.replace_cover(book_id, &book_dir, staged_cover.as_deref())?;
+            client.mark_book_modified(book_id, cover.is_some())?;
+            Ok((book_file, moved, replaced, cover))
+        })?;
+        moved.keep();
+        if let Some(replaced) = replaced {
+            replaced.keep();
+        }
+        if let Some(cover) = cover {
+            cover.keep();
+        }
+
+        self.flush_dirtied_book(book_id)?;
+        Ok(book_file)
+    }
+
+    /// Removes a book's file in `format`, e.g. `"epub"`, moving it to the
+    /// library's trash like Calibre does. It can be brought back with
+    /// [`CalibreClient::restore_format_from_trash`].
+    pub fn remove_format(&mut self, book_id: i32, format: &str) -> Result<(), CalibreError> {
+        let book = self.find_book_with_authors(book_id)?;
+        let book_dir = self.book_dir(&book)?;
+        let existing = find_format(&book, format).ok_or_else(|| {
+            CalibreError::NotFound(format!(
+                "{} format of book {book_id}",
+                format.to_uppercase()
+            ))
+        })?;
+        let file_path = book_dir.join(existing.as_filename());
+
+        let trashed = self.transaction(|client| {
+            client.client_v2.book_files().delete(existing.id)?;
+            client.mark_book_modified(book_id, false)?;
+            // A file that is already gone has nothing to keep in the trash
+            match file_path.is_file() {
+                true => client
+                    .move_format_to_trash(&book, &existing.format, &file_path)
+                    .map(Some),
+                false => Ok(None),
+            }
+        })?;
+        if let Some(trashed) = trashed {
+            trashed.keep();
+        }
+
+        Ok(())
+    }
+
+    /// The name, without extension, a new file of the book gets: the same
+    /// as its other files, or what the [`PathScheme`] says if it has none.
+    pub(crate) fn format_file_name(&self, book: &BookWithAuthorsAndFiles) -> String {
+        match book.files.first() {
+            Some(file) => file.name.clone(),
+            None => self.config.path_scheme.book_file_name(
+                &book.book.title,
+                book.authors
+                    .first()
+                    .map_or("Unknown", |author| author.name.as_str()),
+            ),
+        }
+    }
+
+    pub(crate) fn book_dir(&self, book: &BookWithAuthorsAndFiles) -> Result<PathBuf, CalibreError> {
+        if !is_library_relative(&book.book.path) {
+            return Err(CalibreError::InvalidInput(format!(
+                "book {} has no folder in the library",
+                book.book.id
+            )));
+        }
+        Ok(Path::new(&self.validated_library_path.library_path).join(&book.book.path))
+    }
+
+    /// Moves a staged cover into the book's folder, if there is one. The
+    /// old cover waits next to the staged one until the commit.
+    fn replace_cover(
+        &mut self,
+        book_id: i32,
+        book_dir: &Path,
+        staged_cover: Option<&Path>,
+    ) -> Result<Option<ReplacedCover>, CalibreError> {
+        let Some(staged_cover) = staged_cover else {
+            return Ok(None);
+        };
+        let cover_path = book_dir.join("cover.jpg");
+        let replaced = match cover_path.exists() {
+            true => Some(MovedPath::rename(
+                &cover_path,
+                &staged_cover.with_file_name("replaced-cover.jpg"),
+            )?),
+            false => None,
+        };
+        let cover = MovedPath::rename(staged_cover, &cover_path)?;
+        // The OPF's guide points at the cover
+        self.mark_metadata_dirtied(&[book_id])?;
+
+        Ok(Some(ReplacedCover { cover, replaced }))
+    }
+
+    /// Bumps the book's `last_modified` and, with `new_cover`, sets
+    /// `has_cover`.
+    fn mark_book_modified(&mut self, book_id: i32, new_cover: bool) -> Result<(), CalibreError> {
+        let update = UpdateBookData {
+            has_cover: new_cover.then_some(true),
+            last_modified: Some(Utc::now()),
+            ..Default::default()
+        };
+        self.client_v2.books().update(book_id, update)?;
+        Ok(())
+    }
+}
+
+/// A new cover moved into a book's folder, with the one it replaced.
+///
+/// Dropping the guard moves the new cover out and the old one back, so
+/// returning it from a [`CalibreClient::transaction`] closure undoes the
+/// change if the commit fails.
+struct ReplacedCover {
+    // Dropped first, so the old cover has somewhere to go back to
+    cover: MovedPath,
+    replaced: Option<MovedPath>,
+}
+
+impl ReplacedCover {
+    fn keep(self) {
+        self.cover.keep();
+        if let Some(replaced) = self.replaced {
+            replaced.keep();
+        }
+    }
+}
+
+pub(crate) fn find_format<'a>(
+    book: &'a BookWithAuthorsAndFiles,
+    format: &str,
+) -> Option<&'a BookFile> {
+    book.files
+        .iter()
+        .find(|file| file.format.eq_ignore_ascii_case(format))
+}
+
+fn cover_from_format(path: &Path, refresh_cover: bool) -> Result<Option<Vec<u8>>, CalibreError> {
+    match refresh_cover {
+        true => cover_image_data_from_path(path),
+        false => Ok(None),
+    }
+}
+
+/// Copies `path` into `staging_dir`, so the slow part of adding a file
+/// happens before the transaction starts.
+fn stage_copy(path: &Path, staging_dir: &Path) -> Result<PathBuf, CalibreError> {
+    let staged = staging_dir.join("format");
+    fs::copy(path, &staged).map_err(CalibreError::io(&staged))?;
+    Ok(staged)
+}
+
+/// Writes a new cover into `staging_dir`, if there is one.
+fn stage_cover(cover: Option<&[u8]>, staging_dir: &Path) -> Result<Option<PathBuf>, CalibreError> {
+    let Some(cover) = cover else {
+        return Ok(None);
+    };
+    let staged = staging_dir.join("cover.jpg");
+    fs::write(&staged, cover).map_err(CalibreError::io(&staged))?;
+    Ok(Some(staged))
+}
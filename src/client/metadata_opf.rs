@@ -0,0 +1,491 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+
+use chrono::Utc;
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::Writer;
+
+use crate::client::utils::combined_author_sort;
+use crate::client::{ClientConfig, OpfVersion};
+use crate::entities::language::Language;
+use crate::entities::rating::Rating;
+use crate::entities::tag::Tag;
+use crate::models::Identifier;
+use crate::Author;
+use crate::Book;
+use crate::Publisher;
+use crate::Series;
+
+/// Everything linked to a book that goes into its `metadata.opf`.
+#[derive(Default)]
+pub(crate) struct Metadata<'a> {
+    pub(crate) author_list: &'a [Author],
+    pub(crate) publisher: &'a [Publisher],
+    pub(crate) identifiers: &'a [Identifier],
+    pub(crate) language: Option<&'a Language>,
+    pub(crate) tags: &'a [Tag],
+    pub(crate) rating: Option<&'a Rating>,
+    pub(crate) series: Option<&'a Series>,
+    pub(crate) description: Option<&'a str>,
+    /// The cover image's file name in the book folder.
+    pub(crate) cover: Option<&'a str>,
+}
+
+/// Writes the OPF file Calibre keeps in every book folder, as OPF 2 or
+/// OPF 3 depending on the client's [`OpfVersion`]. Both carry the same
+/// metadata.
+///
+/// All text and attribute values are escaped by the XML writer, so any
+/// title, name or tag produces a well-formed document.
+pub(crate) struct MetadataOpf<'a> {
+    book: &'a Book,
+    metadata: &'a Metadata<'a>,
+    config: &'a ClientConfig,
+}
+
+type OpfWriter = Writer<Vec<u8>>;
+
+impl<'a> MetadataOpf<'a> {
+    pub fn new(book: &'a Book, metadata: &'a Metadata, config: &'a ClientConfig) -> Self {
+        Self {
+            book,
+            metadata,
+            config,
+        }
+    }
+
+    pub fn format(&self) -> String {
+        let mut writer = Writer::new_with_indent(Vec::new(), b' ', 4);
+        let written = match self.config.opf_version {
+            OpfVersion::V2 => self.write_opf2_package(&mut writer),
+            OpfVersion::V3 => self.write_opf3_package(&mut writer),
+        };
+        written.expect("writing to a Vec cannot fail");
+        String::from_utf8(writer.into_inner()).expect("the writer only emits UTF-8")
+    }
+
+    fn write_opf2_package(&self, writer: &mut OpfWriter) -> io::Result<()> {
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))?;
+        writer
+            .create_element("package")
+            .with_attributes([
+                ("xmlns", "http://www.idpf.org/2007/opf"),
+                ("unique-identifier", "uuid_id"),
+                ("version", "2.0"),
+            ])
+            .write_inner_content(|writer| {
+                writer
+                    .create_element("metadata")
+                    .with_attributes([
+                        ("xmlns:dc", "http://purl.org/dc/elements/1.1/"),
+                        ("xmlns:opf", "http://www.idpf.org/2007/opf"),
+                    ])
+                    .write_inner_content(|writer| self.write_opf2_metadata(writer))?;
+                self.write_guide(writer)
+            })?;
+        Ok(())
+    }
+
+    fn write_opf3_package(&self, writer: &mut OpfWriter) -> io::Result<()> {
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))?;
+        writer
+            .create_element("package")
+            .with_attributes([
+                ("xmlns", "http://www.idpf.org/2007/opf"),
+                ("version", "3.0"),
+                ("unique-identifier", "uuid_id"),
+                ("prefix", "calibre: https://calibre-ebook.com"),
+            ])
+            .write_inner_content(|writer| {
+                writer
+                    .create_element("metadata")
+                    .with_attributes([
+                        ("xmlns:dc", "http://purl.org/dc/elements/1.1/"),
+                        ("xmlns:opf", "http://www.idpf.org/2007/opf"),
+                    ])
+                    .write_inner_content(|writer| self.write_opf3_metadata(writer))?;
+                self.write_guide(writer)
+            })?;
+        Ok(())
+    }
+
+    fn write_guide(&self, writer: &mut OpfWriter) -> io::Result<()> {
+        if let Some(cover) = self.metadata.cover {
+            writer
+                .create_element("guide")
+                .write_inner_content(|writer| {
+                    empty_element(
+                        writer,
+                        "reference",
+                        &[("type", "cover"), ("title", "Cover"), ("href", cover)],
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
+    fn write_opf2_metadata(&self, writer: &mut OpfWriter) -> io::Result<()> {
+        let book = self.book;
+        let metadata = self.metadata;
+
+        text_element(
+            writer,
+            "dc:identifier",
+            &[("opf:scheme", "calibre"), ("id", "calibre_id")],
+            &book.id.to_string(),
+        )?;
+        text_element(
+            writer,
+            "dc:identifier",
+            &[("opf:scheme", "uuid"), ("id", "uuid_id")],
+            book.uuid.as_deref().unwrap_or_default(),
+        )?;
+        text_element(writer, "dc:title", &[], &book.title)?;
+
+        // Calibre gives every author the book's combined author sort
+        let author_sort = self.combined_author_sort();
+        for author in metadata.author_list {
+            text_element(
+                writer,
+                "dc:creator",
+                &[("opf:file-as", &author_sort), ("opf:role", "aut")],
+                &author.name,
+            )?;
+        }
+
+        if !metadata.publisher.is_empty() {
+            let publishers = metadata
+                .publisher
+                .iter()
+                .map(|publisher| publisher.name.as_str())
+                .collect::<Vec<&str>>()
+                .join("&");
+            text_element(writer, "dc:publisher", &[], &publishers)?;
+        }
+
+        // The HTML is stored as escaped text, like Calibre does
+        if let Some(description) = metadata.description {
+            text_element(writer, "dc:description", &[], description)?;
+        }
+
+        for identifier in metadata.identifiers {
+            text_element(
+                writer,
+                "dc:identifier",
+                &[("opf:scheme", &identifier.type_)],
+                &identifier.val,
+            )?;
+        }
+
+        if let Some(producer) = &self.config.opf_producer {
+            text_element(
+                writer,
+                "dc:contributor",
+                &[("opf:file-as", &producer.name), ("opf:role", "bkp")],
+                &producer.to_string(),
+            )?;
+        }
+
+        text_element(writer, "dc:date", &[], &self.pubdate())?;
+
+        if let Some(language) = metadata.language {
+            text_element(writer, "dc:language", &[], &language.lang_code)?;
+        }
+
+        for tag in metadata.tags {
+            text_element(writer, "dc:subject", &[], &tag.name)?;
+        }
+
+        if !metadata.author_list.is_empty() {
+            meta_element(
+                writer,
+                "calibre:link_maps",
+                &link_maps(metadata.author_list),
+            )?;
+        }
+
+        if let Some(series) = metadata.series {
+            meta_element(writer, "calibre:series", &series.name)?;
+            meta_element(
+                writer,
+                "calibre:series_index",
+                &book.series_index.to_string(),
+            )?;
+        }
+
+        if let Some(rating) = metadata.rating {
+            meta_element(writer, "calibre:rating", &rating.rating.to_string())?;
+        }
+
+        meta_element(writer, "calibre:timestamp", &self.timestamp())?;
+        meta_element(
+            writer,
+            "calibre:title_sort",
+            book.sort.as_deref().unwrap_or_default(),
+        )?;
+
+        Ok(())
+    }
+
+    /// OPF 3 has no attributes for roles and sort names. They are attached
+    /// to an element's `id` with `<meta refines="#id">` instead.
+    fn write_opf3_metadata(&self, writer: &mut OpfWriter) -> io::Result<()> {
+        let book = self.book;
+        let metadata = self.metadata;
+        let mut next_id = 0;
+        let mut new_id = || {
+            next_id += 1;
+            format!("id-{next_id}")
+        };
+
+        text_element(
+            writer,
+            "dc:identifier",
+            &[("id", "calibre_id")],
+            &format!("calibre:{}", book.id),
+        )?;
+        text_element(
+            writer,
+            "dc:identifier",
+            &[("id", "uuid_id")],
+            &format!("urn:uuid:{}", book.uuid.as_deref().unwrap_or_default()),
+        )?;
+        for identifier in metadata.identifiers {
+            text_element(
+                writer,
+                "dc:identifier",
+                &[],
+                &format!("{}:{}", identifier.type_, identifier.val),
+            )?;
+        }
+
+        text_element(writer, "dc:title", &[("id", "id")], &book.title)?;
+        if let Some(sort) = book.sort.as_deref().filter(|sort| !sort.is_empty()) {
+            refine(writer, "id", "file-as", sort)?;
+        }
+
+        for (author, sort) in metadata.author_list.iter().zip(self.author_sorts()) {
+            let id = new_id();
+            text_element(writer, "dc:creator", &[("id", &id)], &author.name)?;
+            refine_role(writer, &id, "aut")?;
+            refine(writer, &id, "file-as", &sort)?;
+        }
+
+        if let Some(producer) = &self.config.opf_producer {
+            let id = new_id();
+            text_element(
+                writer,
+                "dc:contributor",
+                &[("id", &id)],
+                &producer.to_string(),
+            )?;
+            refine_role(writer, &id, "bkp")?;
+        }
+
+        if !metadata.publisher.is_empty() {
+            let publishers = metadata
+                .publisher
+                .iter()
+                .map(|publisher| publisher.name.as_str())
+                .collect::<Vec<&str>>()
+                .join("&");
+            text_element(writer, "dc:publisher", &[], &publishers)?;
+        }
+
+        if let Some(description) = metadata.description {
+            text_element(writer, "dc:description", &[], description)?;
+        }
+
+        text_element(writer, "dc:date", &[], &self.pubdate())?;
+
+        if let Some(language) = metadata.language {
+            text_element(writer, "dc:language", &[], &language.lang_code)?;
+        }
+
+        for tag in metadata.tags {
+            text_element(writer, "dc:subject", &[], &tag.name)?;
+        }
+
+        text_element(
+            writer,
+            "meta",
+            &[
+                ("property", "dcterms:modified"),
+                ("scheme", "dcterms:W3CDTF"),
+            ],
+            &book.last_modified.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        )?;
+        text_element(
+            writer,
+            "meta",
+            &[
+                ("property", "calibre:timestamp"),
+                ("scheme", "dcterms:W3CDTF"),
+            ],
+            &self.timestamp(),
+        )?;
+
+        if !metadata.author_list.is_empty() {
+            text_element(
+                writer,
+                "meta",
+                &[("property", "calibre:link_maps")],
+                &link_maps(metadata.author_list),
+            )?;
+        }
+
+        if let Some(series) = metadata.series {
+            let id = new_id();
+            text_element(
+                writer,
+                "meta",
+                &[("property", "belongs-to-collection"), ("id", &id)],
+                &series.name,
+            )?;
+            refine(writer, &id, "collection-type", "series")?;
+            refine(
+                writer,
+                &id,
+                "group-position",
+                &book.series_index.to_string(),
+            )?;
+        }
+
+        if let Some(rating) = metadata.rating {
+            text_element(
+                writer,
+                "meta",
+                &[("property", "calibre:rating")],
+                &rating.rating.to_string(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The book's author sort, e.g. `Doe, Jane & Roe, Rick`.
+    fn combined_author_sort(&self) -> String {
+        self.book
+            .author_sort
+            .clone()
+            .unwrap_or_else(|| combined_author_sort(self.metadata.author_list))
+    }
+
+    /// The sort name of each author. They are taken from the book's author
+    /// sort when it has one part per author, so that OPF 2 and OPF 3 agree.
+    fn author_sorts(&self) -> Vec<String> {
+        let authors = self.metadata.author_list;
+        let combined = self.combined_author_sort();
+        let parts = combined.split(" & ").collect::<Vec<&str>>();
+        if parts.len() == authors.len() {
+            parts.into_iter().map(str::to_string).collect()
+        } else {
+            authors.iter().map(Author::sortable_name).collect()
+        }
+    }
+
+    fn pubdate(&self) -> String {
+        match &self.book.pubdate {
+            Some(date) => date.to_rfc3339(),
+            None => "0101-01-01T00:00:00+00:00".to_string(),
+        }
+    }
+
+    fn timestamp(&self) -> String {
+        self.book
+            .timestamp
+            .unwrap_or_else(Utc::now)
+            .format("%Y-%m-%dT%H:%M:%S.%6f%:z")
+            .to_string()
+    }
+}
+
+/// The `calibre:link_maps` JSON, which holds each author's link.
+fn link_maps(author_list: &[Author]) -> String {
+    let authors = author_list
+        .iter()
+        .map(|author| (author.name.as_str(), author.link.as_str()))
+        .collect::<BTreeMap<&str, &str>>();
+    let link_maps = BTreeMap::from([("authors", authors)]);
+
+    serde_json::to_string(&link_maps).unwrap_or_default()
+}
+
+fn text_element(
+    writer: &mut OpfWriter,
+    name: &str,
+    attributes: &[(&str, &str)],
+    text: &str,
+) -> io::Result<()> {
+    writer
+        .create_element(name)
+        .with_attributes(xml_attributes(attributes))
+        .write_text_content(BytesText::new(&xml_safe(text)))?;
+    Ok(())
+}
+
+fn empty_element(
+    writer: &mut OpfWriter,
+    name: &str,
+    attributes: &[(&str, &str)],
+) -> io::Result<()> {
+    writer
+        .create_element(name)
+        .with_attributes(xml_attributes(attributes))
+        .write_empty()?;
+    Ok(())
+}
+
+fn refine(writer: &mut OpfWriter, id: &str, property: &str, value: &str) -> io::Result<()> {
+    let target = format!("#{id}");
+    text_element(
+        writer,
+        "meta",
+        &[("refines", &target), ("property", property)],
+        value,
+    )
+}
+
+fn refine_role(writer: &mut OpfWriter, id: &str, role: &str) -> io::Result<()> {
+    let target = format!("#{id}");
+    text_element(
+        writer,
+        "meta",
+        &[
+            ("refines", &target),
+            ("property", "role"),
+            ("scheme", "marc:relators"),
+        ],
+        role,
+    )
+}
+
+fn meta_element(writer: &mut OpfWriter, name: &str, content: &str) -> io::Result<()> {
+    empty_element(writer, "meta", &[("name", name), ("content", content)])
+}
+
+fn xml_attributes<'a>(
+    attributes: &'a [(&'a str, &'a str)],
+) -> impl Iterator<Item = (&'a str, Cow<'a, str>)> {
+    attributes
+        .iter()
+        .map(|(key, value)| (*key, xml_safe(value)))
+}
+
+/// Drops the characters XML 1.0 cannot represent, even when escaped, such
+/// as most control characters.
+fn xml_safe(text: &str) -> Cow<'_, str> {
+    let is_xml_char = |c: char| {
+        matches!(c,
+            '\t' | '\n' | '\r'
+            | '\u{20}'..='\u{D7FF}'
+            | '\u{E000}'..='\u{FFFD}'
+            | '\u{10000}'..='\u{10FFFF}')
+    };
+    if text.chars().all(is_xml_char) {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(text.chars().filter(|c| is_xml_char(*c)).collect())
+    }
+}
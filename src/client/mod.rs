@@ -1,86 +1,114 @@
 pub mod add_book;
+pub mod check_library;
+pub mod config;
+pub mod delete_book;
+pub mod dirtied;
+pub mod export;
+pub mod formats;
+mod metadata_opf;
+mod relocate;
 pub mod replace_book;
+pub mod restore_library;
+mod transaction;
+pub mod trash;
 pub mod update_book;
 pub mod utils;
 
+pub use config::{ClientConfig, OpfProducer, OpfVersion, PathScheme};
 pub use utils::*;
 
+use metadata_opf::{Metadata, MetadataOpf};
+
 use crate::dtos::author::UpdateAuthorDto;
+use crate::dtos::custom_column::NewCustomColumnDto;
 use crate::entities::language::Language;
 use crate::entities::rating::Rating;
 use crate::entities::tag::Tag;
-use crate::Book;
+use crate::CustomColumn;
+use crate::CustomColumnValue;
 use crate::Publisher;
+use crate::Series;
 use crate::UpsertBookIdentifier;
-use chrono::DateTime;
-use chrono::Utc;
-
-use std::error::Error;
 
 use diesel::RunQueryDsl;
 
 use crate::models::Identifier;
-use crate::persistence::establish_connection;
-use crate::util::ValidDbPath;
+use crate::persistence::{create_database, establish_connection};
+use crate::util::{new_db_path, ValidDbPath};
 use crate::Author;
 use crate::BookWithAuthorsAndFiles;
 use crate::ClientV2;
 
-#[derive(Debug)]
-pub enum CalibreError {
-    DatabaseError,
-}
-
-impl std::fmt::Display for CalibreError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-impl std::error::Error for CalibreError {}
+pub use crate::CalibreError;
 
 pub struct CalibreClient {
     pub validated_library_path: ValidDbPath,
     pub client_v2: ClientV2,
+    config: ClientConfig,
 }
 
 impl CalibreClient {
-    pub fn new(db_path: ValidDbPath) -> CalibreClient {
-        CalibreClient {
+    pub fn new(db_path: ValidDbPath) -> Result<CalibreClient, CalibreError> {
+        Ok(CalibreClient {
             validated_library_path: db_path.clone(),
-            client_v2: ClientV2::new(db_path),
-        }
+            client_v2: ClientV2::new(db_path)?,
+            config: ClientConfig::default(),
+        })
+    }
+
+    /// Replaces the client's [`ClientConfig`]. It applies to everything the
+    /// client writes from then on.
+    pub fn with_config(mut self, config: ClientConfig) -> CalibreClient {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Creates a new, empty Calibre library at `library_root` and returns a
+    /// client for it.
+    ///
+    /// The directory is created if it does not exist. Fails if it already
+    /// contains a `metadata.db`.
+    pub fn create_library(library_root: &str) -> Result<CalibreClient, CalibreError> {
+        std::fs::create_dir_all(library_root).map_err(CalibreError::io(library_root))?;
+        let db_path = new_db_path(library_root).ok_or_else(|| {
+            CalibreError::InvalidInput(format!("{library_root} already contains a library"))
+        })?;
+        create_database(&db_path.database_path)?;
+
+        CalibreClient::new(db_path)
     }
 
     pub fn find_book_with_authors(
         &mut self,
         book_id: i32,
-    ) -> Result<crate::BookWithAuthorsAndFiles, Box<dyn std::error::Error>> {
-        let book = self.client_v2.books().find_by_id(book_id).unwrap().unwrap();
-        let book_desc = self.client_v2.books().get_description(book_id).unwrap();
-        let author_ids = self
+    ) -> Result<crate::BookWithAuthorsAndFiles, CalibreError> {
+        let book = self
             .client_v2
             .books()
-            .find_author_ids_by_book_id(book_id)
-            .unwrap();
+            .find_by_id(book_id)?
+            .ok_or_else(|| CalibreError::NotFound(format!("book {book_id}")))?;
+        let book_desc = self.client_v2.books().get_description(book_id)?;
+        let author_ids = self.client_v2.books().find_author_ids_by_book_id(book_id)?;
 
         let authors: Vec<Author> = author_ids
             .into_iter()
             .map(|author_id| {
-                let authors = self.client_v2.authors().find_by_id(author_id);
-                match authors {
-                    Ok(Some(author)) => Ok(author),
-                    _ => Err(ClientError::GenericError),
-                }
+                self.client_v2
+                    .authors()
+                    .find_by_id(author_id)?
+                    .ok_or_else(|| CalibreError::NotFound(format!("author {author_id}")))
             })
-            .map(|item| item.map_err(|e| e.into()))
-            .collect::<Result<Vec<Author>, Box<dyn Error>>>()?;
+            .collect::<Result<Vec<Author>, CalibreError>>()?;
 
-        let files = self
-            .client_v2
-            .book_files()
-            .list_all_by_book_id(book.id)
-            .map_err(|_| ClientError::GenericError)?;
+        let files = self.client_v2.book_files().list_all_by_book_id(book.id)?;
+        let series = match self.client_v2.books().find_series_id_by_book_id(book.id)? {
+            Some(series_id) => self.client_v2.series().find_by_id(series_id)?,
+            None => None,
+        };
 
         let is_read = self
             .client_v2
@@ -93,67 +121,142 @@ impl CalibreClient {
             book,
             authors,
             files,
+            series,
             book_description_html: book_desc,
             is_read,
         })
     }
 
-    pub fn find_all(
-        &mut self,
-    ) -> Result<Vec<crate::BookWithAuthorsAndFiles>, Box<dyn std::error::Error>> {
+    /// Renders a book's `metadata.opf` from what is in the database now.
+    pub(crate) fn book_metadata_opf(&mut self, book_id: i32) -> Result<String, CalibreError> {
+        let book = self
+            .client_v2
+            .books()
+            .find_by_id(book_id)?
+            .ok_or_else(|| CalibreError::NotFound(format!("book {book_id}")))?;
+
+        let author_list = self
+            .client_v2
+            .books()
+            .find_author_ids_by_book_id(book_id)?
+            .into_iter()
+            .filter_map(|id| self.client_v2.authors().find_by_id(id).transpose())
+            .collect::<Result<Vec<Author>, CalibreError>>()?;
+        let publishers = self
+            .client_v2
+            .books()
+            .find_publisher_ids_by_book_id(book_id)?
+            .into_iter()
+            .filter_map(|id| self.client_v2.publishers().find_by_id(id).transpose())
+            .collect::<Result<Vec<Publisher>, CalibreError>>()?;
+        let identifiers = self.client_v2.books().list_identifiers_for_book(book_id)?;
+        let language = match self
+            .client_v2
+            .books()
+            .find_language_ids_by_book_id(book_id)?
+            .first()
+        {
+            Some(&id) => self.client_v2.languages().find_by_id(id)?,
+            None => None,
+        };
+        let tags = self
+            .client_v2
+            .books()
+            .find_tag_ids_by_book_id(book_id)?
+            .into_iter()
+            .filter_map(|id| self.client_v2.tags().find_by_id(id).transpose())
+            .collect::<Result<Vec<Tag>, CalibreError>>()?;
+        let rating = match self
+            .client_v2
+            .books()
+            .find_rating_ids_by_book_id(book_id)?
+            .first()
+        {
+            Some(&id) => self.client_v2.ratings().find_by_id(id)?,
+            None => None,
+        };
+        let series = match self.client_v2.books().find_series_id_by_book_id(book_id)? {
+            Some(id) => self.client_v2.series().find_by_id(id)?,
+            None => None,
+        };
+        let description = self.client_v2.books().get_description(book_id)?;
+
+        let metadata = Metadata {
+            author_list: &author_list,
+            publisher: &publishers,
+            identifiers: &identifiers,
+            language: language.as_ref(),
+            tags: &tags,
+            rating: rating.as_ref(),
+            series: series.as_ref(),
+            description: description.as_deref(),
+            cover: find_cover_file_name(
+                &std::path::Path::new(&self.validated_library_path.library_path).join(&book.path),
+            ),
+        };
+        Ok(MetadataOpf::new(&book, &metadata, &self.config).format())
+    }
+
+    pub fn find_all(&mut self) -> Result<Vec<crate::BookWithAuthorsAndFiles>, CalibreError> {
         let mut book_list = Vec::new();
-        let books = self.client_v2.books().list().unwrap();
+        let books = self.client_v2.books().list()?;
 
         for book in books {
-            let result = self.find_book_with_authors(book.id);
-            match result {
-                Ok(res) => book_list.push(res),
-                Err(_) => (),
+            if let Ok(res) = self.find_book_with_authors(book.id) {
+                book_list.push(res);
             }
         }
 
         Ok(book_list)
     }
 
-    pub fn list_all_authors(&mut self) -> Result<Vec<crate::Author>, Box<dyn std::error::Error>> {
-        self.client_v2
-            .authors()
-            .list()
-            .map_err(|_| Box::new(CalibreError::DatabaseError) as Box<dyn std::error::Error>)
+    pub fn list_all_authors(&mut self) -> Result<Vec<crate::Author>, CalibreError> {
+        self.client_v2.authors().list()
     }
 
     pub fn list_identifiers_for_book(
         &mut self,
         book_id: i32,
-    ) -> Result<Vec<Identifier>, Box<dyn std::error::Error>> {
-        self.client_v2
-            .books()
-            .list_identifiers_for_book(book_id)
-            .map_err(|_| Box::new(CalibreError::DatabaseError) as Box<dyn std::error::Error>)
+    ) -> Result<Vec<Identifier>, CalibreError> {
+        self.client_v2.books().list_identifiers_for_book(book_id)
     }
 
     pub fn update_author(
         &mut self,
         author_id: i32,
         updates: UpdateAuthorDto,
-    ) -> Result<crate::Author, ()> {
-        self.client_v2.authors().update(author_id, updates)
+    ) -> Result<crate::Author, CalibreError> {
+        self.transaction(|client| {
+            let author = client.client_v2.authors().update(author_id, updates)?;
+            let book_ids = client
+                .client_v2
+                .books()
+                .find_book_ids_by_author_id(author_id)?;
+            client.mark_metadata_dirtied(&book_ids)?;
+            Ok(author)
+        })
     }
 
-    pub fn get_all_authors(&mut self) -> Result<Vec<Author>, Box<dyn Error>> {
-        Ok(self.client_v2.authors().get_all_authors().unwrap())
+    pub fn get_all_authors(&mut self) -> Result<Vec<Author>, CalibreError> {
+        self.client_v2.authors().get_all_authors()
     }
 
     pub fn replace_author_with_translation(
         &mut self,
         author_id: i32,
         translation: &str,
-    ) -> Result<(), Box<dyn Error>> {
-        self.client_v2
-            .authors()
-            .replace_with_translation(author_id, translation)
-            .unwrap();
-        Ok(())
+    ) -> Result<(), CalibreError> {
+        self.transaction(|client| {
+            let book_ids = client
+                .client_v2
+                .books()
+                .find_book_ids_by_author_id(author_id)?;
+            client
+                .client_v2
+                .authors()
+                .replace_with_translation(author_id, translation)?;
+            client.mark_metadata_dirtied(&book_ids)
+        })
     }
 
     // === Identifiers ===
@@ -161,26 +264,40 @@ impl CalibreClient {
     pub fn upsert_book_identifiers(
         &mut self,
         update: Vec<UpsertBookIdentifier>,
-    ) -> Result<Vec<Identifier>, Box<dyn Error>> {
-        let x = update
-            .into_iter()
-            .map(|dto| self.client_v2.books().upsert_book_identifier(dto).unwrap())
-            .collect::<Vec<Identifier>>();
-
-        Ok(x)
+    ) -> Result<Vec<Identifier>, CalibreError> {
+        self.transaction(|client| {
+            let identifiers = update
+                .into_iter()
+                .map(|dto| client.client_v2.books().upsert_book_identifier(dto))
+                .collect::<Result<Vec<Identifier>, CalibreError>>()?;
+            let book_ids = identifiers
+                .iter()
+                .map(|identifier| identifier.book)
+                .collect::<Vec<i32>>();
+            client.mark_metadata_dirtied(&book_ids)?;
+            Ok(identifiers)
+        })
     }
 
-    pub fn delete_book_identifier(&mut self, book_id: i32, identifier_id: i32) -> Result<(), ()> {
-        self.client_v2
-            .books()
-            .delete_book_identifier(book_id, identifier_id)
+    pub fn delete_book_identifier(
+        &mut self,
+        book_id: i32,
+        identifier_id: i32,
+    ) -> Result<(), CalibreError> {
+        self.transaction(|client| {
+            client
+                .client_v2
+                .books()
+                .delete_book_identifier(book_id, identifier_id)?;
+            client.mark_metadata_dirtied(&[book_id])
+        })
     }
 
     pub fn find_book_id_by_identifier(
         &mut self,
         i_type: &str,
         i_value: &str,
-    ) -> Result<Option<i32>, String> {
+    ) -> Result<Option<i32>, CalibreError> {
         self.client_v2
             .books()
             .find_book_id_by_identifier(i_type, i_value)
@@ -191,293 +308,123 @@ impl CalibreClient {
     /// You probably do not need this method, unless you're creating a new
     /// library from an existing database and want to avoid UUID conflicts.
     pub fn dontusethis_randomize_library_uuid(&mut self) -> Result<(), CalibreError> {
-        let conn = establish_connection(&self.validated_library_path.database_path);
-        conn.map(|mut c| {
-            diesel::sql_query("UPDATE library_id SET uuid = uuid4()")
-                .execute(&mut c)
-                .expect("Failed to set new UUID");
-        })
-        .map_err(|_| CalibreError::DatabaseError)
+        let mut conn = establish_connection(&self.validated_library_path.database_path)?;
+        diesel::sql_query("UPDATE library_id SET uuid = uuid4()").execute(&mut conn)?;
+
+        Ok(())
     }
 
-    pub fn get_all_publishers(&mut self) -> Result<Vec<Publisher>, Box<dyn Error>> {
-        Ok(self.client_v2.publishers().get_all_publishers().unwrap())
+    pub fn get_all_publishers(&mut self) -> Result<Vec<Publisher>, CalibreError> {
+        self.client_v2.publishers().get_all_publishers()
     }
 
     pub fn replace_publisher_with_translation(
         &mut self,
         publisher_id: i32,
         translation: &str,
-    ) -> Result<(), Box<dyn Error>> {
-        self.client_v2
-            .publishers()
-            .replace_with_translation(publisher_id, translation)
-            .unwrap();
-        Ok(())
+    ) -> Result<(), CalibreError> {
+        self.transaction(|client| {
+            let book_ids = client
+                .client_v2
+                .books()
+                .find_book_ids_by_publisher_id(publisher_id)?;
+            client
+                .client_v2
+                .publishers()
+                .replace_with_translation(publisher_id, translation)?;
+            client.mark_metadata_dirtied(&book_ids)
+        })
     }
 
-    pub fn get_all_tags(&mut self) -> Result<Vec<Tag>, Box<dyn Error>> {
-        Ok(self.client_v2.tags().get_all_tags().unwrap())
+    pub fn get_all_tags(&mut self) -> Result<Vec<Tag>, CalibreError> {
+        self.client_v2.tags().get_all_tags()
     }
 
     pub fn replace_tag_with_translation(
         &mut self,
         tag_id: i32,
         translation: &str,
-    ) -> Result<(), Box<dyn Error>> {
-        self.client_v2
-            .tags()
-            .replace_with_translation(tag_id, translation)
-            .unwrap();
-        Ok(())
-    }
-}
-
-#[derive(Default)]
-struct Metadata<'a> {
-    author_list: &'a [Author],
-    publisher: &'a [Publisher],
-    identifiers: &'a [Identifier],
-    language: Option<&'a Language>,
-    tags: &'a [Tag],
-    rating: Option<&'a Rating>,
-}
-
-struct MetadataOpf<'a> {
-    book: &'a Book,
-    metadata: &'a Metadata<'a>,
-}
-
-impl<'a> MetadataOpf<'a> {
-    pub fn new(book: &'a Book, metadata: &'a Metadata) -> Self {
-        Self { book, metadata }
-    }
-
-    pub fn format(&self) -> Result<String, ()> {
-        let book_custom_author_sort = self
-            .book
-            .author_sort
-            .clone()
-            .unwrap_or_else(|| self.get_author_sort_string(self.metadata.author_list));
-
-        let authors_string =
-            self.get_authors_string(self.metadata.author_list, &book_custom_author_sort);
-        let publisher_string = self.get_publisher_string(self.metadata.publisher);
-        let identifiers_string = self.get_identifiers_string(self.metadata.identifiers);
-        let pubdate_string = self.get_pubdate_string(self.book.pubdate.as_ref());
-        let language_string = self.get_language_string(self.metadata.language);
-        let tags_string = self.get_tags_string(self.metadata.tags);
-        let link_map_string = self.get_link_map_string(self.metadata.author_list);
-        let rating_string = self.get_rating_string(self.metadata.rating);
-
-        Ok(self.format_metadata_opf(
-            self.book,
-            &authors_string,
-            &publisher_string,
-            &identifiers_string,
-            &pubdate_string,
-            &language_string,
-            &tags_string,
-            &link_map_string,
-            &rating_string,
-        ))
-    }
-
-    fn get_author_sort_string(&self, author_list: &[Author]) -> String {
-        if author_list.is_empty() {
-            return String::new();
-        }
-
-        author_list
-            .iter()
-            .map(|author| author.name.clone())
-            .collect::<Vec<String>>()
-            .join(", ")
-    }
-
-    fn get_authors_string(
-        &self,
-        author_list: &[Author],
-        book_custom_author_sort: &String,
-    ) -> String {
-        if author_list.is_empty() {
-            return String::new();
-        }
-
-        author_list
-            .iter()
-            .map(|author| {
-                format!(
-                    "<dc:creator opf:file-as=\"{sortable}\" opf:role=\"aut\">{author}</dc:creator>",
-                    sortable = book_custom_author_sort.as_str(),
-                    author = author.name
-                )
-            })
-            .collect::<String>()
+    ) -> Result<(), CalibreError> {
+        self.transaction(|client| {
+            let book_ids = client.client_v2.books().find_book_ids_by_tag_id(tag_id)?;
+            client
+                .client_v2
+                .tags()
+                .replace_with_translation(tag_id, translation)?;
+            client.mark_metadata_dirtied(&book_ids)
+        })
     }
 
-    fn get_publisher_string(&self, publisher: &[Publisher]) -> String {
-        if publisher.is_empty() {
-            return String::new();
-        }
-
-        let combined_publishers = publisher
-            .iter()
-            .map(|publ| publ.name.clone())
-            .collect::<Vec<String>>()
-            .join("&amp;");
-
-        format!("<dc:publisher>{}</dc:publisher>", combined_publishers)
+    pub fn get_all_series(&mut self) -> Result<Vec<Series>, CalibreError> {
+        self.client_v2.series().get_all_series()
     }
 
-    fn get_pubdate_string(&self, pubdate: Option<&DateTime<Utc>>) -> String {
-        match pubdate {
-            Some(date) => format!("<dc:date>{}</dc:date>", date.to_rfc3339()),
-            None => "<dc:date>0101-01-01T00:00:00+00:00</dc:date>".to_string(),
-        }
+    /// Renames a series, merging it into an existing series of that name.
+    pub fn replace_series_with_translation(
+        &mut self,
+        series_id: i32,
+        translation: &str,
+    ) -> Result<(), CalibreError> {
+        self.transaction(|client| {
+            let book_ids = client
+                .client_v2
+                .books()
+                .find_book_ids_by_series_id(series_id)?;
+            client
+                .client_v2
+                .series()
+                .replace_with_translation(series_id, translation)?;
+            client.mark_metadata_dirtied(&book_ids)
+        })
     }
 
-    fn get_identifiers_string(&self, identifiers: &[Identifier]) -> String {
-        if identifiers.is_empty() {
-            return String::new();
-        }
+    // === Custom columns ===
 
-        identifiers
-            .iter()
-            .map(|identifier| {
-                format!(
-                    "<dc:identifier opf:scheme=\"{}\">{}</dc:identifier>",
-                    identifier.type_, identifier.val
-                )
-            })
-            .collect::<String>()
+    pub fn list_custom_columns(&mut self) -> Result<Vec<CustomColumn>, CalibreError> {
+        self.client_v2.custom_columns().list()
     }
 
-    fn get_language_string(&self, language: Option<&Language>) -> String {
-        match language {
-            Some(lang) => format!("<dc:language>{}</dc:language>", lang.lang_code),
-            None => String::new(),
-        }
-    }
-
-    fn get_tags_string(&self, tags: &[Tag]) -> String {
-        const INDENT: usize = 8;
-
-        if tags.is_empty() {
-            return String::new();
-        }
-
-        tags.iter()
-            .map(|tag| {
-                format!(
-                    "{}<dc:subject>{}</dc:subject>",
-                    " ".repeat(INDENT),
-                    tag.name
-                )
-            })
-            .collect::<Vec<String>>()
-            .join("\n")
+    pub fn create_custom_column(
+        &mut self,
+        dto: NewCustomColumnDto,
+    ) -> Result<CustomColumn, CalibreError> {
+        self.client_v2.custom_columns().create(dto)
     }
 
-    fn get_link_map_string(&self, author_list: &[Author]) -> String {
-        if author_list.is_empty() {
-            return String::new();
-        }
-
-        let mut authors_map = std::collections::HashMap::new();
-
-        for author in author_list {
-            authors_map.insert(author.name.clone(), author.link.clone());
-        }
-
-        let mut link_maps = std::collections::HashMap::new();
-        link_maps.insert("authors".to_string(), authors_map);
-
-        let json_string = serde_json::to_string(&link_maps).unwrap_or_default();
-        let formatted_json = json_string.replace("\":", "\": ");
-        let escaped_content = formatted_json.replace("\"", "&quot;");
-
-        format!(
-            "<meta name=\"calibre:link_maps\" content=\"{}\"/>",
-            escaped_content
-        )
+    pub fn delete_custom_column(&mut self, column_id: i32) -> Result<(), CalibreError> {
+        self.client_v2.custom_columns().delete(column_id)
     }
 
-    fn get_rating_string(&self, rating: Option<&Rating>) -> String {
-        match rating {
-            Some(r) => format!("<meta name=\"calibre:rating\" content=\"{}\"/>", r.rating),
-            None => String::new(),
-        }
+    /// Reads a book's value in the custom column with lookup name `label`.
+    pub fn get_custom_column_value(
+        &mut self,
+        book_id: i32,
+        label: &str,
+    ) -> Result<Option<CustomColumnValue>, CalibreError> {
+        let column = self.custom_column_by_label(label)?;
+        self.client_v2
+            .custom_columns()
+            .get_value(column.id, book_id)
     }
 
-    fn format_metadata_opf(
-        &self,
-        book: &Book,
-        authors_string: &String,
-        publisher_string: &String,
-        identifiers_string: &String,
-        pub_date_string: &String,
-        language_string: &String,
-        tags_string: &String,
-        link_map_string: &String,
-        rating_string: &String,
-    ) -> String {
-        let raw_xml = format!(
-            r#"<?xml version='1.0' encoding='utf-8'?>
-<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uuid_id" version="2.0">
-    <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
-        <dc:identifier opf:scheme="calibre" id="calibre_id">{calibre_id}</dc:identifier>
-        <dc:identifier opf:scheme="uuid" id="uuid_id">{calibre_uuid}</dc:identifier>
-        <dc:title>{book_title}</dc:title>
-        {authors}
-        {publisher}
-        {identifiers}
-        <dc:contributor opf:file-as="calibre" opf:role="bkp">EhArchive (0.1.0) [https://github.com/AyaseFile/EhArchive]</dc:contributor>
-        {pub_date}
-        {language_iso_639_3}
-{tags}
-        {link_map}
-        {rating}
-        <meta name="calibre:timestamp" content="{now}"/>
-        <meta name="calibre:title_sort" content="{book_title_sortable}"/>
-    </metadata>
-    <guide>
-        <reference type="cover" title="Cover" href="cover.jpg"/>
-    </guide>
-</package>"#,
-            calibre_id = book.id,
-            calibre_uuid = &book.uuid.clone().unwrap_or("".to_string()).as_str(),
-            book_title = book.title,
-            authors = authors_string,
-            publisher = publisher_string,
-            identifiers = identifiers_string,
-            pub_date = pub_date_string,
-            language_iso_639_3 = language_string,
-            tags = tags_string,
-            link_map = link_map_string,
-            rating = rating_string,
-            now = book.timestamp.unwrap().format("%Y-%m-%dT%H:%M:%S.%6f%:z"),
-            book_title_sortable = &book.sort.clone().unwrap_or("".to_string()).as_str()
-        );
-        raw_xml
-            .lines()
-            .filter(|line| {
-                !line.trim().is_empty()
-                    || line.trim().starts_with("<")
-                    || line.trim().ends_with(">")
-            })
-            .collect::<Vec<&str>>()
-            .join("\n")
+    /// Sets or, with `None`, clears a book's value in the custom column with
+    /// lookup name `label`.
+    pub fn set_custom_column_value(
+        &mut self,
+        book_id: i32,
+        label: &str,
+        value: Option<CustomColumnValue>,
+    ) -> Result<(), CalibreError> {
+        let column = self.custom_column_by_label(label)?;
+        self.client_v2
+            .custom_columns()
+            .set_value(column.id, book_id, value)
     }
-}
 
-#[derive(Debug)]
-enum ClientError {
-    GenericError,
-}
-impl std::fmt::Display for ClientError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+    fn custom_column_by_label(&mut self, label: &str) -> Result<CustomColumn, CalibreError> {
+        self.client_v2
+            .custom_columns()
+            .find_by_label(label)?
+            .ok_or_else(|| CalibreError::NotFound(format!("custom column #{label}")))
     }
 }
-impl std::error::Error for ClientError {}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::client::transaction::{MovedPath, PublishedDir, StagedDir};
+use crate::client::*;
+use crate::entities::book_file::UpdateBookFile;
+use crate::BookFile;
+
+impl CalibreClient {
+    /// Moves a book's folder and renames its files to match its current
+    /// title and first author under the client's [`PathScheme`], and points
+    /// `books.path` and `data.name` at them. Returns `None` if nothing had
+    /// to move.
+    ///
+    /// Must be called inside [`CalibreClient::transaction`]. The files are
+    /// linked or copied to their new names and the old ones are only
+    /// removed by [`RelocatedBook::keep`], once the transaction has
+    /// committed. Until then the database refers to the old files, so a
+    /// crash at any point leaves a complete book behind.
+    pub(crate) fn relocate_book(
+        &mut self,
+        book_id: i32,
+    ) -> Result<Option<RelocatedBook>, CalibreError> {
+        let book = self
+            .client_v2
+            .books()
+            .find_by_id(book_id)?
+            .ok_or_else(|| CalibreError::NotFound(format!("book {book_id}")))?;
+        if !is_library_relative(&book.path) {
+            return Ok(None);
+        }
+
+        let primary_author_name = match self
+            .client_v2
+            .books()
+            .find_author_ids_by_book_id(book_id)?
+            .first()
+        {
+            Some(author_id) => self
+                .client_v2
+                .authors()
+                .find_by_id(*author_id)?
+                .map(|author| author.name),
+            None => None,
+        }
+        .unwrap_or_else(|| "Unknown".to_string());
+        let scheme = self.config.path_scheme;
+        let new_path = scheme.book_folder_name(book_id, &book.title, &primary_author_name);
+        let new_name = scheme.book_file_name(&book.title, &primary_author_name);
+
+        let files = self.client_v2.book_files().list_all_by_book_id(book_id)?;
+        if new_path == book.path && files.iter().all(|file| file.name == new_name) {
+            return Ok(None);
+        }
+
+        let library_root = PathBuf::from(&self.validated_library_path.library_path);
+        let old_dir = library_root.join(&book.path);
+        let new_dir = library_root.join(&new_path);
+        let renames = files
+            .iter()
+            .map(|file| {
+                let renamed = BookFile {
+                    name: new_name.clone(),
+                    ..file.clone()
+                };
+                (file.as_filename(), renamed.as_filename())
+            })
+            .collect::<HashMap<String, String>>();
+
+        let mut relocated = RelocatedBook {
+            new_dir: None,
+            old_dir: None,
+            library_root: library_root.clone(),
+            renamed: Vec::new(),
+            moved: Vec::new(),
+            kept: false,
+        };
+        // Without a folder on disk there is nothing to move, but the
+        // database is still brought in line
+        let files_dir = if old_dir.is_dir() && is_same_entry(&old_dir, &new_dir) {
+            // Only the case changed, on a filesystem that ignores it: there
+            // is no second folder to copy into, so the folder is renamed
+            if new_path != book.path {
+                relocated
+                    .moved
+                    .push(MovedPath::rename_case(&old_dir, &new_dir)?);
+            }
+            Some(new_dir)
+        } else if old_dir.is_dir() && new_path != book.path {
+            if new_dir.exists() {
+                return Err(CalibreError::Conflict(format!(
+                    "{} already exists",
+                    new_dir.display()
+                )));
+            }
+            let staging = StagedDir::new(&library_root)?;
+            for entry in fs::read_dir(&old_dir).map_err(CalibreError::io(&old_dir))? {
+                let entry = entry.map_err(CalibreError::io(&old_dir))?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let dest_name = renames.get(&name).unwrap_or(&name);
+                link_or_copy(&entry.path(), &staging.path().join(dest_name))?;
+            }
+            relocated.new_dir = Some(staging.publish(&new_dir)?);
+            relocated.old_dir = Some(old_dir);
+            None
+        } else if old_dir.is_dir() {
+            Some(old_dir)
+        } else {
+            None
+        };
+        if let Some(dir) = files_dir {
+            for (old_name, new_name) in renames.iter().filter(|(old, new)| old != new) {
+                let (from, to) = (dir.join(old_name), dir.join(new_name));
+                if !from.is_file() {
+                    continue;
+                }
+                if is_same_entry(&from, &to) {
+                    relocated.moved.push(MovedPath::rename_case(&from, &to)?);
+                    continue;
+                }
+                if to.exists() {
+                    return Err(CalibreError::Conflict(format!(
+                        "{} already exists",
+                        to.display()
+                    )));
+                }
+                link_or_copy(&from, &to)?;
+                relocated.renamed.push((from, to));
+            }
+        }
+
+        self.client_v2
+            .books()
+            .update(book_id, update_book_data_for_path(Path::new(&new_path)))?;
+        for file in files.iter().filter(|file| file.name != new_name) {
+            let update = UpdateBookFile {
+                name: Some(new_name.clone()),
+                ..Default::default()
+            };
+            self.client_v2.book_files().update(file.id, &update)?;
+        }
+
+        Ok(Some(relocated))
+    }
+}
+
+/// The new copy of a book's files made by [`CalibreClient::relocate_book`].
+///
+/// Dropping the guard removes the new copy again, so returning it from a
+/// [`CalibreClient::transaction`] closure undoes the move if the commit
+/// fails. Call [`RelocatedBook::keep`] once the transaction has committed.
+pub(crate) struct RelocatedBook {
+    /// The new folder, when the book moved to another one.
+    new_dir: Option<PublishedDir>,
+    old_dir: Option<PathBuf>,
+    library_root: PathBuf,
+    /// `(old, new)` paths of files renamed within the same folder.
+    renamed: Vec<(PathBuf, PathBuf)>,
+    /// Folders and files whose name only changed in case, in the order
+    /// they were moved.
+    moved: Vec<MovedPath>,
+    kept: bool,
+}
+
+impl RelocatedBook {
+    /// Removes the old files. Failures are ignored: the database no longer
+    /// refers to them, so they are only clutter.
+    pub(crate) fn keep(mut self) {
+        self.kept = true;
+        if let Some(new_dir) = self.new_dir.take() {
+            new_dir.keep();
+        }
+        for moved in self.moved.drain(..) {
+            moved.keep();
+        }
+        if let Some(old_dir) = &self.old_dir {
+            let _ = fs::remove_dir_all(old_dir);
+            // Fails, as intended, if the author has other books
+            if let Some(parent) = old_dir.parent().filter(|dir| *dir != self.library_root) {
+                let _ = fs::remove_dir(parent);
+            }
+        }
+        for (old, _) in &self.renamed {
+            let _ = fs::remove_file(old);
+        }
+    }
+}
+
+impl Drop for RelocatedBook {
+    fn drop(&mut self) {
+        // A new folder is removed by its own `PublishedDir` guard
+        if !self.kept {
+            for (_, new) in &self.renamed {
+                let _ = fs::remove_file(new);
+            }
+            // Files are moved back before the folder they were moved in
+            while let Some(moved) = self.moved.pop() {
+                drop(moved);
+            }
+        }
+    }
+}
+
+/// Whether `a` and `b` name the same file or folder, as two names that
+/// only differ in case do on a case-insensitive filesystem.
+fn is_same_entry(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        match (fs::canonicalize(a), fs::canonicalize(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Hard-links `from` to `to`, so large books are not copied, and falls
+/// back to a copy where links are not supported. Folders are recreated and
+/// their contents linked one by one.
+fn link_or_copy(from: &Path, to: &Path) -> Result<(), CalibreError> {
+    if from.is_dir() {
+        fs::create_dir(to).map_err(CalibreError::io(to))?;
+        for entry in fs::read_dir(from).map_err(CalibreError::io(from))? {
+            let entry = entry.map_err(CalibreError::io(from))?;
+            link_or_copy(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        return Ok(());
+    }
+
+    fs::hard_link(from, to)
+        .or_else(|_| fs::copy(from, to).map(|_| ()))
+        .map_err(CalibreError::io(to))
+}
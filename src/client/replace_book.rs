@@ -15,10 +15,44 @@ impl CalibreClient {
         &mut self,
         book_id: i32,
         dto: ReplaceLibraryEntryDto,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), CalibreError> {
+        let (contents, relocated) = self.transaction(|client| {
+            let contents = client.replace_book_rows(book_id, dto)?;
+            // The folder and file names follow the new title and first author
+            let relocated = client.relocate_book(book_id)?;
+            Ok((contents, relocated))
+        })?;
+        if let Some(relocated) = relocated {
+            relocated.keep();
+        }
+
+        let book = self
+            .client_v2
+            .books()
+            .find_by_id(book_id)?
+            .ok_or_else(|| CalibreError::NotFound(format!("book {book_id}")))?;
+        let metadata_opf_path = Path::new(&book.path).join("metadata.opf");
+        library_relative_write_file(
+            &self.validated_library_path,
+            &metadata_opf_path,
+            contents.as_bytes(),
+        )
+        .map_err(CalibreError::io(
+            Path::new(&self.validated_library_path.library_path).join(&metadata_opf_path),
+        ))?;
+
+        Ok(())
+    }
+
+    /// Replaces the book's rows and returns its new `metadata.opf`.
+    fn replace_book_rows(
+        &mut self,
+        book_id: i32,
+        dto: ReplaceLibraryEntryDto,
+    ) -> Result<String, CalibreError> {
         let author_list = self.replace_book_authors(book_id, dto.authors)?;
         let timestamp = Utc::now();
-        let _ = self.client_v2.books().update(
+        self.client_v2.books().update(
             book_id,
             UpdateBookData {
                 author_sort: Some(combined_author_sort(&author_list)),
@@ -31,10 +65,14 @@ impl CalibreClient {
                 has_cover: None,
                 last_modified: None,
             },
-        );
+        )?;
 
-        let book_dir_name = gen_book_folder_name(book_id);
-        let book_dir_relative_path = Path::new(&book_dir_name).to_path_buf();
+        let book_dir_relative_path = self
+            .client_v2
+            .books()
+            .find_by_id(book_id)?
+            .map(|book| book.path)
+            .ok_or_else(|| CalibreError::NotFound(format!("book {book_id}")))?;
 
         let publishers = self.replace_book_publishers(book_id, dto.publishers)?;
 
@@ -55,6 +93,13 @@ impl CalibreClient {
 
         let rating = self.replace_book_rating(book_id, dto.rating)?;
 
+        let series = self.replace_book_series(book_id, dto.series, dto.book.series_index)?;
+
+        let description = self
+            .client_v2
+            .books()
+            .set_description(book_id, dto.description.as_deref())?;
+
         let metadata = Metadata {
             author_list: &author_list,
             publisher: &publishers,
@@ -62,52 +107,35 @@ impl CalibreClient {
             language: language.as_ref(),
             tags: &tags,
             rating: rating.as_ref(),
+            series: series.as_ref(),
+            description: description.as_deref(),
+            cover: find_cover_file_name(
+                &Path::new(&self.validated_library_path.library_path).join(&book_dir_relative_path),
+            ),
         };
 
-        let book = self
-            .client_v2
-            .books()
-            .update(
-                book_id,
-                UpdateBookData {
-                    last_modified: Some(Utc::now()),
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-
-        let metadata_opf = MetadataOpf::new(&book, &metadata).format();
-        match metadata_opf {
-            Ok(contents) => {
-                let metadata_opf_path = Path::new(&book_dir_relative_path).join("metadata.opf");
-                let _ = library_relative_write_file(
-                    &self.validated_library_path,
-                    &metadata_opf_path,
-                    contents.as_bytes(),
-                );
-            }
-            Err(_) => (),
-        };
+        let book = self.client_v2.books().update(
+            book_id,
+            UpdateBookData {
+                last_modified: Some(Utc::now()),
+                ..Default::default()
+            },
+        )?;
 
-        Ok(())
+        Ok(MetadataOpf::new(&book, &metadata, &self.config).format())
     }
 
     fn replace_book_authors(
         &mut self,
         book_id: i32,
         authors: Vec<crate::dtos::author::NewAuthorDto>,
-    ) -> Result<Vec<Author>, Box<dyn std::error::Error>> {
-        let author_ids = self
-            .client_v2
-            .books()
-            .find_author_ids_by_book_id(book_id)
-            .unwrap_or_default();
+    ) -> Result<Vec<Author>, CalibreError> {
+        let author_ids = self.client_v2.books().find_author_ids_by_book_id(book_id)?;
 
         for author_id in author_ids {
-            let _ = self
-                .client_v2
+            self.client_v2
                 .books()
-                .unlink_author_from_book(book_id, author_id);
+                .unlink_author_from_book(book_id, author_id)?;
         }
 
         let mut author_list = Vec::new();
@@ -116,11 +144,10 @@ impl CalibreClient {
                 author.sortable_name = Author::sort_author_name_apa(&author.full_name);
             }
 
-            let author = self.client_v2.authors().create_if_missing(author).unwrap();
-            let _ = self
-                .client_v2
+            let author = self.client_v2.authors().create_if_missing(author)?;
+            self.client_v2
                 .books()
-                .link_author_to_book(book_id, author.id);
+                .link_author_to_book(book_id, author.id)?;
             author_list.push(author);
         }
 
@@ -131,31 +158,24 @@ impl CalibreClient {
         &mut self,
         book_id: i32,
         publishers: Vec<crate::dtos::publisher::NewPublisherDto>,
-    ) -> Result<Vec<Publisher>, Box<dyn std::error::Error>> {
+    ) -> Result<Vec<Publisher>, CalibreError> {
         let publisher_ids = self
             .client_v2
             .books()
-            .find_publisher_ids_by_book_id(book_id)
-            .unwrap_or_default();
+            .find_publisher_ids_by_book_id(book_id)?;
 
         for publisher_id in publisher_ids {
-            let _ = self
-                .client_v2
+            self.client_v2
                 .books()
-                .unlink_publisher_from_book(book_id, publisher_id);
+                .unlink_publisher_from_book(book_id, publisher_id)?;
         }
 
         let mut publisher_list = Vec::new();
         for publisher in publishers {
-            let publisher = self
-                .client_v2
-                .publishers()
-                .create_if_missing(publisher)
-                .unwrap();
-            let _ = self
-                .client_v2
+            let publisher = self.client_v2.publishers().create_if_missing(publisher)?;
+            self.client_v2
                 .books()
-                .link_publisher_to_book(book_id, publisher.id);
+                .link_publisher_to_book(book_id, publisher.id)?;
             publisher_list.push(publisher);
         }
 
@@ -166,18 +186,16 @@ impl CalibreClient {
         &mut self,
         book_id: i32,
         language: Option<crate::dtos::language::NewLanguageDto>,
-    ) -> Result<Option<Language>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<Language>, CalibreError> {
         let language_ids = self
             .client_v2
             .books()
-            .find_language_ids_by_book_id(book_id)
-            .unwrap_or_default();
+            .find_language_ids_by_book_id(book_id)?;
 
         for language_id in language_ids {
-            let _ = self
-                .client_v2
+            self.client_v2
                 .books()
-                .unlink_language_from_book(book_id, language_id);
+                .unlink_language_from_book(book_id, language_id)?;
         }
 
         let language = if let Some(language_dto) = language {
@@ -187,12 +205,10 @@ impl CalibreClient {
                     .languages()
                     .create_if_missing(NewLanguageDto {
                         lang_code: canonical_lang.to_639_3().to_string(),
-                    })
-                    .unwrap();
-                let _ = self
-                    .client_v2
+                    })?;
+                self.client_v2
                     .books()
-                    .link_language_to_book(book_id, language.id);
+                    .link_language_to_book(book_id, language.id)?;
                 Some(language)
             } else {
                 None
@@ -208,21 +224,19 @@ impl CalibreClient {
         &mut self,
         book_id: i32,
         tags: Vec<crate::dtos::tag::NewTagDto>,
-    ) -> Result<Vec<Tag>, Box<dyn std::error::Error>> {
-        let tag_ids = self
-            .client_v2
-            .books()
-            .find_tag_ids_by_book_id(book_id)
-            .unwrap_or_default();
+    ) -> Result<Vec<Tag>, CalibreError> {
+        let tag_ids = self.client_v2.books().find_tag_ids_by_book_id(book_id)?;
 
         for tag_id in tag_ids {
-            let _ = self.client_v2.books().unlink_tag_from_book(book_id, tag_id);
+            self.client_v2
+                .books()
+                .unlink_tag_from_book(book_id, tag_id)?;
         }
 
         let mut tag_list = Vec::new();
         for tag in tags {
-            let tag = self.client_v2.tags().create_if_missing(tag).unwrap();
-            let _ = self.client_v2.books().link_tag_to_book(book_id, tag.id);
+            let tag = self.client_v2.tags().create_if_missing(tag)?;
+            self.client_v2.books().link_tag_to_book(book_id, tag.id)?;
             tag_list.push(tag);
         }
 
@@ -233,30 +247,20 @@ impl CalibreClient {
         &mut self,
         book_id: i32,
         rating: Option<crate::dtos::rating::NewRatingDto>,
-    ) -> Result<Option<Rating>, Box<dyn std::error::Error>> {
-        let rating_ids = self
-            .client_v2
-            .books()
-            .find_rating_ids_by_book_id(book_id)
-            .unwrap_or_default();
+    ) -> Result<Option<Rating>, CalibreError> {
+        let rating_ids = self.client_v2.books().find_rating_ids_by_book_id(book_id)?;
 
         for rating_id in rating_ids {
-            let _ = self
-                .client_v2
+            self.client_v2
                 .books()
-                .unlink_rating_from_book(book_id, rating_id);
+                .unlink_rating_from_book(book_id, rating_id)?;
         }
 
         let rating = if let Some(rating_dto) = rating {
-            let rating = self
-                .client_v2
-                .ratings()
-                .create_if_missing(rating_dto)
-                .unwrap();
-            let _ = self
-                .client_v2
+            let rating = self.client_v2.ratings().create_if_missing(rating_dto)?;
+            self.client_v2
                 .books()
-                .link_rating_to_book(book_id, rating.id);
+                .link_rating_to_book(book_id, rating.id)?;
             Some(rating)
         } else {
             None
@@ -264,4 +268,25 @@ impl CalibreClient {
 
         Ok(rating)
     }
+
+    fn replace_book_series(
+        &mut self,
+        book_id: i32,
+        series: Option<crate::dtos::series::NewSeriesDto>,
+        series_index: f32,
+    ) -> Result<Option<Series>, CalibreError> {
+        match series {
+            Some(series_dto) => {
+                let series = self.client_v2.series().create_if_missing(series_dto)?;
+                self.client_v2
+                    .books()
+                    .link_series_to_book(book_id, series.id, series_index)?;
+                Ok(Some(series))
+            }
+            None => {
+                self.client_v2.books().unlink_series_from_book(book_id)?;
+                Ok(None)
+            }
+        }
+    }
 }
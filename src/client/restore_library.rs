@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::client::add_book::{AddBookOptions, ImportReport};
+use crate::client::trash::format_files;
+use crate::client::*;
+use crate::opf::OpfMetadata;
+
+/// What the previous database is renamed to by
+/// [`CalibreClient::restore_library`], as in Calibre.
+pub const PRE_RESTORE_DB_NAME: &str = "metadata_pre_restore.db";
+/// The database is rebuilt under this name and only swapped in at the end,
+/// so an interrupted restore leaves the old one in place.
+const RESTORING_DB_NAME: &str = "metadata_restoring.db";
+
+/// The outcome of [`CalibreClient::restore_library`].
+#[derive(Debug)]
+pub struct RestoreReport {
+    /// The books re-created from their folders.
+    pub restored: Vec<ImportReport>,
+    /// Book folders that could not be restored.
+    pub failed: Vec<RestoreFailure>,
+    /// Where the previous `metadata.db` was moved to, if there was one.
+    pub previous_database: Option<PathBuf>,
+}
+
+/// A book folder that [`CalibreClient::restore_library`] skipped.
+#[derive(Debug)]
+pub struct RestoreFailure {
+    /// Absolute path of the book folder.
+    pub path: PathBuf,
+    pub error: CalibreError,
+}
+
+impl CalibreClient {
+    /// Rebuilds `metadata.db` from the `metadata.opf` and book files in each
+    /// book folder under `library_root`, and returns a client for it.
+    ///
+    /// Books keep the ID and UUID recorded in their OPF, falling back to the
+    /// ID in the folder name. A book whose ID is missing or already taken is
+    /// given a new one. Files stay where they are. Folders whose OPF cannot
+    /// be read, or that hold book files but no OPF, are listed in the
+    /// report instead of failing the restore.
+    ///
+    /// The existing database, if any, is kept as `metadata_pre_restore.db`.
+    pub fn restore_library(
+        library_root: &str,
+    ) -> Result<(CalibreClient, RestoreReport), CalibreError> {
+        let root = Path::new(library_root);
+        let mut folders = Vec::new();
+        find_book_folders(root, &mut folders)?;
+
+        // A leftover from an earlier restore that was interrupted
+        let restoring_path = root.join(RESTORING_DB_NAME);
+        if restoring_path.exists() {
+            fs::remove_file(&restoring_path).map_err(CalibreError::io(&restoring_path))?;
+        }
+        let restoring_db = ValidDbPath {
+            library_path: library_root.to_string(),
+            database_path: restoring_path.to_string_lossy().into_owned(),
+        };
+        create_database(&restoring_db.database_path)?;
+        let mut client = CalibreClient::new(restoring_db)?;
+
+        let mut failed = Vec::new();
+        let mut books = Vec::new();
+        for folder in folders {
+            let opf_path = folder.join("metadata.opf");
+            if !opf_path.is_file() {
+                failed.push(RestoreFailure {
+                    error: CalibreError::NotFound(format!("{}", opf_path.display())),
+                    path: folder,
+                });
+                continue;
+            }
+            match OpfMetadata::from_path(&opf_path) {
+                Ok(metadata) => {
+                    let id = metadata.calibre_id.or_else(|| id_from_folder_name(&folder));
+                    books.push((id, folder, metadata));
+                }
+                Err(error) => failed.push(RestoreFailure {
+                    path: folder,
+                    error,
+                }),
+            }
+        }
+        // Books without an ID go last, so they cannot take one that
+        // another book still has to claim.
+        books.sort_by_key(|(id, folder, _)| (id.is_none(), *id, folder.clone()));
+
+        let mut restored = Vec::new();
+        let mut used_ids = HashSet::new();
+        let mut used_uuids = HashSet::new();
+        for (id, folder, metadata) in books {
+            let result = client.restore_book_folder(
+                root,
+                &folder,
+                metadata,
+                id.filter(|id| used_ids.insert(*id)),
+                &mut used_uuids,
+            );
+            match result {
+                Ok(report) => restored.push(report),
+                Err(error) => failed.push(RestoreFailure {
+                    path: folder,
+                    error,
+                }),
+            }
+        }
+        drop(client);
+
+        let db_path = root.join("metadata.db");
+        let previous_database = if db_path.exists() {
+            let pre_restore_path = root.join(PRE_RESTORE_DB_NAME);
+            fs::rename(&db_path, &pre_restore_path).map_err(CalibreError::io(&db_path))?;
+            Some(pre_restore_path)
+        } else {
+            None
+        };
+        fs::rename(&restoring_path, &db_path).map_err(CalibreError::io(&restoring_path))?;
+
+        let client = CalibreClient::new(ValidDbPath {
+            library_path: library_root.to_string(),
+            database_path: db_path.to_string_lossy().into_owned(),
+        })?;
+        let report = RestoreReport {
+            restored,
+            failed,
+            previous_database,
+        };
+        Ok((client, report))
+    }
+
+    pub(crate) fn restore_book_folder(
+        &mut self,
+        library_root: &Path,
+        folder: &Path,
+        metadata: OpfMetadata,
+        id: Option<i32>,
+        used_uuids: &mut HashSet<String>,
+    ) -> Result<ImportReport, CalibreError> {
+        let files = format_files(folder)?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect();
+        let existing_dir = folder
+            .strip_prefix(library_root)
+            .map_err(|_| {
+                CalibreError::InvalidInput(format!("{} is outside the library", folder.display()))
+            })?
+            .to_path_buf();
+        let options = AddBookOptions {
+            id,
+            uuid: metadata
+                .uuid
+                .clone()
+                .filter(|uuid| used_uuids.insert(uuid.clone())),
+            existing_dir: Some(existing_dir),
+            ..Default::default()
+        };
+
+        self.add_book_with_options(metadata.into_library_entry(files), options)
+    }
+}
+
+/// Collects every folder below `dir` that looks like a book folder: one with
+/// a `metadata.opf`, or with book files directly in it. Hidden folders, such
+/// as the trash, are skipped.
+pub(crate) fn find_book_folders(
+    dir: &Path,
+    folders: &mut Vec<PathBuf>,
+) -> Result<(), CalibreError> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(CalibreError::io(dir))?
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<PathBuf>>();
+    entries.sort();
+
+    for path in entries {
+        if path.join("metadata.opf").is_file() || !format_files(&path)?.is_empty() {
+            folders.push(path);
+        } else {
+            find_book_folders(&path, folders)?;
+        }
+    }
+    Ok(())
+}
+
+/// The book ID at the end of a folder name, as in Calibre's
+/// `Title (12)`, or a folder named only by its ID.
+pub(crate) fn id_from_folder_name(folder: &Path) -> Option<i32> {
+    let name = folder.file_name()?.to_str()?;
+    let id = match name.strip_suffix(')') {
+        Some(rest) => &rest[rest.rfind('(')? + 1..],
+        None => name,
+    };
+    id.parse().ok()
+}
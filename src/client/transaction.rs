@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::client::CalibreClient;
+use crate::CalibreError;
+
+impl CalibreClient {
+    /// Runs `f` inside one database transaction, committing if it returns
+    /// `Ok` and rolling back otherwise.
+    ///
+    /// If the commit itself fails, the value returned by `f` is dropped, so
+    /// guards such as [`PublishedDir`] undo their filesystem changes too.
+    pub(crate) fn transaction<T, F>(&mut self, f: F) -> Result<T, CalibreError>
+    where
+        F: FnOnce(&mut CalibreClient) -> Result<T, CalibreError>,
+    {
+        self.client_v2.begin_transaction()?;
+        match f(self) {
+            Ok(value) => {
+                self.client_v2.commit_transaction()?;
+                Ok(value)
+            }
+            Err(e) => {
+                // The original error is more useful than a failed rollback.
+                let _ = self.client_v2.rollback_transaction();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A temporary directory inside the library that files are written to
+/// before the database changes referring to them are committed.
+///
+/// It lives in the library root so that [`StagedDir::publish`] is a plain
+/// rename on the same filesystem. Unless published, the directory and
+/// everything in it is removed when the guard is dropped.
+pub(crate) struct StagedDir {
+    path: PathBuf,
+    published: bool,
+}
+
+impl StagedDir {
+    pub(crate) fn new(library_root: &Path) -> Result<StagedDir, CalibreError> {
+        let path = library_root.join(format!(".libcalibre-staging-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&path).map_err(CalibreError::io(&path))?;
+        Ok(StagedDir {
+            path,
+            published: false,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the staged directory to `dest`, which must not exist yet.
+    /// Parent directories are created as needed.
+    pub(crate) fn publish(mut self, dest: &Path) -> Result<PublishedDir, CalibreError> {
+        if dest.exists() {
+            return Err(CalibreError::Io {
+                path: dest.to_path_buf(),
+                source: std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    "book folder already exists",
+                ),
+            });
+        }
+        // An author folder made for this book goes again if it is undone
+        let created_parent = dest.parent().filter(|parent| !parent.exists());
+        if let Some(parent) = created_parent {
+            fs::create_dir_all(parent).map_err(CalibreError::io(parent))?;
+        }
+        fs::rename(&self.path, dest).map_err(CalibreError::io(dest))?;
+        self.published = true;
+
+        Ok(PublishedDir {
+            path: Some(dest.to_path_buf()),
+            created_parent: created_parent.map(Path::to_path_buf),
+        })
+    }
+}
+
+impl Drop for StagedDir {
+    fn drop(&mut self) {
+        if !self.published {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// A directory moved into the library by [`StagedDir::publish`]. It is
+/// removed again on drop unless [`PublishedDir::keep`] is called once the
+/// database transaction has committed.
+pub(crate) struct PublishedDir {
+    path: Option<PathBuf>,
+    created_parent: Option<PathBuf>,
+}
+
+impl PublishedDir {
+    pub(crate) fn keep(mut self) -> PathBuf {
+        self.path.take().unwrap_or_default()
+    }
+}
+
+impl Drop for PublishedDir {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = fs::remove_dir_all(path);
+            if let Some(parent) = &self.created_parent {
+                let _ = fs::remove_dir(parent);
+            }
+        }
+    }
+}
+
+/// A file or directory moved with a rename while the database changes that
+/// go with the move are made.
+///
+/// Dropping the guard moves it back, so returning it from a
+/// [`CalibreClient::transaction`] closure undoes the move if the commit
+/// fails. Call [`MovedPath::keep`] once the transaction has committed.
+pub(crate) struct MovedPath {
+    from: PathBuf,
+    to: PathBuf,
+    /// The temporary name a case-only rename went through.
+    via: Option<PathBuf>,
+    kept: bool,
+}
+
+impl MovedPath {
+    /// Renames `from` to `to`, creating the parent directories of `to`.
+    pub(crate) fn rename(from: &Path, to: &Path) -> Result<MovedPath, CalibreError> {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).map_err(CalibreError::io(parent))?;
+        }
+        fs::rename(from, to).map_err(CalibreError::io(from))?;
+        Ok(MovedPath {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            via: None,
+            kept: false,
+        })
+    }
+
+    /// Renames `from` to `to` when the two only differ in case, going
+    /// through a temporary name in the same folder as `from`. A
+    /// case-insensitive filesystem would otherwise see `to` as already
+    /// taken, or keep the old case.
+    pub(crate) fn rename_case(from: &Path, to: &Path) -> Result<MovedPath, CalibreError> {
+        let via = from.with_file_name(format!(".libcalibre-rename-{}", uuid::Uuid::new_v4()));
+        fs::rename(from, &via).map_err(CalibreError::io(from))?;
+        if let Err(e) = fs::rename(&via, to) {
+            let _ = fs::rename(&via, from);
+            return Err(CalibreError::io(to)(e));
+        }
+        Ok(MovedPath {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            via: Some(via),
+            kept: false,
+        })
+    }
+
+    pub(crate) fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for MovedPath {
+    fn drop(&mut self) {
+        if !self.kept {
+            match &self.via {
+                Some(via) => {
+                    let _ = fs::rename(&self.to, via).and_then(|_| fs::rename(via, &self.from));
+                }
+                None => {
+                    let _ = fs::rename(&self.to, &self.from);
+                }
+            }
+        }
+    }
+}
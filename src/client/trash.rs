@@ -0,0 +1,398 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::client::add_book::{AddBookOptions, ImportReport};
+use crate::client::transaction::{MovedPath, StagedDir};
+use crate::client::*;
+use crate::dtos::file::NewFileDto;
+use crate::entities::book_file::NewBookFile;
+use crate::mime_type::MIMETYPE;
+use crate::opf::OpfMetadata;
+use crate::BookFile;
+
+/// The folder in the library root that Calibre 7+ moves deleted books and
+/// formats into.
+pub const TRASH_DIR_NAME: &str = ".caltrash";
+/// How long Calibre keeps trash entries unless configured otherwise.
+pub const DEFAULT_TRASH_EXPIRY_DAYS: u32 = 14;
+
+const BOOK_TRASH_DIR: &str = "b";
+const FORMAT_TRASH_DIR: &str = "f";
+const FORMAT_METADATA_FILE: &str = "metadata.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrashKind {
+    /// A whole book folder, in `.caltrash/b/<id>`.
+    Book,
+    /// Formats removed from a book that still exists, in `.caltrash/f/<id>`.
+    Format,
+}
+
+#[derive(Debug)]
+pub struct TrashEntry {
+    pub kind: TrashKind,
+    pub book_id: i32,
+    pub title: String,
+    pub authors: Vec<String>,
+    /// Upper-case format names, e.g. `EPUB`.
+    pub formats: Vec<String>,
+    pub cover_path: Option<PathBuf>,
+    /// When the entry was moved to the trash. Like Calibre, this is the
+    /// modification time of the entry's folder.
+    pub trashed_at: DateTime<Utc>,
+    pub path: PathBuf,
+}
+
+/// The snapshot Calibre writes next to trashed formats, since the book's
+/// own `metadata.opf` stays in the library.
+#[derive(Serialize, Deserialize)]
+struct FormatTrashMetadata {
+    title: String,
+    authors: Vec<String>,
+}
+
+impl CalibreClient {
+    fn trash_dir(&self) -> PathBuf {
+        Path::new(&self.validated_library_path.library_path).join(TRASH_DIR_NAME)
+    }
+
+    /// Moves a book folder to `.caltrash/b/<id>`, replacing any older entry
+    /// for the same ID. The move is undone if the returned guard is dropped
+    /// without being kept.
+    pub(crate) fn move_book_to_trash(
+        &mut self,
+        book_id: i32,
+        book_dir: &Path,
+    ) -> Result<TrashedPath, CalibreError> {
+        let dest = self
+            .trash_dir()
+            .join(BOOK_TRASH_DIR)
+            .join(book_id.to_string());
+        let replaced = self.set_aside(&dest)?;
+
+        let moved = MovedPath::rename(book_dir, &dest)?;
+        touch(&dest)?;
+        Ok(TrashedPath { moved, replaced })
+    }
+
+    /// Moves one of a book's files to `.caltrash/f/<id>/<format>`, next to
+    /// a `metadata.json` naming the book, replacing any older copy of that
+    /// format. The move is undone if the returned guard is dropped without
+    /// being kept.
+    pub(crate) fn move_format_to_trash(
+        &mut self,
+        book: &BookWithAuthorsAndFiles,
+        format: &str,
+        file_path: &Path,
+    ) -> Result<TrashedPath, CalibreError> {
+        let entry_dir = self
+            .trash_dir()
+            .join(FORMAT_TRASH_DIR)
+            .join(book.book.id.to_string());
+        fs::create_dir_all(&entry_dir).map_err(CalibreError::io(&entry_dir))?;
+        let dest = entry_dir.join(format.to_lowercase());
+        let replaced = self.set_aside(&dest)?;
+
+        let metadata = FormatTrashMetadata {
+            title: book.book.title.clone(),
+            authors: book
+                .authors
+                .iter()
+                .map(|author| author.name.clone())
+                .collect(),
+        };
+        let metadata_path = entry_dir.join(FORMAT_METADATA_FILE);
+        let json = serde_json::to_vec(&metadata)
+            .map_err(std::io::Error::from)
+            .map_err(CalibreError::io(&metadata_path))?;
+        fs::write(&metadata_path, json).map_err(CalibreError::io(&metadata_path))?;
+
+        let moved = MovedPath::rename(file_path, &dest)?;
+        touch(&entry_dir)?;
+        Ok(TrashedPath { moved, replaced })
+    }
+
+    /// Moves an older trash entry at `dest` out of the way, into a staging
+    /// folder that deletes it once the new entry is kept.
+    fn set_aside(&self, dest: &Path) -> Result<Option<ReplacedEntry>, CalibreError> {
+        if !dest.exists() {
+            return Ok(None);
+        }
+        let staging = StagedDir::new(Path::new(&self.validated_library_path.library_path))?;
+        let moved = MovedPath::rename(dest, &staging.path().join("replaced"))?;
+        Ok(Some(ReplacedEntry { moved, staging }))
+    }
+
+    /// Lists every book and format entry in the trash, oldest first.
+    pub fn list_trash(&self) -> Result<Vec<TrashEntry>, CalibreError> {
+        let mut entries = Vec::new();
+        for (kind, dir_name) in [
+            (TrashKind::Book, BOOK_TRASH_DIR),
+            (TrashKind::Format, FORMAT_TRASH_DIR),
+        ] {
+            let dir = self.trash_dir().join(dir_name);
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir).map_err(CalibreError::io(&dir))? {
+                let path = entry.map_err(CalibreError::io(&dir))?.path();
+                // Anything that isn't `<id>/` was not put there by Calibre
+                let Some(book_id) = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.parse::<i32>().ok())
+                else {
+                    continue;
+                };
+                if path.is_dir() {
+                    entries.push(read_trash_entry(kind, book_id, path)?);
+                }
+            }
+        }
+
+        entries.sort_by_key(|entry| entry.trashed_at);
+        Ok(entries)
+    }
+
+    /// Re-creates a trashed book from its stored `metadata.opf`, with its
+    /// old ID and UUID, and removes it from the trash.
+    pub fn restore_book_from_trash(&mut self, book_id: i32) -> Result<ImportReport, CalibreError> {
+        let entry_dir = self
+            .trash_dir()
+            .join(BOOK_TRASH_DIR)
+            .join(book_id.to_string());
+        if !entry_dir.is_dir() {
+            return Err(CalibreError::NotFound(format!("book {book_id} in trash")));
+        }
+
+        let metadata = OpfMetadata::from_path(entry_dir.join("metadata.opf"))?;
+
+        let files = format_files(&entry_dir)?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect();
+        let cover_path = find_cover_file_name(&entry_dir).map(|name| entry_dir.join(name));
+        let options = AddBookOptions {
+            id: Some(book_id),
+            uuid: metadata.uuid.clone(),
+            cover_path,
+            ..Default::default()
+        };
+        let report = self.add_book_with_options(metadata.into_library_entry(files), options)?;
+
+        // The book is back in the library, so a leftover copy in the trash
+        // is not worth failing the restore over.
+        let _ = fs::remove_dir_all(&entry_dir);
+        Ok(report)
+    }
+
+    /// Moves a trashed format back into its book's folder and re-adds it to
+    /// the book. The book must still exist and not have that format.
+    pub fn restore_format_from_trash(
+        &mut self,
+        book_id: i32,
+        format: &str,
+    ) -> Result<BookFile, CalibreError> {
+        let format = format.to_lowercase();
+        let entry_dir = self
+            .trash_dir()
+            .join(FORMAT_TRASH_DIR)
+            .join(book_id.to_string());
+        let trashed_file = entry_dir.join(&format);
+        if !trashed_file.is_file() {
+            return Err(CalibreError::NotFound(format!(
+                "{} format of book {book_id} in trash",
+                format.to_uppercase()
+            )));
+        }
+
+        let book = self.find_book_with_authors(book_id)?;
+        if book
+            .files
+            .iter()
+            .any(|file| file.format.eq_ignore_ascii_case(&format))
+        {
+            return Err(CalibreError::Conflict(format!(
+                "book {book_id} already has a {} format",
+                format.to_uppercase()
+            )));
+        }
+        let name = self.format_file_name(&book);
+        let dest = Path::new(&self.validated_library_path.library_path)
+            .join(&book.book.path)
+            .join(format!("{name}.{format}"));
+        if dest.exists() {
+            return Err(CalibreError::Conflict(format!(
+                "{} already exists",
+                dest.display()
+            )));
+        }
+
+        let (book_file, moved) = self.transaction(|client| {
+            let moved = MovedPath::rename(&trashed_file, &dest)?;
+            let new_file = NewBookFile::try_from(NewFileDto {
+                path: dest.clone(),
+                book_id,
+                name,
+            })?;
+            let book_file = client.client_v2.book_files().create(new_file)?;
+            Ok((book_file, moved))
+        })?;
+        moved.keep();
+
+        if trashed_formats(&entry_dir)?.is_empty() {
+            let _ = fs::remove_dir_all(&entry_dir);
+        }
+        Ok(book_file)
+    }
+
+    /// Permanently deletes trash entries moved there more than
+    /// `max_age_days` ago, returning what was deleted.
+    pub fn expire_trash(&self, max_age_days: u32) -> Result<Vec<TrashEntry>, CalibreError> {
+        let max_age = Duration::from_secs(u64::from(max_age_days) * 24 * 60 * 60);
+        let cutoff: DateTime<Utc> = (SystemTime::now() - max_age).into();
+
+        let mut expired = Vec::new();
+        for entry in self.list_trash()? {
+            if entry.trashed_at < cutoff {
+                fs::remove_dir_all(&entry.path).map_err(CalibreError::io(&entry.path))?;
+                expired.push(entry);
+            }
+        }
+        Ok(expired)
+    }
+}
+
+fn read_trash_entry(
+    kind: TrashKind,
+    book_id: i32,
+    path: PathBuf,
+) -> Result<TrashEntry, CalibreError> {
+    let trashed_at = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(CalibreError::io(&path))?
+        .into();
+
+    // A damaged snapshot should not hide the entry, so it is listed with
+    // whatever could be read.
+    let (title, authors) = match kind {
+        TrashKind::Book => OpfMetadata::from_path(path.join("metadata.opf"))
+            .ok()
+            .map(|metadata| {
+                let authors = metadata.authors();
+                (metadata.title.unwrap_or_default(), authors)
+            })
+            .unwrap_or_default(),
+        TrashKind::Format => fs::read(path.join(FORMAT_METADATA_FILE))
+            .ok()
+            .and_then(|json| serde_json::from_slice::<FormatTrashMetadata>(&json).ok())
+            .map(|metadata| (metadata.title, metadata.authors))
+            .unwrap_or_default(),
+    };
+
+    let formats = match kind {
+        TrashKind::Book => format_files(&path)?
+            .into_iter()
+            .map(|(format, _)| format)
+            .collect(),
+        TrashKind::Format => trashed_formats(&path)?,
+    };
+    let cover_path = find_cover_file_name(&path).map(|name| path.join(name));
+
+    Ok(TrashEntry {
+        kind,
+        book_id,
+        title,
+        authors,
+        formats,
+        cover_path,
+        trashed_at,
+        path,
+    })
+}
+
+/// The book files in a folder, as upper-case format names and paths.
+pub(crate) fn format_files(dir: &Path) -> Result<Vec<(String, PathBuf)>, CalibreError> {
+    let mut files = fs::read_dir(dir)
+        .map_err(CalibreError::io(dir))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            let format = MIMETYPE::from_file_extension(path.extension()?.to_str()?)?;
+            Some((format.to_file_extension().to_uppercase(), path))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// The formats in a `.caltrash/f/<id>` folder. Calibre names each file
+/// after its format, without an extension.
+fn trashed_formats(dir: &Path) -> Result<Vec<String>, CalibreError> {
+    let mut formats = fs::read_dir(dir)
+        .map_err(CalibreError::io(dir))?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name != FORMAT_METADATA_FILE)
+        .map(|name| name.to_uppercase())
+        .collect::<Vec<String>>();
+    formats.sort();
+    Ok(formats)
+}
+
+/// A book folder or file moved into the trash, with the older entry it
+/// replaced.
+///
+/// Dropping the guard moves the new entry back out and the older one back
+/// in, so returning it from a [`CalibreClient::transaction`] closure
+/// undoes the move if the commit fails. The older entry is only deleted by
+/// [`TrashedPath::keep`], once the transaction has committed.
+pub(crate) struct TrashedPath {
+    // Dropped first, so the older entry has somewhere to go back to
+    moved: MovedPath,
+    replaced: Option<ReplacedEntry>,
+}
+
+struct ReplacedEntry {
+    moved: MovedPath,
+    // Dropped after the move back, so it only removes an empty folder
+    staging: StagedDir,
+}
+
+impl TrashedPath {
+    pub(crate) fn keep(self) {
+        self.moved.keep();
+        if let Some(replaced) = self.replaced {
+            replaced.moved.keep();
+            drop(replaced.staging);
+        }
+    }
+}
+
+/// Sets a folder's modification time to now. Renaming keeps the old one,
+/// which would make a freshly trashed book look old enough to expire.
+fn touch(path: &Path) -> Result<(), CalibreError> {
+    let mut options = fs::OpenOptions::new();
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // Windows only opens folders with backup semantics, and setting
+        // their times needs write access to their attributes
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x0100;
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+        options
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS);
+    }
+    #[cfg(not(windows))]
+    options.read(true);
+
+    options
+        .open(path)
+        .and_then(|dir| dir.set_modified(SystemTime::now()))
+        .map_err(CalibreError::io(path))
+}
@@ -1,3 +1,5 @@
+use chrono::Utc;
+
 use crate::dtos::library::UpdateLibraryEntryDto;
 
 use crate::client::*;
@@ -8,45 +10,77 @@ impl CalibreClient {
         &mut self,
         book_id: i32,
         updates: UpdateLibraryEntryDto,
-    ) -> Result<crate::BookWithAuthorsAndFiles, Box<dyn std::error::Error>> {
-        // Write new updates to book
-        let is_read = updates.book.is_read;
-        let book_update = UpdateBookData::try_from(updates.book).unwrap();
-        let _book = self.client_v2.books().update(book_id, book_update);
-
-        if is_read.is_some() {
-            let _set_book_result = self
-                .client_v2
-                .books()
-                .set_book_read_state(book_id, is_read.unwrap());
-        }
+    ) -> Result<crate::BookWithAuthorsAndFiles, CalibreError> {
+        // Validate requested authors before anything is written
+        let author_id_list = updates
+            .author_id_list
+            .map(|ids| {
+                ids.iter()
+                    .map(|author_id| {
+                        author_id.parse::<i32>().map_err(|_| {
+                            CalibreError::InvalidInput(format!("Invalid author ID: {author_id}"))
+                        })
+                    })
+                    .collect::<Result<Vec<i32>, CalibreError>>()
+            })
+            .transpose()?;
+
+        let moves_book = updates.book.title.is_some() || author_id_list.is_some();
+        let relocated = self.transaction(|client| {
+            // Write new updates to book
+            let is_read = updates.book.is_read;
+            let mut book_update = UpdateBookData::try_from(updates.book)?;
+            // Also keeps the changeset non-empty when only links are updated
+            book_update.last_modified.get_or_insert_with(Utc::now);
+            client.client_v2.books().update(book_id, book_update)?;
+
+            if let Some(is_read) = is_read {
+                client
+                    .client_v2
+                    .books()
+                    .set_book_read_state(book_id, is_read)?;
+            }
 
-        match updates.author_id_list {
-            Some(author_id_list) => {
+            if let Some(author_id_list) = author_id_list {
                 // Unlink existing authors
-                let existing_authors = self
+                let existing_authors = client
                     .client_v2
                     .books()
-                    .find_author_ids_by_book_id(book_id)
-                    .unwrap();
-                existing_authors.iter().for_each(|&author_id| {
-                    let _ = self
+                    .find_author_ids_by_book_id(book_id)?;
+                for author_id in existing_authors {
+                    client
                         .client_v2
                         .books()
-                        .unlink_author_from_book(book_id, author_id);
-                });
+                        .unlink_author_from_book(book_id, author_id)?;
+                }
 
                 // Link requested authors to book
-                author_id_list.iter().for_each(|author_id| {
-                    let author_id_int = author_id.parse::<i32>().unwrap();
-                    let _ = self
+                for author_id in author_id_list {
+                    client
                         .client_v2
                         .books()
-                        .link_author_to_book(book_id, author_id_int);
-                });
+                        .link_author_to_book(book_id, author_id)?;
+                }
+            }
+
+            if let Some(description) = updates.description {
+                client
+                    .client_v2
+                    .books()
+                    .set_description(book_id, description.as_deref())?;
+            }
+
+            client.mark_metadata_dirtied(&[book_id])?;
+            // The folder and file names follow the title and first author
+            match moves_book {
+                true => client.relocate_book(book_id),
+                false => Ok(None),
             }
-            None => {}
+        })?;
+        if let Some(relocated) = relocated {
+            relocated.keep();
         }
+        self.flush_dirtied_book(book_id)?;
 
         self.find_book_with_authors(book_id)
     }
@@ -5,6 +5,7 @@ use sanitise_file_name::sanitise;
 
 use std::fs;
 use std::io;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -12,7 +13,18 @@ use crate::entities::book::UpdateBookData;
 use crate::util::ValidDbPath;
 use crate::Author;
 
-pub fn combined_author_sort(author_list: &Vec<Author>) -> String {
+/// The names a book's cover image may have in its folder, in order of
+/// preference. libcalibre itself always writes `cover.jpg`, like Calibre.
+pub(crate) const COVER_FILE_NAMES: [&str; 3] = ["cover.jpg", "cover.jpeg", "cover.png"];
+
+/// The file name of the cover image in `book_dir`, if it has one.
+pub(crate) fn find_cover_file_name(book_dir: &Path) -> Option<&'static str> {
+    COVER_FILE_NAMES
+        .into_iter()
+        .find(|name| book_dir.join(name).is_file())
+}
+
+pub fn combined_author_sort(author_list: &[Author]) -> String {
     author_list
         .iter()
         .map(|author| author.sortable_name())
@@ -26,8 +38,8 @@ pub fn default_pubdate() -> DateTime<Utc> {
         .with_timezone(&Utc)
 }
 
-pub fn update_book_data_for_path(path: &PathBuf) -> UpdateBookData {
-    let path_as_string = path.to_str().unwrap().to_string();
+pub fn update_book_data_for_path(path: &Path) -> UpdateBookData {
+    let path_as_string = path.to_string_lossy().into_owned();
     UpdateBookData {
         author_sort: None,
         title: None,
@@ -41,7 +53,7 @@ pub fn update_book_data_for_path(path: &PathBuf) -> UpdateBookData {
     }
 }
 
-pub fn gen_book_file_name(book_title: &String, author_name: &String) -> String {
+pub fn gen_book_file_name(book_title: &str, author_name: &str) -> String {
     sanitise(&deunicode(
         &"{title} - {author}"
             .replace("{title}", book_title)
@@ -53,6 +65,114 @@ pub fn gen_book_folder_name(book_id: i32) -> String {
     book_id.to_string()
 }
 
+/// The longest Calibre lets each part of a book's path be. It uses a lower
+/// limit on Windows to stay clear of `MAX_PATH`.
+const PATH_LIMIT: usize = if cfg!(windows) { 40 } else { 100 };
+
+/// Device names that Windows will not accept as a folder name.
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The folder Calibre gives a book, `Author/Title (id)`, as it would be
+/// stored in `books.path`.
+pub fn calibre_book_folder_name(book_id: i32, book_title: &str, author_name: &str) -> String {
+    let id_suffix = format!(" ({book_id})");
+    let limit = PATH_LIMIT - id_suffix.len() / 2 - 2;
+
+    let mut author = truncate(&ascii_file_name(author_name), limit)
+        .trim_end_matches([' ', '.'])
+        .to_string();
+    if author.is_empty() {
+        author = "Unknown".to_string();
+    }
+    if WINDOWS_RESERVED_NAMES.contains(&author.to_uppercase().as_str()) {
+        author.push('w');
+    }
+    let title = calibre_path_title(book_title, limit);
+
+    format!("{author}/{title}{id_suffix}")
+}
+
+/// The name, without extension, Calibre gives a book's files:
+/// `Title - Author`.
+pub fn calibre_book_file_name(book_title: &str, author_name: &str) -> String {
+    // Calibre leaves room for its longest extension, `original_epub`
+    let extension_len = 14;
+    let limit = if cfg!(windows) {
+        PATH_LIMIT - extension_len / 2 - 2
+    } else {
+        (PATH_LIMIT - extension_len - 2) * 2
+    };
+
+    let author = truncate(&ascii_file_name(author_name), limit);
+    let title = calibre_path_title(book_title, limit);
+    let name = format!("{title} - {author}");
+    name.trim_end_matches('.').to_string()
+}
+
+fn calibre_path_title(book_title: &str, limit: usize) -> String {
+    let title = truncate(&ascii_file_name(book_title.trim_start()), limit)
+        .trim_end()
+        .to_string();
+    match title.is_empty() {
+        true => "Unknown".to_string(),
+        false => title,
+    }
+}
+
+fn truncate(name: &str, limit: usize) -> String {
+    name.chars().take(limit).collect()
+}
+
+/// Transliterates `name` to ASCII and replaces the characters that some
+/// filesystems do not allow, the way Calibre's `ascii_filename` does.
+pub fn ascii_file_name(name: &str) -> String {
+    let replaced = deunicode(name)
+        .chars()
+        .map(|c| match c {
+            '\\' | '|' | '?' | '*' | '<' | '"' | ':' | '>' | '+' | '/' => '_',
+            c if (c as u32) < 32 => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let (stem, extension) = split_extension(replaced.trim_matches(' '));
+
+    let stem = match !stem.is_empty() && stem.chars().all(|c| c == '.') {
+        true => "_".to_string(),
+        false => stem.replace("..", "_"),
+    };
+    let mut name = stem + extension;
+    // Windows drops trailing dots and spaces, and Unix hides dot files
+    if name.ends_with(['.', ' ']) {
+        name.pop();
+        name.push('_');
+    }
+    if name.starts_with('.') {
+        name.replace_range(..1, "_");
+    }
+    name
+}
+
+/// Splits off the extension like Python's `os.path.splitext`, which
+/// Calibre's sanitising relies on.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(dot) if !name[..dot].trim_start_matches('.').is_empty() => name.split_at(dot),
+        _ => (name, ""),
+    }
+}
+
+/// Whether `books.path` is a folder inside the library. Paths that are
+/// empty or leave the library root are never followed.
+pub(crate) fn is_library_relative(book_path: &str) -> bool {
+    !book_path.is_empty()
+        && Path::new(book_path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
 /// Create a new directory at a library-relative path.
 /// Convenience function to avoid having absolute paths for files everywhere.
 pub fn library_relative_mkdir(valid_db_path: &ValidDbPath, rel_path: PathBuf) -> io::Result<()> {
@@ -1,16 +1,22 @@
-use crate::api::{authors, book_files, books, languages, publishers, ratings, tags};
+use crate::api::lock_connection;
+use crate::api::{
+    authors, book_files, books, custom_columns, languages, publishers, ratings, series, tags,
+};
 use crate::persistence::establish_connection;
 use crate::util::ValidDbPath;
+use crate::CalibreError;
 use crate::ClientV2;
+use diesel::connection::{AnsiTransactionManager, SimpleConnection, TransactionManager};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
 impl ClientV2 {
-    pub fn new(db_path: ValidDbPath) -> Self {
-        let conn = establish_connection(&db_path.database_path).unwrap();
-        ClientV2 {
+    pub fn new(db_path: ValidDbPath) -> Result<Self, CalibreError> {
+        let conn = establish_connection(&db_path.database_path)?;
+        Ok(ClientV2 {
             connection: Arc::new(Mutex::new(conn)),
-        }
+        })
     }
 
     pub fn authors(&mut self) -> authors::AuthorsHandler {
@@ -25,6 +31,10 @@ impl ClientV2 {
         book_files::BookFilesHandler::new(Arc::clone(&self.connection))
     }
 
+    pub fn custom_columns(&mut self) -> custom_columns::CustomColumnsHandler {
+        custom_columns::CustomColumnsHandler::new(Arc::clone(&self.connection))
+    }
+
     pub fn publishers(&mut self) -> publishers::PublishersHandler {
         publishers::PublishersHandler::new(Arc::clone(&self.connection))
     }
@@ -33,6 +43,10 @@ impl ClientV2 {
         languages::LanguagesHandler::new(Arc::clone(&self.connection))
     }
 
+    pub fn series(&mut self) -> series::SeriesHandler {
+        series::SeriesHandler::new(Arc::clone(&self.connection))
+    }
+
     pub fn tags(&mut self) -> tags::TagsHandler {
         tags::TagsHandler::new(Arc::clone(&self.connection))
     }
@@ -40,4 +54,35 @@ impl ClientV2 {
     pub fn ratings(&mut self) -> ratings::RatingsHandler {
         ratings::RatingsHandler::new(Arc::clone(&self.connection))
     }
+
+    /// Makes statements wait up to `timeout` for another connection's
+    /// write lock instead of failing straight away.
+    pub(crate) fn set_busy_timeout(&mut self, timeout: Duration) -> Result<(), CalibreError> {
+        let mut connection = lock_connection(&self.connection);
+        connection.batch_execute(&format!("PRAGMA busy_timeout = {}", timeout.as_millis()))?;
+        Ok(())
+    }
+
+    /// Opens a transaction on the shared connection. Every handler call
+    /// made until the matching commit or rollback is part of it.
+    ///
+    /// `BEGIN IMMEDIATE` takes the write lock up front, so a concurrent
+    /// writer makes this fail straight away instead of at the first write.
+    pub(crate) fn begin_transaction(&mut self) -> Result<(), CalibreError> {
+        let mut connection = lock_connection(&self.connection);
+        AnsiTransactionManager::begin_transaction_sql(&mut *connection, "BEGIN IMMEDIATE")?;
+        Ok(())
+    }
+
+    pub(crate) fn commit_transaction(&mut self) -> Result<(), CalibreError> {
+        let mut connection = lock_connection(&self.connection);
+        AnsiTransactionManager::commit_transaction(&mut *connection)?;
+        Ok(())
+    }
+
+    pub(crate) fn rollback_transaction(&mut self) -> Result<(), CalibreError> {
+        let mut connection = lock_connection(&self.connection);
+        AnsiTransactionManager::rollback_transaction(&mut *connection)?;
+        Ok(())
+    }
 }
@@ -1,44 +1,24 @@
-use std::{error::Error, ffi::OsStr, path::Path};
-
-use mobi::Mobi;
+use std::{ffi::OsStr, path::Path};
 
+use crate::metadata::{cbz, mobi, pdf};
 use crate::mime_type::MIMETYPE;
+use crate::CalibreError;
 
-pub fn cover_image_data_from_path(path: &Path) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+pub fn cover_image_data_from_path(path: &Path) -> Result<Option<Vec<u8>>, CalibreError> {
     let extension = path
         .extension()
         .and_then(OsStr::to_str)
-        .ok_or("Failed to read file extension")?;
+        .ok_or_else(|| CalibreError::UnsupportedFormat(path.display().to_string()))?;
 
     match MIMETYPE::from_file_extension(extension) {
         Some(MIMETYPE::EPUB) => {
-            let mut doc = epub::doc::EpubDoc::new(path)?;
+            let mut doc =
+                epub::doc::EpubDoc::new(path).map_err(CalibreError::metadata_parse(path))?;
             Ok(doc.get_cover().map(|(data, _id)| data))
         }
-        Some(MIMETYPE::MOBI) => {
-            let m = Mobi::from_path(&path);
-            match m {
-                Err(_) => Err("Failed to read mobi file")?,
-                Ok(mobi) => {
-                    let cover_data = mobi.image_records().last().map(|img| img.content.to_vec());
-                    Ok(cover_data)
-                }
-            }
-        }
-        Some(MIMETYPE::CBZ) => {
-            let parent = path.parent().unwrap_or(Path::new(""));
-            let cover_data = ["cover.jpg", "cover.jpeg", "cover.png"]
-                .iter()
-                .find_map(|name| {
-                    let potential_cover = parent.join(name);
-                    potential_cover
-                        .exists()
-                        .then(|| std::fs::read(&potential_cover).ok())
-                        .flatten()
-                });
-
-            Ok(cover_data)
-        }
+        Some(MIMETYPE::MOBI | MIMETYPE::KF7 | MIMETYPE::KF8) => mobi::read_cover(path),
+        Some(MIMETYPE::CBZ) => cbz::read_cover(path),
+        Some(MIMETYPE::PDF) => pdf::read_cover(path),
         _ => Ok(None),
     }
 }
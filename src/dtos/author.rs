@@ -1,4 +1,5 @@
 use crate::entities::author::{NewAuthor, UpdateAuthorData};
+use crate::CalibreError;
 
 #[derive(Clone)]
 pub struct NewAuthorDto {
@@ -14,7 +15,7 @@ pub struct UpdateAuthorDto {
 }
 
 impl TryFrom<NewAuthorDto> for NewAuthor {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: NewAuthorDto) -> Result<Self, Self::Error> {
         Ok(Self {
@@ -26,7 +27,7 @@ impl TryFrom<NewAuthorDto> for NewAuthor {
 }
 
 impl TryFrom<UpdateAuthorDto> for UpdateAuthorData {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: UpdateAuthorDto) -> Result<Self, Self::Error> {
         Ok(Self {
@@ -1,6 +1,7 @@
 use chrono::{DateTime, Utc};
 
 use crate::entities::book::{NewBook, UpdateBookData};
+use crate::CalibreError;
 
 #[derive(Clone)]
 pub struct NewBookDto {
@@ -12,6 +13,7 @@ pub struct NewBookDto {
     pub has_cover: Option<bool>,
 }
 
+#[derive(Default)]
 pub struct UpdateBookDto {
     pub author_sort: Option<String>,
     pub title: Option<String>,
@@ -25,28 +27,12 @@ pub struct UpdateBookDto {
     pub last_modified: Option<DateTime<Utc>>,
 }
 
-impl UpdateBookDto {
-    pub fn default() -> UpdateBookDto {
-        Self {
-            author_sort: None,
-            title: None,
-            timestamp: None,
-            pubdate: None,
-            series_index: None,
-            path: None,
-            flags: None,
-            has_cover: None,
-            is_read: None,
-            last_modified: None,
-        }
-    }
-}
-
 impl TryFrom<NewBookDto> for NewBook {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: NewBookDto) -> Result<Self, Self::Error> {
         Ok(Self {
+            id: None,
             title: dto.title,
             timestamp: dto.timestamp,
             pubdate: dto.pubdate,
@@ -58,7 +44,7 @@ impl TryFrom<NewBookDto> for NewBook {
 }
 
 impl TryFrom<UpdateBookDto> for UpdateBookData {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: UpdateBookDto) -> Result<Self, Self::Error> {
         Ok(Self {
@@ -0,0 +1,76 @@
+use crate::entities::custom_column::{CustomColumnDatatype, NewCustomColumn};
+use crate::CalibreError;
+
+#[derive(Clone)]
+pub struct NewCustomColumnDto {
+    /// The lookup name. Like Calibre, only lower-case letters, digits and
+    /// underscores are allowed, starting with a letter.
+    pub label: String,
+    pub name: String,
+    pub datatype: CustomColumnDatatype,
+    /// Only text columns can hold several values.
+    pub is_multiple: bool,
+    /// Display settings. Enumeration columns need `enum_values` and
+    /// composite columns need `composite_template`.
+    pub display: serde_json::Value,
+}
+
+impl TryFrom<NewCustomColumnDto> for NewCustomColumn {
+    type Error = CalibreError;
+
+    fn try_from(dto: NewCustomColumnDto) -> Result<Self, Self::Error> {
+        let valid_label = dto
+            .label
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_lowercase())
+            && dto
+                .label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid_label {
+            return Err(CalibreError::InvalidInput(format!(
+                "invalid custom column label: {}",
+                dto.label
+            )));
+        }
+        if dto.is_multiple && dto.datatype != CustomColumnDatatype::Text {
+            return Err(CalibreError::InvalidInput(format!(
+                "{} columns cannot hold multiple values",
+                dto.datatype
+            )));
+        }
+
+        let display = match dto.display {
+            serde_json::Value::Null => serde_json::json!({}),
+            display @ serde_json::Value::Object(_) => display,
+            _ => {
+                return Err(CalibreError::InvalidInput(
+                    "custom column display settings must be an object".to_string(),
+                ))
+            }
+        };
+        let required = match dto.datatype {
+            CustomColumnDatatype::Enumeration => Some("enum_values"),
+            CustomColumnDatatype::Composite => Some("composite_template"),
+            _ => None,
+        };
+        if let Some(key) = required.filter(|key| display.get(key).is_none()) {
+            return Err(CalibreError::InvalidInput(format!(
+                "{} columns need `{key}` in their display settings",
+                dto.datatype
+            )));
+        }
+
+        Ok(Self {
+            label: dto.label,
+            name: dto.name,
+            datatype: dto.datatype.as_str().to_string(),
+            mark_for_delete: false,
+            editable: true,
+            display: display.to_string(),
+            is_multiple: dto.is_multiple,
+            normalized: dto.datatype.is_normalized(),
+        })
+    }
+}
@@ -1,8 +1,9 @@
-use std::{fs::File, path::PathBuf};
+use std::path::PathBuf;
 
 use crate::{
     entities::book_file::{NewBookFile, UpdateBookFile},
     mime_type::MIMETYPE,
+    CalibreError,
 };
 
 pub struct NewFileDto {
@@ -19,18 +20,28 @@ pub struct UpdateFileDto {
 }
 
 impl TryFrom<NewFileDto> for NewBookFile {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: NewFileDto) -> Result<Self, Self::Error> {
         match dto.path.exists() {
             true => {
-                let file = File::open(&dto.path).unwrap();
-                let size_bytes = file.metadata().unwrap().len() as i32;
+                let len = std::fs::metadata(&dto.path)
+                    .map_err(CalibreError::io(&dto.path))?
+                    .len();
+                // A wrapped size would be reported by `check_library` as
+                // a mismatch it cannot fix
+                let size_bytes = i32::try_from(len).map_err(|_| {
+                    CalibreError::InvalidInput(format!(
+                        "{} is too large for data.uncompressed_size",
+                        dto.path.display()
+                    ))
+                })?;
                 let ext = match dto.path.extension() {
                     Some(ext) => ext.to_str().unwrap_or(""),
                     None => "",
                 };
-                let format = MIMETYPE::from_file_extension(ext).ok_or(())?;
+                let format = MIMETYPE::from_file_extension(ext)
+                    .ok_or_else(|| CalibreError::UnsupportedFormat(ext.to_string()))?;
 
                 Ok(Self {
                     book: dto.book_id,
@@ -39,13 +50,16 @@ impl TryFrom<NewFileDto> for NewBookFile {
                     name: dto.name,
                 })
             }
-            false => Err(()),
+            false => Err(CalibreError::Io {
+                path: dto.path,
+                source: std::io::ErrorKind::NotFound.into(),
+            }),
         }
     }
 }
 
 impl TryFrom<UpdateFileDto> for UpdateBookFile {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: UpdateFileDto) -> Result<Self, Self::Error> {
         Ok(Self {
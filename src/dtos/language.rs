@@ -1,4 +1,5 @@
 use crate::entities::language::NewLanguage;
+use crate::CalibreError;
 
 #[derive(Clone)]
 pub struct NewLanguageDto {
@@ -6,7 +7,7 @@ pub struct NewLanguageDto {
 }
 
 impl TryFrom<NewLanguageDto> for NewLanguage {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: NewLanguageDto) -> Result<Self, Self::Error> {
         Ok(Self {
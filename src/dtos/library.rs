@@ -6,6 +6,7 @@ use super::{
     language::NewLanguageDto,
     publisher::NewPublisherDto,
     rating::NewRatingDto,
+    series::NewSeriesDto,
     tag::NewTagDto,
 };
 
@@ -19,6 +20,8 @@ pub struct NewLibraryFileDto {
 pub struct UpdateLibraryEntryDto {
     pub book: UpdateBookDto,
     pub author_id_list: Option<Vec<String>>,
+    /// `Some(None)` removes the description, `None` leaves it unchanged.
+    pub description: Option<Option<String>>,
 }
 
 pub struct NewLibraryEntryDto {
@@ -29,6 +32,10 @@ pub struct NewLibraryEntryDto {
     pub language: Option<NewLanguageDto>,
     pub tags: Vec<NewTagDto>,
     pub rating: Option<NewRatingDto>,
+    /// The series the book is in, at `book.series_index`.
+    pub series: Option<NewSeriesDto>,
+    /// An HTML description, sanitised before it is stored.
+    pub description: Option<String>,
     pub files: Option<Vec<NewLibraryFileDto>>,
 }
 
@@ -40,4 +47,8 @@ pub struct ReplaceLibraryEntryDto {
     pub language: Option<NewLanguageDto>,
     pub tags: Vec<NewTagDto>,
     pub rating: Option<NewRatingDto>,
+    /// The series the book is in, at `book.series_index`.
+    pub series: Option<NewSeriesDto>,
+    /// An HTML description, sanitised before it is stored.
+    pub description: Option<String>,
 }
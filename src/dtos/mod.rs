@@ -1,8 +1,10 @@
 pub mod author;
 pub mod book;
+pub mod custom_column;
 pub mod file;
 pub mod language;
 pub mod library;
 pub mod publisher;
 pub mod rating;
+pub mod series;
 pub mod tag;
@@ -1,4 +1,5 @@
 use crate::entities::publisher::NewPublisher;
+use crate::CalibreError;
 
 #[derive(Clone)]
 pub struct NewPublisherDto {
@@ -7,7 +8,7 @@ pub struct NewPublisherDto {
 }
 
 impl TryFrom<NewPublisherDto> for NewPublisher {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: NewPublisherDto) -> Result<Self, Self::Error> {
         Ok(Self {
@@ -1,4 +1,5 @@
 use crate::entities::rating::NewRating;
+use crate::CalibreError;
 
 #[derive(Clone)]
 pub struct NewRatingDto {
@@ -6,7 +7,7 @@ pub struct NewRatingDto {
 }
 
 impl TryFrom<NewRatingDto> for NewRating {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: NewRatingDto) -> Result<Self, Self::Error> {
         Ok(Self { rating: dto.rating })
@@ -0,0 +1,15 @@
+use crate::entities::series::NewSeries;
+use crate::CalibreError;
+
+#[derive(Clone)]
+pub struct NewSeriesDto {
+    pub name: String,
+}
+
+impl TryFrom<NewSeriesDto> for NewSeries {
+    type Error = CalibreError;
+
+    fn try_from(dto: NewSeriesDto) -> Result<Self, Self::Error> {
+        Ok(Self { name: dto.name })
+    }
+}
@@ -1,4 +1,5 @@
 use crate::entities::tag::NewTag;
+use crate::CalibreError;
 
 #[derive(Clone)]
 pub struct NewTagDto {
@@ -6,7 +7,7 @@ pub struct NewTagDto {
 }
 
 impl TryFrom<NewTagDto> for NewTag {
-    type Error = ();
+    type Error = CalibreError;
 
     fn try_from(dto: NewTagDto) -> Result<Self, Self::Error> {
         Ok(Self { name: dto.name })
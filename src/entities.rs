@@ -2,7 +2,9 @@ pub mod author;
 pub mod book;
 pub mod book_aggregate;
 pub mod book_file;
+pub mod custom_column;
 pub mod language;
 pub mod publisher;
 pub mod rating;
+pub mod series;
 pub mod tag;
@@ -27,6 +27,8 @@ pub struct Book {
 #[diesel(table_name = books)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct NewBook {
+    /// Left to SQLite unless a book is re-created with its old ID.
+    pub id: Option<i32>,
     pub title: String,
     pub timestamp: Option<DateTime<Utc>>,
     pub pubdate: Option<DateTime<Utc>>,
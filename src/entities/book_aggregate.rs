@@ -1,10 +1,12 @@
-use crate::entities::{author::Author, book::Book, book_file::BookFile};
+use crate::entities::{author::Author, book::Book, book_file::BookFile, series::Series};
 
 #[derive(Debug)]
 pub struct BookWithAuthorsAndFiles {
     pub book: Book,
     pub authors: Vec<Author>,
     pub files: Vec<BookFile>,
+    /// The series the book is in. Its position is `book.series_index`.
+    pub series: Option<Series>,
     /// A partially HTML-formatted description of the book. User-editable.
     pub book_description_html: Option<String>,
     pub is_read: bool,
@@ -15,6 +17,7 @@ impl BookWithAuthorsAndFiles {
         book: Book,
         authors: Vec<Author>,
         files: Vec<BookFile>,
+        series: Option<Series>,
         description: Option<String>,
         is_read: bool,
     ) -> Self {
@@ -22,6 +25,7 @@ impl BookWithAuthorsAndFiles {
             book,
             authors,
             files,
+            series,
             book_description_html: description,
             is_read,
         }
@@ -22,7 +22,7 @@ impl BookFile {
         let ext = mimetype.to_file_extension();
 
         if ext.is_empty() {
-            format!("{}", self.name)
+            self.name.clone()
         } else {
             format!("{}.{}", self.name, ext)
         }
@@ -0,0 +1,174 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+
+use crate::schema::custom_columns;
+use crate::CalibreError;
+
+#[derive(Clone, Debug, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = custom_columns)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CustomColumn {
+    pub id: i32,
+    /// The lookup name, used as `#label` in Calibre's search and templates.
+    pub label: String,
+    /// The heading shown in Calibre's book list.
+    pub name: String,
+    pub datatype: String,
+    /// Set by Calibre when a column is deleted; it drops the tables on its
+    /// next start.
+    pub mark_for_delete: bool,
+    pub editable: bool,
+    /// Display settings as JSON, e.g. `enum_values` or `composite_template`.
+    pub display: String,
+    pub is_multiple: bool,
+    /// Whether values live in their own table, linked to books through
+    /// `books_custom_column_N_link`, rather than in `custom_column_N`.
+    pub normalized: bool,
+}
+
+impl CustomColumn {
+    pub fn kind(&self) -> Result<CustomColumnDatatype, CalibreError> {
+        self.datatype.parse()
+    }
+
+    /// The parsed `display` settings, or an empty object if they are not
+    /// valid JSON.
+    pub fn display_settings(&self) -> serde_json::Value {
+        serde_json::from_str(&self.display).unwrap_or_else(|_| serde_json::json!({}))
+    }
+
+    pub(crate) fn table_name(&self) -> String {
+        format!("custom_column_{}", self.id)
+    }
+
+    pub(crate) fn link_table_name(&self) -> String {
+        format!("books_custom_column_{}_link", self.id)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = custom_columns)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewCustomColumn {
+    pub label: String,
+    pub name: String,
+    pub datatype: String,
+    pub mark_for_delete: bool,
+    pub editable: bool,
+    pub display: String,
+    pub is_multiple: bool,
+    pub normalized: bool,
+}
+
+/// The kinds of custom column Calibre can create.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CustomColumnDatatype {
+    /// Short text, or a list of them ("tag-like") when `is_multiple`.
+    Text,
+    /// Long text, usually HTML.
+    Comments,
+    Series,
+    /// Text restricted to a fixed list of values.
+    Enumeration,
+    Datetime,
+    Int,
+    Float,
+    Bool,
+    /// Zero to ten, i.e. half stars.
+    Rating,
+    /// Computed by Calibre from a template, so it has no stored values.
+    Composite,
+}
+
+impl CustomColumnDatatype {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CustomColumnDatatype::Text => "text",
+            CustomColumnDatatype::Comments => "comments",
+            CustomColumnDatatype::Series => "series",
+            CustomColumnDatatype::Enumeration => "enumeration",
+            CustomColumnDatatype::Datetime => "datetime",
+            CustomColumnDatatype::Int => "int",
+            CustomColumnDatatype::Float => "float",
+            CustomColumnDatatype::Bool => "bool",
+            CustomColumnDatatype::Rating => "rating",
+            CustomColumnDatatype::Composite => "composite",
+        }
+    }
+
+    /// Whether Calibre stores this kind of column in the normalized layout.
+    pub fn is_normalized(&self) -> bool {
+        matches!(
+            self,
+            CustomColumnDatatype::Text
+                | CustomColumnDatatype::Series
+                | CustomColumnDatatype::Enumeration
+                | CustomColumnDatatype::Rating
+        )
+    }
+
+    /// The SQL type Calibre gives the `value` column.
+    pub(crate) fn sql_type(&self) -> &'static str {
+        match self {
+            CustomColumnDatatype::Rating | CustomColumnDatatype::Int => "INT",
+            CustomColumnDatatype::Text
+            | CustomColumnDatatype::Comments
+            | CustomColumnDatatype::Series
+            | CustomColumnDatatype::Enumeration
+            | CustomColumnDatatype::Composite => "TEXT",
+            CustomColumnDatatype::Float => "REAL",
+            CustomColumnDatatype::Datetime => "timestamp",
+            CustomColumnDatatype::Bool => "BOOL",
+        }
+    }
+}
+
+impl FromStr for CustomColumnDatatype {
+    type Err = CalibreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(CustomColumnDatatype::Text),
+            "comments" => Ok(CustomColumnDatatype::Comments),
+            "series" => Ok(CustomColumnDatatype::Series),
+            "enumeration" => Ok(CustomColumnDatatype::Enumeration),
+            "datetime" => Ok(CustomColumnDatatype::Datetime),
+            "int" => Ok(CustomColumnDatatype::Int),
+            "float" => Ok(CustomColumnDatatype::Float),
+            "bool" => Ok(CustomColumnDatatype::Bool),
+            "rating" => Ok(CustomColumnDatatype::Rating),
+            "composite" => Ok(CustomColumnDatatype::Composite),
+            _ => Err(CalibreError::InvalidInput(format!(
+                "unknown custom column datatype: {s}"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for CustomColumnDatatype {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A book's value in a custom column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CustomColumnValue {
+    /// For text, comments and enumeration columns.
+    Text(String),
+    /// For text columns with `is_multiple` set.
+    TextList(Vec<String>),
+    Series {
+        name: String,
+        index: f64,
+    },
+    Datetime(DateTime<Utc>),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// Zero to ten.
+    Rating(i32),
+}
@@ -0,0 +1,20 @@
+use diesel::prelude::*;
+
+use crate::schema::series;
+
+#[derive(Clone, Debug, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = series)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Series {
+    pub id: i32,
+    pub name: String,
+    /// Filled in from `name` by Calibre's `series_insert_trg`.
+    pub sort: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = series)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewSeries {
+    pub name: String,
+}
@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use diesel::result::DatabaseErrorKind;
+
+#[derive(Debug)]
+pub enum CalibreError {
+    /// The requested row does not exist, e.g. "book 42".
+    NotFound(String),
+    /// A write was rejected by a UNIQUE, NOT NULL, CHECK or foreign key
+    /// constraint (including Calibre's `fkc_*` triggers).
+    ConstraintViolation(diesel::result::Error),
+    /// The data in the library contradicts itself, e.g. two books share an
+    /// identifier that should be unique.
+    Conflict(String),
+    /// Any other failure reported by the database.
+    Database(diesel::result::Error),
+    /// The database could not be opened or created.
+    Connection(diesel::ConnectionError),
+    /// A filesystem operation on `path` failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The file format is not one libcalibre can handle.
+    UnsupportedFormat(String),
+    /// Metadata read from `path` could not be parsed.
+    MetadataParse {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The caller passed a value that cannot be used, e.g. a non-numeric ID.
+    InvalidInput(String),
+}
+
+impl CalibreError {
+    /// Returns a closure wrapping an [`io::Error`] with the path it occurred
+    /// on, for use with `map_err`.
+    pub(crate) fn io(path: impl AsRef<Path>) -> impl FnOnce(io::Error) -> CalibreError {
+        let path = path.as_ref().to_path_buf();
+        move |source| CalibreError::Io { path, source }
+    }
+
+    /// Returns a closure wrapping a parser error with the path of the file
+    /// being parsed, for use with `map_err`.
+    pub(crate) fn metadata_parse<E>(path: impl AsRef<Path>) -> impl FnOnce(E) -> CalibreError
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let path = path.as_ref().to_path_buf();
+        move |source| CalibreError::MetadataParse {
+            path,
+            source: source.into(),
+        }
+    }
+}
+
+impl fmt::Display for CalibreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalibreError::NotFound(what) => write!(f, "Not found: {what}"),
+            CalibreError::ConstraintViolation(e) => write!(f, "Constraint violation: {e}"),
+            CalibreError::Conflict(msg) => write!(f, "Conflicting library data: {msg}"),
+            CalibreError::Database(e) => write!(f, "Database error: {e}"),
+            CalibreError::Connection(e) => write!(f, "Could not open database: {e}"),
+            CalibreError::Io { path, source } => {
+                write!(f, "I/O error on {}: {source}", path.display())
+            }
+            CalibreError::UnsupportedFormat(format) => write!(f, "Unsupported format: {format}"),
+            CalibreError::MetadataParse { path, source } => {
+                write!(
+                    f,
+                    "Could not parse metadata in {}: {source}",
+                    path.display()
+                )
+            }
+            CalibreError::InvalidInput(msg) => write!(f, "Invalid input: {msg}"),
+        }
+    }
+}
+
+impl Error for CalibreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CalibreError::ConstraintViolation(e) | CalibreError::Database(e) => Some(e),
+            CalibreError::Connection(e) => Some(e),
+            CalibreError::Io { source, .. } => Some(source),
+            CalibreError::MetadataParse { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<diesel::result::Error> for CalibreError {
+    fn from(e: diesel::result::Error) -> Self {
+        use diesel::result::Error as DieselError;
+
+        match e {
+            DieselError::NotFound => CalibreError::NotFound("record".to_string()),
+            DieselError::DatabaseError(ref kind, ref info) => match kind {
+                DatabaseErrorKind::UniqueViolation
+                | DatabaseErrorKind::ForeignKeyViolation
+                | DatabaseErrorKind::NotNullViolation
+                | DatabaseErrorKind::CheckViolation => CalibreError::ConstraintViolation(e),
+                // Calibre enforces foreign keys with triggers that RAISE(ABORT)
+                _ if info.message().starts_with("Foreign key violation") => {
+                    CalibreError::ConstraintViolation(e)
+                }
+                _ => CalibreError::Database(e),
+            },
+            _ => CalibreError::Database(e),
+        }
+    }
+}
+
+impl From<diesel::ConnectionError> for CalibreError {
+    fn from(e: diesel::ConnectionError) -> Self {
+        CalibreError::Connection(e)
+    }
+}
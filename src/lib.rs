@@ -4,8 +4,11 @@ pub mod client_v2;
 mod cover_image;
 pub mod dtos;
 mod entities;
+mod error;
+pub mod metadata;
 pub mod mime_type;
 mod models;
+pub mod opf;
 pub mod persistence;
 mod schema;
 pub mod util;
@@ -13,10 +16,13 @@ pub mod util;
 use diesel::SqliteConnection;
 use std::sync::{Arc, Mutex};
 
+pub use error::CalibreError;
+
 pub use entities::{
     author::Author, book::Book, book::UpsertBookIdentifier,
-    book_aggregate::BookWithAuthorsAndFiles, book_file::BookFile, language::Language,
-    publisher::Publisher, rating::Rating, tag::Tag,
+    book_aggregate::BookWithAuthorsAndFiles, book_file::BookFile, custom_column::CustomColumn,
+    custom_column::CustomColumnDatatype, custom_column::CustomColumnValue, language::Language,
+    publisher::Publisher, rating::Rating, series::Series, tag::Tag,
 };
 
 pub struct ClientV2 {
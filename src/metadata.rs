@@ -0,0 +1,134 @@
+//! Reading the metadata embedded in book files, so an import can start
+//! from what the file already says about itself, and writing a library's
+//! metadata back into copies of them on export.
+
+pub mod cbz;
+pub mod epub;
+pub mod mobi;
+pub mod pdf;
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::dtos::library::NewLibraryEntryDto;
+use crate::mime_type::MIMETYPE;
+use crate::opf::OpfMetadata;
+use crate::CalibreError;
+
+/// Reads the metadata embedded in the book file at `path` and turns it into
+/// an entry for [`CalibreClient::add_book`](crate::client::CalibreClient::add_book),
+/// with `path` as its only file. Any field can be changed before the entry
+/// is added.
+///
+/// Formats without embedded metadata, and files without a title, are
+/// titled after the file name.
+pub fn library_entry_from_path(path: &Path) -> Result<NewLibraryEntryDto, CalibreError> {
+    let extension = path.extension().and_then(OsStr::to_str).unwrap_or("");
+    let format = MIMETYPE::from_file_extension(extension)
+        .ok_or_else(|| CalibreError::UnsupportedFormat(extension.to_string()))?;
+
+    let mut metadata = match format {
+        MIMETYPE::CBZ => cbz::read_metadata(path)?,
+        MIMETYPE::EPUB => epub::read_metadata(path)?,
+        MIMETYPE::MOBI | MIMETYPE::KF7 | MIMETYPE::KF8 => mobi::read_metadata(path)?,
+        MIMETYPE::PDF => pdf::read_metadata(path)?,
+        _ => OpfMetadata::default(),
+    };
+    if metadata.title.is_none() {
+        metadata.title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+    }
+
+    Ok(metadata.into_library_entry(vec![path.to_path_buf()]))
+}
+
+/// Creates `destination` and fills it with `write`, removing it again if
+/// that fails so no half-written file is left behind.
+pub(crate) fn write_new_file(
+    destination: &Path,
+    write: impl FnOnce(&mut File) -> Result<(), CalibreError>,
+) -> Result<(), CalibreError> {
+    let mut file = File::create(destination).map_err(CalibreError::io(destination))?;
+    let written = write(&mut file).and_then(|_| {
+        file.flush()
+            .and_then(|_| file.sync_all())
+            .map_err(CalibreError::io(destination))
+    });
+    if written.is_err() {
+        drop(file);
+        let _ = fs::remove_file(destination);
+    }
+    written
+}
+
+/// Copies the zip archive at `source` to `destination` with the contents
+/// of the entries named in `entries` replaced, and those it did not have
+/// appended. Other entries are copied as they are, without recompressing
+/// them.
+pub(crate) fn rewrite_zip(
+    source: &Path,
+    destination: &Path,
+    mut entries: HashMap<String, Vec<u8>>,
+) -> Result<(), CalibreError> {
+    let file = File::open(source).map_err(CalibreError::io(source))?;
+    let mut archive = ZipArchive::new(file).map_err(CalibreError::metadata_parse(source))?;
+
+    write_new_file(destination, |file| {
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        let write_error = |e: ZipError| CalibreError::io(destination)(e.into());
+        for index in 0..archive.len() {
+            let entry = archive
+                .by_index_raw(index)
+                .map_err(CalibreError::metadata_parse(source))?;
+            match entries.remove(entry.name()) {
+                Some(contents) => {
+                    zip.start_file(entry.name(), options).map_err(write_error)?;
+                    zip.write_all(&contents)
+                        .map_err(CalibreError::io(destination))?;
+                }
+                None => zip.raw_copy_file(entry).map_err(write_error)?,
+            }
+        }
+        let mut added = entries.into_iter().collect::<Vec<_>>();
+        added.sort();
+        for (name, contents) in added {
+            zip.start_file(name, options).map_err(write_error)?;
+            zip.write_all(&contents)
+                .map_err(CalibreError::io(destination))?;
+        }
+        zip.finish().map_err(write_error)?;
+        Ok(())
+    })
+}
+
+/// The two-letter form of an ISO 639 language code, as e-book formats
+/// expect, e.g. `en` for Calibre's `eng`. Codes without one are returned
+/// as they are.
+pub(crate) fn short_language_code(code: &str) -> String {
+    isolang::Language::from_639_3(code)
+        .and_then(|language| language.to_639_1())
+        .map_or_else(|| code.to_string(), str::to_string)
+}
+
+/// Serialises one element, escaping its attributes and text.
+pub(crate) fn xml_element(name: &str, attributes: &[(&str, &str)], text: Option<&str>) -> String {
+    let mut writer = quick_xml::Writer::new(Vec::new());
+    let element = writer
+        .create_element(name)
+        .with_attributes(attributes.iter().copied());
+    match text {
+        Some(text) => element.write_text_content(quick_xml::events::BytesText::new(text)),
+        None => element.write_empty(),
+    }
+    .expect("writing to a Vec cannot fail");
+    String::from_utf8(writer.into_inner()).expect("the writer only emits UTF-8")
+}
@@ -0,0 +1,509 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use zip::ZipArchive;
+
+use crate::metadata::{rewrite_zip, short_language_code, xml_element};
+use crate::opf::{OpfCreator, OpfMetadata};
+use crate::CalibreError;
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp"];
+
+/// The order of `ComicInfo.xml`'s elements, which its schema requires.
+const ELEMENT_ORDER: &[&str] = &[
+    "Title",
+    "Series",
+    "Number",
+    "Count",
+    "Volume",
+    "AlternateSeries",
+    "AlternateNumber",
+    "AlternateCount",
+    "Summary",
+    "Notes",
+    "Year",
+    "Month",
+    "Day",
+    "Writer",
+    "Penciller",
+    "Inker",
+    "Colorist",
+    "Letterer",
+    "CoverArtist",
+    "Editor",
+    "Translator",
+    "Publisher",
+    "Imprint",
+    "Genre",
+    "Tags",
+    "Web",
+    "PageCount",
+    "LanguageISO",
+    "Format",
+    "BlackAndWhite",
+    "Manga",
+    "Characters",
+    "Teams",
+    "Locations",
+    "ScanInformation",
+    "StoryArc",
+    "StoryArcNumber",
+    "SeriesGroup",
+    "AgeRating",
+    "Pages",
+    "CommunityRating",
+    "MainCharacterOrTeam",
+    "Review",
+    "GTIN",
+];
+
+/// The elements [`write_metadata`] replaces.
+const MANAGED_ELEMENTS: &[&str] = &[
+    "Title",
+    "Series",
+    "Number",
+    "Summary",
+    "Year",
+    "Month",
+    "Day",
+    "Writer",
+    "Penciller",
+    "Publisher",
+    "Genre",
+    "Tags",
+    "Web",
+    "LanguageISO",
+];
+
+const COMIC_INFO_ROOT: &str = r#"<ComicInfo xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">"#;
+
+/// The fields of a comic's `ComicInfo.xml` that libcalibre reads.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ComicInfo {
+    pub title: Option<String>,
+    pub series: Option<String>,
+    /// The issue number, which need not be numeric, e.g. `"1/2"`.
+    pub number: Option<String>,
+    pub summary: Option<String>,
+    pub writers: Vec<String>,
+    pub pencillers: Vec<String>,
+    pub publisher: Option<String>,
+    pub genres: Vec<String>,
+    pub tags: Vec<String>,
+    pub language_iso: Option<String>,
+    /// Calibre has no column for it, so it is not part of the library
+    /// entry.
+    pub page_count: Option<u32>,
+    pub web: Option<String>,
+    pub pubdate: Option<DateTime<Utc>>,
+    /// The index, in page order, of the page marked `FrontCover`.
+    pub front_cover: Option<usize>,
+}
+
+impl ComicInfo {
+    /// Writers and pencillers become the authors, as Calibre credits both
+    /// for comics; genres and tags become tags, and the web address a
+    /// `url` identifier.
+    pub fn into_opf_metadata(self) -> OpfMetadata {
+        let mut creators: Vec<OpfCreator> = Vec::new();
+        for name in self.writers.into_iter().chain(self.pencillers) {
+            if !creators.iter().any(|creator| creator.name == name) {
+                creators.push(OpfCreator {
+                    name,
+                    file_as: None,
+                    role: None,
+                });
+            }
+        }
+
+        OpfMetadata {
+            title: self.title,
+            creators,
+            publisher: self.publisher,
+            description: self.summary,
+            identifiers: self
+                .web
+                .map(|web| ("url".to_string(), web))
+                .into_iter()
+                .collect(),
+            languages: self.language_iso.into_iter().collect(),
+            tags: self.genres.into_iter().chain(self.tags).collect(),
+            series_index: self
+                .number
+                .as_deref()
+                .and_then(|number| number.parse().ok()),
+            series: self.series,
+            pubdate: self.pubdate,
+            ..Default::default()
+        }
+    }
+}
+
+/// Reads the `ComicInfo.xml` of a CBZ file, if it has one.
+pub fn read_comic_info(path: &Path) -> Result<Option<ComicInfo>, CalibreError> {
+    let mut archive = open(path)?;
+    let Some(name) = archive
+        .file_names()
+        .find(|name| file_name(name).eq_ignore_ascii_case("ComicInfo.xml"))
+        .map(str::to_string)
+    else {
+        return Ok(None);
+    };
+
+    let mut xml = String::new();
+    archive
+        .by_name(&name)
+        .map_err(CalibreError::metadata_parse(path))?
+        .read_to_string(&mut xml)
+        .map_err(CalibreError::metadata_parse(path))?;
+    parse_comic_info(&xml)
+        .map(Some)
+        .map_err(CalibreError::metadata_parse(path))
+}
+
+/// Reads the metadata of a CBZ file from its `ComicInfo.xml`; without one
+/// the metadata is empty.
+pub fn read_metadata(path: &Path) -> Result<OpfMetadata, CalibreError> {
+    Ok(read_comic_info(path)?
+        .map(ComicInfo::into_opf_metadata)
+        .unwrap_or_default())
+}
+
+/// The cover of a CBZ file: the page `ComicInfo.xml` marks as the front
+/// cover or, failing that, the first page, with pages in natural order of
+/// their names so that `page2` comes before `page10`. A `ComicInfo.xml`
+/// that cannot be read counts as marking no front cover.
+pub fn read_cover(path: &Path) -> Result<Option<Vec<u8>>, CalibreError> {
+    // An archive that cannot be opened still fails below
+    let front_cover = read_comic_info(path)
+        .ok()
+        .flatten()
+        .and_then(|info| info.front_cover);
+
+    let mut archive = open(path)?;
+    let mut pages = archive
+        .file_names()
+        .filter(|name| is_page(name))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    pages.sort_by(|a, b| natural_cmp(a, b));
+    let Some(cover) = front_cover
+        .and_then(|index| pages.get(index))
+        .or(pages.first())
+    else {
+        return Ok(None);
+    };
+
+    let mut data = Vec::new();
+    archive
+        .by_name(cover)
+        .map_err(CalibreError::metadata_parse(path))?
+        .read_to_end(&mut data)
+        .map_err(CalibreError::metadata_parse(path))?;
+    Ok(Some(data))
+}
+
+/// Writes a copy of the CBZ at `source` to `destination` with `metadata`
+/// in its `ComicInfo.xml`, adding one if it has none.
+///
+/// Elements libcalibre does not manage, such as the page list, are kept.
+/// Authors who were pencillers in the original stay pencillers and tags
+/// that were genres stay genres; the rest become writers and tags.
+pub fn write_metadata(
+    source: &Path,
+    destination: &Path,
+    metadata: &OpfMetadata,
+) -> Result<(), CalibreError> {
+    let mut archive = open(source)?;
+    let name = archive
+        .file_names()
+        .find(|name| file_name(name).eq_ignore_ascii_case("ComicInfo.xml"))
+        .map(str::to_string);
+    let existing = match &name {
+        Some(name) => {
+            let mut xml = String::new();
+            archive
+                .by_name(name)
+                .map_err(CalibreError::metadata_parse(source))?
+                .read_to_string(&mut xml)
+                .map_err(CalibreError::metadata_parse(source))?;
+            Some(xml)
+        }
+        None => None,
+    };
+
+    let xml = comic_info_xml(existing.as_deref(), metadata)
+        .map_err(CalibreError::metadata_parse(source))?;
+    let name = name.unwrap_or_else(|| "ComicInfo.xml".to_string());
+    rewrite_zip(
+        source,
+        destination,
+        HashMap::from([(name, xml.into_bytes())]),
+    )
+}
+
+fn open(path: &Path) -> Result<ZipArchive<File>, CalibreError> {
+    let file = File::open(path).map_err(CalibreError::io(path))?;
+    ZipArchive::new(file).map_err(CalibreError::metadata_parse(path))
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+/// Whether an archive entry is a page image, leaving out the resource
+/// forks macOS adds and other hidden files.
+fn is_page(name: &str) -> bool {
+    let file_name = file_name(name);
+    let is_image = file_name.rsplit_once('.').is_some_and(|(_, extension)| {
+        IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+    });
+    is_image && !file_name.starts_with('.') && !name.starts_with("__MACOSX/")
+}
+
+/// Compares names case-insensitively, with runs of digits compared by
+/// their value.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(a_first), Some(b_first)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let ordering = match (a_first.is_ascii_digit(), b_first.is_ascii_digit()) {
+            (true, true) => {
+                let (a_digits, a_rest) = split_digits(a);
+                let (b_digits, b_rest) = split_digits(b);
+                (a, b) = (a_rest, b_rest);
+                let (a_digits, b_digits) = (
+                    a_digits.trim_start_matches('0'),
+                    b_digits.trim_start_matches('0'),
+                );
+                a_digits
+                    .len()
+                    .cmp(&b_digits.len())
+                    .then_with(|| a_digits.cmp(b_digits))
+            }
+            _ => {
+                (a, b) = (&a[a_first.len_utf8()..], &b[b_first.len_utf8()..]);
+                a_first.to_lowercase().cmp(b_first.to_lowercase())
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+fn split_digits(text: &str) -> (&str, &str) {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    text.split_at(end)
+}
+
+fn parse_comic_info(xml: &str) -> Result<ComicInfo, quick_xml::Error> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut info = ComicInfo::default();
+    let (mut year, mut month, mut day) = (None, None, None);
+    let mut open: Option<String> = None;
+    let mut text = String::new();
+    let mut page_index = 0;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Page" => {
+                let (image, is_front_cover) = page(&e);
+                if is_front_cover && info.front_cover.is_none() {
+                    info.front_cover = Some(image.unwrap_or(page_index));
+                }
+                page_index += 1;
+            }
+            Event::Start(e) => {
+                open = Some(String::from_utf8_lossy(e.local_name().as_ref()).into_owned());
+                text.clear();
+            }
+            Event::Text(t) => text.push_str(&t.unescape()?),
+            Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+            Event::End(_) => {
+                let value = Some(text.trim().to_string()).filter(|value| !value.is_empty());
+                match (open.take().as_deref(), value) {
+                    (Some("Title"), value) => info.title = value,
+                    (Some("Series"), value) => info.series = value,
+                    (Some("Number"), value) => info.number = value,
+                    (Some("Summary"), value) => info.summary = value,
+                    (Some("Writer"), Some(value)) => info.writers = split_list(&value),
+                    (Some("Penciller"), Some(value)) => info.pencillers = split_list(&value),
+                    (Some("Publisher"), value) => info.publisher = value,
+                    (Some("Genre"), Some(value)) => info.genres = split_list(&value),
+                    (Some("Tags"), Some(value)) => info.tags = split_list(&value),
+                    (Some("LanguageISO"), value) => info.language_iso = value,
+                    (Some("PageCount"), value) => {
+                        info.page_count = value.and_then(|value| value.parse().ok())
+                    }
+                    (Some("Web"), value) => {
+                        info.web = value
+                            .and_then(|value| value.split_whitespace().next().map(str::to_string))
+                    }
+                    (Some("Year"), value) => year = value.and_then(|value| value.parse().ok()),
+                    (Some("Month"), value) => month = value.and_then(|value| value.parse().ok()),
+                    (Some("Day"), value) => day = value.and_then(|value| value.parse().ok()),
+                    _ => {}
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    info.pubdate = year
+        .and_then(|year| NaiveDate::from_ymd_opt(year, month.unwrap_or(1), day.unwrap_or(1)))
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc());
+    Ok(info)
+}
+
+/// A `<Page>`'s `Image` index and whether it is the front cover.
+fn page(e: &BytesStart) -> (Option<usize>, bool) {
+    let attribute = |key: &[u8]| {
+        e.attributes()
+            .flatten()
+            .find(|attr| attr.key.local_name().as_ref() == key)
+            .and_then(|attr| attr.unescape_value().ok())
+            .map(|value| value.into_owned())
+    };
+    let image = attribute(b"Image").and_then(|image| image.parse().ok());
+    (image, attribute(b"Type").as_deref() == Some("FrontCover"))
+}
+
+/// ComicInfo lists several people or tags in one field, separated by
+/// commas.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Renders `ComicInfo.xml` with `metadata` in it, keeping the elements of
+/// `existing` that libcalibre does not manage.
+fn comic_info_xml(
+    existing: Option<&str>,
+    metadata: &OpfMetadata,
+) -> Result<String, quick_xml::Error> {
+    let mut root = COMIC_INFO_ROOT.to_string();
+    let mut elements = Vec::new();
+    let mut info = ComicInfo::default();
+    if let Some(existing) = existing {
+        info = parse_comic_info(existing)?;
+        let mut reader = Reader::from_str(existing);
+        let mut depth = 0;
+        loop {
+            let start = reader.buffer_position() as usize;
+            match reader.read_event()? {
+                Event::Start(_) if depth == 0 => {
+                    root = existing[start..reader.buffer_position() as usize].to_string();
+                    depth += 1;
+                }
+                Event::Start(e) => {
+                    reader.read_to_end(e.name())?;
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                    let end = reader.buffer_position() as usize;
+                    elements.push((name, existing[start..end].to_string()));
+                }
+                Event::Empty(e) if depth == 1 => {
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                    let end = reader.buffer_position() as usize;
+                    elements.push((name, existing[start..end].to_string()));
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+    }
+    elements.retain(|(name, _)| !MANAGED_ELEMENTS.contains(&name.as_str()));
+
+    let mut add = |name: &str, value: Option<String>| {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            elements.push((name.to_string(), xml_element(name, &[], Some(&value))));
+        }
+    };
+    let authors = metadata.authors();
+    let (pencillers, writers): (Vec<_>, Vec<_>) = authors
+        .iter()
+        .partition(|author| info.pencillers.contains(author));
+    let (genres, tags): (Vec<_>, Vec<_>) = metadata
+        .tags
+        .iter()
+        .partition(|tag| info.genres.contains(tag));
+    let join = |items: Vec<&String>| {
+        items
+            .into_iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    add("Title", metadata.title.clone());
+    add("Series", metadata.series.clone());
+    if metadata.series.is_some() {
+        add(
+            "Number",
+            metadata.series_index.map(|index| index.to_string()),
+        );
+    }
+    add("Summary", metadata.description.clone());
+    if let Some(pubdate) = metadata.pubdate {
+        add("Year", Some(pubdate.format("%Y").to_string()));
+        add("Month", Some(pubdate.format("%-m").to_string()));
+        add("Day", Some(pubdate.format("%-d").to_string()));
+    }
+    add("Writer", Some(join(writers)));
+    add("Penciller", Some(join(pencillers)));
+    add("Publisher", metadata.publisher.clone());
+    add("Genre", Some(join(genres)));
+    add("Tags", Some(join(tags)));
+    add(
+        "Web",
+        metadata
+            .identifiers
+            .iter()
+            .find(|(scheme, _)| scheme == "url")
+            .map(|(_, url)| url.clone()),
+    );
+    add(
+        "LanguageISO",
+        metadata
+            .languages
+            .first()
+            .map(|language| short_language_code(language)),
+    );
+
+    // Elements outside the schema go last
+    let position = |name: &str| {
+        ELEMENT_ORDER
+            .iter()
+            .position(|known| *known == name)
+            .unwrap_or(ELEMENT_ORDER.len())
+    };
+    elements.sort_by_key(|(name, _)| position(name));
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str(&root);
+    for (_, element) in elements {
+        xml.push_str("\n  ");
+        xml.push_str(&element);
+    }
+    xml.push_str("\n</ComicInfo>\n");
+    Ok(xml)
+}
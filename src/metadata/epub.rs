@@ -0,0 +1,486 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+
+use chrono::Utc;
+use epub::doc::EpubDoc;
+use quick_xml::errors::IllFormedError;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::metadata::{rewrite_zip, short_language_code, xml_element};
+use crate::opf::OpfMetadata;
+use crate::CalibreError;
+
+/// Reads the metadata in an EPUB's package document: title, creators,
+/// publisher, languages, subjects, identifiers, description, publication
+/// date and Calibre's series, rating and sort metadata.
+///
+/// The Calibre ID an EPUB may carry belongs to the library it was exported
+/// from, so it is dropped, and its UUID is kept as a `uuid` identifier
+/// rather than reused for the new book.
+pub fn read_metadata(path: &Path) -> Result<OpfMetadata, CalibreError> {
+    let mut doc = EpubDoc::new(path).map_err(CalibreError::metadata_parse(path))?;
+    let root_file = doc.root_file.clone();
+    let xml = doc.get_resource_str_by_path(&root_file).ok_or_else(|| {
+        CalibreError::metadata_parse(path)(format!("{} is missing", root_file.display()))
+    })?;
+
+    let mut metadata = OpfMetadata::parse_from(&xml, path)?;
+    metadata.calibre_id = None;
+    if let Some(uuid) = metadata.uuid.take() {
+        metadata.identifiers.push(("uuid".to_string(), uuid));
+    }
+    // Not the time the book was added to this library
+    metadata.timestamp = None;
+
+    Ok(metadata)
+}
+
+/// Writes a copy of the EPUB at `source` to `destination` with `metadata`
+/// in its package document, and `cover`, if given, as its cover image.
+///
+/// Only the metadata libcalibre manages is replaced: the package's unique
+/// identifier, contributors, other dates and any metadata of other tools
+/// are kept. An EPUB without a cover image gets one added to its manifest.
+pub fn write_metadata(
+    source: &Path,
+    destination: &Path,
+    metadata: &OpfMetadata,
+    cover: Option<&[u8]>,
+) -> Result<(), CalibreError> {
+    let mut doc = EpubDoc::new(source).map_err(CalibreError::metadata_parse(source))?;
+    let root_file = doc.root_file.clone();
+    let xml = doc.get_resource_str_by_path(&root_file).ok_or_else(|| {
+        CalibreError::metadata_parse(source)(format!("{} is missing", root_file.display()))
+    })?;
+
+    let mut entries = HashMap::new();
+    let cover_change = match (cover, doc.get_cover_id()) {
+        (None, _) => CoverChange::Keep,
+        (Some(data), Some(id)) if doc.resources.contains_key(&id) => {
+            let (path, media_type) = &doc.resources[&id];
+            entries.insert(zip_entry_name(path), data.to_vec());
+            let (new_media_type, _) = image_type(data);
+            CoverChange::Replace {
+                id,
+                media_type: (media_type != new_media_type).then_some(new_media_type),
+            }
+        }
+        (Some(data), _) => {
+            let (media_type, extension) = image_type(data);
+            let taken = doc
+                .resources
+                .values()
+                .map(|(path, _)| zip_entry_name(path))
+                .collect::<HashSet<_>>();
+            let href = (0..)
+                .map(|n| match n {
+                    0 => format!("cover.{extension}"),
+                    n => format!("cover-{n}.{extension}"),
+                })
+                .find(|href| !taken.contains(&zip_entry_name(&doc.root_base.join(href))))
+                .expect("some cover file name is free");
+            entries.insert(zip_entry_name(&doc.root_base.join(&href)), data.to_vec());
+            CoverChange::Add { href, media_type }
+        }
+    };
+
+    let package = rewrite_package(&xml, metadata, &cover_change)
+        .map_err(CalibreError::metadata_parse(source))?;
+    entries.insert(zip_entry_name(&root_file), package.into_bytes());
+    rewrite_zip(source, destination, entries)
+}
+
+/// What happens to the cover image in the manifest.
+enum CoverChange {
+    Keep,
+    /// The item's data is replaced, and its media type changed if the new
+    /// image has another one.
+    Replace {
+        id: String,
+        media_type: Option<&'static str>,
+    },
+    /// A new item is added at `href`, relative to the package document.
+    Add {
+        href: String,
+        media_type: &'static str,
+    },
+}
+
+/// The id given to a cover image added to the manifest.
+const ADDED_COVER_ID: &str = "calibre_cover";
+
+/// An element of the package document found by [`rewrite_package`], with
+/// its byte range in the document.
+struct Located {
+    span: Range<usize>,
+    name: String,
+    attributes: HashMap<String, String>,
+}
+
+impl Located {
+    fn new(e: &BytesStart, span: Range<usize>) -> Self {
+        let attributes = e
+            .attributes()
+            .flatten()
+            .filter_map(|attr| {
+                let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+                Some((key, attr.unescape_value().ok()?.into_owned()))
+            })
+            .collect();
+        Located {
+            span,
+            name: String::from_utf8_lossy(e.local_name().as_ref()).into_owned(),
+            attributes,
+        }
+    }
+
+    /// Looks an attribute up by its local name, so `opf:event` is found as
+    /// `"event"`.
+    fn attribute(&self, local_name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key.rsplit(':').next() == Some(local_name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Replaces the metadata libcalibre manages in a package document with
+/// `metadata`, in the syntax of the document's OPF version, and applies
+/// the cover change to its manifest.
+fn rewrite_package(
+    xml: &str,
+    metadata: &OpfMetadata,
+    cover_change: &CoverChange,
+) -> Result<String, quick_xml::Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut is_opf3 = false;
+    let mut unique_identifier = None;
+    let mut in_metadata = false;
+    let mut children = Vec::new();
+    let mut metadata_end = None;
+    let mut manifest_end = None;
+    let mut cover_item = None;
+
+    loop {
+        let start = reader.buffer_position() as usize;
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"package" => {
+                let package = Located::new(&e, 0..0);
+                is_opf3 = package
+                    .attribute("version")
+                    .is_some_and(|version| version.starts_with('3'));
+                unique_identifier = package.attribute("unique-identifier").map(str::to_string);
+            }
+            Event::Start(e) if e.local_name().as_ref() == b"metadata" => in_metadata = true,
+            Event::Start(e) if in_metadata => {
+                reader.read_to_end(e.name())?;
+                children.push(Located::new(&e, start..reader.buffer_position() as usize));
+            }
+            Event::Empty(e) if in_metadata => {
+                children.push(Located::new(&e, start..reader.buffer_position() as usize));
+            }
+            Event::End(e) if e.local_name().as_ref() == b"metadata" => {
+                in_metadata = false;
+                metadata_end = Some(start);
+            }
+            // Only an empty `<item/>` can be rewritten as a whole
+            Event::Empty(e) if e.local_name().as_ref() == b"item" => {
+                let item = Located::new(&e, start..reader.buffer_position() as usize);
+                if let CoverChange::Replace {
+                    id,
+                    media_type: Some(_),
+                } = cover_change
+                {
+                    if item.attribute("id") == Some(id) {
+                        cover_item = Some((item, e.into_owned()));
+                    }
+                }
+            }
+            Event::End(e) if e.local_name().as_ref() == b"manifest" => manifest_end = Some(start),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    let metadata_end =
+        metadata_end.ok_or_else(|| IllFormedError::MissingEndTag("metadata".to_string()))?;
+
+    let adds_cover = matches!(cover_change, CoverChange::Add { .. });
+    let is_replaced = |child: &Located| match child.name.as_str() {
+        "title" | "creator" | "publisher" | "description" | "language" | "subject" => true,
+        "date" => child
+            .attribute("event")
+            .is_none_or(|event| event == "publication"),
+        "identifier" => child.attribute("id") != unique_identifier.as_deref(),
+        "meta" => {
+            let name = child.attribute("name").unwrap_or_default();
+            let property = child.attribute("property").unwrap_or_default();
+            name.starts_with("calibre:")
+                || (adds_cover && name == "cover")
+                || property.starts_with("calibre:")
+                || property == "belongs-to-collection"
+                || (is_opf3 && property == "dcterms:modified")
+        }
+        _ => false,
+    };
+    let replaced_ids = children
+        .iter()
+        .filter(|child| is_replaced(child))
+        .filter_map(|child| child.attribute("id"))
+        .collect::<HashSet<_>>();
+    let refines_replaced = |child: &Located| {
+        child
+            .attribute("refines")
+            .is_some_and(|target| replaced_ids.contains(target.trim_start_matches('#')))
+    };
+
+    let mut edits = children
+        .iter()
+        .filter(|child| is_replaced(child) || refines_replaced(child))
+        .map(|child| (with_leading_space(xml, child.span.clone()), String::new()))
+        .collect::<Vec<_>>();
+    let mut new_metadata = match is_opf3 {
+        true => opf3_metadata(metadata),
+        false => opf2_metadata(metadata),
+    };
+    if adds_cover {
+        new_metadata.push(xml_element(
+            "meta",
+            &[("name", "cover"), ("content", ADDED_COVER_ID)],
+            None,
+        ));
+    }
+    edits.push(insert_before_end(xml, metadata_end, &new_metadata));
+
+    match cover_change {
+        CoverChange::Add { href, media_type } => {
+            let manifest_end = manifest_end
+                .ok_or_else(|| IllFormedError::MissingEndTag("manifest".to_string()))?;
+            let mut attributes = vec![
+                ("id", ADDED_COVER_ID),
+                ("href", href.as_str()),
+                ("media-type", media_type),
+            ];
+            if is_opf3 {
+                attributes.push(("properties", "cover-image"));
+            }
+            let item = xml_element("item", &attributes, None);
+            edits.push(insert_before_end(xml, manifest_end, &[item]));
+        }
+        CoverChange::Replace {
+            media_type: Some(media_type),
+            ..
+        } => {
+            if let Some((item, start)) = cover_item {
+                let attributes = start
+                    .attributes()
+                    .flatten()
+                    .filter(|attr| attr.key.as_ref() != b"media-type")
+                    .collect::<Vec<_>>();
+                let mut retyped = start.clone();
+                retyped.clear_attributes();
+                retyped.extend_attributes(attributes);
+                retyped.push_attribute(("media-type", *media_type));
+                let mut writer = Writer::new(Vec::new());
+                writer.write_event(Event::Empty(retyped))?;
+                let retyped = String::from_utf8_lossy(&writer.into_inner()).into_owned();
+                edits.push((item.span, retyped));
+            }
+        }
+        CoverChange::Replace { .. } | CoverChange::Keep => {}
+    }
+
+    edits.sort_by_key(|(span, _)| (span.start, span.end));
+    let mut package = String::with_capacity(xml.len());
+    let mut copied = 0;
+    for (span, replacement) in edits {
+        package.push_str(&xml[copied..span.start]);
+        package.push_str(&replacement);
+        copied = span.end;
+    }
+    package.push_str(&xml[copied..]);
+    Ok(package)
+}
+
+fn opf2_metadata(metadata: &OpfMetadata) -> Vec<String> {
+    let mut elements = Vec::new();
+    if let Some(title) = &metadata.title {
+        elements.push(xml_element("dc:title", &[], Some(title)));
+    }
+    for (author, sort) in authors_with_sort_names(metadata) {
+        let mut attributes = vec![("opf:role", "aut")];
+        if let Some(sort) = sort.as_deref() {
+            attributes.push(("opf:file-as", sort));
+        }
+        elements.push(xml_element("dc:creator", &attributes, Some(author)));
+    }
+    elements.extend(common_elements(metadata));
+    for (scheme, value) in &metadata.identifiers {
+        elements.push(xml_element(
+            "dc:identifier",
+            &[("opf:scheme", scheme)],
+            Some(value),
+        ));
+    }
+
+    let meta = |name: &str, content: &str| {
+        xml_element("meta", &[("name", name), ("content", content)], None)
+    };
+    if let Some(series) = &metadata.series {
+        elements.push(meta("calibre:series", series));
+        let index = metadata.series_index.unwrap_or(1.0).to_string();
+        elements.push(meta("calibre:series_index", &index));
+    }
+    if let Some(rating) = metadata.rating {
+        elements.push(meta("calibre:rating", &rating.to_string()));
+    }
+    if let Some(title_sort) = &metadata.title_sort {
+        elements.push(meta("calibre:title_sort", title_sort));
+    }
+    elements
+}
+
+/// OPF 3 attaches roles, sort names and series positions with
+/// `<meta refines>`. Calibre's own properties are left out, as the package
+/// may not declare the `calibre` prefix.
+fn opf3_metadata(metadata: &OpfMetadata) -> Vec<String> {
+    let refine = |id: &str, property: &str, value: &str| {
+        let target = format!("#{id}");
+        xml_element(
+            "meta",
+            &[("refines", &target), ("property", property)],
+            Some(value),
+        )
+    };
+
+    let mut elements = Vec::new();
+    if let Some(title) = &metadata.title {
+        elements.push(xml_element(
+            "dc:title",
+            &[("id", "calibre_title")],
+            Some(title),
+        ));
+        if let Some(title_sort) = &metadata.title_sort {
+            elements.push(refine("calibre_title", "file-as", title_sort));
+        }
+    }
+    for (n, (author, sort)) in authors_with_sort_names(metadata).into_iter().enumerate() {
+        let id = format!("calibre_creator{}", n + 1);
+        elements.push(xml_element("dc:creator", &[("id", &id)], Some(author)));
+        let target = format!("#{id}");
+        elements.push(xml_element(
+            "meta",
+            &[
+                ("refines", &target),
+                ("property", "role"),
+                ("scheme", "marc:relators"),
+            ],
+            Some("aut"),
+        ));
+        if let Some(sort) = sort.as_deref() {
+            elements.push(refine(&id, "file-as", sort));
+        }
+    }
+    elements.extend(common_elements(metadata));
+    for (scheme, value) in &metadata.identifiers {
+        let identifier = match scheme.as_str() {
+            "isbn" | "uuid" => format!("urn:{scheme}:{value}"),
+            _ => format!("{scheme}:{value}"),
+        };
+        elements.push(xml_element("dc:identifier", &[], Some(&identifier)));
+    }
+    if let Some(series) = &metadata.series {
+        elements.push(xml_element(
+            "meta",
+            &[
+                ("property", "belongs-to-collection"),
+                ("id", "calibre_series"),
+            ],
+            Some(series),
+        ));
+        elements.push(refine("calibre_series", "collection-type", "series"));
+        let index = metadata.series_index.unwrap_or(1.0).to_string();
+        elements.push(refine("calibre_series", "group-position", &index));
+    }
+    let modified = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    elements.push(xml_element(
+        "meta",
+        &[("property", "dcterms:modified")],
+        Some(&modified),
+    ));
+    elements
+}
+
+/// The elements written the same way in OPF 2 and OPF 3.
+fn common_elements(metadata: &OpfMetadata) -> Vec<String> {
+    let mut elements = Vec::new();
+    if let Some(publisher) = &metadata.publisher {
+        elements.push(xml_element("dc:publisher", &[], Some(publisher)));
+    }
+    if let Some(description) = &metadata.description {
+        elements.push(xml_element("dc:description", &[], Some(description)));
+    }
+    if let Some(pubdate) = metadata.pubdate {
+        let date = pubdate.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+        elements.push(xml_element("dc:date", &[], Some(&date)));
+    }
+    for language in &metadata.languages {
+        let language = short_language_code(language);
+        elements.push(xml_element("dc:language", &[], Some(&language)));
+    }
+    for tag in &metadata.tags {
+        elements.push(xml_element("dc:subject", &[], Some(tag)));
+    }
+    elements
+}
+
+fn authors_with_sort_names(metadata: &OpfMetadata) -> Vec<(&str, Option<String>)> {
+    metadata
+        .creators
+        .iter()
+        .filter(|creator| creator.is_author())
+        .map(|creator| creator.name.as_str())
+        .zip(metadata.author_sort_names())
+        .collect()
+}
+
+/// Extends a span back over the whitespace before it, so that removing an
+/// element does not leave an empty line behind.
+fn with_leading_space(xml: &str, span: Range<usize>) -> Range<usize> {
+    let start = xml[..span.start].trim_end().len();
+    start..span.end
+}
+
+/// Inserts `elements` before the closing tag at `at`, one per line and
+/// indented one level deeper than the tag. Returns the span to replace and
+/// its replacement.
+fn insert_before_end(xml: &str, at: usize, elements: &[String]) -> (Range<usize>, String) {
+    let insert_at = xml[..at].trim_end().len();
+    let (separator, indent) = match xml[insert_at..at].rsplit_once('\n') {
+        Some((_, closing_indent)) => ("\n", format!("{closing_indent}    ")),
+        None => ("", String::new()),
+    };
+
+    let mut block = String::new();
+    for element in elements {
+        block.push_str(separator);
+        block.push_str(&indent);
+        block.push_str(element);
+    }
+    (insert_at..insert_at, block)
+}
+
+/// The media type and file extension of a cover image, which Calibre keeps
+/// as either a PNG or a JPEG.
+fn image_type(data: &[u8]) -> (&'static str, &'static str) {
+    match data.starts_with(b"\x89PNG") {
+        true => ("image/png", "png"),
+        false => ("image/jpeg", "jpg"),
+    }
+}
+
+/// The name of a zip entry, which always uses `/` as separator.
+fn zip_entry_name(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::Path;
+
+use mobi::headers::ExthRecord;
+use mobi::Mobi;
+
+use crate::opf::{parse_date, OpfCreator, OpfMetadata};
+use crate::CalibreError;
+
+/// The EXTH value of cover and thumbnail offsets that are not set.
+const NO_OFFSET: u32 = 0xFFFF_FFFF;
+
+/// Reads the metadata in the EXTH header of a MOBI, AZW or AZW3 file:
+/// title, authors, publisher, description, subjects, publication date,
+/// language and its ISBN and ASIN, the latter as a `mobi-asin` identifier
+/// like Calibre does.
+///
+/// Without an EXTH title, the book's full name from the MOBI header is
+/// used.
+pub fn read_metadata(path: &Path) -> Result<OpfMetadata, CalibreError> {
+    let mobi = open(path)?;
+
+    let mut metadata = OpfMetadata {
+        title: Some(mobi.title().trim().to_string()).filter(|title| !title.is_empty()),
+        publisher: exth_string(&mobi, ExthRecord::Publisher),
+        description: exth_string(&mobi, ExthRecord::Description),
+        pubdate: exth_string(&mobi, ExthRecord::PublishDate).and_then(|date| parse_date(&date)),
+        ..Default::default()
+    };
+    // Some tools write every author to one record, separated by `&`
+    metadata.creators = exth_strings(&mobi, ExthRecord::Author)
+        .iter()
+        .flat_map(|authors| authors.split('&'))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| OpfCreator {
+            name: name.to_string(),
+            file_as: None,
+            role: None,
+        })
+        .collect();
+    metadata.tags = exth_strings(&mobi, ExthRecord::Subject);
+    metadata.languages = exth_strings(&mobi, ExthRecord::Language);
+    if let Some(isbn) = exth_string(&mobi, ExthRecord::Isbn) {
+        metadata.identifiers.push(("isbn".to_string(), isbn));
+    }
+    if let Some(asin) = exth_string(&mobi, ExthRecord::Asin) {
+        metadata.identifiers.push(("mobi-asin".to_string(), asin));
+    }
+
+    Ok(metadata)
+}
+
+/// The cover image of a MOBI, AZW or AZW3 file.
+///
+/// The EXTH cover offset says which image record is the cover; without
+/// one the thumbnail is used, and failing that the first image, which is
+/// where Kindle tools put the cover.
+pub fn read_cover(path: &Path) -> Result<Option<Vec<u8>>, CalibreError> {
+    let mobi = open(path)?;
+    let first_image_index = mobi.metadata.mobi.first_image_index;
+    if first_image_index == NO_OFFSET {
+        return Ok(None);
+    }
+
+    if !records_in_bounds(&mobi) {
+        return Err(CalibreError::metadata_parse(path)(
+            "record table points past the end of the file",
+        ));
+    }
+
+    let raw_records = mobi.raw_records();
+    let records = raw_records.records();
+    let cover = [ExthRecord::CoverOffset, ExthRecord::ThumbOffset]
+        .into_iter()
+        .filter_map(|record| exth_u32(&mobi, record))
+        .filter(|offset| *offset != NO_OFFSET)
+        .chain([0])
+        .filter_map(|offset| first_image_index.checked_add(offset))
+        .filter_map(|index| records.get(index as usize))
+        .map(|record| record.content)
+        .find(|content| is_image(content));
+
+    Ok(cover.map(<[u8]>::to_vec))
+}
+
+fn open(path: &Path) -> Result<Mobi, CalibreError> {
+    let bytes = fs::read(path).map_err(CalibreError::io(path))?;
+    Mobi::new(bytes).map_err(CalibreError::metadata_parse(path))
+}
+
+/// Whether every record in the PDB record table lies within the file.
+///
+/// `Mobi::new` only reads the first record, and `Mobi::raw_records`
+/// slices the file by the table's offsets without checking them, so a
+/// truncated file would make it panic.
+fn records_in_bounds(mobi: &Mobi) -> bool {
+    let records = &mobi.metadata.records.records;
+    let len = mobi.content.len();
+    // `raw_records` ends each record this many bytes before the next one
+    // starts; the value follows the 78-byte PDB header and the table
+    let gap_offset = 78 + 8 * records.len();
+    let Some(&[high, low]) = mobi.content.get(gap_offset..gap_offset + 2) else {
+        return false;
+    };
+    let gap = u16::from_be_bytes([high, low]) as usize;
+
+    records.iter().all(|record| record.offset as usize <= len)
+        && records.windows(2).all(|pair| {
+            let (start, next) = (pair[0].offset as usize, pair[1].offset as usize);
+            next <= gap || start <= next - gap
+        })
+}
+
+/// Every value of an EXTH record, trimmed, with empty ones left out.
+fn exth_strings(mobi: &Mobi, record: ExthRecord) -> Vec<String> {
+    mobi.metadata
+        .exth_record(record)
+        .into_iter()
+        .flatten()
+        .map(|value| String::from_utf8_lossy(value).trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn exth_string(mobi: &Mobi, record: ExthRecord) -> Option<String> {
+    exth_strings(mobi, record).into_iter().next()
+}
+
+fn exth_u32(mobi: &Mobi, record: ExthRecord) -> Option<u32> {
+    let value = mobi.metadata.exth_record(record)?.first()?;
+    Some(u32::from_be_bytes(value.as_slice().try_into().ok()?))
+}
+
+/// Whether a record holds a JPEG, PNG, GIF or BMP image, rather than one of
+/// the resource records that follow the images.
+fn is_image(content: &[u8]) -> bool {
+    [
+        &b"\xFF\xD8\xFF"[..],
+        b"\x89PNG\r\n\x1A\n",
+        b"GIF87a",
+        b"GIF89a",
+        b"BM",
+    ]
+    .iter()
+    .any(|magic| content.starts_with(magic))
+}
@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+use lopdf::{
+    decode_text_string, dictionary, text_string, Dictionary, Document, Object, ObjectId, Stream,
+};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::ResolveResult;
+use quick_xml::NsReader;
+
+use crate::metadata::{short_language_code, write_new_file, xml_element};
+use crate::opf::{parse_date, OpfCreator, OpfMetadata};
+use crate::CalibreError;
+
+/// How far up the page tree a page's resources are looked for.
+const MAX_PAGE_TREE_DEPTH: usize = 64;
+
+const RDF_NAMESPACE: &[u8] = b"http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// The XMP namespaces libcalibre reads, with the prefixes they are known
+/// by whatever prefix the packet binds them to.
+const XMP_NAMESPACES: &[(&[u8], &str)] = &[
+    (b"http://purl.org/dc/elements/1.1/", "dc"),
+    (b"http://ns.adobe.com/pdf/1.3/", "pdf"),
+    (b"http://ns.adobe.com/xap/1.0/", "xmp"),
+];
+
+/// The metadata of a PDF that libcalibre reads, from its document
+/// information dictionary and its XMP metadata.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PdfInfo {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    /// The document's subject, or its XMP description.
+    pub subject: Option<String>,
+    pub keywords: Vec<String>,
+    pub publisher: Option<String>,
+    pub languages: Vec<String>,
+    /// The publication date, which only XMP has.
+    pub pubdate: Option<DateTime<Utc>>,
+    pub creation_date: Option<DateTime<Utc>>,
+    /// Calibre has no column for it, so it is not part of the library
+    /// entry.
+    pub page_count: usize,
+}
+
+impl PdfInfo {
+    /// The subject becomes the description and the keywords tags. Without
+    /// a publication date the creation date is used, which for most PDFs
+    /// is when the book was typeset.
+    pub fn into_opf_metadata(self) -> OpfMetadata {
+        OpfMetadata {
+            title: self.title,
+            creators: self
+                .authors
+                .into_iter()
+                .map(|name| OpfCreator {
+                    name,
+                    file_as: None,
+                    role: None,
+                })
+                .collect(),
+            publisher: self.publisher,
+            description: self.subject,
+            languages: self.languages,
+            tags: self.keywords,
+            pubdate: self.pubdate.or(self.creation_date),
+            ..Default::default()
+        }
+    }
+}
+
+/// Reads the metadata of a PDF. Where the document information dictionary
+/// and the XMP metadata disagree, the XMP wins, as tools that write both
+/// put more in it.
+///
+/// A missing or broken XMP packet is ignored, as many PDFs have one that
+/// nothing reads.
+pub fn read_pdf_info(path: &Path) -> Result<PdfInfo, CalibreError> {
+    let doc = Document::load(path).map_err(CalibreError::metadata_parse(path))?;
+    let mut info = PdfInfo {
+        page_count: doc.get_pages().len(),
+        ..Default::default()
+    };
+
+    if let Some(dictionary) = info_dictionary(&doc) {
+        let text = |key: &[u8]| info_string(&doc, dictionary, key);
+        info.title = text(b"Title");
+        info.authors =
+            text(b"Author").map_or_else(Vec::new, |authors| split_list(&authors, &['&', ';']));
+        info.subject = text(b"Subject");
+        info.keywords =
+            text(b"Keywords").map_or_else(Vec::new, |keywords| split_list(&keywords, &[',', ';']));
+        info.creation_date = text(b"CreationDate").and_then(|date| parse_pdf_date(&date));
+    }
+
+    let properties = xmp_packet_of(&doc)
+        .and_then(|xmp| parse_xmp(&xmp).ok())
+        .unwrap_or_default();
+    let first = |name: &str| {
+        properties
+            .get(name)
+            .and_then(|values| values.first())
+            .cloned()
+    };
+    if let Some(title) = first("dc:title") {
+        info.title = Some(title);
+    }
+    if let Some(creators) = properties.get("dc:creator") {
+        info.authors = creators.clone();
+    }
+    if let Some(description) = first("dc:description") {
+        info.subject = Some(description);
+    }
+    if let Some(subjects) = properties.get("dc:subject") {
+        info.keywords = subjects.clone();
+    } else if let Some(keywords) = first("pdf:Keywords") {
+        info.keywords = split_list(&keywords, &[',', ';']);
+    }
+    if let Some(publisher) = first("dc:publisher") {
+        info.publisher = Some(publisher);
+    }
+    if let Some(languages) = properties.get("dc:language") {
+        info.languages = languages.clone();
+    }
+    info.pubdate = first("dc:date").and_then(|date| parse_date(&date));
+    if let Some(date) = first("xmp:CreateDate").and_then(|date| parse_date(&date)) {
+        info.creation_date = Some(date);
+    }
+
+    Ok(info)
+}
+
+/// Reads the metadata of a PDF; see [`read_pdf_info`].
+pub fn read_metadata(path: &Path) -> Result<OpfMetadata, CalibreError> {
+    read_pdf_info(path).map(PdfInfo::into_opf_metadata)
+}
+
+/// The cover of a PDF: the largest image on its first page, which for
+/// most books is the cover itself.
+///
+/// Only JPEG images are stored in a form that can be used as a cover as
+/// they are, so PDFs whose first page has none have no cover.
+pub fn read_cover(path: &Path) -> Result<Option<Vec<u8>>, CalibreError> {
+    let doc = Document::load(path).map_err(CalibreError::metadata_parse(path))?;
+    let Some(page_id) = doc.page_iter().next() else {
+        return Ok(None);
+    };
+
+    let cover = page_xobjects(&doc, page_id)
+        .into_iter()
+        .filter_map(|id| doc.get_object(id).and_then(Object::as_stream).ok())
+        .filter(|image| is_jpeg(image))
+        .max_by_key(|image| {
+            let dimension = |key: &[u8]| image.dict.get(key).and_then(Object::as_i64).unwrap_or(0);
+            dimension(b"Width").saturating_mul(dimension(b"Height"))
+        });
+
+    Ok(cover.map(|image| image.content.clone()))
+}
+
+/// Writes a copy of the PDF at `source` to `destination` with `metadata` in
+/// its document information dictionary and its XMP metadata stream, the
+/// way Calibre does.
+///
+/// Encrypted PDFs fail with [`CalibreError::UnsupportedFormat`], as the
+/// copy would lose its encryption.
+pub fn write_metadata(
+    source: &Path,
+    destination: &Path,
+    metadata: &OpfMetadata,
+) -> Result<(), CalibreError> {
+    let mut doc = Document::load(source).map_err(CalibreError::metadata_parse(source))?;
+    if doc.was_encrypted() {
+        return Err(CalibreError::UnsupportedFormat(format!(
+            "{} is encrypted",
+            source.display()
+        )));
+    }
+
+    let info_id = match doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) if doc.get_dictionary(id).is_ok() => id,
+        _ => {
+            let id = doc.add_object(Dictionary::new());
+            doc.trailer.set("Info", id);
+            id
+        }
+    };
+    let info = doc
+        .get_dictionary_mut(info_id)
+        .map_err(CalibreError::metadata_parse(source))?;
+    let authors = metadata.authors().join(" & ");
+    let keywords = metadata.tags.join(", ");
+    for (key, value) in [
+        ("Title", metadata.title.as_deref().unwrap_or_default()),
+        ("Author", authors.as_str()),
+        (
+            "Subject",
+            metadata.description.as_deref().unwrap_or_default(),
+        ),
+        ("Keywords", keywords.as_str()),
+    ] {
+        if value.is_empty() {
+            info.remove(key.as_bytes());
+        } else {
+            info.set(key, text_string(value));
+        }
+    }
+    info.set("ModDate", text_string(&pdf_date(Utc::now())));
+
+    let mut xmp = Stream::new(
+        dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+        xmp_packet(metadata).into_bytes(),
+    );
+    // Tools that only scan the file for the packet need it uncompressed
+    xmp.allows_compression = false;
+    // The old packet is replaced in place, so it does not linger as an
+    // orphaned object for those tools to find first
+    let old_xmp_id = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Metadata"))
+        .and_then(Object::as_reference);
+    let xmp_id = match old_xmp_id {
+        Ok(id) if doc.objects.contains_key(&id) => {
+            doc.objects.insert(id, xmp.into());
+            id
+        }
+        _ => doc.add_object(xmp),
+    };
+    doc.catalog_mut()
+        .map_err(CalibreError::metadata_parse(source))?
+        .set("Metadata", xmp_id);
+
+    write_new_file(destination, |file| {
+        doc.save_to(file)
+            .map_err(|e| CalibreError::io(destination)(std::io::Error::other(e)))
+    })
+}
+
+/// A date in the PDF format, e.g. `D:20240131120000+00'00'`.
+fn pdf_date(date: DateTime<Utc>) -> String {
+    date.format("D:%Y%m%d%H%M%S+00'00'").to_string()
+}
+
+/// The XMP packet Calibre embeds: Dublin Core for the common metadata,
+/// XMP identifiers, and Calibre's own namespace for the series and rating.
+fn xmp_packet(metadata: &OpfMetadata) -> String {
+    let li = |text: &str| xml_element("rdf:li", &[], Some(text));
+    let li_default = |text: &str| xml_element("rdf:li", &[("xml:lang", "x-default")], Some(text));
+    let container = |name: &str, kind: &str, items: Vec<String>| {
+        format!(
+            "<{name}><rdf:{kind}>{}</rdf:{kind}></{name}>",
+            items.concat()
+        )
+    };
+
+    let mut properties = Vec::new();
+    if let Some(title) = &metadata.title {
+        properties.push(container("dc:title", "Alt", vec![li_default(title)]));
+    }
+    let authors = metadata.authors();
+    if !authors.is_empty() {
+        let items = authors.iter().map(|author| li(author)).collect();
+        properties.push(container("dc:creator", "Seq", items));
+    }
+    if let Some(description) = &metadata.description {
+        properties.push(container(
+            "dc:description",
+            "Alt",
+            vec![li_default(description)],
+        ));
+    }
+    if let Some(publisher) = &metadata.publisher {
+        properties.push(container("dc:publisher", "Bag", vec![li(publisher)]));
+    }
+    if !metadata.languages.is_empty() {
+        let items = metadata
+            .languages
+            .iter()
+            .map(|language| li(&short_language_code(language)))
+            .collect();
+        properties.push(container("dc:language", "Bag", items));
+    }
+    if !metadata.tags.is_empty() {
+        let items = metadata.tags.iter().map(|tag| li(tag)).collect();
+        properties.push(container("dc:subject", "Bag", items));
+        let keywords = metadata.tags.join(", ");
+        properties.push(xml_element("pdf:Keywords", &[], Some(&keywords)));
+    }
+    if let Some(pubdate) = metadata.pubdate {
+        let date = pubdate.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+        properties.push(container("dc:date", "Seq", vec![li(&date)]));
+    }
+    if !metadata.identifiers.is_empty() {
+        let items = metadata
+            .identifiers
+            .iter()
+            .map(|(scheme, value)| {
+                format!(
+                    r#"<rdf:li rdf:parseType="Resource">{}{}</rdf:li>"#,
+                    xml_element("xmpidq:Scheme", &[], Some(scheme)),
+                    xml_element("rdf:value", &[], Some(value)),
+                )
+            })
+            .collect();
+        properties.push(container("xmp:Identifier", "Bag", items));
+    }
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    properties.push(xml_element("xmp:MetadataDate", &[], Some(&now)));
+    if let Some(series) = &metadata.series {
+        let index = format!("{:.2}", metadata.series_index.unwrap_or(1.0));
+        properties.push(format!(
+            r#"<calibre:series rdf:parseType="Resource">{}{}</calibre:series>"#,
+            xml_element("rdf:value", &[], Some(series)),
+            xml_element("calibreSI:series_index", &[], Some(&index)),
+        ));
+    }
+    if let Some(rating) = metadata.rating {
+        properties.push(xml_element(
+            "calibre:rating",
+            &[],
+            Some(&rating.to_string()),
+        ));
+    }
+
+    let mut packet = String::from(concat!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+        "  <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+        "    <rdf:Description rdf:about=\"\"",
+        " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+        " xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"",
+        " xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"",
+        " xmlns:xmpidq=\"http://ns.adobe.com/xmp/Identifier/qual/1.0/\"",
+        " xmlns:calibre=\"http://calibre-ebook.com/xmp-namespace\"",
+        " xmlns:calibreSI=\"http://calibre-ebook.com/xmp-namespace-series-index\">\n",
+    ));
+    for property in properties {
+        packet.push_str("      ");
+        packet.push_str(&property);
+        packet.push('\n');
+    }
+    packet.push_str("    </rdf:Description>\n  </rdf:RDF>\n</x:xmpmeta>\n");
+    packet.push_str("<?xpacket end=\"w\"?>");
+    packet
+}
+
+fn info_dictionary(doc: &Document) -> Option<&Dictionary> {
+    let info = doc.trailer.get(b"Info").ok()?;
+    doc.dereference(info).ok()?.1.as_dict().ok()
+}
+
+/// A text string of the document information dictionary, trimmed, or
+/// `None` if it is missing or empty.
+fn info_string(doc: &Document, dictionary: &Dictionary, key: &[u8]) -> Option<String> {
+    let (_, value) = doc.dereference(dictionary.get(key).ok()?).ok()?;
+    let text = decode_text_string(value).ok()?;
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// The catalog's XMP packet, decompressed.
+fn xmp_packet_of(doc: &Document) -> Option<String> {
+    let metadata = doc.catalog().ok()?.get(b"Metadata").ok()?;
+    let stream = doc.dereference(metadata).ok()?.1.as_stream().ok()?;
+    let content = stream.decompressed_content().ok()?;
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// The values of the XMP properties libcalibre reads, keyed by their
+/// usual qualified name, e.g. `dc:title`. A property holding an array has
+/// a value per item; properties may also be written as attributes of
+/// `rdf:Description`.
+fn parse_xmp(xml: &str) -> Result<HashMap<String, Vec<String>>, quick_xml::Error> {
+    let mut reader = NsReader::from_str(xml.trim_start_matches('\u{feff}'));
+    let mut properties: HashMap<String, Vec<String>> = HashMap::new();
+    // The properties and structure fields that are open, `None` for the
+    // ones libcalibre does not read
+    let mut open: Vec<Option<String>> = Vec::new();
+    let mut items = Vec::new();
+    let mut text = String::new();
+    // Properties only appear inside an `rdf:Description`, not in the
+    // wrapping `x:xmpmeta`
+    let mut descriptions = 0;
+
+    loop {
+        let (namespace, event) = reader.read_resolved_event()?;
+        let namespace = match namespace {
+            ResolveResult::Bound(namespace) => Some(namespace.as_ref().to_vec()),
+            _ => None,
+        };
+        let is_rdf = namespace.as_deref() == Some(RDF_NAMESPACE);
+        let is_start = matches!(event, Event::Start(_));
+        match event {
+            Event::Start(e) | Event::Empty(e)
+                if is_rdf && e.local_name().as_ref() == b"Description" =>
+            {
+                if is_start {
+                    descriptions += 1;
+                }
+                for (name, value) in description_attributes(&reader, &e) {
+                    properties.entry(name).or_default().push(value);
+                }
+                text.clear();
+            }
+            Event::Start(_) if is_rdf => text.clear(),
+            Event::Start(_) | Event::End(_) if descriptions == 0 => {}
+            Event::Start(e) => {
+                if open.is_empty() {
+                    items.clear();
+                }
+                open.push(property_name(namespace.as_deref(), e.local_name().as_ref()));
+                text.clear();
+            }
+            Event::Text(t) => text.push_str(&t.unescape()?),
+            Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+            Event::End(e) if is_rdf => {
+                if e.local_name().as_ref() == b"Description" {
+                    descriptions -= 1;
+                }
+                if e.local_name().as_ref() == b"li" && open.len() == 1 {
+                    items.extend(Some(text.trim().to_string()).filter(|item| !item.is_empty()));
+                }
+                text.clear();
+            }
+            Event::End(_) => {
+                let property = open.pop().flatten();
+                if let Some(name) = property.filter(|_| open.is_empty()) {
+                    if items.is_empty() && !text.trim().is_empty() {
+                        items.push(text.trim().to_string());
+                    }
+                    properties.entry(name).or_default().append(&mut items);
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(properties)
+}
+
+/// The properties written as attributes of an `rdf:Description`.
+fn description_attributes(reader: &NsReader<&[u8]>, e: &BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+        .flatten()
+        .filter_map(|attr| {
+            let (namespace, local_name) = reader.resolve_attribute(attr.key);
+            let name = match namespace {
+                ResolveResult::Bound(namespace) => {
+                    property_name(Some(namespace.as_ref()), local_name.as_ref())?
+                }
+                _ => return None,
+            };
+            let value = attr.unescape_value().ok()?.trim().to_string();
+            Some((name, value)).filter(|(_, value)| !value.is_empty())
+        })
+        .collect()
+}
+
+/// The qualified name libcalibre knows a property by, if it is in one of
+/// the namespaces it reads.
+fn property_name(namespace: Option<&[u8]>, local_name: &[u8]) -> Option<String> {
+    let (_, prefix) = XMP_NAMESPACES
+        .iter()
+        .find(|(uri, _)| Some(*uri) == namespace)?;
+    Some(format!("{prefix}:{}", String::from_utf8_lossy(local_name)))
+}
+
+/// The image XObjects in a page's resources.
+fn page_xobjects(doc: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    let Some(xobjects) = page_resources(doc, page_id)
+        .and_then(|resources| resources.get(b"XObject").ok())
+        .and_then(|xobjects| doc.dereference(xobjects).ok()?.1.as_dict().ok())
+    else {
+        return Vec::new();
+    };
+    xobjects
+        .iter()
+        .filter_map(|(_, xobject)| xobject.as_reference().ok())
+        .filter(|id| {
+            doc.get_object(*id)
+                .and_then(Object::as_stream)
+                .and_then(|stream| stream.dict.get(b"Subtype"))
+                .and_then(Object::as_name)
+                .is_ok_and(|subtype| subtype == b"Image")
+        })
+        .collect()
+}
+
+/// A page's resources, which it may inherit from the nearest node of the
+/// page tree above it that has some.
+fn page_resources(doc: &Document, page_id: ObjectId) -> Option<&Dictionary> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    // Bounds the walk in page trees with a cycle
+    for _ in 0..MAX_PAGE_TREE_DEPTH {
+        if let Ok(resources) = node.get(b"Resources") {
+            return doc.dereference(resources).ok()?.1.as_dict().ok();
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+/// Whether an image stream is a JPEG, whose content is the JPEG file.
+fn is_jpeg(image: &Stream) -> bool {
+    let filter = match image.dict.get(b"Filter") {
+        Ok(Object::Array(filters)) if filters.len() == 1 => &filters[0],
+        Ok(filter) => filter,
+        Err(_) => return false,
+    };
+    filter.as_name().is_ok_and(|name| name == b"DCTDecode")
+}
+
+/// Splits a list of names or keywords on any of `separators`.
+fn split_list(value: &str, separators: &[char]) -> Vec<String> {
+    value
+        .split(separators)
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses a PDF date such as `D:20240131120000+01'00'`, in which everything
+/// after the year is optional. Dates without a time zone are taken as UTC.
+fn parse_pdf_date(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    let text = text.strip_prefix("D:").unwrap_or(text);
+    let digits_end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (digits, zone) = text.split_at(digits_end);
+    let field = |start: usize, default: u32| match digits.get(start..start + 2) {
+        Some(field) => field.parse().ok(),
+        None => Some(default),
+    };
+
+    let year = digits.get(..4)?.parse().ok()?;
+    let date = NaiveDate::from_ymd_opt(year, field(4, 1)?, field(6, 1)?)?.and_hms_opt(
+        field(8, 0)?,
+        field(10, 0)?,
+        field(12, 0)?,
+    )?;
+    let offset = zone_offset(zone).unwrap_or(FixedOffset::east_opt(0)?);
+    let date = offset.from_local_datetime(&date).single()?;
+    Some(date.with_timezone(&Utc))
+}
+
+/// The offset of a PDF date's time zone, e.g. `+01'00'`.
+fn zone_offset(zone: &str) -> Option<FixedOffset> {
+    let sign = match zone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let zone = zone[1..].replace('\'', "");
+    let hours: i32 = zone.get(..2)?.parse().ok()?;
+    let minutes: i32 = zone
+        .get(2..4)
+        .map_or(Some(0), |minutes| minutes.parse().ok())?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
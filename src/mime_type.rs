@@ -24,7 +24,7 @@ impl MIMETYPE {
         }
     }
 
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::should_implement_trait)]
     pub fn from_str(mimetype: &str) -> Option<Self> {
         match mimetype {
             "application/epub+zip" => Some(MIMETYPE::EPUB),
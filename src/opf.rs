@@ -0,0 +1,465 @@
+//! Reading OPF package documents, such as the `metadata.opf` Calibre keeps
+//! in every book folder or a sidecar OPF shipped with a book.
+//!
+//! Both OPF 2 (`opf:` attributes and `<meta name=".." content=".."/>`) and
+//! OPF 3 (`<meta property="..">` refinements) are understood, including the
+//! `calibre:` extensions Calibre writes in each version.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use quick_xml::errors::IllFormedError;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::dtos::author::NewAuthorDto;
+use crate::dtos::book::NewBookDto;
+use crate::dtos::language::NewLanguageDto;
+use crate::dtos::library::{NewLibraryEntryDto, NewLibraryFileDto};
+use crate::dtos::publisher::NewPublisherDto;
+use crate::dtos::rating::NewRatingDto;
+use crate::dtos::series::NewSeriesDto;
+use crate::dtos::tag::NewTagDto;
+use crate::{CalibreError, UpsertBookIdentifier};
+
+/// The book metadata found in an OPF package document.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OpfMetadata {
+    /// The book's ID in the Calibre library that wrote the file.
+    pub calibre_id: Option<i32>,
+    pub uuid: Option<String>,
+    pub title: Option<String>,
+    pub title_sort: Option<String>,
+    /// Every `<dc:creator>`, authors and otherwise, in document order.
+    pub creators: Vec<OpfCreator>,
+    pub publisher: Option<String>,
+    /// The HTML description, already unescaped.
+    pub description: Option<String>,
+    /// `(scheme, value)` pairs with lower-case schemes, not including the
+    /// calibre and uuid ones.
+    pub identifiers: Vec<(String, String)>,
+    pub languages: Vec<String>,
+    pub tags: Vec<String>,
+    /// Zero to ten, like Calibre's `ratings` table.
+    pub rating: Option<i32>,
+    pub series: Option<String>,
+    pub series_index: Option<f32>,
+    pub pubdate: Option<DateTime<Utc>>,
+    /// When the book was added to the library.
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Author name to link, from `calibre:link_maps`.
+    pub author_links: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpfCreator {
+    pub name: String,
+    pub file_as: Option<String>,
+    /// A MARC relator code such as `aut` or `edt`.
+    pub role: Option<String>,
+}
+
+impl OpfCreator {
+    /// Creators without a role are treated as authors, as Calibre does.
+    pub fn is_author(&self) -> bool {
+        self.role
+            .as_deref()
+            .is_none_or(|role| role.eq_ignore_ascii_case("aut"))
+    }
+}
+
+/// A metadata element with its attributes, keyed by local name.
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    text: String,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+}
+
+impl OpfMetadata {
+    /// Reads and parses the OPF file at `path`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<OpfMetadata, CalibreError> {
+        let path = path.as_ref();
+        let xml = fs::read_to_string(path).map_err(CalibreError::io(path))?;
+        OpfMetadata::parse_from(&xml, path)
+    }
+
+    /// Parses an OPF document that was read from `path`, e.g. the package
+    /// document inside an EPUB.
+    pub(crate) fn parse_from(xml: &str, path: &Path) -> Result<OpfMetadata, CalibreError> {
+        parse_elements(xml)
+            .map(OpfMetadata::from_elements)
+            .map_err(CalibreError::metadata_parse(path))
+    }
+
+    /// Parses an OPF document.
+    pub fn parse(xml: &str) -> Result<OpfMetadata, CalibreError> {
+        parse_elements(xml)
+            .map(OpfMetadata::from_elements)
+            .map_err(|e| CalibreError::InvalidInput(format!("not a valid OPF document: {e}")))
+    }
+
+    /// The names of the creators that are authors.
+    pub fn authors(&self) -> Vec<String> {
+        self.creators
+            .iter()
+            .filter(|creator| creator.is_author())
+            .map(|creator| creator.name.clone())
+            .collect()
+    }
+
+    fn from_elements(elements: Vec<Element>) -> OpfMetadata {
+        // OPF 3 attaches roles, sort names and series positions to other
+        // elements with `<meta refines="#id" property="..">`.
+        let mut refinements: HashMap<&str, HashMap<&str, &str>> = HashMap::new();
+        for element in &elements {
+            if let (Some(target), Some(property)) =
+                (element.attribute("refines"), element.attribute("property"))
+            {
+                refinements
+                    .entry(target.trim_start_matches('#'))
+                    .or_default()
+                    .insert(property, element.text.trim());
+            }
+        }
+        let refinement = |element: &Element, property: &str| -> Option<String> {
+            let id = element.attribute("id")?;
+            refinements
+                .get(id)
+                .and_then(|properties| properties.get(property))
+                .map(|value| value.to_string())
+        };
+
+        let mut metadata = OpfMetadata::default();
+        for element in &elements {
+            let text = element.text.trim().to_string();
+            match element.name.as_str() {
+                "meta" => {
+                    if element.attribute("refines").is_some() {
+                        continue;
+                    }
+                    if let (Some(name), Some(content)) =
+                        (element.attribute("name"), element.attribute("content"))
+                    {
+                        metadata.apply_meta(name, content);
+                    } else if element.attribute("property") == Some("belongs-to-collection") {
+                        let collection_type = refinement(element, "collection-type");
+                        if collection_type.is_none_or(|kind| kind == "series") && !text.is_empty() {
+                            metadata.series = Some(text);
+                            metadata.series_index = refinement(element, "group-position")
+                                .and_then(|index| index.parse().ok());
+                        }
+                    } else if let Some(property) = element.attribute("property") {
+                        metadata.apply_meta(property, &text);
+                    }
+                }
+                _ if text.is_empty() => {}
+                "title" if metadata.title.is_none() => {
+                    let sort = element
+                        .attribute("file-as")
+                        .map(str::to_string)
+                        .or_else(|| refinement(element, "file-as"));
+                    metadata.title_sort = metadata.title_sort.take().or(sort);
+                    metadata.title = Some(text);
+                }
+                "creator" => metadata.creators.push(OpfCreator {
+                    name: text,
+                    file_as: element
+                        .attribute("file-as")
+                        .map(str::to_string)
+                        .or_else(|| refinement(element, "file-as")),
+                    role: element
+                        .attribute("role")
+                        .map(str::to_string)
+                        .or_else(|| refinement(element, "role")),
+                }),
+                "publisher" if metadata.publisher.is_none() => metadata.publisher = Some(text),
+                "description" if metadata.description.is_none() => {
+                    metadata.description = Some(text)
+                }
+                "language" => metadata.languages.push(text),
+                "subject" => metadata.tags.push(text),
+                // EPUBs may also date their creation or last modification
+                "date"
+                    if element
+                        .attribute("event")
+                        .is_none_or(|event| event.eq_ignore_ascii_case("publication")) =>
+                {
+                    metadata.pubdate = parse_date(&text)
+                }
+                "identifier" => metadata.apply_identifier(element.attribute("scheme"), &text),
+                _ => {}
+            }
+        }
+
+        metadata
+    }
+
+    fn apply_identifier(&mut self, scheme: Option<&str>, text: &str) {
+        // OPF 3 has no scheme attribute and writes `isbn:..` or
+        // `urn:uuid:..` instead
+        let (scheme, value) = match scheme {
+            Some(scheme) => (scheme.to_lowercase(), text),
+            None => {
+                let text = text.strip_prefix("urn:").unwrap_or(text);
+                match text.split_once(':') {
+                    Some((scheme, value)) => (scheme.to_lowercase(), value),
+                    None if is_isbn(text) => ("isbn".to_string(), text),
+                    None => return,
+                }
+            }
+        };
+
+        match scheme.as_str() {
+            "calibre" => self.calibre_id = value.parse().ok(),
+            "uuid" => self.uuid = Some(value.to_string()),
+            _ => self.identifiers.push((scheme, value.to_string())),
+        }
+    }
+
+    fn apply_meta(&mut self, name: &str, content: &str) {
+        match name {
+            "calibre:rating" => {
+                self.rating = content
+                    .parse::<f32>()
+                    .ok()
+                    .map(|rating| rating.round() as i32)
+            }
+            "calibre:timestamp" => self.timestamp = parse_date(content),
+            "calibre:title_sort" if !content.is_empty() => {
+                self.title_sort = Some(content.to_string())
+            }
+            "calibre:series" if !content.is_empty() => self.series = Some(content.to_string()),
+            "calibre:series_index" => self.series_index = content.parse().ok(),
+            "calibre:link_maps" => {
+                #[derive(serde::Deserialize)]
+                struct LinkMaps {
+                    #[serde(default)]
+                    authors: HashMap<String, Option<String>>,
+                }
+                if let Ok(link_maps) = serde_json::from_str::<LinkMaps>(content) {
+                    self.author_links = link_maps
+                        .authors
+                        .into_iter()
+                        .filter_map(|(author, link)| Some((author, link?)))
+                        .filter(|(_, link)| !link.is_empty())
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The sort name of each author. Calibre writes the book's combined
+    /// author sort, e.g. `Doe, Jane & Roe, Rick`, on every creator, so it
+    /// is split up again when there is one part per author.
+    pub(crate) fn author_sort_names(&self) -> Vec<Option<String>> {
+        let authors = self
+            .creators
+            .iter()
+            .filter(|creator| creator.is_author())
+            .collect::<Vec<_>>();
+
+        if let Some(shared) = authors.first().and_then(|first| first.file_as.as_deref()) {
+            let all_shared = authors
+                .iter()
+                .all(|author| author.file_as.as_deref() == Some(shared));
+            if authors.len() > 1 && all_shared {
+                let parts = shared.split(" & ").collect::<Vec<_>>();
+                if parts.len() != authors.len() {
+                    return vec![None; authors.len()];
+                }
+                return parts
+                    .into_iter()
+                    .map(|part| Some(part.to_string()))
+                    .collect();
+            }
+        }
+
+        authors
+            .iter()
+            .map(|author| author.file_as.clone())
+            .collect()
+    }
+
+    /// Turns the metadata into an entry that can be added to a library with
+    /// `files` as its formats.
+    pub fn into_library_entry(self, files: Vec<PathBuf>) -> NewLibraryEntryDto {
+        let sort_names = self.author_sort_names();
+        let authors = self
+            .creators
+            .iter()
+            .filter(|creator| creator.is_author())
+            .zip(sort_names)
+            .map(|(creator, sortable_name)| NewAuthorDto {
+                full_name: creator.name.clone(),
+                sortable_name: sortable_name.unwrap_or_default(),
+                external_url: self.author_links.get(&creator.name).cloned(),
+            })
+            .collect();
+
+        NewLibraryEntryDto {
+            book: NewBookDto {
+                title: self.title.unwrap_or_else(|| "Unknown".to_string()),
+                timestamp: self.timestamp,
+                pubdate: self.pubdate,
+                series_index: self.series_index.unwrap_or(1.0),
+                flags: 1,
+                has_cover: None,
+            },
+            authors,
+            publishers: self
+                .publisher
+                .into_iter()
+                .map(|name| NewPublisherDto { name, sort: None })
+                .collect(),
+            identifiers: self
+                .identifiers
+                .into_iter()
+                .map(|(label, value)| UpsertBookIdentifier {
+                    book_id: 0,
+                    id: None,
+                    label,
+                    value,
+                })
+                .collect(),
+            language: self
+                .languages
+                .into_iter()
+                .next()
+                .map(|lang_code| NewLanguageDto { lang_code }),
+            tags: self
+                .tags
+                .into_iter()
+                .map(|name| NewTagDto { name })
+                .collect(),
+            rating: self.rating.map(|rating| NewRatingDto { rating }),
+            series: self.series.map(|name| NewSeriesDto { name }),
+            description: self.description,
+            files: Some(
+                files
+                    .into_iter()
+                    .map(|path| NewLibraryFileDto { path })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Whether `text` looks like an ISBN-10 or ISBN-13, with or without
+/// hyphens. The check digit is not verified.
+fn is_isbn(text: &str) -> bool {
+    let digits = text.replace(['-', ' '], "");
+    if !digits.is_ascii() {
+        return false;
+    }
+    let (body, check) = digits.split_at(digits.len().saturating_sub(1));
+    let check_ok = check
+        .chars()
+        .all(|c| c.is_ascii_digit() || (digits.len() == 10 && c.eq_ignore_ascii_case(&'x')));
+    matches!(digits.len(), 10 | 13) && body.chars().all(|c| c.is_ascii_digit()) && check_ok
+}
+
+/// Collects the children of `<metadata>`, with their text.
+fn parse_elements(xml: &str) -> Result<Vec<Element>, quick_xml::Error> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut elements = Vec::new();
+    let mut in_metadata = false;
+    let mut open: Option<Element> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let name = local_name(&e);
+                if name == "metadata" {
+                    in_metadata = true;
+                } else if in_metadata {
+                    open = Some(element(&e, name));
+                }
+            }
+            Event::Empty(e) if in_metadata => elements.push(element(&e, local_name(&e))),
+            Event::Text(t) => {
+                if let Some(element) = open.as_mut() {
+                    // Older libcalibre versions wrote text unescaped, so
+                    // fall back to the raw text rather than failing.
+                    match t.unescape() {
+                        Ok(text) => element.text.push_str(&text),
+                        Err(_) => element.text.push_str(&String::from_utf8_lossy(&t)),
+                    }
+                }
+            }
+            Event::CData(t) => {
+                if let Some(element) = open.as_mut() {
+                    element.text.push_str(&String::from_utf8_lossy(&t));
+                }
+            }
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"metadata" {
+                    in_metadata = false;
+                } else if let Some(element) = open.take() {
+                    elements.push(element);
+                }
+            }
+            // A truncated file, e.g. one cut short by a crash
+            Event::Eof if in_metadata => {
+                return Err(IllFormedError::MissingEndTag("metadata".to_string()).into());
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(elements)
+}
+
+fn local_name(e: &BytesStart) -> String {
+    String::from_utf8_lossy(e.local_name().as_ref()).into_owned()
+}
+
+/// Attributes are keyed by local name, so `opf:scheme` and `scheme` are
+/// both found as `"scheme"`.
+fn element(e: &BytesStart, name: String) -> Element {
+    let attributes = e
+        .attributes()
+        .flatten()
+        .filter_map(|attr| {
+            let key = String::from_utf8_lossy(attr.key.local_name().as_ref()).into_owned();
+            let value = attr.unescape_value().ok()?.into_owned();
+            Some((key, value))
+        })
+        .collect();
+
+    Element {
+        name,
+        attributes,
+        text: String::new(),
+    }
+}
+
+/// Parses the dates Calibre and other tools write, from full timestamps
+/// down to a bare year.
+pub(crate) fn parse_date(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    let padded = match text.len() {
+        4 => format!("{text}-01-01"),
+        7 => format!("{text}-01"),
+        _ => text.get(..10)?.to_string(),
+    };
+    NaiveDate::parse_from_str(&padded, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc())
+}
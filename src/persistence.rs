@@ -1,3 +1,4 @@
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
 use diesel::sql_types::Text;
 use regex::Regex;
@@ -37,13 +38,15 @@ pub fn sort_book_title(title: String) -> String {
     title.clone()
 }
 
-pub fn establish_connection(db_path: &str) -> Result<diesel::SqliteConnection, ()> {
+pub fn establish_connection(
+    db_path: &str,
+) -> Result<diesel::SqliteConnection, diesel::ConnectionError> {
     // Setup custom SQL functions. Required because Calibre does this.
     // See: https://github.com/kovidgoyal/calibre/blob/7f3ccb333d906f5867636dd0dc4700b495e5ae6f/src/calibre/library/database.py#L55-L70
     define_sql_function!(fn title_sort(title: Text) -> Text);
     define_sql_function!(fn uuid4() -> Text);
 
-    let mut connection = diesel::SqliteConnection::establish(db_path).or(Err(()))?;
+    let mut connection = diesel::SqliteConnection::establish(db_path)?;
 
     // Register SQL function implementations. Ignore any errors.
     let _ = title_sort_utils::register_impl(&mut connection, sort_book_title);
@@ -51,3 +54,29 @@ pub fn establish_connection(db_path: &str) -> Result<diesel::SqliteConnection, (
 
     Ok(connection)
 }
+
+/// The full Calibre schema for an empty library, including indices, triggers,
+/// the `library_id` row and default preferences.
+const LIBRARY_SCHEMA: &str = include_str!("../resources/metadata_sqlite.sql");
+
+/// Creates a new Calibre database at `db_path` and returns a connection to it.
+///
+/// Fails if a file already exists at `db_path`, so an existing library is
+/// never overwritten.
+pub fn create_database(db_path: &str) -> Result<diesel::SqliteConnection, diesel::ConnectionError> {
+    if std::path::Path::new(db_path).exists() {
+        return Err(diesel::ConnectionError::BadConnection(format!(
+            "Database already exists: {db_path}"
+        )));
+    }
+
+    let mut connection = establish_connection(db_path)?;
+    match connection.batch_execute(LIBRARY_SCHEMA) {
+        Ok(_) => Ok(connection),
+        Err(e) => {
+            drop(connection);
+            let _ = std::fs::remove_file(db_path);
+            Err(diesel::ConnectionError::CouldntSetupConfiguration(e))
+        }
+    }
+}
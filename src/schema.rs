@@ -240,3 +240,5 @@ diesel::joinable!(identifiers -> books (book));
 
 diesel::allow_tables_to_appear_in_same_query!(books_authors_link, books, authors);
 diesel::allow_tables_to_appear_in_same_query!(books, identifiers);
+diesel::allow_tables_to_appear_in_same_query!(books_tags_link, tags);
+diesel::allow_tables_to_appear_in_same_query!(books_publishers_link, publishers);
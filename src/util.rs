@@ -1,6 +1,8 @@
-use isolang::Language;
+use std::collections::HashSet;
 use std::path::Path;
 
+use isolang::Language;
+
 #[derive(Clone)]
 pub struct ValidDbPath {
     pub(crate) library_path: String,
@@ -21,21 +23,103 @@ pub fn get_db_path(library_root: &str) -> Option<ValidDbPath> {
     }
 }
 
+/// For a given library root directory, return the path the SQLite database
+/// should be created at, if no database exists there yet.
+pub(crate) fn new_db_path(library_root: &str) -> Option<ValidDbPath> {
+    let db_path = Path::new(library_root).join("metadata.db");
+    if db_path.exists() {
+        None
+    } else {
+        Some(ValidDbPath {
+            database_path: db_path.to_str().map(|s| s.to_string())?,
+            library_path: library_root.to_string(),
+        })
+    }
+}
+
 pub fn canonicalize_lang(raw: &str) -> Option<Language> {
     let raw = raw.trim().to_lowercase();
     if raw.is_empty() {
         return None;
     }
 
-    if let Some(lang) = Language::from_name_case_insensitive(&raw) {
-        return Some(lang);
-    }
-
-    match raw.len() {
-        2 => Language::from_639_1(&raw),
-        3 => Language::from_639_3(&raw),
+    // Codes come first: some are also language names, e.g. "en" is the En
+    // language of Vietnam, not English. Region and script subtags, as in
+    // the `en-US` EPUBs use, are dropped.
+    let mut subtags = raw.split(['-', '_']);
+    let code = subtags.next().unwrap_or_default();
+    let is_region_or_script = |subtag: &str| match subtag.len() {
+        2 | 4 => subtag.chars().all(|c| c.is_ascii_alphabetic()),
+        3 => subtag.chars().all(|c| c.is_ascii_digit()),
+        _ => false,
+    };
+    let code = match subtags.all(is_region_or_script) {
+        true => code,
+        false => raw.as_str(),
+    };
+    let by_code = match code.len() {
+        2 => Language::from_639_1(code),
+        3 => Language::from_639_3(code),
         _ => None,
-    }
+    };
+
+    by_code.or_else(|| Language::from_name_case_insensitive(&raw))
+}
+
+/// The tags Calibre's comments editor produces and keeps in descriptions.
+const DESCRIPTION_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "div",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "s",
+    "small",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+
+/// Cleans a book description down to the HTML Calibre allows in comments,
+/// dropping scripts, styles, event handlers and unknown tags.
+///
+/// Returns `None` if nothing but whitespace is left.
+pub fn sanitize_description(html: &str) -> Option<String> {
+    let cleaned = ammonia::Builder::default()
+        .tags(DESCRIPTION_TAGS.iter().copied().collect::<HashSet<_>>())
+        .generic_attributes(HashSet::from(["dir", "lang", "title"]))
+        .link_rel(None)
+        .clean(html)
+        .to_string();
+
+    let trimmed = cleaned.trim();
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
 }
 
 trait LanguageExt {
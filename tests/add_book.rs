@@ -0,0 +1,110 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use libcalibre::client::add_book::ImportWarning;
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::language::NewLanguageDto;
+use libcalibre::dtos::library::{NewLibraryEntryDto, NewLibraryFileDto};
+
+fn new_entry(title: &str, files: Vec<NewLibraryFileDto>) -> NewLibraryEntryDto {
+    NewLibraryEntryDto {
+        tags: vec![common::tag("Fiction")],
+        files: Some(files),
+        ..common::new_entry(title)
+    }
+}
+
+fn library_entries(library_root: &Path) -> Vec<String> {
+    let mut entries = fs::read_dir(library_root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    entries.sort();
+    entries
+}
+
+#[test]
+fn add_book_moves_staged_files_into_place() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let source = sources.path().join("book.txt");
+    fs::write(&source, b"Once upon a time").unwrap();
+
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let report = client
+        .add_book(new_entry(
+            "Staged",
+            vec![NewLibraryFileDto { path: source }],
+        ))
+        .unwrap();
+
+    let books = client.find_all().unwrap();
+    assert_eq!(books.len(), 1);
+    assert_eq!(report.book.book.id, books[0].book.id);
+    assert_eq!(books[0].book.path, "Jane Doe/Staged (1)");
+    let book_dir = library.path().join(&books[0].book.path);
+    assert!(book_dir.join("metadata.opf").is_file());
+    assert_eq!(
+        report.file_paths,
+        vec![book_dir.join("Staged - Jane Doe.txt")]
+    );
+    assert!(report.file_paths[0].is_file());
+    assert_eq!(
+        library_entries(library.path()),
+        vec!["Jane Doe", "metadata.db"]
+    );
+}
+
+#[test]
+fn add_book_reports_linked_ids_and_warnings() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    // Not a real EPUB, so cover extraction fails
+    let source = sources.path().join("book.epub");
+    fs::write(&source, b"not a zip archive").unwrap();
+
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let mut entry = new_entry("Warnings", vec![NewLibraryFileDto { path: source }]);
+    entry.language = Some(NewLanguageDto {
+        lang_code: "not-a-language".to_string(),
+    });
+    let report = client.add_book(entry).unwrap();
+
+    assert_eq!(report.author_ids, vec![report.book.authors[0].id]);
+    assert_eq!(report.tag_ids.len(), 1);
+    assert_eq!(report.language_id, None);
+    assert_eq!(report.cover_path, None);
+    assert!(!report.book.book.has_cover.unwrap_or(false));
+    assert!(matches!(
+        report.warnings.as_slice(),
+        [
+            ImportWarning::LanguageNotRecognised(code),
+            ImportWarning::CoverExtractionFailed(_),
+        ] if code == "not-a-language"
+    ));
+}
+
+#[test]
+fn failed_add_book_leaves_no_rows_or_files() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let present = sources.path().join("book.txt");
+    fs::write(&present, b"Once upon a time").unwrap();
+    // The first file is copied before the second one is found to be missing
+    let files = vec![
+        NewLibraryFileDto { path: present },
+        NewLibraryFileDto {
+            path: sources.path().join("missing.pdf"),
+        },
+    ];
+
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    assert!(client.add_book(new_entry("Half Copied", files)).is_err());
+
+    assert!(client.find_all().unwrap().is_empty());
+    assert!(client.list_all_authors().unwrap().is_empty());
+    assert!(client.get_all_tags().unwrap().is_empty());
+    assert_eq!(library_entries(library.path()), vec!["metadata.db"]);
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use libcalibre::client::CalibreClient;
+use libcalibre::metadata::{cbz, library_entry_from_path};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+const COMIC_INFO: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<ComicInfo xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <Title>The Long Night</Title>
+  <Series>Night Watch</Series>
+  <Number>3</Number>
+  <Summary>&lt;p&gt;Dark.&lt;/p&gt;</Summary>
+  <Year>2021</Year>
+  <Month>6</Month>
+  <Writer>Jane Doe, John Roe</Writer>
+  <Penciller>John Roe, Ann Poe</Penciller>
+  <Publisher>Nightfall Press</Publisher>
+  <Genre>Horror</Genre>
+  <Tags>Vampires, Noir</Tags>
+  <Web>https://example.com/night-watch/3 https://example.com/other</Web>
+  <PageCount>3</PageCount>
+  <LanguageISO>en</LanguageISO>
+  <Pages>
+    <Page Image="0" Type="InnerCover"/>
+    <Page Image="2" Type="FrontCover"/>
+  </Pages>
+</ComicInfo>"#;
+
+/// Writes a CBZ whose pages, in natural order, are `p2`, `p09` and `p10`.
+fn write_cbz(path: &Path, comic_info: Option<&str>) {
+    let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+    let mut entries = vec![
+        ("__MACOSX/._p2.jpg", "resource fork"),
+        ("pages/p10.jpg", "page 10"),
+        ("pages/p2.jpg", "page 2"),
+        ("pages/p09.png", "page 9"),
+    ];
+    if let Some(comic_info) = comic_info {
+        entries.push(("ComicInfo.xml", comic_info));
+    }
+    for (name, contents) in entries {
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[test]
+fn the_first_page_in_natural_order_is_the_cover() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("comic.cbz");
+    write_cbz(&path, None);
+
+    assert_eq!(cbz::read_cover(&path).unwrap().unwrap(), b"page 2");
+    assert_eq!(cbz::read_comic_info(&path).unwrap(), None);
+    let entry = library_entry_from_path(&path).unwrap();
+    assert_eq!(entry.book.title, "comic");
+}
+
+#[test]
+fn malformed_comic_info_falls_back_to_the_first_page() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("comic.cbz");
+    write_cbz(&path, Some("<ComicInfo><Title>Oops</Series></ComicInfo>"));
+
+    assert!(cbz::read_comic_info(&path).is_err());
+    assert_eq!(cbz::read_cover(&path).unwrap().unwrap(), b"page 2");
+}
+
+#[test]
+fn comic_info_fills_the_library_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let library = tempfile::tempdir().unwrap();
+    let path = dir.path().join("comic.cbz");
+    write_cbz(&path, Some(COMIC_INFO));
+
+    let info = cbz::read_comic_info(&path).unwrap().unwrap();
+    assert_eq!(info.page_count, Some(3));
+    assert_eq!(info.front_cover, Some(2));
+    assert_eq!(cbz::read_cover(&path).unwrap().unwrap(), b"page 10");
+
+    let entry = library_entry_from_path(&path).unwrap();
+    assert_eq!(entry.book.title, "The Long Night");
+    assert_eq!(entry.book.series_index, 3.0);
+    assert_eq!(
+        entry.book.pubdate.map(|date| date.to_rfc3339()).as_deref(),
+        Some("2021-06-01T00:00:00+00:00")
+    );
+    assert_eq!(entry.series.as_ref().unwrap().name, "Night Watch");
+    assert_eq!(
+        entry
+            .authors
+            .iter()
+            .map(|author| author.full_name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["Jane Doe", "John Roe", "Ann Poe"]
+    );
+    assert_eq!(entry.publishers[0].name, "Nightfall Press");
+    assert_eq!(
+        entry
+            .tags
+            .iter()
+            .map(|tag| tag.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["Horror", "Vampires", "Noir"]
+    );
+    assert_eq!(entry.identifiers[0].label, "url");
+    assert_eq!(
+        entry.identifiers[0].value,
+        "https://example.com/night-watch/3"
+    );
+    assert_eq!(entry.description.as_deref(), Some("<p>Dark.</p>"));
+
+    // The cover comes from inside the archive, so the source folder's
+    // other contents do not matter
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let report = client.add_book(entry).unwrap();
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    assert!(report.language_id.is_some());
+    assert_eq!(fs::read(report.cover_path.unwrap()).unwrap(), b"page 10");
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use diesel::connection::SimpleConnection;
+use libcalibre::client::check_library::{BadPath, CoverMismatch, LibraryFixes, OrphanedRows};
+use libcalibre::client::{update_book_data_for_path, CalibreClient};
+use libcalibre::dtos::library::NewLibraryEntryDto;
+use libcalibre::persistence::establish_connection;
+
+/// Adds a book by `author`, tagged with its own title so each book has
+/// an item only it uses.
+fn add_tagged_book(client: &mut CalibreClient, sources: &Path, title: &str, author: &str) -> i32 {
+    let entry = NewLibraryEntryDto {
+        authors: vec![common::author(author)],
+        tags: vec![common::tag(title)],
+        ..common::new_entry(title)
+    };
+    common::add_txt_book(client, sources, entry)
+}
+
+#[test]
+fn a_fresh_library_is_clean() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    add_tagged_book(&mut client, sources.path(), "Clean", "Jane Doe");
+
+    let check = client.check_library(LibraryFixes::default()).unwrap();
+    assert!(check.is_clean(), "{check:?}");
+}
+
+#[test]
+fn problems_are_reported_and_fixed() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let damaged = add_tagged_book(&mut client, sources.path(), "Damaged", "Jane Doe");
+    let moved = add_tagged_book(&mut client, sources.path(), "Moved", "Jane Doe");
+    let forgotten = add_tagged_book(&mut client, sources.path(), "Forgotten", "John Roe");
+    let damaged_dir = library.path().join("Jane Doe").join("Damaged (1)");
+    let forgotten_dir = library.path().join("John Roe").join("Forgotten (3)");
+
+    // The file changed size, a cover and two stray files appeared
+    fs::write(damaged_dir.join("Damaged - Jane Doe.txt"), "Longer now").unwrap();
+    fs::write(damaged_dir.join("cover.jpg"), "cover").unwrap();
+    fs::write(damaged_dir.join("Damaged - Jane Doe.pdf"), "pdf").unwrap();
+    fs::write(damaged_dir.join("notes.md"), "notes").unwrap();
+    // A file went missing and the book's path was mangled
+    let moved_dir = library.path().join("Jane Doe").join("Moved (2)");
+    fs::remove_file(moved_dir.join("Moved - Jane Doe.txt")).unwrap();
+    client
+        .client_v2
+        .books()
+        .update(moved, update_book_data_for_path(Path::new("../Moved")))
+        .unwrap();
+    // The book's rows are gone, but its folder and author are not
+    client.client_v2.books().delete(forgotten).unwrap();
+    client
+        .client_v2
+        .books()
+        .mark_metadata_dirtied(&[99])
+        .unwrap();
+
+    let check = client.check_library(LibraryFixes::default()).unwrap();
+    assert_eq!(check.missing_files, vec![]);
+    assert_eq!(
+        check
+            .size_mismatches
+            .iter()
+            .map(|m| (m.recorded, m.actual))
+            .collect::<Vec<_>>(),
+        vec![(7, 10)]
+    );
+    assert_eq!(
+        check.cover_mismatches,
+        vec![CoverMismatch {
+            book_id: damaged,
+            has_cover: false,
+        }]
+    );
+    assert_eq!(
+        check
+            .extra_files
+            .iter()
+            .map(|file| (file.path.clone(), file.format.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                damaged_dir.join("Damaged - Jane Doe.pdf"),
+                Some("PDF".to_string())
+            ),
+            (damaged_dir.join("notes.md"), None),
+        ]
+    );
+    assert_eq!(
+        check.bad_paths,
+        vec![BadPath {
+            book_id: moved,
+            path: "../Moved".to_string(),
+        }]
+    );
+    // Until its path is fixed, the moved book's folder looks unused too
+    assert_eq!(check.extra_folders, vec![moved_dir, forgotten_dir]);
+    assert_eq!(
+        check.orphaned_rows,
+        vec![OrphanedRows {
+            table: "metadata_dirtied".to_string(),
+            column: "book".to_string(),
+            count: 1,
+        }]
+    );
+    assert_eq!(check.unused_authors.len(), 1);
+    assert_eq!(check.unused_tags.len(), 1);
+
+    let fixes = LibraryFixes {
+        missing_files: true,
+        extra_files: true,
+        size_mismatches: true,
+        cover_mismatches: true,
+        bad_paths: true,
+        extra_folders: true,
+        orphaned_rows: true,
+        unused_items: true,
+    };
+    let check = client.check_library(fixes).unwrap();
+    assert!(
+        check.failed_folders.is_empty(),
+        "{:?}",
+        check.failed_folders
+    );
+
+    // With its path back, the moved book's missing file shows up
+    let check = client.check_library(fixes).unwrap();
+    assert_eq!(check.missing_files.len(), 1);
+    assert_eq!(check.missing_files[0].book_id, moved);
+
+    let check = client.check_library(LibraryFixes::default()).unwrap();
+    assert_eq!(
+        check
+            .extra_files
+            .iter()
+            .map(|f| f.path.clone())
+            .collect::<Vec<_>>(),
+        vec![damaged_dir.join("notes.md")]
+    );
+    assert!(check.missing_files.is_empty());
+    assert!(check.size_mismatches.is_empty());
+    assert!(check.cover_mismatches.is_empty());
+    assert!(check.bad_paths.is_empty());
+    assert!(check.extra_folders.is_empty());
+    assert!(check.orphaned_rows.is_empty());
+    assert!(check.unused_authors.is_empty() && check.unused_tags.is_empty());
+
+    let book = client.find_book_with_authors(damaged).unwrap();
+    assert_eq!(book.book.has_cover, Some(true));
+    let mut formats = book
+        .files
+        .iter()
+        .map(|f| f.format.clone())
+        .collect::<Vec<_>>();
+    formats.sort();
+    assert_eq!(formats, vec!["PDF", "TXT"]);
+    // The forgotten book is back with its old ID
+    let restored = client.find_book_with_authors(forgotten).unwrap();
+    assert_eq!(restored.book.title, "Forgotten");
+    assert_eq!(restored.authors[0].name, "John Roe");
+}
+
+#[test]
+fn sizes_too_large_to_record_are_not_fixed() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    add_tagged_book(&mut client, sources.path(), "Huge", "Jane Doe");
+
+    // A sparse file, so the test does not need 3 GiB of disk
+    let path = library
+        .path()
+        .join("Jane Doe")
+        .join("Huge (1)")
+        .join("Huge - Jane Doe.txt");
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(3 << 30)
+        .unwrap();
+    let extra = path.with_extension("pdf");
+    fs::File::create(&extra).unwrap().set_len(3 << 30).unwrap();
+
+    let fixes = LibraryFixes {
+        size_mismatches: true,
+        extra_files: true,
+        ..LibraryFixes::default()
+    };
+    let check = client.check_library(fixes).unwrap();
+    assert_eq!(check.unfixable_sizes, check.size_mismatches);
+    assert_eq!(check.unfixable_sizes.len(), 1);
+    assert_eq!(check.unfixable_sizes[0].path, path);
+
+    // The recorded size is left alone, not wrapped around, and the extra
+    // file is not added with one
+    let check = client.check_library(LibraryFixes::default()).unwrap();
+    assert_eq!(check.size_mismatches[0].recorded, 4);
+    assert!(check.unfixable_sizes.is_empty());
+    assert_eq!(check.extra_files.len(), 1);
+    assert_eq!(check.extra_files[0].path, extra);
+}
+
+#[test]
+fn items_with_many_links_are_checked() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    add_tagged_book(&mut client, sources.path(), "Clean", "Jane Doe");
+
+    // More link rows than SQLite builds commonly allow bound variables in
+    // one statement: 500 books, each by 500 authors and with 500 tags
+    let mut conn =
+        establish_connection(&library.path().join("metadata.db").to_string_lossy()).unwrap();
+    conn.batch_execute(
+        "CREATE TEMP TABLE n AS WITH RECURSIVE n(i) AS
+             (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500) SELECT i FROM n;
+         INSERT INTO books (title, path) SELECT 'Book ' || i, '' FROM n;
+         INSERT INTO authors (name, sort) SELECT 'Author ' || i, 'Author ' || i FROM n;
+         INSERT INTO tags (name) SELECT 'Tag ' || i FROM n;
+         INSERT INTO books_authors_link (book, author)
+         SELECT books.id, authors.id FROM books, authors
+         WHERE books.path = '' AND authors.name LIKE 'Author %';
+         INSERT INTO books_tags_link (book, tag)
+         SELECT books.id, tags.id FROM books, tags
+         WHERE books.path = '' AND tags.name LIKE 'Tag %';",
+    )
+    .unwrap();
+
+    let check = client.check_library(LibraryFixes::default()).unwrap();
+    assert!(check.unused_authors.is_empty());
+    assert!(check.unused_tags.is_empty());
+}
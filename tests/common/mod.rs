@@ -0,0 +1,75 @@
+//! Fixtures shared by the integration tests. Each test file uses only some
+//! of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::author::NewAuthorDto;
+use libcalibre::dtos::book::NewBookDto;
+use libcalibre::dtos::library::{NewLibraryEntryDto, NewLibraryFileDto};
+use libcalibre::dtos::tag::NewTagDto;
+
+pub fn new_book(title: &str) -> NewBookDto {
+    NewBookDto {
+        title: title.to_string(),
+        timestamp: None,
+        pubdate: None,
+        series_index: 1.0,
+        flags: 1,
+        has_cover: None,
+    }
+}
+
+pub fn author(name: &str) -> NewAuthorDto {
+    NewAuthorDto {
+        full_name: name.to_string(),
+        sortable_name: String::new(),
+        external_url: None,
+    }
+}
+
+pub fn tag(name: &str) -> NewTagDto {
+    NewTagDto {
+        name: name.to_string(),
+    }
+}
+
+/// An entry for a book by Jane Doe with no files and no other metadata.
+pub fn new_entry(title: &str) -> NewLibraryEntryDto {
+    NewLibraryEntryDto {
+        book: new_book(title),
+        authors: vec![author("Jane Doe")],
+        publishers: vec![],
+        identifiers: vec![],
+        language: None,
+        tags: vec![],
+        rating: None,
+        series: None,
+        description: None,
+        files: Some(vec![]),
+    }
+}
+
+/// Writes `<title>.txt` to `sources`, with the title as its text.
+pub fn txt_file(sources: &Path, title: &str) -> NewLibraryFileDto {
+    let path = sources.join(format!("{title}.txt"));
+    fs::write(&path, title).unwrap();
+    NewLibraryFileDto { path }
+}
+
+/// Adds `entry` with a text file made by [`txt_file`] as its only file,
+/// and returns the book's ID.
+pub fn add_txt_book(client: &mut CalibreClient, sources: &Path, entry: NewLibraryEntryDto) -> i32 {
+    let file = txt_file(sources, &entry.book.title);
+    client
+        .add_book(NewLibraryEntryDto {
+            files: Some(vec![file]),
+            ..entry
+        })
+        .unwrap()
+        .book
+        .book
+        .id
+}
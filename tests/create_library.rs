@@ -0,0 +1,109 @@
+use std::fs;
+
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Text};
+use libcalibre::client::CalibreClient;
+use libcalibre::CalibreError;
+
+#[derive(QueryableByName)]
+struct Name {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+fn names(conn: &mut SqliteConnection, sql: &str) -> Vec<String> {
+    diesel::sql_query(sql)
+        .load::<Name>(conn)
+        .unwrap()
+        .into_iter()
+        .map(|row| row.name)
+        .collect()
+}
+
+#[test]
+fn create_library_sets_up_calibres_schema() {
+    let root = tempfile::tempdir().unwrap();
+    // Missing folders are created
+    let library = root.path().join("Books").join("Calibre Library");
+    CalibreClient::create_library(library.to_str().unwrap()).unwrap();
+
+    let db_path = library.join("metadata.db");
+    let mut conn = SqliteConnection::establish(db_path.to_str().unwrap()).unwrap();
+    let tables = names(
+        &mut conn,
+        "SELECT name FROM sqlite_master WHERE type = 'table'",
+    );
+    for table in [
+        "books",
+        "authors",
+        "books_authors_link",
+        "data",
+        "comments",
+        "identifiers",
+        "languages",
+        "publishers",
+        "ratings",
+        "series",
+        "tags",
+        "custom_columns",
+        "library_id",
+        "preferences",
+        "metadata_dirtied",
+    ] {
+        assert!(tables.iter().any(|name| name == table), "missing {table}");
+    }
+    let triggers = names(
+        &mut conn,
+        "SELECT name FROM sqlite_master WHERE type = 'trigger'",
+    );
+    for trigger in [
+        "books_insert_trg",
+        "books_update_trg",
+        "books_delete_trg",
+        "fkc_delete_on_authors",
+        "series_update_trg",
+    ] {
+        assert!(
+            triggers.iter().any(|name| name == trigger),
+            "missing {trigger}"
+        );
+    }
+
+    let uuids = names(&mut conn, "SELECT uuid AS name FROM library_id");
+    assert_eq!(uuids.len(), 1);
+    assert_eq!(uuids[0].len(), 36);
+    let mut preferences = names(&mut conn, "SELECT key AS name FROM preferences");
+    preferences.sort();
+    assert_eq!(
+        preferences,
+        vec![
+            "bools_are_tristate",
+            "grouped_search_terms",
+            "saved_searches",
+            "user_categories"
+        ]
+    );
+    let version = diesel::sql_query("SELECT user_version AS count FROM pragma_user_version")
+        .get_result::<Count>(&mut conn)
+        .unwrap();
+    assert_eq!(version.count, 26);
+}
+
+#[test]
+fn create_library_refuses_an_existing_library() {
+    let library = tempfile::tempdir().unwrap();
+    let root = library.path().to_str().unwrap();
+    CalibreClient::create_library(root).unwrap();
+    let db_path = library.path().join("metadata.db");
+    let before = fs::read(&db_path).unwrap();
+
+    let result = CalibreClient::create_library(root);
+    assert!(matches!(result, Err(CalibreError::InvalidInput(_))));
+    assert_eq!(fs::read(&db_path).unwrap(), before);
+}
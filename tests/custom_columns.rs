@@ -0,0 +1,243 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use serde_json::json;
+
+use common::new_entry;
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::custom_column::NewCustomColumnDto;
+use libcalibre::{CalibreError, CustomColumnDatatype, CustomColumnValue};
+
+fn column(label: &str, datatype: CustomColumnDatatype) -> NewCustomColumnDto {
+    NewCustomColumnDto {
+        label: label.to_string(),
+        name: label.to_uppercase(),
+        datatype,
+        is_multiple: false,
+        display: json!({}),
+    }
+}
+
+fn library() -> (tempfile::TempDir, CalibreClient, i32) {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = client.add_book(new_entry("Columns")).unwrap().book.book.id;
+    (library, client, book_id)
+}
+
+#[test]
+fn values_round_trip_for_every_stored_datatype() {
+    let (_library, mut client, book_id) = library();
+    let date = Utc.with_ymd_and_hms(2024, 2, 29, 13, 45, 0).unwrap();
+    let cases = [
+        (
+            column("genre", CustomColumnDatatype::Text),
+            CustomColumnValue::Text("Fantasy".to_string()),
+        ),
+        (
+            column("notes", CustomColumnDatatype::Comments),
+            CustomColumnValue::Text("<p>Long notes</p>".to_string()),
+        ),
+        (
+            column("arc", CustomColumnDatatype::Series),
+            CustomColumnValue::Series {
+                name: "Rivers".to_string(),
+                index: 2.5,
+            },
+        ),
+        (
+            NewCustomColumnDto {
+                display: json!({"enum_values": ["owned", "wanted"], "enum_colors": []}),
+                ..column("status", CustomColumnDatatype::Enumeration)
+            },
+            CustomColumnValue::Text("wanted".to_string()),
+        ),
+        (
+            column("finished", CustomColumnDatatype::Datetime),
+            CustomColumnValue::Datetime(date),
+        ),
+        (
+            column("pages", CustomColumnDatatype::Int),
+            CustomColumnValue::Int(412),
+        ),
+        (
+            column("price", CustomColumnDatatype::Float),
+            CustomColumnValue::Float(9.99),
+        ),
+        (
+            column("signed", CustomColumnDatatype::Bool),
+            CustomColumnValue::Bool(true),
+        ),
+        (
+            column("stars", CustomColumnDatatype::Rating),
+            CustomColumnValue::Rating(7),
+        ),
+    ];
+
+    for (dto, value) in cases {
+        let label = dto.label.clone();
+        let created = client.create_custom_column(dto).unwrap();
+        assert_eq!(created.normalized, created.kind().unwrap().is_normalized());
+
+        assert_eq!(
+            client.get_custom_column_value(book_id, &label).unwrap(),
+            None
+        );
+        client
+            .set_custom_column_value(book_id, &label, Some(value.clone()))
+            .unwrap();
+        assert_eq!(
+            client.get_custom_column_value(book_id, &label).unwrap(),
+            Some(value),
+            "#{label}"
+        );
+        client
+            .set_custom_column_value(book_id, &label, None)
+            .unwrap();
+        assert_eq!(
+            client.get_custom_column_value(book_id, &label).unwrap(),
+            None
+        );
+    }
+    assert_eq!(client.list_custom_columns().unwrap().len(), 9);
+}
+
+#[test]
+fn multiple_text_values_are_shared_between_books() {
+    let (_library, mut client, first) = library();
+    let second = client.add_book(new_entry("Second")).unwrap().book.book.id;
+    client
+        .create_custom_column(NewCustomColumnDto {
+            is_multiple: true,
+            ..column("moods", CustomColumnDatatype::Text)
+        })
+        .unwrap();
+
+    let moods = |values: &[&str]| {
+        Some(CustomColumnValue::TextList(
+            values.iter().map(|value| value.to_string()).collect(),
+        ))
+    };
+    client
+        .set_custom_column_value(first, "moods", moods(&["dark", "funny", "Dark"]))
+        .unwrap();
+    client
+        .set_custom_column_value(second, "moods", moods(&["FUNNY", "tense"]))
+        .unwrap();
+
+    assert_eq!(
+        client.get_custom_column_value(first, "moods").unwrap(),
+        moods(&["dark", "funny"])
+    );
+    // Matching is case-insensitive, so the second book shares "funny"
+    assert_eq!(
+        client.get_custom_column_value(second, "moods").unwrap(),
+        moods(&["funny", "tense"])
+    );
+
+    client
+        .set_custom_column_value(first, "moods", moods(&[]))
+        .unwrap();
+    assert_eq!(
+        client.get_custom_column_value(first, "moods").unwrap(),
+        None
+    );
+    assert_eq!(
+        client.get_custom_column_value(second, "moods").unwrap(),
+        moods(&["funny", "tense"])
+    );
+}
+
+#[test]
+fn values_that_do_not_fit_the_column_are_rejected() {
+    let (_library, mut client, book_id) = library();
+    client
+        .create_custom_column(NewCustomColumnDto {
+            display: json!({"enum_values": ["owned", "wanted"]}),
+            ..column("status", CustomColumnDatatype::Enumeration)
+        })
+        .unwrap();
+    client
+        .create_custom_column(column("stars", CustomColumnDatatype::Rating))
+        .unwrap();
+    client
+        .create_custom_column(NewCustomColumnDto {
+            display: json!({"composite_template": "{title}"}),
+            ..column("label", CustomColumnDatatype::Composite)
+        })
+        .unwrap();
+
+    for (label, value) in [
+        ("status", CustomColumnValue::Text("lost".to_string())),
+        ("status", CustomColumnValue::Int(1)),
+        ("stars", CustomColumnValue::Rating(11)),
+        ("label", CustomColumnValue::Text("x".to_string())),
+    ] {
+        assert!(
+            matches!(
+                client.set_custom_column_value(book_id, label, Some(value)),
+                Err(CalibreError::InvalidInput(_))
+            ),
+            "#{label}"
+        );
+    }
+    assert!(matches!(
+        client.get_custom_column_value(book_id, "missing"),
+        Err(CalibreError::NotFound(_))
+    ));
+    assert!(matches!(
+        client.create_custom_column(column("Bad Label", CustomColumnDatatype::Int)),
+        Err(CalibreError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        client.create_custom_column(column("status", CustomColumnDatatype::Int)),
+        Err(CalibreError::ConstraintViolation(_))
+    ));
+}
+
+#[test]
+fn deleting_a_column_or_book_removes_its_values() {
+    let (_library, mut client, book_id) = library();
+    let genre = client
+        .create_custom_column(column("genre", CustomColumnDatatype::Text))
+        .unwrap();
+    client
+        .create_custom_column(column("pages", CustomColumnDatatype::Int))
+        .unwrap();
+    client
+        .set_custom_column_value(
+            book_id,
+            "genre",
+            Some(CustomColumnValue::Text("Horror".to_string())),
+        )
+        .unwrap();
+    client
+        .set_custom_column_value(book_id, "pages", Some(CustomColumnValue::Int(10)))
+        .unwrap();
+
+    client.delete_custom_column(genre.id).unwrap();
+    let remaining = client.list_custom_columns().unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].label, "pages");
+
+    // A new column may reuse the label
+    client
+        .create_custom_column(column("genre", CustomColumnDatatype::Text))
+        .unwrap();
+    assert_eq!(
+        client.get_custom_column_value(book_id, "genre").unwrap(),
+        None
+    );
+
+    client.delete_book(book_id).unwrap();
+    let book_id = client
+        .restore_book_from_trash(book_id)
+        .unwrap()
+        .book
+        .book
+        .id;
+    assert_eq!(
+        client.get_custom_column_value(book_id, "pages").unwrap(),
+        None
+    );
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use std::fs;
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::book::UpdateBookDto;
+use libcalibre::dtos::library::{NewLibraryEntryDto, NewLibraryFileDto, UpdateLibraryEntryDto};
+use libcalibre::persistence::establish_connection;
+use libcalibre::CalibreError;
+
+fn new_entry(title: &str, tag: &str, files: Vec<NewLibraryFileDto>) -> NewLibraryEntryDto {
+    NewLibraryEntryDto {
+        tags: vec![common::tag(tag)],
+        files: Some(files),
+        ..common::new_entry(title)
+    }
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+fn count_rows(conn: &mut SqliteConnection, sql: &str) -> i64 {
+    diesel::sql_query(sql)
+        .get_result::<Count>(conn)
+        .unwrap()
+        .count
+}
+
+#[test]
+fn delete_book_removes_rows_folder_and_unused_items() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let source = sources.path().join("book.txt");
+    fs::write(&source, b"Once upon a time").unwrap();
+
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let doomed = client
+        .add_book(new_entry(
+            "Doomed",
+            "Only Doomed",
+            vec![NewLibraryFileDto { path: source }],
+        ))
+        .unwrap()
+        .book;
+    let kept = client
+        .add_book(new_entry("Kept", "Only Kept", vec![]))
+        .unwrap()
+        .book;
+    client
+        .update_book(
+            doomed.book.id,
+            UpdateLibraryEntryDto {
+                book: UpdateBookDto {
+                    is_read: Some(true),
+                    ..Default::default()
+                },
+                author_id_list: None,
+                description: None,
+            },
+        )
+        .unwrap();
+    let doomed_dir = library.path().join(&doomed.book.path);
+    assert!(doomed_dir.is_dir());
+
+    client.delete_book(doomed.book.id).unwrap();
+
+    let books = client.find_all().unwrap();
+    assert_eq!(books.len(), 1);
+    assert_eq!(books[0].book.id, kept.book.id);
+    assert!(!doomed_dir.exists());
+
+    // The shared author stays, the tag only the deleted book used does not
+    assert_eq!(client.list_all_authors().unwrap().len(), 1);
+    let tags = client.get_all_tags().unwrap();
+    assert_eq!(
+        tags.iter().map(|tag| tag.name.as_str()).collect::<Vec<_>>(),
+        vec!["Only Kept"]
+    );
+
+    let mut conn =
+        establish_connection(&library.path().join("metadata.db").to_string_lossy()).unwrap();
+    let id = doomed.book.id;
+    for table in [
+        "data",
+        "books_authors_link",
+        "books_tags_link",
+        "custom_column_1",
+    ] {
+        assert_eq!(
+            count_rows(
+                &mut conn,
+                &format!("SELECT COUNT(*) AS count FROM {table} WHERE book = {id}")
+            ),
+            0,
+            "{table} still refers to the deleted book"
+        );
+    }
+}
+
+#[test]
+fn deleting_a_missing_book_is_not_found() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+
+    assert!(matches!(
+        client.delete_book(42),
+        Err(CalibreError::NotFound(_))
+    ));
+}
+
+#[test]
+fn failed_delete_keeps_the_book_folder() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book = client
+        .add_book(new_entry("Guarded", "Fiction", vec![]))
+        .unwrap()
+        .book;
+    let book_dir = library.path().join(&book.book.path);
+
+    // Deleting the book row fails after its link rows are already gone
+    let mut conn =
+        establish_connection(&library.path().join("metadata.db").to_string_lossy()).unwrap();
+    conn.batch_execute(
+        "CREATE TRIGGER refuse_delete BEFORE DELETE ON books
+         BEGIN SELECT RAISE(ABORT, 'refused'); END;",
+    )
+    .unwrap();
+
+    assert!(client.delete_book(book.book.id).is_err());
+    assert!(book_dir.is_dir());
+    assert_eq!(client.find_all().unwrap().len(), 1);
+    assert_eq!(client.get_all_tags().unwrap().len(), 1);
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::fs;
+
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::book::UpdateBookDto;
+use libcalibre::dtos::library::{NewLibraryEntryDto, UpdateLibraryEntryDto};
+
+fn new_entry(description: Option<&str>) -> NewLibraryEntryDto {
+    NewLibraryEntryDto {
+        description: description.map(str::to_string),
+        ..common::new_entry("Described")
+    }
+}
+
+fn update_description(description: Option<&str>) -> UpdateLibraryEntryDto {
+    UpdateLibraryEntryDto {
+        book: UpdateBookDto::default(),
+        author_id_list: None,
+        description: Some(description.map(str::to_string)),
+    }
+}
+
+#[test]
+fn description_is_sanitised_and_written_to_the_opf() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+
+    let report = client
+        .add_book(new_entry(Some(
+            r#"<p onclick="steal()">A <b>bold</b> tale &amp; more.</p><script>alert(1)</script><img src="x">"#,
+        )))
+        .unwrap();
+
+    let expected = "<p>A <b>bold</b> tale &amp; more.</p>";
+    assert_eq!(report.book.book_description_html.as_deref(), Some(expected));
+
+    let opf = fs::read_to_string(
+        library
+            .path()
+            .join(&report.book.book.path)
+            .join("metadata.opf"),
+    )
+    .unwrap();
+    assert!(opf.contains(
+        "<dc:description>&lt;p&gt;A &lt;b&gt;bold&lt;/b&gt; tale &amp;amp; more.&lt;/p&gt;</dc:description>"
+    ));
+}
+
+#[test]
+fn description_can_be_updated_and_cleared() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = client.add_book(new_entry(None)).unwrap().book.book.id;
+
+    let book = client
+        .update_book(book_id, update_description(Some("<p>First</p>")))
+        .unwrap();
+    assert_eq!(book.book_description_html.as_deref(), Some("<p>First</p>"));
+
+    let book = client
+        .update_book(book_id, update_description(Some("<p>Second</p>")))
+        .unwrap();
+    assert_eq!(book.book_description_html.as_deref(), Some("<p>Second</p>"));
+    let opf_path = library.path().join(&book.book.path).join("metadata.opf");
+    assert!(fs::read_to_string(&opf_path).unwrap().contains("Second"));
+
+    // Markup with no content left after sanitising counts as no description
+    let book = client
+        .update_book(book_id, update_description(Some("<script>x</script> ")))
+        .unwrap();
+    assert_eq!(book.book_description_html, None);
+
+    client
+        .update_book(book_id, update_description(Some("<p>Third</p>")))
+        .unwrap();
+    let book = client
+        .update_book(book_id, update_description(None))
+        .unwrap();
+    assert_eq!(book.book_description_html, None);
+    assert!(!fs::read_to_string(&opf_path)
+        .unwrap()
+        .contains("dc:description"));
+}
+
+#[test]
+fn description_survives_the_trash() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = client
+        .add_book(new_entry(Some("<p>Kept &lt;safe&gt;</p>")))
+        .unwrap()
+        .book
+        .book
+        .id;
+
+    client.delete_book(book_id).unwrap();
+    let restored = client.restore_book_from_trash(book_id).unwrap();
+    assert_eq!(
+        restored.book.book_description_html.as_deref(),
+        Some("<p>Kept &lt;safe&gt;</p>")
+    );
+}
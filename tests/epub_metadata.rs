@@ -0,0 +1,136 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{TimeZone, Utc};
+use libcalibre::client::CalibreClient;
+use libcalibre::metadata::{epub, library_entry_from_path};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+const PACKAGE: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:identifier id="uid">urn:uuid:0b9c4a54-2f6e-4a6f-9d55-5c1d7b0a1e11</dc:identifier>
+    <dc:identifier>978-0-441-01359-3</dc:identifier>
+    <dc:identifier opf:scheme="calibre">42</dc:identifier>
+    <dc:title id="title">Dune &amp; Sand</dc:title>
+    <dc:creator id="a1">Frank Herbert</dc:creator>
+    <meta refines="#a1" property="role" scheme="marc:relators">aut</meta>
+    <meta refines="#a1" property="file-as">Herbert, Frank</meta>
+    <dc:creator id="ill">John Schoenherr</dc:creator>
+    <meta refines="#ill" property="role" scheme="marc:relators">ill</meta>
+    <dc:publisher>Chilton Books</dc:publisher>
+    <dc:language>en-US</dc:language>
+    <dc:subject>Science Fiction</dc:subject>
+    <dc:subject>Desert</dc:subject>
+    <dc:description>&lt;p&gt;Spice.&lt;/p&gt;</dc:description>
+    <dc:date>1965-08-01</dc:date>
+    <meta property="dcterms:modified">2020-01-01T00:00:00Z</meta>
+    <meta name="calibre:series" content="Dune"/>
+    <meta name="calibre:series_index" content="1"/>
+    <meta name="calibre:timestamp" content="2019-05-05T00:00:00+00:00"/>
+  </metadata>
+  <manifest>
+    <item id="text" href="text.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine>
+    <itemref idref="text"/>
+  </spine>
+</package>"##;
+
+fn write_epub(path: &Path, package: &str) {
+    let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+    let stored = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    zip.start_file("mimetype", stored).unwrap();
+    zip.write_all(b"application/epub+zip").unwrap();
+    for (name, contents) in [
+        ("META-INF/container.xml", CONTAINER),
+        ("OEBPS/content.opf", package),
+        ("OEBPS/text.xhtml", "<html><body><p>Text</p></body></html>"),
+    ] {
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[test]
+fn epub_package_metadata_is_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dune.epub");
+    write_epub(&path, PACKAGE);
+
+    let metadata = epub::read_metadata(&path).unwrap();
+    assert_eq!(metadata.title.as_deref(), Some("Dune & Sand"));
+    assert_eq!(metadata.authors(), vec!["Frank Herbert"]);
+    assert_eq!(metadata.creators[1].role.as_deref(), Some("ill"));
+    assert_eq!(metadata.calibre_id, None);
+    assert_eq!(metadata.uuid, None);
+    assert_eq!(
+        metadata.identifiers,
+        vec![
+            ("isbn".to_string(), "978-0-441-01359-3".to_string()),
+            (
+                "uuid".to_string(),
+                "0b9c4a54-2f6e-4a6f-9d55-5c1d7b0a1e11".to_string()
+            ),
+        ]
+    );
+    assert_eq!(
+        metadata.pubdate,
+        Some(Utc.with_ymd_and_hms(1965, 8, 1, 0, 0, 0).unwrap())
+    );
+    assert_eq!(metadata.timestamp, None);
+    assert_eq!(metadata.series.as_deref(), Some("Dune"));
+}
+
+#[test]
+fn extracted_entries_can_be_adjusted_and_added() {
+    let dir = tempfile::tempdir().unwrap();
+    let library = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dune.epub");
+    write_epub(&path, PACKAGE);
+
+    let mut entry = library_entry_from_path(&path).unwrap();
+    assert_eq!(entry.book.title, "Dune & Sand");
+    assert_eq!(entry.authors[0].sortable_name, "Herbert, Frank");
+    assert_eq!(entry.publishers[0].name, "Chilton Books");
+    assert_eq!(
+        entry
+            .tags
+            .iter()
+            .map(|tag| tag.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["Science Fiction", "Desert"]
+    );
+    assert_eq!(entry.description.as_deref(), Some("<p>Spice.</p>"));
+    assert_eq!(entry.files.as_ref().unwrap()[0].path, path);
+
+    entry.book.title = "Dune".to_string();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let report = client.add_book(entry).unwrap();
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    assert_eq!(report.book.book.title, "Dune");
+    assert_eq!(report.book.authors[0].name, "Frank Herbert");
+    assert!(report.language_id.is_some());
+    assert_eq!(report.book.files[0].format, "EPUB");
+}
+
+#[test]
+fn files_without_metadata_are_titled_after_their_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Some Notes.txt");
+    fs::write(&path, "notes").unwrap();
+
+    let entry = library_entry_from_path(&path).unwrap();
+    assert_eq!(entry.book.title, "Some Notes");
+    assert!(entry.authors.is_empty());
+}
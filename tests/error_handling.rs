@@ -0,0 +1,129 @@
+mod common;
+
+use common::{author, new_entry, tag};
+use diesel::connection::SimpleConnection;
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::book::UpdateBookDto;
+use libcalibre::dtos::library::{ReplaceLibraryEntryDto, UpdateLibraryEntryDto};
+use libcalibre::persistence::establish_connection;
+use libcalibre::util::get_db_path;
+use libcalibre::CalibreError;
+
+#[test]
+fn corrupt_database_returns_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("metadata.db"), b"this is not a sqlite file").unwrap();
+    let db_path = get_db_path(dir.path().to_str().unwrap()).unwrap();
+
+    match CalibreClient::new(db_path) {
+        // SQLite only reads the file on first use, so opening may succeed
+        Ok(mut client) => {
+            assert!(client.find_all().is_err());
+            assert!(client.add_book(new_entry("Corrupt")).is_err());
+        }
+        Err(e) => assert!(matches!(e, CalibreError::Connection(_))),
+    }
+}
+
+#[test]
+fn malformed_row_returns_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let library_root = dir.path().to_str().unwrap();
+    let mut client = CalibreClient::create_library(library_root).unwrap();
+
+    let mut conn = establish_connection(&dir.path().join("metadata.db").to_string_lossy()).unwrap();
+    conn.batch_execute("INSERT INTO books (title, timestamp) VALUES ('Broken', 'not a date')")
+        .unwrap();
+
+    assert!(client.find_book_with_authors(1).is_err());
+    assert!(client.find_all().is_err());
+}
+
+#[test]
+fn locked_database_returns_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let library_root = dir.path().to_str().unwrap();
+    let mut client = CalibreClient::create_library(library_root).unwrap();
+
+    let mut conn = establish_connection(&dir.path().join("metadata.db").to_string_lossy()).unwrap();
+    conn.batch_execute("BEGIN EXCLUSIVE").unwrap();
+
+    assert!(client.add_book(new_entry("Locked")).is_err());
+    assert!(client.find_all().is_err());
+    assert!(client.list_all_authors().is_err());
+
+    conn.batch_execute("ROLLBACK").unwrap();
+    assert!(client.find_all().is_ok());
+}
+
+#[test]
+fn missing_book_is_not_found() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(dir.path().to_str().unwrap()).unwrap();
+
+    let result = client.find_book_with_authors(42);
+    assert!(matches!(result, Err(CalibreError::NotFound(_))));
+}
+
+#[test]
+fn book_without_authors_or_files_does_not_panic() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(dir.path().to_str().unwrap()).unwrap();
+
+    let mut entry = new_entry("Anonymous");
+    entry.authors.clear();
+    entry.files = Some(vec![]);
+    assert!(client.add_book(entry).is_ok());
+}
+
+#[test]
+fn invalid_author_id_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(dir.path().to_str().unwrap()).unwrap();
+    client.add_book(new_entry("Update me")).unwrap();
+
+    let result = client.update_book(
+        1,
+        UpdateLibraryEntryDto {
+            book: UpdateBookDto::default(),
+            author_id_list: Some(vec!["not a number".to_string()]),
+            description: None,
+        },
+    );
+    assert!(matches!(result, Err(CalibreError::InvalidInput(_))));
+}
+
+#[test]
+fn failed_metadata_replacement_is_rolled_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(dir.path().to_str().unwrap()).unwrap();
+    let book_id = client.add_book(new_entry("Original")).unwrap().book.book.id;
+
+    let mut conn = establish_connection(&dir.path().join("metadata.db").to_string_lossy()).unwrap();
+    conn.batch_execute(
+        "CREATE TRIGGER no_tags BEFORE INSERT ON books_tags_link
+         BEGIN SELECT RAISE(ABORT, 'no tags'); END",
+    )
+    .unwrap();
+
+    let entry = new_entry("Replaced");
+    let result = client.replace_book_metadata(
+        book_id,
+        ReplaceLibraryEntryDto {
+            book: entry.book,
+            authors: vec![author("John Roe")],
+            publishers: vec![],
+            identifiers: vec![],
+            language: None,
+            tags: vec![tag("Fantasy")],
+            rating: None,
+            series: None,
+            description: None,
+        },
+    );
+    assert!(matches!(result, Err(CalibreError::Database(_))));
+
+    let book = client.find_book_with_authors(book_id).unwrap();
+    assert_eq!(book.book.title, "Original");
+    assert_eq!(book.authors[0].name, "Jane Doe");
+}
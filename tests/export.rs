@@ -0,0 +1,322 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use epub::doc::EpubDoc;
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::book::UpdateBookDto;
+use libcalibre::dtos::library::UpdateLibraryEntryDto;
+use libcalibre::metadata::{cbz, epub as epub_metadata, library_entry_from_path};
+use libcalibre::CalibreError;
+use lopdf::{dictionary, Document, Object, Stream};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+const PACKAGE: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:5f2b7e5c-3b4a-4f8e-9c1d-2a6b8e0f4d31</dc:identifier>
+    <dc:identifier>978-0-441-01359-3</dc:identifier>
+    <dc:title id="title">Dune (Draft)</dc:title>
+    <dc:creator id="a1">Frank Herbert</dc:creator>
+    <meta refines="#a1" property="role" scheme="marc:relators">aut</meta>
+    <dc:contributor id="c1">Some Tool</dc:contributor>
+    <meta refines="#c1" property="role" scheme="marc:relators">bkp</meta>
+    <dc:language>en</dc:language>
+    <dc:subject>Science Fiction</dc:subject>
+    <meta property="dcterms:modified">2020-01-01T00:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="text" href="text.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine>
+    <itemref idref="text"/>
+  </spine>
+</package>"##;
+
+const COMIC_INFO: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<ComicInfo>
+  <Title>Issue Three</Title>
+  <Series>Night Watch</Series>
+  <Number>3</Number>
+  <Writer>Jane Doe</Writer>
+  <Penciller>John Roe</Penciller>
+  <Genre>Horror</Genre>
+  <PageCount>2</PageCount>
+  <Pages>
+    <Page Image="1" Type="FrontCover"/>
+  </Pages>
+</ComicInfo>"#;
+
+fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+    let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+    for (name, contents) in entries {
+        let options = match *name {
+            "mimetype" => {
+                SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored)
+            }
+            _ => SimpleFileOptions::default(),
+        };
+        zip.start_file(*name, options).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+fn write_epub(path: &Path) {
+    write_zip(
+        path,
+        &[
+            ("mimetype", b"application/epub+zip"),
+            (
+                "META-INF/container.xml",
+                br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#,
+            ),
+            ("OEBPS/content.opf", PACKAGE.as_bytes()),
+            ("OEBPS/text.xhtml", b"<html><body><p>Text</p></body></html>"),
+        ],
+    );
+}
+
+const STALE_XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+      <dc:description><rdf:Alt><rdf:li xml:lang="x-default">Stale description</rdf:li></rdf:Alt></dc:description>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+fn write_pdf(path: &Path) {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let contents_id = doc.add_object(Stream::new(dictionary! {}, Vec::new()));
+    let page_id = doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        "Contents" => contents_id,
+    });
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    doc.trailer.set("Root", catalog_id);
+    let info_id = doc.add_object(dictionary! {
+        "Title" => Object::string_literal("Old Title"),
+        "Producer" => Object::string_literal("Some Tool"),
+    });
+    doc.trailer.set("Info", info_id);
+    doc.save(path).unwrap();
+}
+
+/// Adds the file at `path` with the metadata embedded in it, then corrects
+/// its title in the library.
+fn add_and_retitle(client: &mut CalibreClient, path: &Path, title: &str) -> i32 {
+    let entry = library_entry_from_path(path).unwrap();
+    let book_id = client.add_book(entry).unwrap().book.book.id;
+    client
+        .update_book(
+            book_id,
+            UpdateLibraryEntryDto {
+                book: UpdateBookDto {
+                    title: Some(title.to_string()),
+                    ..Default::default()
+                },
+                author_id_list: None,
+                description: None,
+            },
+        )
+        .unwrap();
+    book_id
+}
+
+fn library_file(library: &Path, client: &mut CalibreClient, book_id: i32) -> PathBuf {
+    let book = client.find_book_with_authors(book_id).unwrap();
+    library
+        .join(&book.book.path)
+        .join(book.files[0].as_filename())
+}
+
+#[test]
+fn epubs_are_exported_with_the_library_metadata_and_cover() {
+    let (sources, library, exports) = (tempdir(), tempdir(), tempdir());
+    let source = sources.path().join("dune.epub");
+    write_epub(&source);
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_and_retitle(&mut client, &source, "Dune");
+    let in_library = library_file(library.path(), &mut client, book_id);
+    let book_dir = in_library.parent().unwrap().to_path_buf();
+    fs::write(book_dir.join("cover.jpg"), b"\xFF\xD8\xFFcover").unwrap();
+    let before = fs::read(&in_library).unwrap();
+
+    let exported = exports.path().join("Dune.epub");
+    client.export_format(book_id, "epub", &exported).unwrap();
+
+    assert_eq!(fs::read(&in_library).unwrap(), before);
+    let metadata = epub_metadata::read_metadata(&exported).unwrap();
+    assert_eq!(metadata.title.as_deref(), Some("Dune"));
+    assert_eq!(metadata.authors(), vec!["Frank Herbert"]);
+    assert_eq!(metadata.tags, vec!["Science Fiction"]);
+    assert_eq!(metadata.languages, vec!["en"]);
+    // The package keeps its own identity and other tools' metadata
+    assert!(metadata.identifiers.contains(&(
+        "uuid".to_string(),
+        "5f2b7e5c-3b4a-4f8e-9c1d-2a6b8e0f4d31".to_string()
+    )));
+    assert_eq!(
+        metadata
+            .identifiers
+            .iter()
+            .filter(|(scheme, _)| scheme == "isbn")
+            .count(),
+        1
+    );
+
+    let mut doc = EpubDoc::new(&exported).unwrap();
+    let package =
+        String::from_utf8(doc.get_resource_by_path("OEBPS/content.opf").unwrap()).unwrap();
+    assert!(package.contains(r#"<dc:contributor id="c1">Some Tool</dc:contributor>"#));
+    assert!(!package.contains("Dune (Draft)"));
+    assert_eq!(
+        doc.get_cover(),
+        Some((b"\xFF\xD8\xFFcover".to_vec(), "image/jpeg".to_string()))
+    );
+    assert!(doc.get_resource_str("text").is_some());
+}
+
+#[test]
+fn comics_keep_their_pages_and_pencillers() {
+    let (sources, library, exports) = (tempdir(), tempdir(), tempdir());
+    let source = sources.path().join("comic.cbz");
+    write_zip(
+        &source,
+        &[
+            ("p1.jpg", b"page 1"),
+            ("p2.jpg", b"page 2"),
+            ("ComicInfo.xml", COMIC_INFO.as_bytes()),
+        ],
+    );
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_and_retitle(&mut client, &source, "The Third Night");
+
+    let exported = exports.path().join("comic.cbz");
+    client.export_format(book_id, "CBZ", &exported).unwrap();
+
+    let info = cbz::read_comic_info(&exported).unwrap().unwrap();
+    assert_eq!(info.title.as_deref(), Some("The Third Night"));
+    assert_eq!(info.series.as_deref(), Some("Night Watch"));
+    assert_eq!(info.number.as_deref(), Some("3"));
+    assert_eq!(info.writers, vec!["Jane Doe"]);
+    assert_eq!(info.pencillers, vec!["John Roe"]);
+    assert_eq!(info.genres, vec!["Horror"]);
+    assert_eq!(info.page_count, Some(2));
+    assert_eq!(cbz::read_cover(&exported).unwrap().unwrap(), b"page 2");
+}
+
+#[test]
+fn pdfs_get_an_info_dictionary_and_xmp() {
+    let (sources, library, exports) = (tempdir(), tempdir(), tempdir());
+    let source = sources.path().join("paper.pdf");
+    write_pdf(&source);
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_and_retitle(&mut client, &source, "Café Paper");
+
+    let exported = exports.path().join("paper.pdf");
+    client.export_format(book_id, "pdf", &exported).unwrap();
+
+    let doc = Document::load(&exported).unwrap();
+    let info_id = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    let info = doc.get_dictionary(info_id).unwrap();
+    let title = lopdf::decode_text_string(info.get(b"Title").unwrap()).unwrap();
+    assert_eq!(title, "Café Paper");
+    assert_eq!(
+        info.get(b"Producer").unwrap().as_str().unwrap(),
+        b"Some Tool"
+    );
+    let xmp_id = doc
+        .catalog()
+        .unwrap()
+        .get(b"Metadata")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    let xmp = doc.get_object(xmp_id).unwrap().as_stream().unwrap();
+    let xmp = String::from_utf8(xmp.content.clone()).unwrap();
+    assert!(xmp.contains(r#"<rdf:li xml:lang="x-default">Café Paper</rdf:li>"#));
+}
+
+#[test]
+fn pdfs_lose_a_description_cleared_in_the_library() {
+    let (sources, library, exports) = (tempdir(), tempdir(), tempdir());
+    let source = sources.path().join("paper.pdf");
+    write_pdf(&source);
+    let mut doc = Document::load(&source).unwrap();
+    let info_id = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    doc.get_dictionary_mut(info_id)
+        .unwrap()
+        .set("Subject", Object::string_literal("Stale description"));
+    let xmp_id = doc.add_object(Stream::new(
+        dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+        STALE_XMP.as_bytes().to_vec(),
+    ));
+    doc.catalog_mut().unwrap().set("Metadata", xmp_id);
+    doc.save(&source).unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_and_retitle(&mut client, &source, "Paper");
+    assert!(client
+        .find_book_with_authors(book_id)
+        .unwrap()
+        .book_description_html
+        .is_some());
+
+    client
+        .update_book(
+            book_id,
+            UpdateLibraryEntryDto {
+                book: UpdateBookDto::default(),
+                author_id_list: None,
+                description: Some(None),
+            },
+        )
+        .unwrap();
+    let exported = exports.path().join("paper.pdf");
+    client.export_format(book_id, "pdf", &exported).unwrap();
+
+    let entry = library_entry_from_path(&exported).unwrap();
+    assert_eq!(entry.description, None);
+    // Neither the Subject nor the old packet is left anywhere in the file
+    let bytes = fs::read(&exported).unwrap();
+    assert!(!bytes
+        .windows(b"Stale description".len())
+        .any(|window| window == b"Stale description"));
+}
+
+#[test]
+fn other_formats_cannot_be_exported_with_metadata() {
+    let (sources, library, exports) = (tempdir(), tempdir(), tempdir());
+    let source = sources.path().join("notes.txt");
+    fs::write(&source, "notes").unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_and_retitle(&mut client, &source, "Notes");
+
+    let result = client.export_format(book_id, "txt", &exports.path().join("notes.txt"));
+    assert!(matches!(result, Err(CalibreError::UnsupportedFormat(_))));
+    let result = client.export_format(book_id, "epub", &exports.path().join("notes.epub"));
+    assert!(matches!(result, Err(CalibreError::NotFound(_))));
+}
+
+fn tempdir() -> tempfile::TempDir {
+    tempfile::tempdir().unwrap()
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+
+use common::{add_txt_book, new_entry};
+use diesel::connection::SimpleConnection;
+use libcalibre::client::trash::TrashKind;
+use libcalibre::client::CalibreClient;
+use libcalibre::persistence::establish_connection;
+use libcalibre::CalibreError;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+#[test]
+fn formats_are_added_once_and_replaced_in_place() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), new_entry("Formats"));
+    let before = client.find_book_with_authors(book_id).unwrap();
+
+    let comic = sources.path().join("pages.cbz");
+    fs::write(&comic, "pages").unwrap();
+    let added = client.add_format(book_id, &comic, false).unwrap();
+    assert_eq!(added.format, "CBZ");
+    assert_eq!(added.name, "Formats - Jane Doe");
+    assert_eq!(added.uncompressed_size, 5);
+
+    let book = client.find_book_with_authors(book_id).unwrap();
+    assert_eq!(book.files.len(), 2);
+    assert!(book.book.last_modified > before.book.last_modified);
+    let comic_in_library = library
+        .path()
+        .join(&book.book.path)
+        .join("Formats - Jane Doe.cbz");
+    assert_eq!(fs::read_to_string(&comic_in_library).unwrap(), "pages");
+
+    // One file per format
+    let result = client.add_format(book_id, &comic, false);
+    assert!(matches!(result, Err(CalibreError::Conflict(_))));
+
+    fs::write(&comic, "more pages").unwrap();
+    let replaced = client.replace_format(book_id, &comic, false).unwrap();
+    assert_eq!(replaced.id, added.id);
+    assert_eq!(replaced.uncompressed_size, 10);
+    assert_eq!(fs::read_to_string(&comic_in_library).unwrap(), "more pages");
+
+    let pdf = sources.path().join("book.pdf");
+    fs::write(&pdf, "pdf").unwrap();
+    let result = client.replace_format(book_id, &pdf, false);
+    assert!(matches!(result, Err(CalibreError::NotFound(_))));
+
+    // Nothing is left behind in the library root
+    let mut entries = fs::read_dir(library.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    entries.sort();
+    assert_eq!(entries, vec!["Jane Doe", "metadata.db"]);
+}
+
+#[test]
+fn removed_formats_go_to_the_trash() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), new_entry("Formats"));
+    let book_dir = library
+        .path()
+        .join(client.find_book_with_authors(book_id).unwrap().book.path);
+
+    client.remove_format(book_id, "txt").unwrap();
+
+    assert!(client
+        .find_book_with_authors(book_id)
+        .unwrap()
+        .files
+        .is_empty());
+    assert!(!book_dir.join("Formats - Jane Doe.txt").exists());
+    let trash = client.list_trash().unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].kind, TrashKind::Format);
+    assert_eq!(trash[0].formats, vec!["TXT"]);
+    assert_eq!(trash[0].title, "Formats");
+    assert_eq!(trash[0].authors, vec!["Jane Doe"]);
+
+    let result = client.remove_format(book_id, "txt");
+    assert!(matches!(result, Err(CalibreError::NotFound(_))));
+
+    client.restore_format_from_trash(book_id, "txt").unwrap();
+    assert_eq!(
+        fs::read_to_string(book_dir.join("Formats - Jane Doe.txt")).unwrap(),
+        "Formats"
+    );
+}
+
+#[test]
+fn unreadable_covers_stop_the_format_being_added() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), new_entry("Formats"));
+
+    // Not a real EPUB, so its cover cannot be read
+    let epub = sources.path().join("book.epub");
+    fs::write(&epub, "not a zip").unwrap();
+    let result = client.add_format(book_id, &epub, true);
+    assert!(matches!(result, Err(CalibreError::MetadataParse { .. })));
+    assert_eq!(
+        client.find_book_with_authors(book_id).unwrap().files.len(),
+        1
+    );
+
+    // Without a cover refresh the file is taken as it is
+    client.add_format(book_id, &epub, false).unwrap();
+    assert_eq!(
+        client.find_book_with_authors(book_id).unwrap().files.len(),
+        2
+    );
+}
+
+#[test]
+fn a_failed_cover_refresh_keeps_the_old_cover() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), new_entry("Formats"));
+    let book_dir = library
+        .path()
+        .join(client.find_book_with_authors(book_id).unwrap().book.path);
+    fs::write(book_dir.join("cover.jpg"), "old cover").unwrap();
+
+    // A comic whose only page becomes the new cover
+    let comic = sources.path().join("pages.cbz");
+    let mut zip = ZipWriter::new(fs::File::create(&comic).unwrap());
+    zip.start_file("page.jpg", SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(b"new cover").unwrap();
+    zip.finish().unwrap();
+
+    // Fails after the cover has been swapped, when the book is updated
+    let mut conn =
+        establish_connection(&library.path().join("metadata.db").to_string_lossy()).unwrap();
+    conn.batch_execute(
+        "CREATE TRIGGER no_updates BEFORE UPDATE ON books
+         BEGIN SELECT RAISE(ABORT, 'no updates'); END",
+    )
+    .unwrap();
+    assert!(client.add_format(book_id, &comic, true).is_err());
+    assert_eq!(
+        fs::read_to_string(book_dir.join("cover.jpg")).unwrap(),
+        "old cover"
+    );
+    assert!(!book_dir.join("Formats - Jane Doe.cbz").exists());
+
+    conn.batch_execute("DROP TRIGGER no_updates").unwrap();
+    client.add_format(book_id, &comic, true).unwrap();
+    assert_eq!(
+        fs::read_to_string(book_dir.join("cover.jpg")).unwrap(),
+        "new cover"
+    );
+    let mut entries = fs::read_dir(library.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    entries.sort();
+    assert_eq!(entries, vec!["Jane Doe", "metadata.db"]);
+}
+
+#[test]
+fn files_too_large_to_record_are_rejected() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), new_entry("Formats"));
+
+    // A sparse file, so the test does not need 3 GiB of disk
+    let pdf = sources.path().join("huge.pdf");
+    fs::File::create(&pdf).unwrap().set_len(3 << 30).unwrap();
+    let result = client.add_format(book_id, &pdf, false);
+    assert!(matches!(result, Err(CalibreError::InvalidInput(_))));
+
+    let txt = sources.path().join("huge.txt");
+    fs::File::create(&txt).unwrap().set_len(3 << 30).unwrap();
+    let result = client.replace_format(book_id, &txt, false);
+    assert!(matches!(result, Err(CalibreError::InvalidInput(_))));
+
+    let book = client.find_book_with_authors(book_id).unwrap();
+    assert_eq!(book.files.len(), 1);
+    assert_eq!(book.files[0].uncompressed_size, 7);
+}
@@ -0,0 +1,190 @@
+mod common;
+
+use std::fs;
+use std::time::Duration;
+
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::author::UpdateAuthorDto;
+use libcalibre::dtos::book::UpdateBookDto;
+use libcalibre::dtos::library::{NewLibraryEntryDto, UpdateLibraryEntryDto};
+use libcalibre::dtos::publisher::NewPublisherDto;
+use libcalibre::opf::OpfMetadata;
+
+fn new_entry(title: &str, tags: &[&str]) -> NewLibraryEntryDto {
+    NewLibraryEntryDto {
+        publishers: vec![NewPublisherDto {
+            name: "Acme".to_string(),
+            sort: None,
+        }],
+        tags: tags.iter().map(|name| common::tag(name)).collect(),
+        ..common::new_entry(title)
+    }
+}
+
+fn library() -> (tempfile::TempDir, CalibreClient) {
+    let library = tempfile::tempdir().unwrap();
+    let client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    (library, client)
+}
+
+fn read_opf(library: &tempfile::TempDir, client: &mut CalibreClient, book_id: i32) -> OpfMetadata {
+    let book = client.find_book_with_authors(book_id).unwrap();
+    OpfMetadata::from_path(library.path().join(book.book.path).join("metadata.opf")).unwrap()
+}
+
+#[test]
+fn translations_dirty_books_until_they_are_flushed() {
+    let (library, mut client) = library();
+    let first = client.add_book(new_entry("First", &["Fantasy"])).unwrap();
+    let second = client.add_book(new_entry("Second", &["Fantasy"])).unwrap();
+    let other = client.add_book(new_entry("Other", &["Horror"])).unwrap();
+    let [first, second, other] = [first, second, other].map(|report| report.book.book.id);
+    assert_eq!(client.dirtied_book_ids().unwrap(), Vec::<i32>::new());
+
+    let fantasy = client
+        .get_all_tags()
+        .unwrap()
+        .into_iter()
+        .find(|tag| tag.name == "Fantasy")
+        .unwrap();
+    client
+        .replace_tag_with_translation(fantasy.id, "Fantasy & Sci-Fi")
+        .unwrap();
+    assert_eq!(client.dirtied_book_ids().unwrap(), vec![first, second]);
+    // The OPF is only rewritten by a flush
+    assert_eq!(read_opf(&library, &mut client, first).tags, vec!["Fantasy"]);
+
+    let author_id = client.get_all_authors().unwrap()[0].id;
+    client
+        .update_author(
+            author_id,
+            UpdateAuthorDto {
+                full_name: Some("Jane Q. Doe".to_string()),
+                sortable_name: None,
+                external_url: None,
+            },
+        )
+        .unwrap();
+    let mut dirtied = client.dirtied_book_ids().unwrap();
+    dirtied.sort();
+    assert_eq!(dirtied, vec![first, second, other]);
+
+    // Deleting a book also drops its mark
+    client.delete_book(other).unwrap();
+    let mut dirtied = client.dirtied_book_ids().unwrap();
+    dirtied.sort();
+    assert_eq!(dirtied, vec![first, second]);
+
+    let mut flushed = client.flush_dirtied_metadata().unwrap();
+    flushed.sort();
+    assert_eq!(flushed, vec![first, second]);
+    assert_eq!(client.dirtied_book_ids().unwrap(), Vec::<i32>::new());
+    for book_id in [first, second] {
+        let metadata = read_opf(&library, &mut client, book_id);
+        assert_eq!(metadata.tags, vec!["Fantasy & Sci-Fi"]);
+        assert_eq!(metadata.authors(), vec!["Jane Q. Doe"]);
+    }
+}
+
+#[test]
+fn update_book_rewrites_its_opf_straight_away() {
+    let (library, mut client) = library();
+    let book_id = client
+        .add_book(new_entry("Before", &[]))
+        .unwrap()
+        .book
+        .book
+        .id;
+
+    client
+        .update_book(
+            book_id,
+            UpdateLibraryEntryDto {
+                book: UpdateBookDto {
+                    title: Some("After".to_string()),
+                    ..Default::default()
+                },
+                author_id_list: None,
+                description: None,
+            },
+        )
+        .unwrap();
+
+    assert_eq!(client.dirtied_book_ids().unwrap(), Vec::<i32>::new());
+    assert_eq!(
+        read_opf(&library, &mut client, book_id).title.as_deref(),
+        Some("After")
+    );
+}
+
+#[test]
+fn background_flusher_writes_dirtied_books() {
+    let (library, mut client) = library();
+    let book_id = client
+        .add_book(new_entry("Flushed", &[]))
+        .unwrap()
+        .book
+        .book
+        .id;
+    let flusher = client
+        .spawn_metadata_flusher(Duration::from_millis(10))
+        .unwrap();
+
+    let publisher_id = client.get_all_publishers().unwrap()[0].id;
+    client
+        .replace_publisher_with_translation(publisher_id, "Acme <Books>")
+        .unwrap();
+
+    // Stopping flushes whatever is still dirtied
+    flusher.stop().unwrap();
+    assert_eq!(client.dirtied_book_ids().unwrap(), Vec::<i32>::new());
+    assert_eq!(
+        read_opf(&library, &mut client, book_id)
+            .publisher
+            .as_deref(),
+        Some("Acme <Books>")
+    );
+    let opf_path = library
+        .path()
+        .join(client.find_book_with_authors(book_id).unwrap().book.path)
+        .join("metadata.opf");
+    assert!(fs::metadata(opf_path).unwrap().is_file());
+}
+
+#[test]
+fn writes_wait_for_an_active_flusher() {
+    let (library, mut client) = library();
+    let book_ids: Vec<i32> = (0..40)
+        .map(|i| {
+            client
+                .add_book(new_entry(&format!("Book {i}"), &[]))
+                .unwrap()
+                .book
+                .book
+                .id
+        })
+        .collect();
+    let flusher = client
+        .spawn_metadata_flusher(Duration::from_millis(1))
+        .unwrap();
+
+    // Each rename dirties every book, so the flusher is busy writing while
+    // the next one runs
+    let publisher_id = client.get_all_publishers().unwrap()[0].id;
+    for i in 0..100 {
+        client
+            .replace_publisher_with_translation(publisher_id, &format!("Acme {i}"))
+            .unwrap();
+    }
+
+    flusher.stop().unwrap();
+    assert_eq!(client.dirtied_book_ids().unwrap(), Vec::<i32>::new());
+    for book_id in book_ids {
+        assert_eq!(
+            read_opf(&library, &mut client, book_id)
+                .publisher
+                .as_deref(),
+            Some("Acme 99")
+        );
+    }
+}
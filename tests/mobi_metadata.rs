@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::Path;
+
+use libcalibre::metadata::{library_entry_from_path, mobi};
+use libcalibre::CalibreError;
+
+const LOGO: &[u8] = b"\xFF\xD8\xFFlogo";
+const COVER: &[u8] = b"\xFF\xD8\xFFcover";
+const THUMBNAIL: &[u8] = b"\xFF\xD8\xFFthumbnail";
+const BACK_MATTER: &[u8] = b"\x89PNG\r\n\x1A\nback matter";
+
+/// Writes a MOBI file with one uncompressed text record followed by the
+/// logo, cover, thumbnail and back matter images, and `exth` as its EXTH
+/// records.
+fn write_mobi(path: &Path, full_name: &str, exth: ExthRecords) {
+    let mut exth_header = b"EXTH".to_vec();
+    let exth_records = exth
+        .iter()
+        .flat_map(|(kind, value)| {
+            [
+                kind.to_be_bytes().to_vec(),
+                (value.len() as u32 + 8).to_be_bytes().to_vec(),
+                value.to_vec(),
+            ]
+        })
+        .flatten()
+        .collect::<Vec<u8>>();
+    exth_header.extend((exth_records.len() as u32 + 12).to_be_bytes());
+    exth_header.extend((exth.len() as u32).to_be_bytes());
+    exth_header.extend(exth_records);
+
+    let text = b"<html><body>Text</body></html>".to_vec();
+    let name_offset = 16 + 232 + exth_header.len() as u32;
+    let mut mobi_header = b"MOBI".to_vec();
+    for field in [232, 2, 65001, 1, 6] {
+        mobi_header.extend(u32::to_be_bytes(field));
+    }
+    mobi_header.extend([0xFF; 40]);
+    // First non-book record, full name offset and length
+    for field in [2, name_offset, full_name.len() as u32] {
+        mobi_header.extend(u32::to_be_bytes(field));
+    }
+    mobi_header.extend([0; 4]);
+    // Input and output languages, format version and first image record
+    for field in [0, 0, 6, 2] {
+        mobi_header.extend(u32::to_be_bytes(field));
+    }
+    mobi_header.extend([0; 16]);
+    mobi_header.extend(u32::to_be_bytes(0x40));
+    mobi_header.resize(16 + 232 - 16, 0);
+
+    let mut record0 = Vec::new();
+    record0.extend(1u16.to_be_bytes());
+    record0.extend(0u16.to_be_bytes());
+    record0.extend((text.len() as u32).to_be_bytes());
+    record0.extend(1u16.to_be_bytes());
+    record0.extend(4096u16.to_be_bytes());
+    record0.extend([0; 4]);
+    record0.extend(mobi_header);
+    record0.extend(exth_header);
+    record0.extend(full_name.as_bytes());
+    record0.extend([0; 2]);
+
+    let records = [record0, text, LOGO.to_vec(), COVER.to_vec()]
+        .into_iter()
+        .chain([THUMBNAIL.to_vec(), BACK_MATTER.to_vec()])
+        .collect::<Vec<_>>();
+    let mut file = Vec::new();
+    let mut pdb_name = b"Test_Book".to_vec();
+    pdb_name.resize(32, 0);
+    file.extend(pdb_name);
+    file.extend([0; 4]);
+    file.extend([0; 24]);
+    file.extend(b"BOOKMOBI");
+    file.extend([0; 8]);
+    file.extend((records.len() as u16).to_be_bytes());
+    let mut offset = 78 + 8 * records.len() as u32 + 2;
+    for (id, record) in records.iter().enumerate() {
+        file.extend(offset.to_be_bytes());
+        file.extend((id as u32 * 2).to_be_bytes());
+        offset += record.len() as u32;
+    }
+    file.extend([0; 2]);
+    for record in records {
+        file.extend(record);
+    }
+    fs::write(path, file).unwrap();
+}
+
+type ExthRecords<'a> = &'a [(u32, &'a [u8])];
+
+const EXTH: ExthRecords = &[
+    (503, b"The Left Hand of Darkness"),
+    (100, b"Ursula K. Le Guin & Jane Doe"),
+    (101, b"Ace Books"),
+    (103, b"<p>Winter.</p>"),
+    (104, b"978-0-441-47812-5"),
+    (105, b"Science Fiction"),
+    (106, b"1969-03-01T00:00:00+00:00"),
+    (113, b"B000FC1PJI"),
+    (524, b"en-gb"),
+    (201, &[0, 0, 0, 1]),
+    (202, &[0, 0, 0, 2]),
+];
+
+#[test]
+fn exth_metadata_is_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("book.azw3");
+    write_mobi(&path, "Full Name", EXTH);
+
+    let metadata = mobi::read_metadata(&path).unwrap();
+    assert_eq!(metadata.title.as_deref(), Some("The Left Hand of Darkness"));
+    assert_eq!(metadata.authors(), vec!["Ursula K. Le Guin", "Jane Doe"]);
+    assert_eq!(metadata.publisher.as_deref(), Some("Ace Books"));
+    assert_eq!(metadata.description.as_deref(), Some("<p>Winter.</p>"));
+    assert_eq!(metadata.tags, vec!["Science Fiction"]);
+    assert_eq!(metadata.languages, vec!["en-gb"]);
+    assert_eq!(
+        metadata.identifiers,
+        vec![
+            ("isbn".to_string(), "978-0-441-47812-5".to_string()),
+            ("mobi-asin".to_string(), "B000FC1PJI".to_string()),
+        ]
+    );
+    assert_eq!(
+        metadata.pubdate.map(|date| date.to_rfc3339()).as_deref(),
+        Some("1969-03-01T00:00:00+00:00")
+    );
+
+    let entry = library_entry_from_path(&path).unwrap();
+    assert_eq!(entry.book.title, "The Left Hand of Darkness");
+    assert_eq!(entry.publishers[0].name, "Ace Books");
+    assert_eq!(entry.language.unwrap().lang_code, "en-gb");
+}
+
+#[test]
+fn the_full_name_is_the_fallback_title() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("book.mobi");
+    write_mobi(&path, "Full Name", &[]);
+
+    let metadata = mobi::read_metadata(&path).unwrap();
+    assert_eq!(metadata.title.as_deref(), Some("Full Name"));
+    assert!(metadata.creators.is_empty());
+}
+
+#[test]
+fn the_cover_comes_from_the_exth_offsets() {
+    let dir = tempfile::tempdir().unwrap();
+    let cases: [(&str, ExthRecords, &[u8]); 3] = [
+        ("cover.mobi", EXTH, COVER),
+        ("thumbnail.azw", &[(202, &[0, 0, 0, 2])], THUMBNAIL),
+        ("first_image.azw3", &[], LOGO),
+    ];
+    for (name, exth, expected) in cases {
+        let path = dir.path().join(name);
+        write_mobi(&path, "Full Name", exth);
+        assert_eq!(
+            mobi::read_cover(&path).unwrap().as_deref(),
+            Some(expected),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn a_truncated_file_has_no_readable_cover() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("truncated.azw3");
+    write_mobi(&path, "Full Name", EXTH);
+    // Cut the file off in the middle of the cover, so the records after it
+    // start past its end
+    let bytes = fs::read(&path).unwrap();
+    let cover_start = bytes
+        .windows(COVER.len())
+        .position(|window| window == COVER)
+        .unwrap();
+    fs::write(&path, &bytes[..cover_start + 4]).unwrap();
+
+    assert!(mobi::read_metadata(&path).is_ok());
+    assert!(matches!(
+        mobi::read_cover(&path),
+        Err(CalibreError::MetadataParse { .. })
+    ));
+}
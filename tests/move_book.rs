@@ -0,0 +1,156 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{author, new_book, new_entry};
+use libcalibre::client::{CalibreClient, ClientConfig, PathScheme};
+use libcalibre::dtos::book::UpdateBookDto;
+use libcalibre::dtos::library::{
+    NewLibraryEntryDto, ReplaceLibraryEntryDto, UpdateLibraryEntryDto,
+};
+use libcalibre::opf::OpfMetadata;
+use libcalibre::CalibreError;
+
+fn add_txt_book(client: &mut CalibreClient, sources: &Path, title: &str, author_name: &str) -> i32 {
+    let entry = NewLibraryEntryDto {
+        authors: vec![author(author_name)],
+        ..new_entry(title)
+    };
+    common::add_txt_book(client, sources, entry)
+}
+
+fn retitle(client: &mut CalibreClient, book_id: i32, title: &str) -> Result<(), CalibreError> {
+    client
+        .update_book(
+            book_id,
+            UpdateLibraryEntryDto {
+                book: UpdateBookDto {
+                    title: Some(title.to_string()),
+                    ..Default::default()
+                },
+                author_id_list: None,
+                description: None,
+            },
+        )
+        .map(|_| ())
+}
+
+#[test]
+fn retitled_books_move_to_a_new_folder() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), "Draft", "Jane Doe");
+    let old_dir = library.path().join("Jane Doe").join("Draft (1)");
+    fs::write(old_dir.join("cover.jpg"), "cover").unwrap();
+
+    retitle(&mut client, book_id, "Final").unwrap();
+
+    let book = client.find_book_with_authors(book_id).unwrap();
+    assert_eq!(book.book.path, "Jane Doe/Final (1)");
+    assert_eq!(book.files[0].name, "Final - Jane Doe");
+    let new_dir = library.path().join(&book.book.path);
+    assert_eq!(
+        fs::read_to_string(new_dir.join("Final - Jane Doe.txt")).unwrap(),
+        "Draft"
+    );
+    assert_eq!(
+        fs::read_to_string(new_dir.join("cover.jpg")).unwrap(),
+        "cover"
+    );
+    let metadata = OpfMetadata::from_path(new_dir.join("metadata.opf")).unwrap();
+    assert_eq!(metadata.title.as_deref(), Some("Final"));
+    assert!(!old_dir.exists());
+    // The author still has a book, so their folder stays
+    assert!(library.path().join("Jane Doe").is_dir());
+}
+
+#[test]
+fn replacing_the_author_moves_the_book_and_drops_the_empty_folder() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let moved = add_txt_book(&mut client, sources.path(), "Moved", "Jane Doe");
+    let stays = add_txt_book(&mut client, sources.path(), "Stays", "John Roe");
+
+    client
+        .replace_book_metadata(
+            moved,
+            ReplaceLibraryEntryDto {
+                book: new_book("Moved"),
+                authors: vec![author("John Roe")],
+                publishers: vec![],
+                identifiers: vec![],
+                language: None,
+                tags: vec![],
+                rating: None,
+                series: None,
+                description: None,
+            },
+        )
+        .unwrap();
+
+    let book = client.find_book_with_authors(moved).unwrap();
+    assert_eq!(book.book.path, "John Roe/Moved (1)");
+    assert!(library
+        .path()
+        .join(&book.book.path)
+        .join("Moved - John Roe.txt")
+        .is_file());
+    assert!(!library.path().join("Jane Doe").exists());
+    let other = client.find_book_with_authors(stays).unwrap();
+    assert!(library
+        .path()
+        .join(&other.book.path)
+        .join("Stays - John Roe.txt")
+        .is_file());
+}
+
+#[test]
+fn book_id_scheme_only_renames_the_files() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let config = ClientConfig {
+        path_scheme: PathScheme::BookId,
+        ..Default::default()
+    };
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap())
+        .unwrap()
+        .with_config(config);
+    let book_id = add_txt_book(&mut client, sources.path(), "Draft", "Jane Doe");
+
+    retitle(&mut client, book_id, "Final").unwrap();
+
+    let book = client.find_book_with_authors(book_id).unwrap();
+    assert_eq!(book.book.path, "1");
+    assert_eq!(book.files[0].name, "Final - Jane Doe");
+    let book_dir = library.path().join("1");
+    assert!(book_dir.join("Final - Jane Doe.txt").is_file());
+    assert!(!book_dir.join("Draft - Jane Doe.txt").exists());
+}
+
+#[test]
+fn failed_moves_leave_the_book_where_it_was() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), "Draft", "Jane Doe");
+    // Something that isn't the book is already where it would move to
+    let squatter = library.path().join("Jane Doe").join("Final (1)");
+    fs::create_dir_all(&squatter).unwrap();
+
+    let result = retitle(&mut client, book_id, "Final");
+    assert!(matches!(result, Err(CalibreError::Conflict(_))));
+
+    let book = client.find_book_with_authors(book_id).unwrap();
+    assert_eq!(book.book.title, "Draft");
+    assert_eq!(book.book.path, "Jane Doe/Draft (1)");
+    assert_eq!(book.files[0].name, "Draft - Jane Doe");
+    assert!(library
+        .path()
+        .join(&book.book.path)
+        .join("Draft - Jane Doe.txt")
+        .is_file());
+    assert_eq!(fs::read_dir(&squatter).unwrap().count(), 0);
+}
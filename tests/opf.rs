@@ -0,0 +1,498 @@
+use chrono::{TimeZone, Utc};
+
+use std::fs;
+
+use libcalibre::client::{CalibreClient, ClientConfig, OpfProducer, OpfVersion};
+use libcalibre::dtos::author::NewAuthorDto;
+use libcalibre::dtos::book::{NewBookDto, UpdateBookDto};
+use libcalibre::dtos::language::NewLanguageDto;
+use libcalibre::dtos::library::{NewLibraryEntryDto, UpdateLibraryEntryDto};
+use libcalibre::dtos::publisher::NewPublisherDto;
+use libcalibre::dtos::rating::NewRatingDto;
+use libcalibre::dtos::series::NewSeriesDto;
+use libcalibre::dtos::tag::NewTagDto;
+use libcalibre::opf::{OpfCreator, OpfMetadata};
+use libcalibre::{CalibreError, UpsertBookIdentifier};
+
+const CALIBRE_OPF2: &str = r#"<?xml version='1.0' encoding='utf-8'?>
+<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uuid_id" version="2.0">
+    <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+        <dc:identifier opf:scheme="calibre" id="calibre_id">17</dc:identifier>
+        <dc:identifier opf:scheme="uuid" id="uuid_id">0a6a2c7c-3bd4-4e57-9a4e-3d7f4a3e5e11</dc:identifier>
+        <dc:title>Good Omens</dc:title>
+        <dc:creator opf:file-as="Pratchett, Terry &amp; Gaiman, Neil" opf:role="aut">Terry Pratchett</dc:creator>
+        <dc:creator opf:file-as="Pratchett, Terry &amp; Gaiman, Neil" opf:role="aut">Neil Gaiman</dc:creator>
+        <dc:creator opf:role="ill">Paul Kidby</dc:creator>
+        <dc:contributor opf:file-as="calibre" opf:role="bkp">calibre (7.0.0) [https://calibre-ebook.com]</dc:contributor>
+        <dc:date>1990-05-01T00:00:00+00:00</dc:date>
+        <dc:description>&lt;p&gt;The world ends on a &lt;b&gt;Saturday&lt;/b&gt;.&lt;/p&gt;</dc:description>
+        <dc:publisher>Gollancz</dc:publisher>
+        <dc:identifier opf:scheme="ISBN">9780575048003</dc:identifier>
+        <dc:language>eng</dc:language>
+        <dc:subject>Fantasy</dc:subject>
+        <dc:subject>Humour</dc:subject>
+        <meta name="calibre:author_link_map" content="{}"/>
+        <meta name="calibre:link_maps" content="{&quot;authors&quot;: {&quot;Neil Gaiman&quot;: &quot;https://neilgaiman.com&quot;, &quot;Terry Pratchett&quot;: &quot;&quot;}}"/>
+        <meta name="calibre:rating" content="8.0"/>
+        <meta name="calibre:series" content="Standalone"/>
+        <meta name="calibre:series_index" content="1.5"/>
+        <meta name="calibre:timestamp" content="2023-11-02T10:15:30.123456+00:00"/>
+        <meta name="calibre:title_sort" content="Good Omens"/>
+    </metadata>
+    <guide>
+        <reference type="cover" title="Cover" href="cover.jpg"/>
+    </guide>
+</package>"#;
+
+const CALIBRE_OPF3: &str = r##"<?xml version='1.0' encoding='utf-8'?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uuid_id" prefix="calibre: https://calibre-ebook.com">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:identifier id="calibre_id">calibre:3</dc:identifier>
+    <dc:identifier id="uuid_id">urn:uuid:7c2b1e8d-5f0a-4a1b-9c3d-2e4f6a8b0c1d</dc:identifier>
+    <dc:identifier>isbn:9780441013593</dc:identifier>
+    <dc:title id="id">Dune</dc:title>
+    <meta refines="#id" property="file-as">Dune</meta>
+    <dc:creator id="id-1">Frank Herbert</dc:creator>
+    <meta refines="#id-1" property="role" scheme="marc:relators">aut</meta>
+    <meta refines="#id-1" property="file-as">Herbert, Frank</meta>
+    <dc:creator id="id-2">John Schoenherr</dc:creator>
+    <meta refines="#id-2" property="role" scheme="marc:relators">ill</meta>
+    <dc:language>en</dc:language>
+    <dc:date>1965</dc:date>
+    <meta property="dcterms:modified" scheme="dcterms:W3CDTF">2024-01-01T00:00:00Z</meta>
+    <meta property="calibre:timestamp" scheme="dcterms:W3CDTF">2024-01-01T12:00:00Z</meta>
+    <meta property="calibre:rating">10</meta>
+    <meta property="belongs-to-collection" id="id-3">Dune Chronicles</meta>
+    <meta refines="#id-3" property="collection-type">series</meta>
+    <meta refines="#id-3" property="group-position">1</meta>
+  </metadata>
+</package>"##;
+
+#[test]
+fn calibre_opf2_is_parsed() {
+    let metadata = OpfMetadata::parse(CALIBRE_OPF2).unwrap();
+
+    assert_eq!(metadata.calibre_id, Some(17));
+    assert_eq!(
+        metadata.uuid.as_deref(),
+        Some("0a6a2c7c-3bd4-4e57-9a4e-3d7f4a3e5e11")
+    );
+    assert_eq!(metadata.title.as_deref(), Some("Good Omens"));
+    assert_eq!(metadata.authors(), vec!["Terry Pratchett", "Neil Gaiman"]);
+    assert_eq!(
+        metadata.creators[2],
+        OpfCreator {
+            name: "Paul Kidby".to_string(),
+            file_as: None,
+            role: Some("ill".to_string()),
+        }
+    );
+    assert_eq!(
+        metadata.description.as_deref(),
+        Some("<p>The world ends on a <b>Saturday</b>.</p>")
+    );
+    assert_eq!(
+        metadata.identifiers,
+        vec![("isbn".to_string(), "9780575048003".to_string())]
+    );
+    assert_eq!(metadata.rating, Some(8));
+    assert_eq!(metadata.series.as_deref(), Some("Standalone"));
+    assert_eq!(metadata.series_index, Some(1.5));
+    assert_eq!(
+        metadata.pubdate,
+        Some(Utc.with_ymd_and_hms(1990, 5, 1, 0, 0, 0).unwrap())
+    );
+
+    let entry = metadata.into_library_entry(vec![]);
+    let authors = entry
+        .authors
+        .iter()
+        .map(|author| {
+            (
+                author.full_name.as_str(),
+                author.sortable_name.as_str(),
+                author.external_url.as_deref(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        authors,
+        vec![
+            ("Terry Pratchett", "Pratchett, Terry", None),
+            (
+                "Neil Gaiman",
+                "Gaiman, Neil",
+                Some("https://neilgaiman.com")
+            ),
+        ]
+    );
+    assert_eq!(entry.publishers[0].name, "Gollancz");
+    assert_eq!(entry.language.unwrap().lang_code, "eng");
+    assert_eq!(entry.tags.len(), 2);
+    assert_eq!(entry.series.unwrap().name, "Standalone");
+    assert_eq!(entry.book.series_index, 1.5);
+}
+
+#[test]
+fn calibre_opf3_is_parsed() {
+    let metadata = OpfMetadata::parse(CALIBRE_OPF3).unwrap();
+
+    assert_eq!(metadata.calibre_id, Some(3));
+    assert_eq!(
+        metadata.uuid.as_deref(),
+        Some("7c2b1e8d-5f0a-4a1b-9c3d-2e4f6a8b0c1d")
+    );
+    assert_eq!(metadata.title_sort.as_deref(), Some("Dune"));
+    assert_eq!(metadata.authors(), vec!["Frank Herbert"]);
+    assert_eq!(
+        metadata.creators[0].file_as.as_deref(),
+        Some("Herbert, Frank")
+    );
+    assert_eq!(metadata.creators[1].role.as_deref(), Some("ill"));
+    assert_eq!(
+        metadata.identifiers,
+        vec![("isbn".to_string(), "9780441013593".to_string())]
+    );
+    assert_eq!(metadata.rating, Some(10));
+    assert_eq!(metadata.series.as_deref(), Some("Dune Chronicles"));
+    assert_eq!(metadata.series_index, Some(1.0));
+    assert_eq!(
+        metadata.pubdate,
+        Some(Utc.with_ymd_and_hms(1965, 1, 1, 0, 0, 0).unwrap())
+    );
+    assert_eq!(
+        metadata.timestamp,
+        Some(Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap())
+    );
+}
+
+#[test]
+fn written_opf_matches_the_database() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let report = client
+        .add_book(NewLibraryEntryDto {
+            book: NewBookDto {
+                title: "The Round Trip".to_string(),
+                timestamp: None,
+                pubdate: Some(Utc.with_ymd_and_hms(2001, 9, 9, 0, 0, 0).unwrap()),
+                series_index: 3.0,
+                flags: 1,
+                has_cover: None,
+            },
+            authors: vec![NewAuthorDto {
+                full_name: "Jane Doe".to_string(),
+                sortable_name: String::new(),
+                external_url: None,
+            }],
+            publishers: vec![NewPublisherDto {
+                name: "Acme".to_string(),
+                sort: None,
+            }],
+            identifiers: vec![UpsertBookIdentifier {
+                book_id: 0,
+                id: None,
+                label: "isbn".to_string(),
+                value: "1234567890".to_string(),
+            }],
+            language: Some(NewLanguageDto {
+                lang_code: "en".to_string(),
+            }),
+            tags: vec![NewTagDto {
+                name: "Travel".to_string(),
+            }],
+            rating: Some(NewRatingDto { rating: 6 }),
+            series: Some(NewSeriesDto {
+                name: "Journeys".to_string(),
+            }),
+            description: Some("<p>There and back.</p>".to_string()),
+            files: Some(vec![]),
+        })
+        .unwrap();
+    let book = &report.book;
+
+    let metadata =
+        OpfMetadata::from_path(library.path().join(&book.book.path).join("metadata.opf")).unwrap();
+    assert_eq!(metadata.calibre_id, Some(book.book.id));
+    assert_eq!(metadata.uuid, book.book.uuid);
+    assert_eq!(metadata.title.as_ref(), Some(&book.book.title));
+    assert_eq!(metadata.title_sort, book.book.sort);
+    assert_eq!(metadata.authors(), vec![book.authors[0].name.clone()]);
+    assert_eq!(
+        metadata.creators[0].file_as.as_deref(),
+        Some(book.authors[0].sort.as_deref().unwrap())
+    );
+    assert_eq!(metadata.publisher.as_deref(), Some("Acme"));
+    assert_eq!(
+        metadata.identifiers,
+        vec![("isbn".to_string(), "1234567890".to_string())]
+    );
+    assert_eq!(metadata.languages, vec!["eng"]);
+    assert_eq!(metadata.tags, vec!["Travel"]);
+    assert_eq!(metadata.rating, Some(6));
+    assert_eq!(metadata.series.as_deref(), Some("Journeys"));
+    assert_eq!(metadata.series_index, Some(book.book.series_index));
+    assert_eq!(metadata.description, book.book_description_html);
+    assert_eq!(metadata.pubdate, book.book.pubdate);
+}
+
+#[test]
+fn opf2_and_opf3_carry_the_same_metadata() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book = client
+        .add_book(NewLibraryEntryDto {
+            book: NewBookDto {
+                title: "The Two Versions".to_string(),
+                timestamp: Some(Utc.with_ymd_and_hms(2020, 2, 2, 2, 2, 2).unwrap()),
+                pubdate: Some(Utc.with_ymd_and_hms(1999, 9, 9, 0, 0, 0).unwrap()),
+                series_index: 4.5,
+                flags: 1,
+                has_cover: None,
+            },
+            authors: ["Jane Doe", "Rick Roe"]
+                .iter()
+                .map(|name| NewAuthorDto {
+                    full_name: name.to_string(),
+                    sortable_name: String::new(),
+                    external_url: Some(format!("https://example.com/{name}")),
+                })
+                .collect(),
+            publishers: vec![NewPublisherDto {
+                name: "Acme".to_string(),
+                sort: None,
+            }],
+            identifiers: vec![UpsertBookIdentifier {
+                book_id: 0,
+                id: None,
+                label: "isbn".to_string(),
+                value: "9780000000002".to_string(),
+            }],
+            language: Some(NewLanguageDto {
+                lang_code: "fr".to_string(),
+            }),
+            tags: vec![NewTagDto {
+                name: "Mystery & Suspense".to_string(),
+            }],
+            rating: Some(NewRatingDto { rating: 4 }),
+            series: Some(NewSeriesDto {
+                name: "Versions".to_string(),
+            }),
+            description: Some("<p>Same either way.</p>".to_string()),
+            files: Some(vec![]),
+        })
+        .unwrap()
+        .book;
+    let opf_path = library.path().join(&book.book.path).join("metadata.opf");
+    let opf2 = fs::read_to_string(&opf_path).unwrap();
+    assert!(opf2.contains(r#"version="2.0""#));
+
+    let mut client = client.with_config(ClientConfig {
+        opf_version: OpfVersion::V3,
+        ..Default::default()
+    });
+    client
+        .update_book(
+            book.book.id,
+            UpdateLibraryEntryDto {
+                book: UpdateBookDto::default(),
+                author_id_list: None,
+                description: Some(Some("<p>Same either way.</p>".to_string())),
+            },
+        )
+        .unwrap();
+    let opf3 = fs::read_to_string(&opf_path).unwrap();
+    assert!(opf3.contains(r#"version="3.0""#));
+    assert!(opf3.contains(r#"property="belongs-to-collection""#));
+    assert!(opf3.contains(r#"property="dcterms:modified""#));
+    assert!(!opf3.contains("opf:role"));
+
+    let opf2 = OpfMetadata::parse(&opf2).unwrap();
+    let opf3 = OpfMetadata::parse(&opf3).unwrap();
+    // OPF 2 repeats the combined author sort on every creator, while OPF 3
+    // gives each its own. Both describe the same authors.
+    let sort_names = |metadata: &OpfMetadata| {
+        metadata
+            .clone()
+            .into_library_entry(vec![])
+            .authors
+            .into_iter()
+            .map(|author| author.sortable_name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(sort_names(&opf2), vec!["Doe, Jane", "Roe, Rick"]);
+    assert_eq!(sort_names(&opf2), sort_names(&opf3));
+    assert_eq!(opf3.creators[0].file_as.as_deref(), Some("Doe, Jane"));
+    assert_eq!(
+        OpfMetadata {
+            creators: vec![],
+            ..opf2
+        },
+        OpfMetadata {
+            creators: vec![],
+            ..opf3
+        }
+    );
+}
+
+#[test]
+fn hostile_strings_survive_a_round_trip() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let title = "Tom & Jerry <Vol. 1> ]]> &amp;";
+    let authors = ["O'Brien \"The <Great>\"", "Smith & Wesson"];
+    let tags = ["say \"cheese\" & 'grin'", "<script>alert(1)</script>"];
+    let link = "https://example.com/?a=1&b=\"2\"";
+    let report = client
+        .add_book(NewLibraryEntryDto {
+            book: NewBookDto {
+                title: title.to_string(),
+                timestamp: None,
+                pubdate: None,
+                series_index: 1.0,
+                flags: 1,
+                has_cover: None,
+            },
+            authors: authors
+                .iter()
+                .map(|name| NewAuthorDto {
+                    full_name: name.to_string(),
+                    sortable_name: String::new(),
+                    external_url: Some(link.to_string()),
+                })
+                .collect(),
+            publishers: vec![NewPublisherDto {
+                name: "A&B <Press>".to_string(),
+                sort: None,
+            }],
+            identifiers: vec![UpsertBookIdentifier {
+                book_id: 0,
+                id: None,
+                label: "custom".to_string(),
+                value: "<x & \"y\">".to_string(),
+            }],
+            language: None,
+            tags: tags
+                .iter()
+                .map(|name| NewTagDto {
+                    name: name.to_string(),
+                })
+                .collect(),
+            rating: None,
+            series: Some(NewSeriesDto {
+                name: "<![CDATA[ & ]]>\u{1}".to_string(),
+            }),
+            description: Some("<p>Fish &amp; chips</p>".to_string()),
+            files: Some(vec![]),
+        })
+        .unwrap();
+    let book = &report.book;
+
+    let metadata =
+        OpfMetadata::from_path(library.path().join(&book.book.path).join("metadata.opf")).unwrap();
+    assert_eq!(metadata.title.as_deref(), Some(title));
+    assert_eq!(metadata.title_sort, book.book.sort);
+    assert_eq!(metadata.authors(), authors);
+    assert_eq!(metadata.publisher.as_deref(), Some("A&B <Press>"));
+    assert_eq!(
+        metadata.identifiers,
+        vec![("custom".to_string(), "<x & \"y\">".to_string())]
+    );
+    assert_eq!(metadata.tags, tags);
+    // Control characters cannot be written to XML at all, so are dropped
+    assert_eq!(metadata.series.as_deref(), Some("<![CDATA[ & ]]>"));
+    assert_eq!(metadata.description, book.book_description_html);
+    assert_eq!(
+        metadata.author_links.get(authors[0]).map(String::as_str),
+        Some(link)
+    );
+}
+
+#[test]
+fn producer_and_cover_follow_the_client() {
+    let library = tempfile::tempdir().unwrap();
+    let client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let mut client = client.with_config(ClientConfig {
+        opf_producer: Some(OpfProducer {
+            name: "Citadel".to_string(),
+            version: Some("1.2.0".to_string()),
+            url: Some("https://example.com/citadel".to_string()),
+        }),
+        ..Default::default()
+    });
+    let book = client
+        .add_book(NewLibraryEntryDto {
+            book: NewBookDto {
+                title: "Produced".to_string(),
+                timestamp: None,
+                pubdate: None,
+                series_index: 1.0,
+                flags: 1,
+                has_cover: None,
+            },
+            authors: vec![],
+            publishers: vec![],
+            identifiers: vec![],
+            language: None,
+            tags: vec![],
+            rating: None,
+            series: None,
+            description: None,
+            files: Some(vec![]),
+        })
+        .unwrap()
+        .book;
+    let book_dir = library.path().join(&book.book.path);
+    let opf = fs::read_to_string(book_dir.join("metadata.opf")).unwrap();
+    assert!(opf.contains(
+        r#"<dc:contributor opf:file-as="Citadel" opf:role="bkp">Citadel (1.2.0) [https://example.com/citadel]</dc:contributor>"#
+    ));
+    assert!(!opf.contains("EhArchive"));
+    // Without a cover there is nothing for the guide to point at
+    assert!(!opf.contains("<guide>"));
+
+    fs::write(book_dir.join("cover.png"), b"not really a png").unwrap();
+    let mut client = client.with_config(ClientConfig {
+        opf_producer: None,
+        ..Default::default()
+    });
+    client
+        .update_book(
+            book.book.id,
+            UpdateLibraryEntryDto {
+                book: UpdateBookDto::default(),
+                author_id_list: None,
+                description: Some(Some("<p>Now with a cover</p>".to_string())),
+            },
+        )
+        .unwrap();
+    let opf = fs::read_to_string(book_dir.join("metadata.opf")).unwrap();
+    assert!(!opf.contains("dc:contributor"));
+    assert!(opf.contains(r#"<reference type="cover" title="Cover" href="cover.png"/>"#));
+}
+
+#[test]
+fn malformed_opf_is_rejected() {
+    assert!(matches!(
+        OpfMetadata::parse("<package><metadata><dc:title>Oops</dc:creator></metadata>"),
+        Err(CalibreError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn bare_non_ascii_identifiers_are_skipped() {
+    let opf = r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier>本</dc:identifier>
+    <dc:identifier>978044101359本</dc:identifier>
+    <dc:identifier>0441013593</dc:identifier>
+    <dc:title>Dune</dc:title>
+  </metadata>
+</package>"#;
+
+    let metadata = OpfMetadata::parse(opf).unwrap();
+    assert_eq!(
+        metadata.identifiers,
+        vec![("isbn".to_string(), "0441013593".to_string())]
+    );
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use std::fs;
+
+use libcalibre::client::{
+    ascii_file_name, calibre_book_file_name, calibre_book_folder_name, CalibreClient, ClientConfig,
+    PathScheme,
+};
+use libcalibre::dtos::library::{NewLibraryEntryDto, NewLibraryFileDto};
+
+fn new_entry(title: &str, author: &str, files: Vec<NewLibraryFileDto>) -> NewLibraryEntryDto {
+    NewLibraryEntryDto {
+        authors: vec![common::author(author)],
+        files: Some(files),
+        ..common::new_entry(title)
+    }
+}
+
+#[test]
+fn books_are_filed_the_way_calibre_files_them() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let source = sources.path().join("book.txt");
+    fs::write(&source, "text").unwrap();
+
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let report = client
+        .add_book(new_entry(
+            "Über: the *best* book?",
+            "Émile Zola",
+            vec![NewLibraryFileDto { path: source }],
+        ))
+        .unwrap();
+
+    assert_eq!(
+        report.book.book.path,
+        "Emile Zola/Uber_ the _best_ book_ (1)"
+    );
+    assert_eq!(
+        report.file_paths,
+        vec![library
+            .path()
+            .join("Emile Zola")
+            .join("Uber_ the _best_ book_ (1)")
+            .join("Uber_ the _best_ book_ - Emile Zola.txt")]
+    );
+    assert!(report.file_paths[0].is_file());
+}
+
+#[test]
+fn book_id_scheme_keeps_the_old_layout() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let source = sources.path().join("book.txt");
+    fs::write(&source, "text").unwrap();
+
+    let config = ClientConfig {
+        path_scheme: PathScheme::BookId,
+        ..Default::default()
+    };
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap())
+        .unwrap()
+        .with_config(config);
+    let report = client
+        .add_book(new_entry(
+            "Old Style",
+            "Jane Doe",
+            vec![NewLibraryFileDto { path: source }],
+        ))
+        .unwrap();
+
+    assert_eq!(report.book.book.path, "1");
+    assert_eq!(
+        report.file_paths,
+        vec![library.path().join("1").join("Old Style - Jane Doe.txt")]
+    );
+}
+
+#[test]
+fn names_are_sanitised_and_shortened_like_calibre() {
+    assert_eq!(ascii_file_name("  a/b\\c:d  "), "a_b_c_d");
+    assert_eq!(ascii_file_name(".hidden"), "_hidden");
+    assert_eq!(ascii_file_name("Ends with a dot."), "Ends with a dot_");
+    assert_eq!(ascii_file_name("..."), "_");
+    assert_eq!(ascii_file_name("a..b.txt"), "a_b.txt");
+
+    // Windows device names and empty names never become folders
+    assert_eq!(
+        calibre_book_folder_name(7, "Title", "con"),
+        "conw/Title (7)"
+    );
+    assert_eq!(calibre_book_folder_name(7, " ", ""), "Unknown/Unknown (7)");
+
+    let long = "x".repeat(300);
+    let folder = calibre_book_folder_name(12, &long, &long);
+    let (author, title) = folder.split_once('/').unwrap();
+    let limit = if cfg!(windows) { 36 } else { 96 };
+    assert_eq!(author.len(), limit);
+    assert_eq!(title, format!("{} (12)", "x".repeat(limit)));
+
+    let file = calibre_book_file_name(&long, &long);
+    let limit = if cfg!(windows) { 31 } else { 168 };
+    assert_eq!(file, format!("{0} - {0}", "x".repeat(limit)));
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::Path;
+
+use libcalibre::client::CalibreClient;
+use libcalibre::metadata::{library_entry_from_path, pdf};
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+
+/// XMP with the Dublin Core namespace bound to an unusual prefix, and some
+/// properties written as attributes.
+const XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:pdf="http://ns.adobe.com/pdf/1.3/"
+        xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+        pdf:Keywords="Space; Opera" xmp:CreateDate="2018-04-01T10:00:00Z"/>
+    <rdf:Description rdf:about="" xmlns:purl="http://purl.org/dc/elements/1.1/"
+        xmlns:xmpidq="http://ns.adobe.com/xmp/Identifier/qual/1.0/">
+      <purl:title><rdf:Alt><rdf:li xml:lang="x-default">Leviathan &amp; Co</rdf:li></rdf:Alt></purl:title>
+      <purl:creator><rdf:Seq><rdf:li>James Corey</rdf:li><rdf:li>Ann Poe</rdf:li></rdf:Seq></purl:creator>
+      <purl:description><rdf:Alt><rdf:li xml:lang="x-default">Ships.</rdf:li></rdf:Alt></purl:description>
+      <purl:publisher><rdf:Bag><rdf:li>Orbit</rdf:li></rdf:Bag></purl:publisher>
+      <purl:language><rdf:Bag><rdf:li>en</rdf:li></rdf:Bag></purl:language>
+      <purl:date><rdf:Seq><rdf:li>2011-06-02</rdf:li></rdf:Seq></purl:date>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+fn image(doc: &mut Document, width: i64, height: i64, filter: &str, content: &[u8]) -> Object {
+    let dict = dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Image",
+        "Width" => width,
+        "Height" => height,
+        "ColorSpace" => "DeviceRGB",
+        "BitsPerComponent" => 8,
+        "Filter" => filter,
+    };
+    doc.add_object(Stream::new(dict, content.to_vec())).into()
+}
+
+/// Writes `doc` as a PDF with `pages` pages, which all inherit the
+/// `xobjects` from the page tree.
+fn write_pdf(
+    path: &Path,
+    mut doc: Document,
+    pages: usize,
+    xobjects: Dictionary,
+    info: Dictionary,
+    xmp: Option<&str>,
+) {
+    let pages_id = doc.new_object_id();
+    let kids = (0..pages)
+        .map(|_| {
+            doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id })
+                .into()
+        })
+        .collect::<Vec<Object>>();
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages as i64,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            "Resources" => dictionary! { "XObject" => xobjects },
+        }),
+    );
+    let mut catalog = dictionary! { "Type" => "Catalog", "Pages" => pages_id };
+    if let Some(xmp) = xmp {
+        let dict = dictionary! { "Type" => "Metadata", "Subtype" => "XML" };
+        catalog.set("Metadata", doc.add_object(Stream::new(dict, xmp.into())));
+    }
+    let catalog_id = doc.add_object(catalog);
+    doc.trailer.set("Root", catalog_id);
+    if !info.is_empty() {
+        let info_id = doc.add_object(info);
+        doc.trailer.set("Info", info_id);
+    }
+    doc.save(path).unwrap();
+}
+
+#[test]
+fn xmp_wins_over_the_info_dictionary() {
+    let dir = tempfile::tempdir().unwrap();
+    let library = tempfile::tempdir().unwrap();
+    let path = dir.path().join("leviathan.pdf");
+
+    let mut doc = Document::with_version("1.7");
+    let mut xobjects = Dictionary::new();
+    xobjects.set(
+        "Im1",
+        image(&mut doc, 20, 30, "DCTDecode", b"\xFF\xD8\xFFlogo"),
+    );
+    xobjects.set(
+        "Im2",
+        image(&mut doc, 600, 900, "DCTDecode", b"\xFF\xD8\xFFcover"),
+    );
+    // Larger, but raw pixels that cannot be stored as they are
+    xobjects.set("Im3", image(&mut doc, 1200, 1800, "FlateDecode", b"pixels"));
+    let info = dictionary! {
+        "Title" => Object::string_literal("Info Title"),
+        "Author" => Object::string_literal("Someone Else"),
+        "Subject" => Object::string_literal("Something else"),
+    };
+    write_pdf(&path, doc, 3, xobjects, info, Some(XMP));
+
+    let info = pdf::read_pdf_info(&path).unwrap();
+    assert_eq!(info.title.as_deref(), Some("Leviathan & Co"));
+    assert_eq!(info.authors, vec!["James Corey", "Ann Poe"]);
+    assert_eq!(info.subject.as_deref(), Some("Ships."));
+    assert_eq!(info.keywords, vec!["Space", "Opera"]);
+    assert_eq!(
+        info.creation_date.map(|date| date.to_rfc3339()).as_deref(),
+        Some("2018-04-01T10:00:00+00:00")
+    );
+    assert_eq!(info.page_count, 3);
+    assert_eq!(
+        pdf::read_cover(&path).unwrap().unwrap(),
+        b"\xFF\xD8\xFFcover"
+    );
+
+    let entry = library_entry_from_path(&path).unwrap();
+    assert_eq!(entry.book.title, "Leviathan & Co");
+    assert_eq!(
+        entry.book.pubdate.map(|date| date.to_rfc3339()).as_deref(),
+        Some("2011-06-02T00:00:00+00:00")
+    );
+    assert_eq!(entry.publishers[0].name, "Orbit");
+    assert_eq!(entry.description.as_deref(), Some("Ships."));
+
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let report = client.add_book(entry).unwrap();
+    assert!(report.language_id.is_some());
+    assert_eq!(
+        fs::read(report.cover_path.unwrap()).unwrap(),
+        b"\xFF\xD8\xFFcover"
+    );
+}
+
+#[test]
+fn the_info_dictionary_is_read_without_xmp() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("paper.pdf");
+    let info = dictionary! {
+        "Title" => Object::string_literal(" A Paper "),
+        "Author" => Object::string_literal("Jane Doe & John Roe"),
+        "Subject" => Object::string_literal("On papers"),
+        "Keywords" => Object::string_literal("writing, science; review"),
+        "CreationDate" => Object::string_literal("D:20190305120000+01'00'"),
+    };
+    write_pdf(
+        &path,
+        Document::with_version("1.7"),
+        1,
+        Dictionary::new(),
+        info,
+        None,
+    );
+
+    let info = pdf::read_pdf_info(&path).unwrap();
+    assert_eq!(info.title.as_deref(), Some("A Paper"));
+    assert_eq!(info.authors, vec!["Jane Doe", "John Roe"]);
+    assert_eq!(info.keywords, vec!["writing", "science", "review"]);
+    assert_eq!(info.page_count, 1);
+    assert_eq!(pdf::read_cover(&path).unwrap(), None);
+
+    let entry = library_entry_from_path(&path).unwrap();
+    assert_eq!(entry.description.as_deref(), Some("On papers"));
+    // Without a publication date the creation date stands in
+    assert_eq!(
+        entry.book.pubdate.map(|date| date.to_rfc3339()).as_deref(),
+        Some("2019-03-05T11:00:00+00:00")
+    );
+    assert_eq!(entry.tags.len(), 3);
+
+    let untitled = dir.path().join("untitled.pdf");
+    write_pdf(
+        &untitled,
+        Document::with_version("1.7"),
+        2,
+        Dictionary::new(),
+        Dictionary::new(),
+        None,
+    );
+    let entry = library_entry_from_path(&untitled).unwrap();
+    assert_eq!(entry.book.title, "untitled");
+    assert!(entry.authors.is_empty());
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use std::fs;
+
+use common::{add_txt_book, author, new_book, new_entry, tag};
+use libcalibre::client::restore_library::PRE_RESTORE_DB_NAME;
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::book::NewBookDto;
+use libcalibre::dtos::library::NewLibraryEntryDto;
+use libcalibre::dtos::publisher::NewPublisherDto;
+use libcalibre::dtos::rating::NewRatingDto;
+use libcalibre::dtos::series::NewSeriesDto;
+use libcalibre::{BookWithAuthorsAndFiles, CalibreError, UpsertBookIdentifier};
+
+/// An entry with every kind of metadata a restore has to bring back.
+fn full_entry(title: &str) -> NewLibraryEntryDto {
+    NewLibraryEntryDto {
+        book: NewBookDto {
+            series_index: 2.0,
+            ..new_book(title)
+        },
+        authors: vec![author("Jane Doe"), author("John Roe")],
+        publishers: vec![NewPublisherDto {
+            name: "Acme".to_string(),
+            sort: None,
+        }],
+        identifiers: vec![UpsertBookIdentifier {
+            book_id: 0,
+            id: None,
+            label: "isbn".to_string(),
+            value: format!("isbn-{title}"),
+        }],
+        tags: vec![tag("Fiction")],
+        rating: Some(NewRatingDto { rating: 8 }),
+        series: Some(NewSeriesDto {
+            name: "Restorations".to_string(),
+        }),
+        description: Some("<p>Back again.</p>".to_string()),
+        ..new_entry(title)
+    }
+}
+
+fn summary(book: &BookWithAuthorsAndFiles) -> impl PartialEq + std::fmt::Debug {
+    (
+        book.book.id,
+        book.book.uuid.clone(),
+        book.book.title.clone(),
+        book.book.path.clone(),
+        book.book.has_cover,
+        book.book.series_index,
+        book.authors
+            .iter()
+            .map(|author| (author.name.clone(), author.sort.clone()))
+            .collect::<Vec<_>>(),
+        book.files
+            .iter()
+            .map(|file| {
+                (
+                    file.name.clone(),
+                    file.format.clone(),
+                    file.uncompressed_size,
+                )
+            })
+            .collect::<Vec<_>>(),
+        book.book_description_html.clone(),
+        book.series.as_ref().map(|series| series.name.clone()),
+    )
+}
+
+#[test]
+fn library_is_rebuilt_from_book_folders() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let library_root = library.path().to_str().unwrap();
+    let mut client = CalibreClient::create_library(library_root).unwrap();
+    let first = add_txt_book(&mut client, sources.path(), full_entry("First"));
+    let gone = add_txt_book(&mut client, sources.path(), full_entry("Gone"));
+    let third = add_txt_book(&mut client, sources.path(), full_entry("Third"));
+    client.delete_book(gone).unwrap();
+    let before = [first, third].map(|id| client.find_book_with_authors(id).unwrap());
+    drop(client);
+
+    fs::write(library.path().join("metadata.db"), "not a database").unwrap();
+
+    let (mut client, report) = CalibreClient::restore_library(library_root).unwrap();
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    assert_eq!(report.restored.len(), 2);
+    assert_eq!(
+        report.previous_database,
+        Some(library.path().join(PRE_RESTORE_DB_NAME))
+    );
+
+    let mut after = client.find_all().unwrap();
+    after.sort_by_key(|book| book.book.id);
+    assert_eq!(after.len(), 2);
+    for (before, after) in before.iter().zip(&after) {
+        assert_eq!(summary(before), summary(after));
+        assert_eq!(
+            client.list_identifiers_for_book(after.book.id).unwrap()[0].val,
+            format!("isbn-{}", after.book.title)
+        );
+    }
+    assert_eq!(client.get_all_tags().unwrap().len(), 1);
+    assert_eq!(client.get_all_publishers().unwrap().len(), 1);
+    assert_eq!(client.get_all_authors().unwrap().len(), 2);
+
+    // The trashed book stays in the trash and keeps its ID free
+    let restored = client.restore_book_from_trash(gone).unwrap();
+    assert_eq!(restored.book.book.id, gone);
+}
+
+#[test]
+fn unreadable_folders_are_reported() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let library_root = library.path().to_str().unwrap();
+    let mut client = CalibreClient::create_library(library_root).unwrap();
+    let kept = add_txt_book(&mut client, sources.path(), full_entry("Kept"));
+    let broken = add_txt_book(&mut client, sources.path(), full_entry("Broken"));
+    let broken_dir = library
+        .path()
+        .join(client.find_book_with_authors(broken).unwrap().book.path);
+    drop(client);
+
+    fs::write(broken_dir.join("metadata.opf"), "<package><metadata>").unwrap();
+    // A Calibre-style folder with a book but no OPF
+    let orphan_dir = library.path().join("Someone").join("Orphan (9)");
+    fs::create_dir_all(&orphan_dir).unwrap();
+    fs::write(orphan_dir.join("Orphan.epub"), "").unwrap();
+    fs::remove_file(library.path().join("metadata.db")).unwrap();
+
+    let (mut client, report) = CalibreClient::restore_library(library_root).unwrap();
+    assert_eq!(report.previous_database, None);
+    assert_eq!(report.restored.len(), 1);
+    assert_eq!(report.restored[0].book.book.id, kept);
+
+    let mut failed = report
+        .failed
+        .iter()
+        .map(|failure| failure.path.clone())
+        .collect::<Vec<_>>();
+    failed.sort();
+    assert_eq!(failed, vec![broken_dir.clone(), orphan_dir]);
+    assert!(report
+        .failed
+        .iter()
+        .any(|failure| failure.path == broken_dir
+            && matches!(failure.error, CalibreError::MetadataParse { .. })));
+
+    assert!(client.find_book_with_authors(broken).is_err());
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::fs;
+
+use common::{author, new_book};
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::book::NewBookDto;
+use libcalibre::dtos::library::{NewLibraryEntryDto, ReplaceLibraryEntryDto};
+use libcalibre::dtos::series::NewSeriesDto;
+
+fn book(title: &str, series_index: f32) -> NewBookDto {
+    NewBookDto {
+        series_index,
+        ..new_book(title)
+    }
+}
+
+fn new_entry(title: &str, series: &str, series_index: f32) -> NewLibraryEntryDto {
+    NewLibraryEntryDto {
+        book: book(title, series_index),
+        authors: vec![author("Terry Pratchett")],
+        series: Some(NewSeriesDto {
+            name: series.to_string(),
+        }),
+        ..common::new_entry(title)
+    }
+}
+
+#[test]
+fn added_book_is_in_its_series() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+
+    let report = client
+        .add_book(new_entry("Equal Rites", "The Discworld", 3.5))
+        .unwrap();
+
+    let series = report.book.series.as_ref().unwrap();
+    assert_eq!(series.name, "The Discworld");
+    assert_eq!(series.sort.as_deref(), Some("Discworld, The"));
+    assert_eq!(report.series_id, Some(series.id));
+    assert_eq!(report.book.book.series_index, 3.5);
+
+    let opf = fs::read_to_string(
+        library
+            .path()
+            .join(&report.book.book.path)
+            .join("metadata.opf"),
+    )
+    .unwrap();
+    assert!(opf.contains(r#"<meta name="calibre:series" content="The Discworld"/>"#));
+    assert!(opf.contains(r#"<meta name="calibre:series_index" content="3.5"/>"#));
+}
+
+#[test]
+fn renaming_onto_an_existing_series_merges_them() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let first = client
+        .add_book(new_entry("The Colour of Magic", "Discworld", 1.0))
+        .unwrap();
+    let second = client
+        .add_book(new_entry("The Light Fantastic", "Disc World", 2.0))
+        .unwrap();
+    let typo_id = second.series_id.unwrap();
+
+    client
+        .replace_series_with_translation(typo_id, "Discworld")
+        .unwrap();
+
+    let all_series = client.get_all_series().unwrap();
+    assert_eq!(all_series.len(), 1);
+    assert_eq!(all_series[0].id, first.series_id.unwrap());
+    let moved = client.find_book_with_authors(second.book.book.id).unwrap();
+    assert_eq!(moved.series.unwrap().name, "Discworld");
+    assert_eq!(moved.book.series_index, 2.0);
+
+    client
+        .replace_series_with_translation(all_series[0].id, "Diskworld")
+        .unwrap();
+    assert_eq!(client.get_all_series().unwrap()[0].name, "Diskworld");
+}
+
+#[test]
+fn replacing_metadata_without_a_series_removes_it() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let added = client
+        .add_book(new_entry("Mort", "Discworld", 4.0))
+        .unwrap();
+    let book_id = added.book.book.id;
+
+    client
+        .replace_book_metadata(
+            book_id,
+            ReplaceLibraryEntryDto {
+                book: book("Mort", 4.0),
+                authors: vec![],
+                publishers: vec![],
+                identifiers: vec![],
+                language: None,
+                tags: vec![],
+                rating: None,
+                series: None,
+                description: None,
+            },
+        )
+        .unwrap();
+
+    let book = client.find_book_with_authors(book_id).unwrap();
+    assert!(book.series.is_none());
+    assert_eq!(book.book.series_index, 1.0);
+}
+
+#[test]
+fn deleting_the_last_book_removes_its_series() {
+    let library = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let kept = client
+        .add_book(new_entry("Sourcery", "Discworld", 5.0))
+        .unwrap();
+    let only = client
+        .add_book(new_entry("Good Omens", "Standalone", 1.0))
+        .unwrap();
+
+    client.delete_book(only.book.book.id).unwrap();
+    client.delete_book(kept.book.book.id).unwrap();
+    assert!(client.get_all_series().unwrap().is_empty());
+
+    // The series comes back with the book
+    let restored = client.restore_book_from_trash(kept.book.book.id).unwrap();
+    assert_eq!(restored.book.series.unwrap().name, "Discworld");
+    assert_eq!(restored.book.book.series_index, 5.0);
+}
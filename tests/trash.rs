@@ -0,0 +1,206 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use common::{new_entry, tag};
+use diesel::connection::SimpleConnection;
+use libcalibre::client::trash::{TrashKind, DEFAULT_TRASH_EXPIRY_DAYS, TRASH_DIR_NAME};
+use libcalibre::client::CalibreClient;
+use libcalibre::dtos::library::NewLibraryEntryDto;
+use libcalibre::persistence::establish_connection;
+use libcalibre::CalibreError;
+
+fn add_txt_book(client: &mut CalibreClient, sources: &Path, title: &str) -> i32 {
+    let entry = NewLibraryEntryDto {
+        tags: vec![tag("Fiction")],
+        ..new_entry(title)
+    };
+    common::add_txt_book(client, sources, entry)
+}
+
+fn set_age(path: &Path, days: u64) {
+    let then = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+    fs::File::open(path).unwrap().set_modified(then).unwrap();
+}
+
+#[test]
+fn deleted_book_can_be_restored_from_trash() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), "Recycled");
+    let original = client.find_book_with_authors(book_id).unwrap();
+
+    client.delete_book(book_id).unwrap();
+
+    let trash = client.list_trash().unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].kind, TrashKind::Book);
+    assert_eq!(trash[0].book_id, book_id);
+    assert_eq!(trash[0].title, "Recycled");
+    assert_eq!(trash[0].authors, vec!["Jane Doe"]);
+    assert_eq!(trash[0].formats, vec!["TXT"]);
+    assert_eq!(
+        trash[0].path,
+        library
+            .path()
+            .join(TRASH_DIR_NAME)
+            .join("b")
+            .join(book_id.to_string())
+    );
+
+    let report = client.restore_book_from_trash(book_id).unwrap();
+    assert_eq!(report.book.book.id, book_id);
+    assert_eq!(report.book.book.uuid, original.book.uuid);
+    assert_eq!(report.book.book.title, "Recycled");
+    assert_eq!(report.book.authors[0].name, "Jane Doe");
+    assert_eq!(report.book.files.len(), 1);
+    assert!(report.file_paths[0].is_file());
+    assert_eq!(report.tag_ids.len(), 1);
+    assert!(client.list_trash().unwrap().is_empty());
+}
+
+#[test]
+fn restoring_over_an_existing_id_conflicts() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let deleted = add_txt_book(&mut client, sources.path(), "Deleted");
+    let existing = add_txt_book(&mut client, sources.path(), "Existing");
+    client.delete_book(deleted).unwrap();
+
+    // A trash entry whose ID is in use again, e.g. from another library
+    let book_trash = library.path().join(TRASH_DIR_NAME).join("b");
+    fs::create_dir(book_trash.join(existing.to_string())).unwrap();
+    fs::copy(
+        book_trash.join(deleted.to_string()).join("metadata.opf"),
+        book_trash.join(existing.to_string()).join("metadata.opf"),
+    )
+    .unwrap();
+
+    assert!(matches!(
+        client.restore_book_from_trash(existing),
+        Err(CalibreError::Conflict(_))
+    ));
+    assert!(matches!(
+        client.restore_book_from_trash(42),
+        Err(CalibreError::NotFound(_))
+    ));
+    assert_eq!(client.list_trash().unwrap().len(), 2);
+    assert_eq!(client.find_all().unwrap().len(), 1);
+}
+
+#[test]
+fn failed_delete_keeps_the_older_trash_entry() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), "Kept");
+    let book_dir = library
+        .path()
+        .join(client.find_book_with_authors(book_id).unwrap().book.path);
+
+    // An older entry for the same ID, left over from an earlier delete
+    let older = library
+        .path()
+        .join(TRASH_DIR_NAME)
+        .join("b")
+        .join(book_id.to_string());
+    fs::create_dir_all(&older).unwrap();
+    fs::write(older.join("metadata.opf"), "older").unwrap();
+
+    let mut conn =
+        establish_connection(&library.path().join("metadata.db").to_string_lossy()).unwrap();
+    conn.batch_execute(
+        "CREATE TRIGGER no_deletes BEFORE DELETE ON books
+         BEGIN SELECT RAISE(ABORT, 'no deletes'); END",
+    )
+    .unwrap();
+
+    assert!(client.delete_book(book_id).is_err());
+    assert_eq!(
+        fs::read_to_string(older.join("metadata.opf")).unwrap(),
+        "older"
+    );
+    assert!(book_dir.join("Kept - Jane Doe.txt").is_file());
+    assert!(client.find_book_with_authors(book_id).is_ok());
+
+    conn.batch_execute("DROP TRIGGER no_deletes").unwrap();
+    client.delete_book(book_id).unwrap();
+    assert_ne!(
+        fs::read_to_string(older.join("metadata.opf")).unwrap(),
+        "older"
+    );
+    assert!(older.join("Kept - Jane Doe.txt").is_file());
+    // The staging folder holding the older entry is cleaned up
+    assert!(fs::read_dir(library.path()).unwrap().all(|entry| !entry
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .starts_with(".libcalibre-staging")));
+}
+
+#[test]
+fn trashed_format_can_be_restored() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let book_id = add_txt_book(&mut client, sources.path(), "Formats");
+
+    // Lay the entry out the way Calibre does when it removes a format
+    let entry = library
+        .path()
+        .join(TRASH_DIR_NAME)
+        .join("f")
+        .join(book_id.to_string());
+    fs::create_dir_all(&entry).unwrap();
+    fs::write(entry.join("pdf"), b"%PDF-1.4").unwrap();
+    fs::write(
+        entry.join("metadata.json"),
+        r#"{"title": "Formats", "authors": ["Jane Doe"]}"#,
+    )
+    .unwrap();
+
+    let trash = client.list_trash().unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].kind, TrashKind::Format);
+    assert_eq!(trash[0].title, "Formats");
+    assert_eq!(trash[0].formats, vec!["PDF"]);
+
+    let restored = client.restore_format_from_trash(book_id, "PDF").unwrap();
+    assert_eq!(restored.format, "PDF");
+    let book = client.find_book_with_authors(book_id).unwrap();
+    assert_eq!(book.files.len(), 2);
+    assert!(library
+        .path()
+        .join(&book.book.path)
+        .join(restored.as_filename())
+        .is_file());
+    assert!(!entry.exists());
+}
+
+#[test]
+fn expire_trash_removes_only_old_entries() {
+    let library = tempfile::tempdir().unwrap();
+    let sources = tempfile::tempdir().unwrap();
+    let mut client = CalibreClient::create_library(library.path().to_str().unwrap()).unwrap();
+    let old = add_txt_book(&mut client, sources.path(), "Old");
+    let recent = add_txt_book(&mut client, sources.path(), "Recent");
+    client.delete_book(old).unwrap();
+    client.delete_book(recent).unwrap();
+
+    let trash = client.list_trash().unwrap();
+    let old_entry = trash.iter().find(|entry| entry.book_id == old).unwrap();
+    set_age(&old_entry.path, 30);
+
+    let expired = client.expire_trash(DEFAULT_TRASH_EXPIRY_DAYS).unwrap();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].book_id, old);
+    assert!(!expired[0].path.exists());
+
+    let remaining = client.list_trash().unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].book_id, recent);
+}